@@ -0,0 +1,45 @@
+//! Moving elements in batches with `send_batch` and `recv_batch` against
+//! one `send` and `recv` per element.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use mpmcbq::RingBuffer;
+
+const CAPACITY: usize = 1024;
+
+fn batches(c: &mut Criterion) {
+    let (s, r) = RingBuffer::<u64>::new(CAPACITY);
+    let mut group = c.benchmark_group("batch");
+    let mut buf = Vec::with_capacity(128);
+
+    for size in [8, 32, 128] {
+        let items: Vec<u64> = (0..size as u64).collect();
+
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("batched", size), &items, |b, items| {
+            b.iter(|| {
+                assert_eq!(s.send_batch(items), size);
+                buf.clear();
+                assert_eq!(r.recv_batch(&mut buf, size), size);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("single", size), &items, |b, items| {
+            b.iter(|| {
+                for &d in items {
+                    assert!(s.send(d));
+                }
+
+                for _ in 0..size {
+                    assert!(r.recv().is_ok());
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, batches);
+criterion_main!(benches);
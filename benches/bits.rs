@@ -0,0 +1,47 @@
+//! Flags through a `RingBuffer<bool>` one by one against a `BitQueue`,
+//! 4096 flags sent and then received.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mpmcbq::{BitQueue, RingBuffer};
+
+const FLAGS: usize = 4096;
+
+fn flags(c: &mut Criterion) {
+    let bits: Vec<bool> = (0..FLAGS).map(|i| i % 3 == 0).collect();
+    let mut group = c.benchmark_group("flags");
+
+    group.throughput(Throughput::Elements(FLAGS as u64));
+
+    let (s, r) = RingBuffer::<bool>::new(FLAGS);
+
+    group.bench_function("bool", |b| {
+        b.iter(|| {
+            for &bit in &bits {
+                assert!(s.send(bit));
+            }
+
+            std::iter::from_fn(|| r.recv().ok())
+                .filter(|&bit| bit)
+                .count()
+        })
+    });
+
+    let (_bits, mut s, mut r) = BitQueue::new(FLAGS / 64);
+    let mut out = Vec::with_capacity(FLAGS);
+
+    group.bench_function("packed", |b| {
+        b.iter(|| {
+            out.clear();
+            assert_eq!(s.send_bits(&bits), FLAGS);
+            assert!(s.flush());
+            r.recv_bits(&mut out);
+            out.iter().filter(|&&bit| bit).count()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, flags);
+criterion_main!(benches);
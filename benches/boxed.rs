@@ -0,0 +1,62 @@
+//! Copying payloads through the ring against boxing them, at 64, 256 and
+//! 1024 bytes: a burst of sends, then receiving the burst.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use mpmcbq::{BoxedChannel, RingBuffer};
+
+const BURST: usize = 256;
+
+#[derive(Clone, Copy)]
+struct Payload<const N: usize>([u8; N]);
+
+impl<const N: usize> Default for Payload<N> {
+    fn default() -> Self {
+        Self([0; N])
+    }
+}
+
+fn sizes<const N: usize>(c: &mut Criterion) {
+    let mut group = c.benchmark_group("payload");
+
+    group.throughput(Throughput::Elements(BURST as u64));
+
+    let (s, r) = RingBuffer::<Payload<N>>::new(BURST);
+
+    group.bench_function(BenchmarkId::new("copied", N), |b| {
+        b.iter(|| {
+            for i in 0..BURST {
+                assert!(s.send(Payload([i as u8; N])));
+            }
+
+            std::iter::from_fn(|| r.recv().ok())
+                .map(|d| d.0[N - 1] as usize)
+                .sum::<usize>()
+        })
+    });
+
+    let (_boxed, s, r) = BoxedChannel::<Payload<N>>::new(BURST);
+
+    group.bench_function(BenchmarkId::new("boxed", N), |b| {
+        b.iter(|| {
+            for i in 0..BURST {
+                assert!(s.send(Payload([i as u8; N])).is_ok());
+            }
+
+            std::iter::from_fn(|| r.recv().ok())
+                .map(|d| d.0[N - 1] as usize)
+                .sum::<usize>()
+        })
+    });
+
+    group.finish();
+}
+
+fn payloads(c: &mut Criterion) {
+    sizes::<64>(c);
+    sizes::<256>(c);
+    sizes::<1024>(c);
+}
+
+criterion_group!(benches, payloads);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+//! One producer and one consumer moving 4 KiB elements through a
+//! `RingBuffer`, copied in and out with `send`/`recv` or built and read in
+//! place with `claim`/`recv_ref`.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mpmcbq::RingBuffer;
+
+const CAPACITY: usize = 64;
+const ELEMENTS: u64 = 10_000;
+
+struct Frame([u64; 512]);
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame([0; 512])
+    }
+}
+
+fn copied() {
+    let (s, r) = RingBuffer::<Frame>::new(CAPACITY);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..ELEMENTS {
+                let mut frame = Frame([i; 512]);
+
+                while let Err(e) = s.try_send(frame) {
+                    frame = e.into_inner();
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for _ in 0..ELEMENTS {
+            let last = loop {
+                match r.recv() {
+                    Ok(frame) => break frame.0[511],
+                    Err(_) => thread::yield_now(),
+                }
+            };
+
+            criterion::black_box(last);
+        }
+    });
+}
+
+fn in_place() {
+    let (s, r) = RingBuffer::<Frame>::new(CAPACITY);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..ELEMENTS {
+                loop {
+                    match s.claim() {
+                        Ok(mut slot) => break slot.0.fill(i),
+                        Err(_) => thread::yield_now(),
+                    }
+                }
+            }
+        });
+
+        for _ in 0..ELEMENTS {
+            let last = loop {
+                match r.recv_ref() {
+                    Ok(frame) => break frame.0[511],
+                    Err(_) => thread::yield_now(),
+                }
+            };
+
+            criterion::black_box(last);
+        }
+    });
+}
+
+fn transfer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_elements");
+
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function("copied", |b| b.iter(copied));
+    group.bench_function("in_place", |b| b.iter(in_place));
+    group.finish();
+}
+
+criterion_group!(benches, transfer);
+criterion_main!(benches);
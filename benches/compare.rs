@@ -0,0 +1,95 @@
+//! `RingBuffer` next to `std::sync::mpsc::sync_channel` of the same
+//! capacity, moving `ELEMENTS` elements with one producer and one consumer,
+//! with four producers and one consumer, and, for the queue alone as std
+//! has no multi-consumer channel, with two of each. crossbeam-channel is not
+//! a dependency, so it is not in the comparison.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mpmcbq::RingBuffer;
+
+const CAPACITY: usize = 128;
+const ELEMENTS: u64 = 20_000;
+
+fn mpmcbq(producers: u64, consumers: u64) {
+    let (s, r) = RingBuffer::<u64>::new(CAPACITY);
+    let received = AtomicU64::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..producers {
+            let s = &s;
+
+            scope.spawn(move || {
+                for i in 0..ELEMENTS / producers {
+                    while !s.send(i) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+
+        for _ in 0..consumers {
+            let (r, received) = (&r, &received);
+
+            scope.spawn(move || {
+                while received.load(Ordering::Relaxed) < ELEMENTS / producers * producers {
+                    match r.recv() {
+                        Ok(_) => {
+                            received.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(_) => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn std_mpsc(producers: u64) {
+    let (s, r) = mpsc::sync_channel::<u64>(CAPACITY);
+
+    thread::scope(|scope| {
+        for _ in 0..producers {
+            let s = s.clone();
+
+            scope.spawn(move || {
+                for i in 0..ELEMENTS / producers {
+                    s.send(i).unwrap();
+                }
+            });
+        }
+
+        drop(s);
+
+        for _ in r {}
+    });
+}
+
+fn compare(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spsc");
+
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function("mpmcbq", |b| b.iter(|| mpmcbq(1, 1)));
+    group.bench_function("std", |b| b.iter(|| std_mpsc(1)));
+    group.finish();
+
+    let mut group = c.benchmark_group("mpsc");
+
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function("mpmcbq", |b| b.iter(|| mpmcbq(4, 1)));
+    group.bench_function("std", |b| b.iter(|| std_mpsc(4)));
+    group.finish();
+
+    let mut group = c.benchmark_group("mpmc");
+
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function("mpmcbq", |b| b.iter(|| mpmcbq(2, 2)));
+    group.finish();
+}
+
+criterion_group!(benches, compare);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+//! Discarding a full queue with `skip` against a `recv` loop.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use mpmcbq::{Receiver, RingBuffer, Sender};
+
+const CAPACITY: usize = 4096;
+
+fn fill(s: &mut Sender<u64>) {
+    for i in 0..CAPACITY as u64 {
+        assert!(s.send(i));
+    }
+}
+
+fn recv_all(r: &mut Receiver<u64>) -> usize {
+    std::iter::from_fn(|| r.recv().ok()).count()
+}
+
+fn discard(c: &mut Criterion) {
+    let (mut s, mut r) = RingBuffer::<u64>::new(CAPACITY);
+    let mut group = c.benchmark_group("discard");
+
+    group.bench_function("skip", |b| {
+        b.iter_batched(
+            || fill(&mut s),
+            |_| r.skip(CAPACITY),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("recv", |b| {
+        b.iter_batched(
+            || fill(&mut s),
+            |_| recv_all(&mut r),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, discard);
+criterion_main!(benches);
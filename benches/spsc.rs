@@ -0,0 +1,63 @@
+//! One producer and one consumer moving elements through a `RingBuffer` and
+//! an `SpscRing` of the same capacity, as `examples/bench.rs` does by default.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mpmcbq::{RingBuffer, SpscRing};
+
+const CAPACITY: usize = 128;
+const ELEMENTS: u64 = 100_000;
+
+fn mpmc() {
+    let (s, r) = RingBuffer::<u64>::new(CAPACITY);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..ELEMENTS {
+                while !s.send(i) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for _ in 0..ELEMENTS {
+            while r.recv().is_err() {
+                thread::yield_now();
+            }
+        }
+    });
+}
+
+fn spsc() {
+    let (_q, mut s, mut r) = SpscRing::<u64>::new(CAPACITY);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..ELEMENTS {
+                while s.send(i).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for _ in 0..ELEMENTS {
+            while r.recv().is_none() {
+                thread::yield_now();
+            }
+        }
+    });
+}
+
+fn transfer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transfer");
+
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function("mpmc", |b| b.iter(mpmc));
+    group.bench_function("spsc", |b| b.iter(spsc));
+    group.finish();
+}
+
+criterion_group!(benches, transfer);
+criterion_main!(benches);
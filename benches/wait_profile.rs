@@ -0,0 +1,90 @@
+//! Latency and consumer CPU time of each wait profile under an intermittent
+//! load: one element every 50µs, so the consumer waits between any two.
+//! Prints the table cited in the `WaitProfile` docs.
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mpmcbq::{RingBuffer, WaitProfile};
+
+const ELEMENTS: usize = 20_000;
+const INTERVAL: Duration = Duration::from_micros(50);
+
+// CPU time of the calling thread, where the platform reports it.
+fn thread_cpu() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let ns = stat.split_whitespace().next()?.parse().ok()?;
+
+    Some(Duration::from_nanos(ns))
+}
+
+fn run(profile: WaitProfile) -> (Duration, Duration, Option<Duration>) {
+    let (_q, s, r) = RingBuffer::<u64>::builder()
+        .capacity(64)
+        .wait_profile(profile)
+        .build();
+    let epoch = Instant::now();
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for _ in 0..ELEMENTS {
+                thread::sleep(INTERVAL);
+                assert!(s.send(epoch.elapsed().as_nanos() as u64));
+            }
+        });
+
+        let consumer = scope.spawn(move || {
+            let mut buf = Vec::with_capacity(1);
+            let mut latencies = Vec::with_capacity(ELEMENTS);
+            let cpu = thread_cpu();
+
+            while latencies.len() < ELEMENTS {
+                buf.clear();
+                r.recv_batch_blocking(1, 1, Duration::from_secs(1), &mut buf);
+
+                for &sent in &buf {
+                    latencies.push(epoch.elapsed().as_nanos() as u64 - sent);
+                }
+            }
+
+            let cpu = thread_cpu().zip(cpu).map(|(end, start)| end - start);
+
+            (latencies, cpu)
+        });
+
+        let (mut latencies, cpu) = consumer.join().unwrap();
+
+        latencies.sort_unstable();
+
+        let at = |p: usize| Duration::from_nanos(latencies[latencies.len() * p / 100]);
+
+        (at(50), at(99), cpu.map(|cpu| cpu / ELEMENTS as u32))
+    })
+}
+
+fn main() {
+    println!(
+        "{:<30}  {:>10}  {:>10}  {:>16}",
+        "profile", "p50", "p99", "CPU per element"
+    );
+
+    for profile in [
+        WaitProfile::LowLatency,
+        WaitProfile::Balanced,
+        WaitProfile::PowerSave,
+        WaitProfile::Spin,
+        WaitProfile::SpinThenYield { spins: 1000 },
+    ] {
+        let (p50, p99, cpu) = run(profile);
+        let cpu = cpu.map_or("n/a".to_string(), |cpu| format!("{:.1?}", cpu));
+
+        println!(
+            "{:<30}  {:>10.1?}  {:>10.1?}  {:>16}",
+            format!("{:?}", profile),
+            p50,
+            p99,
+            cpu
+        );
+    }
+}
@@ -0,0 +1,55 @@
+#!/bin/sh
+# Expensive test profile. Not part of the per-commit gates; run before a
+# release or when touching the claim/publish protocol.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+# Millions of elements per producer in the stress tests instead of the
+# default that keeps `cargo test` quick.
+export MPMCBQ_STRESS_ITEMS="${MPMCBQ_STRESS_ITEMS:-5000000}"
+
+# Every test and the demo workload, once with the tuned orderings and once
+# with every atomic forced to SeqCst.
+for features in "" "strict-ordering"; do
+    echo "== features: ${features:-default}"
+    cargo test --release --all-targets --features "async stats registry tracing $features"
+    cargo run --release --features "bench $features"
+done
+
+# The padding variants change the layout of every queue header.
+for features in "no-padding" "pad-128"; do
+    echo "== features: $features"
+    cargo test --release --lib --features "$features"
+done
+
+# Single-threaded mode: blocking calls that cannot wait must not hang. The
+# tests that share a queue between threads are left out, and so are the
+# benches, which all do; everything else runs.
+echo "== features: single-threaded"
+cargo test --release --features "async stats registry single-threaded"
+cargo build --release --lib --target wasm32-unknown-unknown --features async
+
+# 32-bit targets: usize is 32 bits wide, positions still take 64-bit atomics.
+for target in i686-unknown-linux-gnu armv7-unknown-linux-gnueabihf; do
+    echo "== target: $target"
+    if command -v cross >/dev/null; then
+        cross test --release --target "$target"
+    else
+        cargo test --release --target "$target"
+    fi
+done
+
+# ThreadSanitizer over the unit tests and the 4x4 MPMC stress. std is rebuilt
+# instrumented so its atomics and locks are visible to TSan; no suppressions
+# are needed or accepted without a justification next to them.
+echo "== ThreadSanitizer"
+RUSTFLAGS="-Zsanitizer=thread" TSAN_OPTIONS="halt_on_error=1" \
+MPMCBQ_STRESS_ITEMS=200000 \
+    cargo +nightly test -Zbuild-std --target x86_64-unknown-linux-gnu \
+        --target-dir target/tsan --lib --test fifo -- --test-threads=1
+
+# Loom models of the claim paths, every interleaving up to the preemption
+# bound. The ring's atomics become loom's, so nothing else runs in this build.
+echo "== loom"
+RUSTFLAGS="--cfg loom" cargo test --release --target-dir target/loom --test loom
@@ -0,0 +1,305 @@
+//! Producers and consumers moving timestamped elements through one queue,
+//! for comparing machines and builds:
+//!
+//! ```text
+//! cargo run --release --features bench --example bench -- \
+//!     --producers 2 --consumers 2 --payload 64 --duration 2 --pin
+//! ```
+//!
+//! Sends and receives are the blocking ones, which wait as `--wait` says.
+//! Prints the throughput of every timed round and the percentiles of the
+//! time from taking an element's timestamp to receiving it, waiting for
+//! room included, over all of them.
+
+use std::env;
+use std::hint;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use mpmcbq::bench::{self, Config, Ctx, Workload};
+use mpmcbq::{Receiver, RecvTimeoutError, Sender, WaitProfile};
+
+const USAGE: &str = "\
+usage: bench [options]
+  --producers N     producer threads (1)
+  --consumers N     consumer threads (1)
+  --capacity N      queue capacity (128)
+  --payload BYTES   element size: 8, 64, 256 or 1024 (8)
+  --elements N      elements each producer sends (1000000)
+  --duration SECS   send for this long instead of a number of elements
+  --rounds N        timed rounds (5)
+  --warmup N        untimed rounds first (1)
+  --pin             pin the threads to cores
+  --wait PROFILE    low-latency, balanced, power-save, spin or yield (balanced)";
+
+// The latency of every SAMPLE-th element a consumer receives is kept.
+const SAMPLE: u64 = 16;
+
+struct Options {
+    config: Config,
+    payload: usize,
+    elements: u64,
+    duration: Option<Duration>,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        config: Config::default(),
+        payload: 8,
+        elements: 1_000_000,
+        duration: None,
+    };
+
+    while let Some(flag) = args.next() {
+        let flag = flag.as_str();
+
+        match flag {
+            "--producers" => options.config.producers = value(flag, args.next())?,
+            "--consumers" => options.config.consumers = value(flag, args.next())?,
+            "--capacity" => options.config.capacity = value(flag, args.next())?,
+            "--payload" => options.payload = value(flag, args.next())?,
+            "--elements" => options.elements = value(flag, args.next())?,
+            "--duration" => {
+                let secs: f64 = value(flag, args.next())?;
+
+                options.duration = Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .filter(|d| !d.is_zero());
+
+                if options.duration.is_none() {
+                    return Err(format!("--duration: invalid duration {}", secs));
+                }
+            }
+            "--rounds" => options.config.rounds = value(flag, args.next())?,
+            "--warmup" => options.config.warmup = value(flag, args.next())?,
+            "--pin" => options.config.pin = true,
+            "--wait" => {
+                options.config.wait = match args.next().as_deref() {
+                    Some("low-latency") => WaitProfile::LowLatency,
+                    Some("balanced") => WaitProfile::Balanced,
+                    Some("power-save") => WaitProfile::PowerSave,
+                    Some("spin") => WaitProfile::Spin,
+                    Some("yield") => WaitProfile::SpinThenYield { spins: 0 },
+                    Some(other) => return Err(format!("--wait: unknown profile {}", other)),
+                    None => return Err("--wait needs a value".to_string()),
+                }
+            }
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+
+    let Config {
+        producers,
+        consumers,
+        capacity,
+        rounds,
+        ..
+    } = options.config;
+
+    if producers == 0 || consumers == 0 || capacity == 0 || rounds == 0 {
+        return Err("thread counts, capacity and rounds must be > 0".to_string());
+    }
+
+    Ok(options)
+}
+
+fn value<N: FromStr>(flag: &str, value: Option<String>) -> Result<N, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+
+    value
+        .parse()
+        .map_err(|_| format!("{}: invalid number {}", flag, value))
+}
+
+// One round: producers send `[timestamp; W]` until they sent `elements`
+// or `duration` is up, consumers receive until the producers are done and
+// the queue is empty.
+struct Timed<'r> {
+    elements: u64,
+    duration: Option<Duration>,
+    epoch: Instant,
+    producing: AtomicUsize,
+    sent: AtomicU64,
+    received: AtomicU64,
+    // `None` for a warmup round.
+    results: Option<&'r Results>,
+}
+
+// Of all timed rounds: the sampled latencies in nanoseconds, and the
+// elements of each round.
+#[derive(Default)]
+struct Results {
+    latencies: Mutex<Vec<u64>>,
+    counts: Mutex<Vec<u64>>,
+}
+
+impl<'r> Timed<'r> {
+    fn nanos(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+}
+
+impl<'r, const W: usize> Workload<[u64; W]> for Timed<'r> {
+    fn producer(&self, tx: &mut Sender<'_, [u64; W]>, _: &Ctx) {
+        let end = self.duration.map(|d| Instant::now() + d);
+        let mut n = 0;
+
+        loop {
+            let done = match end {
+                // Looking at the clock only now and then.
+                Some(end) => n % 64 == 0 && Instant::now() >= end,
+                None => n == self.elements,
+            };
+
+            if done {
+                break;
+            }
+
+            if tx.send_blocking([self.nanos(); W]).is_err() {
+                panic!("the consumers left early");
+            }
+
+            n += 1;
+        }
+
+        self.sent.fetch_add(n, Ordering::Relaxed);
+        // Orders the sends before a consumer seeing the count drop.
+        self.producing.fetch_sub(1, Ordering::Release);
+    }
+
+    fn consumer(&self, rx: &mut Receiver<'_, [u64; W]>, _: &Ctx) {
+        let mut latencies = Vec::new();
+        let mut n = 0;
+
+        loop {
+            // Loaded before receiving: if every producer was done then, an
+            // empty queue stays empty.
+            let done = self.producing.load(Ordering::Acquire) == 0;
+
+            match rx.recv_timeout(Duration::from_millis(1)) {
+                Ok(d) => {
+                    if n % SAMPLE == 0 {
+                        latencies.push(self.nanos().saturating_sub(d[0]));
+                    }
+
+                    hint::black_box(d[W - 1]);
+                    n += 1;
+                }
+                Err(RecvTimeoutError::Timeout) if done => break,
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(e) => panic!("receive failed: {}", e),
+            }
+        }
+
+        self.received.fetch_add(n, Ordering::Relaxed);
+
+        if let Some(results) = self.results {
+            results.latencies.lock().unwrap().append(&mut latencies);
+        }
+    }
+
+    fn verify(&self) -> Result<(), String> {
+        let (sent, received) = (
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+        );
+
+        if sent != received {
+            return Err(format!("{} sent, {} received", sent, received));
+        }
+
+        if let Some(results) = self.results {
+            results.counts.lock().unwrap().push(received);
+        }
+
+        Ok(())
+    }
+}
+
+fn run<const W: usize>(options: &Options) -> Result<(), String> {
+    let config = &options.config;
+    let results = Results::default();
+    let mut made = 0;
+    let report = bench::run::<[u64; W], _>(config, || {
+        made += 1;
+
+        Timed {
+            elements: options.elements,
+            duration: options.duration,
+            epoch: Instant::now(),
+            producing: AtomicUsize::new(config.producers),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            results: (made > config.warmup).then_some(&results),
+        }
+    })?;
+
+    println!(
+        "{} producers, {} consumers, capacity {}, {} byte elements, {:?}",
+        config.producers,
+        config.consumers,
+        config.capacity,
+        W * 8,
+        config.wait
+    );
+
+    for (i, (elapsed, n)) in report
+        .rounds
+        .iter()
+        .zip(&*results.counts.lock().unwrap())
+        .enumerate()
+    {
+        println!(
+            "round {}: {} elements in {:?}, {:.2}M elements/s",
+            i,
+            n,
+            elapsed,
+            *n as f64 / elapsed.as_secs_f64() / 1e6
+        );
+    }
+
+    let mut samples = results.latencies.into_inner().unwrap();
+
+    samples.sort_unstable();
+
+    if let Some(&max) = samples.last() {
+        let at = |q: f64| Duration::from_nanos(samples[((samples.len() - 1) as f64 * q) as usize]);
+
+        println!(
+            "latency p50: {:?} p90: {:?} p99: {:?} p99.9: {:?} max: {:?}",
+            at(0.5),
+            at(0.9),
+            at(0.99),
+            at(0.999),
+            Duration::from_nanos(max)
+        );
+    }
+
+    println!("{}", report);
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let result = parse(args.into_iter()).and_then(|options| match options.payload {
+        8 => run::<1>(&options),
+        64 => run::<8>(&options),
+        256 => run::<32>(&options),
+        1024 => run::<128>(&options),
+        n => Err(format!("--payload: unsupported size {}", n)),
+    });
+
+    if let Err(e) = result {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
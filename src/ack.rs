@@ -0,0 +1,187 @@
+//! Acknowledgement mode: [`Receiver::recv_ack`] hands out an [`AckGuard`],
+//! and an element whose guard is dropped unacknowledged goes to a side queue
+//! that every receive drains before the ring, as do elements put back with
+//! `Receiver::unrecv`. Delivery is at least once; redelivered elements lose
+//! their place in the send order.
+//!
+//! [`Receiver::recv_ack`]: crate::Receiver::recv_ack
+
+use std::collections::VecDeque;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::order;
+use crate::rb::RingBuffer;
+
+// Unacknowledged elements with their redelivery counts. `len` lets the
+// common case, nothing to redeliver, skip the lock.
+pub(crate) struct Retry<T> {
+    len: AtomicUsize,
+    items: Mutex<VecDeque<(T, u32)>>,
+}
+
+impl<T> Retry<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            len: AtomicUsize::new(0),
+            items: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<(T, u32)>> {
+        #[cfg(test)]
+        crate::rb::hooks::lock();
+
+        // Only whole pushes and pops change the queue, so a panic elsewhere
+        // can't leave it torn.
+        self.items.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues `d` for redelivery if `live()`, evaluated under the lock,
+    /// still holds, and hands it back otherwise.
+    pub(crate) fn push(
+        &self,
+        d: T,
+        redeliveries: u32,
+        live: impl FnOnce() -> bool,
+    ) -> Result<(), T> {
+        let mut items = self.lock();
+
+        if !live() {
+            return Err(d);
+        }
+
+        items.push_back((d, redeliveries));
+        self.len.fetch_add(1, order::RETRY);
+        Ok(())
+    }
+
+    /// Like [`Retry::push`], but for an element that was just popped and
+    /// has to keep its place at the front.
+    pub(crate) fn push_front(
+        &self,
+        d: T,
+        redeliveries: u32,
+        live: impl FnOnce() -> bool,
+    ) -> Result<(), T> {
+        let mut items = self.lock();
+
+        if !live() {
+            return Err(d);
+        }
+
+        items.push_front((d, redeliveries));
+        self.len.fetch_add(1, order::RETRY);
+        Ok(())
+    }
+
+    pub(crate) fn pop(&self) -> Option<(T, u32)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let item = self.lock().pop_front();
+
+        if item.is_some() {
+            self.len.fetch_sub(1, order::RETRY);
+        }
+
+        item
+    }
+
+    /// Like [`Retry::pop`], but only takes the front element if `pred`
+    /// accepts it: `None` if nothing is queued, `Some(None)` if the front
+    /// element was rejected.
+    pub(crate) fn pop_if(&self, pred: impl FnOnce(&T) -> bool) -> Option<Option<(T, u32)>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut items = self.lock();
+        let (d, _) = items.front()?;
+
+        if !pred(d) {
+            return Some(None);
+        }
+
+        self.len.fetch_sub(1, order::RETRY);
+        Some(items.pop_front())
+    }
+
+    /// Empties the queue, returning what it held.
+    pub(crate) fn clear(&self) -> VecDeque<(T, u32)> {
+        let mut items = self.lock();
+
+        self.len.fetch_sub(items.len(), order::RETRY);
+        std::mem::take(&mut *items)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(order::RETRY)
+    }
+}
+
+/// An element received with [`Receiver::recv_ack`]. Dropping it without
+/// calling [`AckGuard::ack`], including by unwinding, queues the element for
+/// redelivery to the next `recv_ack` caller.
+///
+/// [`Receiver::recv_ack`]: crate::Receiver::recv_ack
+pub struct AckGuard<'r, T> {
+    rb: &'r RingBuffer<'r, T>,
+    generation: u16,
+    // Taken out by ack() or by the drop that requeues it.
+    d: ManuallyDrop<T>,
+    redeliveries: u32,
+    acked: bool,
+}
+
+impl<'r, T> AckGuard<'r, T> {
+    pub(crate) fn new(rb: &'r RingBuffer<'r, T>, generation: u16, d: T, redeliveries: u32) -> Self {
+        Self {
+            rb,
+            generation,
+            d: ManuallyDrop::new(d),
+            redeliveries,
+            acked: false,
+        }
+    }
+
+    /// Marks the element as processed and returns it.
+    pub fn ack(mut self) -> T {
+        self.acked = true;
+        // The drop below leaves `d` alone once acked.
+        unsafe { ManuallyDrop::take(&mut self.d) }
+    }
+
+    /// How many times the element was delivered before and not
+    /// acknowledged. A count that keeps growing points at an element
+    /// consumers can't process.
+    pub fn redeliveries(&self) -> u32 {
+        self.redeliveries
+    }
+}
+
+impl<'r, T> Deref for AckGuard<'r, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.d
+    }
+}
+
+impl<'r, T> Drop for AckGuard<'r, T> {
+    fn drop(&mut self) {
+        if !self.acked {
+            let d = unsafe { ManuallyDrop::take(&mut self.d) };
+
+            self.rb
+                .requeue(self.generation, d, self.redeliveries.saturating_add(1));
+        }
+    }
+}
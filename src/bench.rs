@@ -0,0 +1,389 @@
+//! A benchmark harness for custom workloads, with the `bench` feature.
+//!
+//! [`run`] does the parts every benchmark repeats: a fresh queue per round,
+//! one thread per producer and consumer released together, optional core
+//! pinning, warmup rounds, timing, verification and a summary. A
+//! [`Workload`] only says what a producer sends and what a consumer does
+//! with it. With the `perf-counters` feature each round is also measured
+//! with hardware counters, see [`PerfCounts`].
+
+#[cfg(feature = "perf-counters")]
+mod perf;
+
+use std::fmt;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::rb::{Receiver, RingBuffer, Sender};
+use crate::wait::WaitProfile;
+
+#[cfg(feature = "perf-counters")]
+pub use perf::PerfCounts;
+
+/// Where a producer or consumer thread sits in the run.
+#[derive(Clone, Copy, Debug)]
+pub struct Ctx {
+    /// Index among the producers, or among the consumers.
+    pub index: usize,
+    pub producers: usize,
+    pub consumers: usize,
+}
+
+/// The payload generation and consumption of one benchmark round.
+///
+/// A round returns once every producer and consumer returned, so consumers
+/// must know when to stop, e.g. from a shared count or an end marker, and
+/// it fails if they leave elements in the queue.
+pub trait Workload<T>: Sync {
+    fn producer(&self, tx: &mut Sender<'_, T>, ctx: &Ctx);
+
+    fn consumer(&self, rx: &mut Receiver<'_, T>, ctx: &Ctx);
+
+    /// Checks the round after all threads returned.
+    fn verify(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// How [`run`] sets up the queue and threads.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub capacity: usize,
+    pub producers: usize,
+    pub consumers: usize,
+    /// Untimed rounds run first.
+    pub warmup: usize,
+    /// Timed rounds.
+    pub rounds: usize,
+    /// Pins the threads to cores, round robin, where the platform allows.
+    pub pin: bool,
+    /// How the queue's blocking calls wait.
+    pub wait: WaitProfile,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capacity: 128,
+            producers: 1,
+            consumers: 1,
+            warmup: 1,
+            rounds: 5,
+            pin: false,
+            wait: WaitProfile::Balanced,
+        }
+    }
+}
+
+/// Timings of the timed rounds of a [`run`].
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Wall time of each round, from releasing the threads to the last one
+    /// returning, in run order.
+    pub rounds: Vec<Duration>,
+    /// Hardware counters of each round, in run order, or why they could
+    /// not be read.
+    #[cfg(feature = "perf-counters")]
+    pub perf: Result<Vec<PerfCounts>, String>,
+}
+
+impl Report {
+    fn sorted(&self) -> Vec<Duration> {
+        let mut rounds = self.rounds.clone();
+
+        rounds.sort_unstable();
+        rounds
+    }
+
+    pub fn min(&self) -> Duration {
+        self.sorted()[0]
+    }
+
+    pub fn median(&self) -> Duration {
+        let rounds = self.sorted();
+
+        rounds[rounds.len() / 2]
+    }
+
+    pub fn max(&self) -> Duration {
+        *self.sorted().last().unwrap()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rounds: {} min: {:?} median: {:?} max: {:?}",
+            self.rounds.len(),
+            self.min(),
+            self.median(),
+            self.max()
+        )?;
+
+        #[cfg(feature = "perf-counters")]
+        match &self.perf {
+            Ok(perf) => {
+                let n = perf.len().max(1) as u64;
+                let mean = |f: fn(&PerfCounts) -> u64| perf.iter().map(f).sum::<u64>() / n;
+
+                write!(
+                    f,
+                    " per round: instructions: {} cycles: {} llc misses: {} branch misses: {}",
+                    mean(|p| p.instructions),
+                    mean(|p| p.cycles),
+                    mean(|p| p.llc_misses),
+                    mean(|p| p.branch_misses)
+                )?;
+            }
+            Err(e) => write!(f, " ({})", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `config.warmup` untimed and `config.rounds` timed rounds, each on a
+/// fresh queue with a fresh workload from `make`. Fails with the message of
+/// the first round that does not verify.
+///
+/// # Panics
+///
+/// If `config.rounds` or a thread count is zero, or a thread panics.
+pub fn run<T, W>(config: &Config, mut make: impl FnMut() -> W) -> Result<Report, String>
+where
+    T: Send,
+    W: Workload<T>,
+{
+    assert!(config.rounds > 0, "rounds must be > 0");
+    assert!(
+        config.producers > 0 && config.consumers > 0,
+        "thread counts must be > 0"
+    );
+
+    let mut rounds = Vec::with_capacity(config.rounds);
+    #[cfg(feature = "perf-counters")]
+    let mut perf = Ok(Vec::with_capacity(config.rounds));
+
+    for round in 0..config.warmup + config.rounds {
+        let workload = make();
+        let measured = run_round(config, &workload)
+            .and_then(|measured| workload.verify().map(|()| measured))
+            .map_err(|e| format!("round {}: {}", round, e))?;
+
+        if round >= config.warmup {
+            rounds.push(measured.elapsed);
+
+            #[cfg(feature = "perf-counters")]
+            if let Ok(all) = &mut perf {
+                match measured.perf {
+                    Ok(counts) => all.push(counts),
+                    Err(e) => perf = Err(e),
+                }
+            }
+        }
+    }
+
+    Ok(Report {
+        rounds,
+        #[cfg(feature = "perf-counters")]
+        perf,
+    })
+}
+
+// What run_round() measured.
+struct Round {
+    elapsed: Duration,
+    #[cfg(feature = "perf-counters")]
+    perf: Result<PerfCounts, String>,
+}
+
+fn run_round<T, W>(config: &Config, workload: &W) -> Result<Round, String>
+where
+    T: Send,
+    W: Workload<T>,
+{
+    let (s, r) = RingBuffer::<T>::builder()
+        .capacity(config.capacity)
+        .wait_profile(config.wait)
+        .channel();
+    let start = Barrier::new(config.producers + config.consumers + 1);
+    let cores = if config.pin {
+        core_affinity::get_core_ids().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    // Opened before the threads are spawned so that they inherit it.
+    #[cfg(feature = "perf-counters")]
+    let mut perf = perf::Perf::open();
+
+    let elapsed = thread::scope(|scope| {
+        let ctx = |index| Ctx {
+            index,
+            producers: config.producers,
+            consumers: config.consumers,
+        };
+        let pin = |thread: usize| {
+            if let Some(core) = cores.get(thread % cores.len().max(1)) {
+                core_affinity::set_for_current(*core);
+            }
+        };
+
+        let producers = (0..config.producers).map(|i| {
+            let (mut s, start, ctx) = (s.clone(), &start, ctx(i));
+
+            scope.spawn(move || {
+                pin(i);
+                start.wait();
+                workload.producer(&mut s, &ctx);
+            })
+        });
+        let consumers = (0..config.consumers).map(|i| {
+            let (mut r, start, ctx) = (r.clone(), &start, ctx(i));
+
+            scope.spawn(move || {
+                pin(config.producers + i);
+                start.wait();
+                workload.consumer(&mut r, &ctx);
+            })
+        });
+        let threads: Vec<_> = producers.chain(consumers).collect();
+
+        #[cfg(feature = "perf-counters")]
+        if let Ok(counters) = &mut perf {
+            if let Err(e) = counters.enable() {
+                perf = Err(e);
+            }
+        }
+
+        start.wait();
+
+        let begin = Instant::now();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        begin.elapsed()
+    });
+
+    if !s.empty() {
+        return Err("elements left in the queue".to_string());
+    }
+
+    Ok(Round {
+        elapsed,
+        #[cfg(feature = "perf-counters")]
+        perf: perf.and_then(|mut counters| {
+            counters.disable()?;
+            counters.read()
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Producers send 1..=ITEMS, consumers add up what they get.
+    struct Sum {
+        received: AtomicU64,
+        sum: AtomicU64,
+    }
+
+    const ITEMS: u64 = 1000;
+
+    impl Workload<u64> for Sum {
+        fn producer(&self, tx: &mut Sender<'_, u64>, _: &Ctx) {
+            for i in 1..=ITEMS {
+                while !tx.send(i) {
+                    thread::yield_now();
+                }
+            }
+        }
+
+        fn consumer(&self, rx: &mut Receiver<'_, u64>, ctx: &Ctx) {
+            let total = ITEMS * ctx.producers as u64;
+
+            while self.received.load(Ordering::Relaxed) < total {
+                match rx.recv() {
+                    Ok(d) => {
+                        self.sum.fetch_add(d, Ordering::Relaxed);
+                        self.received.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(_) => thread::yield_now(),
+                }
+            }
+        }
+
+        fn verify(&self) -> Result<(), String> {
+            let sum = self.sum.load(Ordering::Relaxed);
+
+            match sum == 2 * ITEMS * (ITEMS + 1) / 2 {
+                true => Ok(()),
+                false => Err(format!("sum {}", sum)),
+            }
+        }
+    }
+
+    #[test]
+    fn rounds() {
+        let config = Config {
+            producers: 2,
+            consumers: 2,
+            rounds: 3,
+            ..Config::default()
+        };
+        let report = run(&config, || Sum {
+            received: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        })
+        .unwrap();
+
+        assert_eq!(report.rounds.len(), 3);
+        assert!(report.min() <= report.median() && report.median() <= report.max());
+    }
+
+    #[test]
+    fn verify_fails() {
+        // A single producer's sum is only half the expected one.
+        let config = Config {
+            warmup: 0,
+            ..Config::default()
+        };
+        let result = run(&config, || Sum {
+            received: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        });
+
+        assert_eq!(result.unwrap_err(), "round 0: sum 500500");
+    }
+
+    #[cfg(feature = "perf-counters")]
+    #[test]
+    fn perf_counts() {
+        let config = Config {
+            warmup: 0,
+            producers: 2,
+            rounds: 2,
+            ..Config::default()
+        };
+        let report = run(&config, || Sum {
+            received: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        })
+        .unwrap();
+
+        // Where perf_event_open is forbidden the run still succeeds.
+        match &report.perf {
+            Ok(perf) => {
+                assert_eq!(perf.len(), 2);
+                assert!(perf.iter().all(|p| p.instructions > 0 && p.cycles > 0));
+            }
+            Err(e) => assert!(report.to_string().ends_with(&format!("({})", e))),
+        }
+    }
+}
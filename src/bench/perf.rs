@@ -0,0 +1,110 @@
+//! Hardware counters around a benchmark round, with the `perf-counters`
+//! feature. Linux only: they are opened with `perf_event_open`, which
+//! `kernel.perf_event_paranoid` may forbid, in which case the report says
+//! why instead.
+
+/// Hardware counters of one round, summed over every producer and
+/// consumer thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerfCounts {
+    pub instructions: u64,
+    pub cycles: u64,
+    /// Cache misses as counted by the generic hardware event, which most
+    /// CPUs map to the last level cache.
+    pub llc_misses: u64,
+    pub branch_misses: u64,
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use perf_event::events::Hardware;
+    use perf_event::{Builder, Counter};
+
+    use super::PerfCounts;
+
+    // One counter per event rather than a group: a group can't be read
+    // once it is inherited by the worker threads.
+    pub(crate) struct Perf([Counter; 4]);
+
+    impl Perf {
+        /// Opens disabled counters that threads spawned from now on inherit.
+        pub(crate) fn open() -> Result<Self, String> {
+            let open = |kind: Hardware| {
+                let mut builder = Builder::new().kind(kind);
+
+                builder.inherit(true);
+                builder
+                    .build()
+                    .map_err(|e| format!("perf counters unavailable: {}", e))
+            };
+
+            Ok(Self([
+                open(Hardware::INSTRUCTIONS)?,
+                open(Hardware::CPU_CYCLES)?,
+                open(Hardware::CACHE_MISSES)?,
+                open(Hardware::BRANCH_MISSES)?,
+            ]))
+        }
+
+        pub(crate) fn enable(&mut self) -> Result<(), String> {
+            self.for_each(Counter::enable)
+        }
+
+        pub(crate) fn disable(&mut self) -> Result<(), String> {
+            self.for_each(Counter::disable)
+        }
+
+        fn for_each(
+            &mut self,
+            f: impl FnMut(&mut Counter) -> std::io::Result<()>,
+        ) -> Result<(), String> {
+            self.0.iter_mut().try_for_each(f).map_err(|e| e.to_string())
+        }
+
+        /// Totals so far, including those of threads that have exited.
+        pub(crate) fn read(&mut self) -> Result<PerfCounts, String> {
+            let mut counts = [0; 4];
+
+            for (count, counter) in counts.iter_mut().zip(&mut self.0) {
+                *count = counter.read().map_err(|e| e.to_string())?;
+            }
+
+            let [instructions, cycles, llc_misses, branch_misses] = counts;
+
+            Ok(PerfCounts {
+                instructions,
+                cycles,
+                llc_misses,
+                branch_misses,
+            })
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::PerfCounts;
+
+    // Never opened.
+    pub(crate) enum Perf {}
+
+    impl Perf {
+        pub(crate) fn open() -> Result<Self, String> {
+            Err("perf counters are only available on Linux".to_string())
+        }
+
+        pub(crate) fn enable(&mut self) -> Result<(), String> {
+            match *self {}
+        }
+
+        pub(crate) fn disable(&mut self) -> Result<(), String> {
+            match *self {}
+        }
+
+        pub(crate) fn read(&mut self) -> Result<PerfCounts, String> {
+            match *self {}
+        }
+    }
+}
+
+pub(crate) use imp::Perf;
@@ -0,0 +1,264 @@
+//! Queues of large payloads, stored as boxes.
+//!
+//! Every slot of a ring is as large as `T`. A [`BoxedChannel`] boxes each
+//! element on send and unboxes it on receive, so the slots only hold
+//! pointers.
+//!
+//! It is not a speedup by itself: the payload is still moved once in and
+//! once out, now with an allocation in between. Measured with
+//! `cargo bench --bench boxed`, bursts of 256 on a single CPU, per element:
+//!
+//! ```text
+//!   payload   copied    boxed
+//!   64B       83ns      197ns
+//!   256B      140ns     204ns
+//!   1KB       226ns     331ns
+//! ```
+//!
+//! The gap stays around 100ns, the cost of the allocation, while the ring
+//! of a copied 1KB payload takes 1KB per slot. Use it when the ring's
+//! memory matters more than the latency.
+
+use std::fmt;
+
+use crate::error::{TryRecvError, TrySendError};
+use crate::rb::{Receiver, RingBox, RingBuffer, Sender};
+
+fn unbox<T>(e: TrySendError<Box<T>>) -> TrySendError<T> {
+    match e {
+        TrySendError::Full(b) => TrySendError::Full(*b),
+        TrySendError::Stale(b) => TrySendError::Stale(*b),
+        TrySendError::Closed(b) => TrySendError::Closed(*b),
+        TrySendError::Disconnected(b) => TrySendError::Disconnected(*b),
+    }
+}
+
+/// A bounded queue of `T` that stores `Box<T>`. Like the [`RingBox`] of a
+/// [`RingBuffer`] it shares the queue with the handles, and the last of them
+/// frees the elements nobody received.
+pub struct BoxedChannel<'a, T: Send> {
+    rb: RingBox<'a, Box<T>>,
+}
+
+pub struct BoxedSender<'a, T: Send> {
+    inner: Sender<'a, Box<T>>,
+}
+
+pub struct BoxedReceiver<'a, T: Send> {
+    inner: Receiver<'a, Box<T>>,
+}
+
+impl<'a, T: Send> BoxedChannel<'a, T> {
+    /// Creates a queue of capacity `n`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(n: usize) -> (Self, BoxedSender<'a, T>, BoxedReceiver<'a, T>) {
+        let (rb, inner_s, inner_r) = RingBuffer::new_boxed(n);
+
+        (
+            Self { rb },
+            BoxedSender { inner: inner_s },
+            BoxedReceiver { inner: inner_r },
+        )
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
+    pub fn empty(&self) -> bool {
+        self.rb.empty()
+    }
+}
+
+impl<'a, T: Send> fmt::Debug for BoxedChannel<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedChannel")
+            .field("capacity", &self.rb.capacity())
+            .field("empty", &self.rb.empty())
+            .finish()
+    }
+}
+
+impl<'a, T: Send> BoxedSender<'a, T> {
+    /// Boxes and enqueues `d`, handing it back if the queue is full or the
+    /// sender is stale.
+    pub fn send(&self, d: T) -> Result<(), T> {
+        self.try_send(d).map_err(TrySendError::into_inner)
+    }
+
+    /// Boxes and enqueues `d`, or hands it back saying why it could not be.
+    pub fn try_send(&self, d: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(Box::new(d)).map_err(unbox)
+    }
+
+    pub fn empty(&self) -> bool {
+        self.inner.empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// A new sender of the same queue, `None` once every sender is gone.
+    pub fn try_clone(&self) -> Option<Self> {
+        self.inner.try_clone().map(|inner| Self { inner })
+    }
+}
+
+impl<'a, T: Send> Clone for BoxedSender<'a, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<'a, T: Send> BoxedReceiver<'a, T> {
+    /// Dequeues and unboxes an element. The error is `false` if the queue
+    /// is empty and `true` if the receiver is stale, as for
+    /// [`Receiver::recv`].
+    pub fn recv(&self) -> Result<T, bool> {
+        self.try_recv().map_err(|e| e == TryRecvError::Stale)
+    }
+
+    /// Dequeues and unboxes an element, or says why there is none.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv().map(|b| *b)
+    }
+
+    pub fn empty(&self) -> bool {
+        self.inner.empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<'a, T: Send> Clone for BoxedReceiver<'a, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Counts its drops in a counter shared by one test.
+    struct Counted {
+        id: u64,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_q, s, r) = BoxedChannel::<String>::new(4);
+
+        assert_eq!(s.send("a".to_string()), Ok(()));
+        assert_eq!(s.send("b".to_string()), Ok(()));
+        assert_eq!(r.recv().as_deref(), Ok("a"));
+        assert_eq!(r.recv().as_deref(), Ok("b"));
+        assert_eq!(r.recv(), Err(false));
+    }
+
+    #[test]
+    fn full_hands_back() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (q, s, r) = BoxedChannel::new(2);
+        let mut sent = 0;
+
+        loop {
+            let d = Counted {
+                id: sent,
+                drops: drops.clone(),
+            };
+
+            match s.try_send(d) {
+                Ok(()) => sent += 1,
+                Err(TrySendError::Full(d)) => {
+                    assert_eq!(d.id, sent);
+                    break;
+                }
+                Err(_) => unreachable!(),
+            }
+        }
+
+        // Only the refused element was dropped so far.
+        assert!(sent > 0);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+
+        drop((s, r));
+        drop(q);
+        assert_eq!(drops.load(Ordering::Relaxed), sent as usize + 1);
+    }
+
+    #[test]
+    fn no_leaks() {
+        const ITEMS: u64 = 10_000;
+        // Fewer than the capacity, so the producers can finish.
+        const LEFT: usize = 50;
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (q, s, r) = BoxedChannel::new(64);
+        let received = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let (s, drops) = (s.clone(), drops.clone());
+
+                scope.spawn(move || {
+                    for id in 0..ITEMS {
+                        let mut d = Counted {
+                            id,
+                            drops: drops.clone(),
+                        };
+
+                        while let Err(back) = s.send(d) {
+                            d = back;
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            for _ in 0..2 {
+                let (r, received) = (r.clone(), &received);
+
+                // Stop early, leaving elements for the channel to free.
+                scope.spawn(move || {
+                    while received.load(Ordering::Relaxed) < 2 * ITEMS as usize - LEFT {
+                        match r.recv() {
+                            Ok(d) => {
+                                assert!(d.id < ITEMS);
+                                received.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(_) => thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+
+        let received = received.load(Ordering::Relaxed);
+
+        assert!(received < 2 * ITEMS as usize);
+        assert!(drops.load(Ordering::Relaxed) >= received);
+
+        drop((s, r));
+        drop(q);
+        assert_eq!(drops.load(Ordering::Relaxed), 2 * ITEMS as usize);
+    }
+}
@@ -0,0 +1,320 @@
+//! Broadcast (fan-out) queues: every receiver sees every element.
+//!
+//! Senders never wait for receivers. Once the ring is full a send overwrites
+//! the oldest element, and a receiver that falls more than a lap behind
+//! skips ahead and is told how many elements it missed. Each slot is a
+//! seqlock stamped with the position it holds:
+//!
+//! ```text
+//!   stamp == 0            never written
+//!   stamp == 2 * p + 1    being written for position p
+//!   stamp == 2 * p + 2    holds the element sent at position p
+//! ```
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+
+use crossbeam_utils::Backoff;
+
+use crate::error::BroadcastRecvError;
+use crate::order;
+use crate::pad::CachePadded;
+use crate::rb::MAX_CAPACITY;
+
+struct Stamped<T> {
+    stamp: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+/// A bounded broadcast queue. Create it with [`Broadcast::new`]; the handles
+/// share it, and the last of them, or the returned `Arc`, frees it.
+pub struct Broadcast<T: Default + Copy> {
+    slots: Box<[Stamped<T>]>,
+    // The next position to be claimed by a sender.
+    head: CachePadded<AtomicU64>,
+}
+
+pub struct BroadcastSender<T: Default + Copy> {
+    q: Arc<Broadcast<T>>,
+}
+
+/// A cursor into a [`Broadcast`] queue. Every receiver reads every element
+/// sent after it subscribed, independently of the others.
+pub struct BroadcastReceiver<T: Default + Copy> {
+    q: Arc<Broadcast<T>>,
+    // The next position to read.
+    pos: u64,
+}
+
+// Senders on different threads write different slots, and receivers only
+// copy elements out, so sharing the queue needs no more than moving `T`.
+unsafe impl<T: Default + Copy> Sync for Broadcast<T> where T: Send {}
+
+fn writing(pos: u64) -> u64 {
+    2 * pos + 1
+}
+
+fn published(pos: u64) -> u64 {
+    2 * pos + 2
+}
+
+impl<T: Default + Copy> Broadcast<T> {
+    /// Creates a queue keeping the last `n` elements, rounded up to a power
+    /// of two.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(n: usize) -> (Arc<Self>, BroadcastSender<T>, BroadcastReceiver<T>) {
+        assert!(n > 0 && n <= MAX_CAPACITY, "invalid capacity {}", n);
+
+        let slots = (0..n.next_power_of_two())
+            .map(|_| Stamped {
+                stamp: AtomicU64::new(0),
+                data: UnsafeCell::new(T::default()),
+            })
+            .collect();
+
+        let q = Arc::new(Self {
+            slots,
+            head: CachePadded::new(AtomicU64::new(0)),
+        });
+        let s = BroadcastSender { q: q.clone() };
+        let r = BroadcastReceiver {
+            q: q.clone(),
+            pos: 0,
+        };
+
+        (q, s, r)
+    }
+
+    /// Number of elements kept for receivers that lag behind.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, pos: u64) -> &Stamped<T> {
+        &self.slots[(pos & (self.slots.len() as u64 - 1)) as usize]
+    }
+
+    fn send(&self, d: T) {
+        let pos = self.head.fetch_add(1, order::CLAIM);
+        let slot = self.slot(pos);
+        let lap = self.slots.len() as u64;
+        let previous = if pos < lap { 0 } else { published(pos - lap) };
+        let backoff = Backoff::new();
+
+        // A sender a lap behind may still be writing this slot.
+        while slot.stamp.load(order::SLOT) != previous {
+            backoff.snooze();
+        }
+
+        slot.stamp.store(writing(pos), order::CLAIM);
+        atomic::fence(order::OVERWRITE);
+
+        unsafe { ptr::write_volatile(slot.data.get(), d) };
+
+        slot.stamp.store(published(pos), order::PUBLISH);
+    }
+
+    fn recv(&self, pos: &mut u64) -> Result<T, BroadcastRecvError> {
+        let slot = self.slot(*pos);
+        let want = published(*pos);
+        let stamp = slot.stamp.load(order::SLOT);
+
+        if stamp < want {
+            return Err(BroadcastRecvError::Empty);
+        }
+
+        if stamp == want {
+            // Seqlock read, see `Cell::peek` in rb.rs.
+            let d = unsafe { ptr::read_volatile(slot.data.get() as *const MaybeUninit<T>) };
+
+            atomic::fence(order::PEEK);
+
+            if slot.stamp.load(order::PEEK_CHECK) == want {
+                *pos += 1;
+
+                return Ok(unsafe { d.assume_init() });
+            }
+        }
+
+        // Overwritten: skip to the oldest position that may still be intact.
+        let oldest = self
+            .head
+            .load(order::SNAPSHOT)
+            .saturating_sub(self.slots.len() as u64)
+            .max(*pos + 1);
+        let missed = oldest - *pos;
+
+        *pos = oldest;
+
+        Err(BroadcastRecvError::Lagged(missed))
+    }
+
+    // The position up to `k` elements behind the newest one, skipping any
+    // that were already overwritten.
+    fn replay_from(&self, k: usize) -> u64 {
+        let head = self.head.load(order::SNAPSHOT);
+        let k = (k as u64).min(self.slots.len() as u64);
+        let mut pos = head.saturating_sub(k);
+
+        while pos < head && self.slot(pos).stamp.load(order::SNAPSHOT) > published(pos) {
+            pos += 1;
+        }
+
+        pos
+    }
+}
+
+impl<T: Default + Copy> BroadcastSender<T> {
+    /// Sends `d` to every receiver, overwriting the oldest element if the
+    /// ring is full. Only waits if a sender a whole lap behind has not
+    /// finished writing the same slot yet.
+    pub fn send(&mut self, d: T) {
+        self.q.send(d)
+    }
+
+    /// A receiver that sees everything sent from now on.
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            q: self.q.clone(),
+            pos: self.q.head.load(order::SNAPSHOT),
+        }
+    }
+}
+
+impl<T: Default + Copy> Clone for BroadcastSender<T> {
+    fn clone(&self) -> Self {
+        Self { q: self.q.clone() }
+    }
+}
+
+impl<T: Default + Copy> BroadcastReceiver<T> {
+    /// Returns the next element, or why there is none.
+    pub fn try_recv(&mut self) -> Result<T, BroadcastRecvError> {
+        self.q.recv(&mut self.pos)
+    }
+
+    /// Like [`BroadcastReceiver::try_recv`], skipping over lost elements.
+    pub fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.try_recv() {
+                Ok(d) => return Some(d),
+                Err(BroadcastRecvError::Empty) => return None,
+                Err(BroadcastRecvError::Lagged(_)) => (),
+            }
+        }
+    }
+
+    /// A new receiver that sees everything sent from now on.
+    pub fn subscribe(&self) -> Self {
+        self.subscribe_with_replay(0)
+    }
+
+    /// A new receiver that starts up to `k` elements in the past: with the
+    /// last `min(k, available)` elements still in the ring, followed by
+    /// everything sent from now on. Elements already overwritten are not
+    /// replayed.
+    ///
+    /// The replayed elements are not held back for the new receiver. If
+    /// senders overwrite them before it reads them, its first reads report
+    /// [`BroadcastRecvError::Lagged`] instead.
+    pub fn subscribe_with_replay(&self, k: usize) -> Self {
+        Self {
+            q: self.q.clone(),
+            pos: self.q.replay_from(k),
+        }
+    }
+}
+
+impl<T: Default + Copy> Clone for BroadcastReceiver<T> {
+    /// A receiver at the same position.
+    fn clone(&self) -> Self {
+        Self {
+            q: self.q.clone(),
+            pos: self.pos,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fan_out() {
+        let (_q, mut s, mut r1) = Broadcast::<u64>::new(4);
+        let mut r2 = r1.clone();
+
+        for i in 0..3 {
+            s.send(i);
+        }
+
+        for r in [&mut r1, &mut r2] {
+            assert_eq!([r.recv(), r.recv(), r.recv()], [Some(0), Some(1), Some(2)]);
+            assert_eq!(r.try_recv(), Err(BroadcastRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn lagged() {
+        let (q, mut s, mut r) = Broadcast::<u64>::new(4);
+
+        for i in 0..10 {
+            s.send(i);
+        }
+
+        assert_eq!(q.capacity(), 4);
+        assert_eq!(r.try_recv(), Err(BroadcastRecvError::Lagged(6)));
+        assert_eq!(r.recv(), Some(6));
+    }
+
+    #[test]
+    fn replay() {
+        let (_q, mut s, r) = Broadcast::<u64>::new(8);
+
+        for i in 0..5 {
+            s.send(i);
+        }
+
+        let mut late = r.subscribe_with_replay(3);
+        let mut now = r.subscribe();
+        let mut all = r.subscribe_with_replay(100);
+
+        s.send(5);
+
+        let drain =
+            |r: &mut BroadcastReceiver<u64>| std::iter::from_fn(|| r.recv()).collect::<Vec<_>>();
+
+        assert_eq!(drain(&mut late), [2, 3, 4, 5]);
+        assert_eq!(drain(&mut now), [5]);
+        assert_eq!(drain(&mut all), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn outlives_queue() {
+        let (q, mut s, mut r) = Broadcast::<u64>::new(4);
+
+        // The handles keep the queue alive.
+        drop(q);
+        s.send(1);
+        assert_eq!(r.recv(), Some(1));
+    }
+
+    #[test]
+    fn replay_after_overwrite() {
+        let (_q, mut s, r) = Broadcast::<u64>::new(4);
+
+        for i in 0..10 {
+            s.send(i);
+        }
+
+        let mut late = r.subscribe_with_replay(100);
+
+        assert_eq!(
+            std::iter::from_fn(|| late.recv()).collect::<Vec<_>>(),
+            [6, 7, 8, 9]
+        );
+    }
+}
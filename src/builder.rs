@@ -0,0 +1,361 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use crate::placement::Placement;
+use crate::rb::{ChannelId, MemoryFootprint, Receiver, RingBox, RingBuffer, Sender};
+use crate::rendezvous::RendezvousSender;
+use crate::wait::WaitProfile;
+
+// Sends a discarded element on, returning whether it fit.
+pub(crate) type DeadLetter<'a, T> = dyn Fn(T) -> bool + Send + Sync + 'a;
+
+pub(crate) type OnEvent<'a> = dyn Fn(ChannelId, Event) + Send + Sync + 'a;
+
+pub(crate) type OnReady<'a> = dyn Fn(ChannelId) + Send + Sync + 'a;
+
+/// What [`Sender::send`] and [`Sender::try_send`] do when the queue is full,
+/// see [`Builder::on_full`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnFull {
+    /// Fail, handing the element back.
+    #[default]
+    Reject,
+    /// Sleep until there is room, as [`Sender::send_blocking`] does.
+    Block,
+    /// Drop the oldest element to make room, as [`Sender::send_replace`]
+    /// does.
+    Overwrite,
+    /// Like `Overwrite`, but pass the oldest element to the queue set with
+    /// [`Builder::dead_letter`], if any, instead of dropping it.
+    Evict,
+}
+
+/// How senders contend for the tail of the queue, see
+/// [`Builder::fairness`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// Whichever sender's claim CAS lands first wins. The fastest, but under
+    /// contention a sender can lose the race for a long stretch.
+    #[default]
+    Unfair,
+    /// Senders claim one at a time, in the order they arrive, so each waits
+    /// for at most one claim per sender ahead of it, [`Sender::try_send`]
+    /// included. A sender preempted during its claim holds up the others
+    /// until it runs again.
+    Ticket,
+}
+
+/// A change in a queue's handles, or its end, passed to the hook set with
+/// [`Builder::on_event`]. The counts are the ones right after the change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A sender was added, by a clone, [`Receiver::new_sender`] or
+    /// [`RingBuffer::reset_generation`]. Priority senders count as senders.
+    SenderAdded {
+        senders: u32,
+    },
+    SenderDropped {
+        senders: u32,
+    },
+    /// A receiver was added, as for `SenderAdded`.
+    ReceiverAdded {
+        receivers: u32,
+    },
+    ReceiverDropped {
+        receivers: u32,
+    },
+    /// The queue is being dropped, with `len` elements still in it, those
+    /// waiting for redelivery included.
+    Dropped {
+        len: usize,
+    },
+}
+
+/// Configuration of a [`RingBuffer`].
+///
+/// The queue keeps a copy of the builder it was created from, which
+/// [`RingBuffer::clone_empty`] and `Builder::from(&queue)` hand back.
+pub struct Builder<'a, T> {
+    pub(crate) name: Option<String>,
+    pub(crate) capacity: usize,
+    pub(crate) headroom: usize,
+    pub(crate) wait: WaitProfile,
+    // A closure rather than the sender itself, which would make the builder
+    // and the queue invariant in 'a.
+    pub(crate) dead_letter: Option<Arc<DeadLetter<'a, T>>>,
+    // Weights of the priority and the bulk lane.
+    pub(crate) lanes: Option<(u32, u32)>,
+    pub(crate) on_full: OnFull,
+    pub(crate) fairness: Fairness,
+    pub(crate) on_event: Option<Arc<OnEvent<'a>>>,
+    pub(crate) on_readable: Option<Arc<OnReady<'a>>>,
+    pub(crate) on_writable: Option<Arc<OnReady<'a>>>,
+    pub(crate) placement: Placement,
+
+    _covariant: PhantomData<&'a ()>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> Builder<'a, T> {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            capacity: 0,
+            headroom: 0,
+            wait: WaitProfile::Balanced,
+            dead_letter: None,
+            lanes: None,
+            on_full: OnFull::Reject,
+            fairness: Fairness::Unfair,
+            on_event: None,
+            on_readable: None,
+            on_writable: None,
+            placement: Placement::default(),
+            _covariant: PhantomData,
+            _marker: PhantomData,
+        }
+    }
+
+    /// A name for the queue, shown by `Debug` and the metrics labels.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Number of elements the queue holds. Required, and above zero: for a
+    /// queue of none, see [`Builder::rendezvous`].
+    pub fn capacity(mut self, n: usize) -> Self {
+        self.capacity = n;
+        self
+    }
+
+    /// Keeps the last `k` slots for [`PrioritySender`](crate::PrioritySender)s: plain sends treat
+    /// the queue as full while fewer than `k` slots are free. Must be below
+    /// the capacity.
+    pub fn reserve_headroom(mut self, k: usize) -> Self {
+        self.headroom = k;
+        self
+    }
+
+    /// How blocking calls wait, for receivers and for senders waiting for
+    /// room. Defaults to [`WaitProfile::Balanced`].
+    pub fn wait_profile(mut self, profile: WaitProfile) -> Self {
+        self.wait = profile;
+        self
+    }
+
+    /// Aligns the slot array to `bytes`, a power of two, e.g. 4096 to start
+    /// it on a page of its own. Defaults to the alignment of a slot.
+    pub fn align(mut self, bytes: usize) -> Self {
+        self.placement.align = Some(bytes);
+        self
+    }
+
+    /// Maps the slots on huge pages: explicit ones if the hugetlbfs pool has
+    /// enough free, transparent ones otherwise. The slot array is rounded up
+    /// to whole 2 MiB pages. Needs the `numa` feature.
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    pub fn huge_pages(mut self) -> Self {
+        self.placement.huge_pages = true;
+        self
+    }
+
+    /// Binds the slots to NUMA node `node`, whichever thread touches them
+    /// first; building panics if the node does not exist. The queue's
+    /// header is not moved, so build it on a thread of that node too. Needs
+    /// the `numa` feature.
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    pub fn numa_node(mut self, node: u32) -> Self {
+        self.placement.node = Some(node);
+        self
+    }
+
+    /// Forwards every element the queue discards to `s`, e.g. the contents
+    /// thrown away by [`RingBuffer::reset_generation`], without blocking.
+    /// Elements that don't fit are dropped and counted by
+    /// [`RingBuffer::dead_letter_failures`].
+    ///
+    /// Queues built from this builder or cloned from their configuration
+    /// share `s`, which keeps its queue alive for as long as any of them
+    /// holds it.
+    pub fn dead_letter(mut self, s: Sender<'a, T>) -> Self
+    where
+        T: Send,
+    {
+        let s = Mutex::new(s);
+
+        self.dead_letter = Some(Arc::new(move |d| {
+            let s = s.lock().unwrap_or_else(|e| e.into_inner());
+
+            s.try_send_now(d).is_ok()
+        }));
+        self
+    }
+
+    /// Gives [`PrioritySender`](crate::PrioritySender)s a lane of their own,
+    /// of the same capacity, instead of the headroom. While both lanes have
+    /// elements, [`Receiver::try_recv`] and [`Receiver::recv`] take up to
+    /// `priority` elements from the priority lane for every `bulk` from the
+    /// other, in deficit round robin; an empty lane leaves the other one
+    /// the whole turn. Each receiver keeps its own count of the round, so
+    /// the queue shares no extra state.
+    ///
+    /// Other receive calls only read the bulk lane.
+    ///
+    /// # Panics
+    ///
+    /// If either weight is zero.
+    pub fn lane_weights(mut self, priority: u32, bulk: u32) -> Self {
+        assert!(priority > 0 && bulk > 0, "lane weights must be > 0");
+        self.lanes = Some((priority, bulk));
+        self
+    }
+
+    /// What plain sends do when the queue is full. Defaults to
+    /// [`OnFull::Reject`]. Lets the code that sends stay the same whichever
+    /// behaviour the queue was configured with; the explicit calls
+    /// ([`Sender::send_blocking`], [`Sender::send_replace`], ...) behave as
+    /// documented regardless, and so do
+    /// [`PrioritySender`](crate::PrioritySender)s.
+    pub fn on_full(mut self, policy: OnFull) -> Self {
+        self.on_full = policy;
+        self
+    }
+
+    /// How senders claim slots. Defaults to [`Fairness::Unfair`]; with
+    /// [`Fairness::Ticket`], a latency-sensitive sender has a bounded wait
+    /// however many others keep sending.
+    pub fn fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = fairness;
+        self
+    }
+
+    /// Calls `hook` with the queue's id on every [`Event`]: handles added
+    /// and dropped, and the queue's own drop. It runs on the thread that
+    /// caused the event, inside the clone or drop, so it should be quick.
+    /// Nothing is reported per element.
+    ///
+    /// Queues built from this builder or cloned from their configuration
+    /// share `hook`.
+    pub fn on_event(mut self, hook: impl Fn(ChannelId, Event) + Send + Sync + 'a) -> Self {
+        self.on_event = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` with the queue's id when an element arrives, or the
+    /// queue is closed or loses its last sender, once per
+    /// [`Receiver::arm_readable`]; the queue starts armed. See
+    /// [`ready`](crate::ready) for the protocol.
+    ///
+    /// Queues built from this builder or cloned from their configuration
+    /// share `hook`.
+    pub fn on_readable(mut self, hook: impl Fn(ChannelId) + Send + Sync + 'a) -> Self {
+        self.on_readable = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` with the queue's id when a receive frees a slot, or the
+    /// queue is closed or loses its last receiver, once per
+    /// [`Sender::arm_writable`]; the queue starts disarmed. See
+    /// [`ready`](crate::ready).
+    pub fn on_writable(mut self, hook: impl Fn(ChannelId) + Send + Sync + 'a) -> Self {
+        self.on_writable = Some(Arc::new(hook));
+        self
+    }
+
+    /// Bytes the queue will occupy, see [`RingBuffer::memory_footprint`].
+    pub fn estimate_footprint(&self) -> MemoryFootprint {
+        RingBuffer::estimate_footprint(self)
+    }
+
+    pub fn build(self) -> (RingBox<'a, T>, Sender<'a, T>, Receiver<'a, T>) {
+        RingBuffer::with_config(self, 0)
+    }
+
+    /// Builds a queue of capacity zero, owned by its handles: each send
+    /// waits for a receiver to take the element, see
+    /// [`rendezvous`](crate::rendezvous). The capacity and headroom set
+    /// are ignored.
+    pub fn rendezvous(mut self) -> (RendezvousSender<'a, T>, Receiver<'a, T>) {
+        self.capacity = 1;
+        self.headroom = 0;
+
+        let (s, r) = self.channel();
+
+        (RendezvousSender::new(s), r)
+    }
+
+    /// Builds a queue owned by its handles, see [`RingBuffer::new`].
+    pub fn channel(self) -> (Sender<'a, T>, Receiver<'a, T>) {
+        let (_, s, r) = self.build();
+
+        (s, r)
+    }
+
+    /// Builds a queue and lends its handles to `f`, so the borrow checker
+    /// sees that they do not outlive it. The references cannot leave `f`;
+    /// handles cloned from them can, and keep the queue alive until the
+    /// last of them is dropped, as with [`Builder::channel`].
+    pub fn scope<R>(self, f: impl FnOnce(&Sender<'a, T>, &Receiver<'a, T>) -> R) -> R
+    where
+        T: 'a,
+    {
+        let (s, r) = self.channel();
+
+        f(&s, &r)
+    }
+}
+
+impl<'a, T> Default for Builder<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Clone for Builder<'a, T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            capacity: self.capacity,
+            headroom: self.headroom,
+            wait: self.wait,
+            dead_letter: self.dead_letter.clone(),
+            lanes: self.lanes,
+            on_full: self.on_full,
+            fairness: self.fairness,
+            on_event: self.on_event.clone(),
+            on_readable: self.on_readable.clone(),
+            on_writable: self.on_writable.clone(),
+            placement: self.placement,
+            _covariant: PhantomData,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for Builder<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("name", &self.name)
+            .field("capacity", &self.capacity)
+            .field("headroom", &self.headroom)
+            .field("wait", &self.wait)
+            .field("dead_letter", &self.dead_letter.is_some())
+            .field("lanes", &self.lanes)
+            .field("on_full", &self.on_full)
+            .field("fairness", &self.fairness)
+            .field("on_event", &self.on_event.is_some())
+            .field("on_readable", &self.on_readable.is_some())
+            .field("on_writable", &self.on_writable.is_some())
+            .field("placement", &self.placement)
+            .finish()
+    }
+}
+
+impl<'a, T> From<&RingBuffer<'a, T>> for Builder<'a, T> {
+    fn from(rb: &RingBuffer<'a, T>) -> Self {
+        rb.config().clone()
+    }
+}
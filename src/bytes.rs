@@ -0,0 +1,316 @@
+//! A queue of bytes for one sender and one receiver, read in place.
+//!
+//! A `RingBuffer<u8>` keeps a position next to every byte, so the bytes of
+//! a stream are never contiguous in memory. A [`ByteRing`] keeps them in one
+//! plain buffer with a count of bytes written and one of bytes consumed.
+//! The receiver reads in two phases, as with a bip buffer or
+//! [`BufRead`](std::io::BufRead): [`ByteReceiver::readable_regions`] lends
+//! out the published bytes as up to two slices, two when they wrap around
+//! the end of the buffer, and [`ByteReceiver::consume`] frees however many
+//! of them were used. The slices can go straight to `writev`, with no copy
+//! into a temporary buffer.
+//!
+//! Until consumed, the bytes belong to the receiver and the sender does not
+//! overwrite them, so a partial write only consumes what the socket took
+//! and the rest is lent out again next time.
+
+use std::cell::UnsafeCell;
+use std::io::IoSlice;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use crate::order;
+use crate::pad::CachePadded;
+use crate::rb::MAX_CAPACITY;
+
+/// A bounded byte queue. Create it with [`ByteRing::new`]; the handles share
+/// it, and the last of them, or the returned `Arc`, frees it.
+pub struct ByteRing {
+    buf: Box<[UnsafeCell<u8>]>,
+    // Bytes written so far. Only the sender stores it.
+    head: CachePadded<AtomicUsize>,
+    // Bytes consumed so far. Only the receiver stores it.
+    tail: CachePadded<AtomicUsize>,
+}
+
+pub struct ByteSender {
+    q: Arc<ByteRing>,
+}
+
+pub struct ByteReceiver {
+    q: Arc<ByteRing>,
+}
+
+/// Published bytes lent out by [`ByteReceiver::readable_regions`].
+#[derive(Clone, Copy, Debug)]
+pub struct ReadRegion<'r> {
+    bytes: &'r [u8],
+}
+
+// The sender only writes bytes the receiver does not own, and each handle
+// takes `&mut self` to move its count on.
+unsafe impl Sync for ByteRing {}
+
+impl ByteRing {
+    /// Creates a queue of `n` bytes, rounded up to a power of two.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(n: usize) -> (Arc<Self>, ByteSender, ByteReceiver) {
+        assert!(n > 0 && n <= MAX_CAPACITY, "invalid capacity {}", n);
+
+        let q = Arc::new(Self {
+            buf: (0..n.next_power_of_two())
+                .map(|_| UnsafeCell::new(0))
+                .collect(),
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+        });
+        let s = ByteSender { q: q.clone() };
+        let r = ByteReceiver { q: q.clone() };
+
+        (q, s, r)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Bytes written and not yet consumed. Only a snapshot while the
+    /// handles are in use.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(order::SNAPSHOT);
+
+        self.head.load(order::SNAPSHOT).wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The buffer from byte `offset` on, which must be in bounds.
+    fn at(&self, offset: usize) -> *mut u8 {
+        debug_assert!(offset < self.buf.len());
+
+        UnsafeCell::raw_get(unsafe { self.buf.as_ptr().add(offset) })
+    }
+
+    // The bytes at stream positions `from..from + len`, split where they
+    // wrap around.
+    fn split(&self, from: usize, len: usize) -> ((usize, usize), (usize, usize)) {
+        let start = from & (self.buf.len() - 1);
+        let first = len.min(self.buf.len() - start);
+
+        ((start, first), (0, len - first))
+    }
+}
+
+impl ByteSender {
+    fn q(&self) -> &ByteRing {
+        &self.q
+    }
+
+    /// Copies as much of `data` as there is room for and publishes it, and
+    /// returns how many bytes that was.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let q = self.q();
+        let head = q.head.load(order::SNAPSHOT);
+        // Pairs with the receiver's consume: the bytes it frees are no
+        // longer being read.
+        let tail = q.tail.load(order::BYTES_LOAD);
+        let n = data.len().min(q.capacity() - head.wrapping_sub(tail));
+        let ((start, first), (_, second)) = q.split(head, n);
+
+        // Nobody reads these bytes until the head says they are written.
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), q.at(start), first);
+            ptr::copy_nonoverlapping(data[first..].as_ptr(), q.at(0), second);
+        }
+
+        q.head.store(head.wrapping_add(n), order::BYTES_STORE);
+        n
+    }
+
+    /// Free room, which only grows until the next write.
+    pub fn room(&self) -> usize {
+        let q = self.q();
+
+        q.capacity() - q.len()
+    }
+}
+
+impl ByteReceiver {
+    fn q(&self) -> &ByteRing {
+        &self.q
+    }
+
+    /// The published bytes, oldest first, as one region or two if they wrap
+    /// around the end of the buffer. The first region is empty only if
+    /// there are no bytes. Nothing is freed until [`ByteReceiver::consume`].
+    pub fn readable_regions(&mut self) -> (ReadRegion<'_>, Option<ReadRegion<'_>>) {
+        let q = self.q();
+        let tail = q.tail.load(order::SNAPSHOT);
+        // Pairs with the sender's write: its bytes are there.
+        let head = q.head.load(order::BYTES_LOAD);
+        let ((start, first), (_, second)) = q.split(tail, head.wrapping_sub(tail));
+
+        // The sender leaves bytes up to `head` alone until consumed, and
+        // consuming takes `&mut self`, which outlives the regions.
+        let region = |offset, len| ReadRegion {
+            bytes: unsafe { &*ptr::slice_from_raw_parts(q.at(offset), len) },
+        };
+
+        (
+            region(start, first),
+            (second > 0).then(|| region(0, second)),
+        )
+    }
+
+    /// Frees the oldest `n` bytes for the sender to reuse.
+    ///
+    /// # Panics
+    ///
+    /// If fewer than `n` bytes are published.
+    pub fn consume(&mut self, n: usize) {
+        let q = self.q();
+        let tail = q.tail.load(order::SNAPSHOT);
+        let available = q.head.load(order::BYTES_LOAD).wrapping_sub(tail);
+
+        assert!(n <= available, "consuming {} of {} bytes", n, available);
+
+        q.tail.store(tail.wrapping_add(n), order::BYTES_STORE);
+    }
+}
+
+impl<'r> ReadRegion<'r> {
+    /// The region as a buffer for vectored writes.
+    pub fn io_slice(&self) -> IoSlice<'r> {
+        IoSlice::new(self.bytes)
+    }
+}
+
+impl<'r> Deref for ReadRegion<'r> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn wrapped_regions() {
+        let (q, mut s, mut r) = ByteRing::new(8);
+
+        assert_eq!(s.write(b"abcdef"), 6);
+        assert_eq!(&*r.readable_regions().0, b"abcdef");
+        r.consume(4);
+
+        // Only the free room is taken, wrapping around the end.
+        assert_eq!(s.write(b"ghijklmn"), 6);
+        assert_eq!(s.room(), 0);
+
+        let (first, second) = r.readable_regions();
+
+        assert_eq!(
+            (&*first, second.as_deref()),
+            (&b"efgh"[..], Some(&b"ijkl"[..]))
+        );
+
+        let mut socket = Vec::new();
+
+        assert_eq!(
+            socket
+                .write_vectored(&[first.io_slice(), second.unwrap().io_slice()])
+                .unwrap(),
+            8
+        );
+        r.consume(8);
+        assert_eq!(socket, b"efghijkl");
+        assert!(q.is_empty());
+        assert!(r.readable_regions().0.is_empty());
+    }
+
+    #[test]
+    fn outlives_queue() {
+        let (q, mut s, mut r) = ByteRing::new(8);
+
+        drop(q);
+        assert_eq!(s.write(b"ab"), 2);
+        assert_eq!(&*r.readable_regions().0, b"ab");
+    }
+
+    #[test]
+    #[should_panic(expected = "consuming 3 of 2 bytes")]
+    fn consume_too_much() {
+        let (_q, mut s, mut r) = ByteRing::new(8);
+
+        s.write(b"ab");
+        r.consume(3);
+    }
+
+    #[test]
+    fn partial_consumes() {
+        const BYTES: usize = 1 << 18;
+
+        let stream: Vec<u8> = (0..BYTES).map(|i| (i * 7 + i / 251) as u8).collect();
+        let (_q, mut s, mut r) = ByteRing::new(1000);
+        let mut socket = Vec::with_capacity(BYTES);
+
+        thread::scope(|scope| {
+            let stream = &stream;
+
+            scope.spawn(move || {
+                let mut state = 0x2545_f491_4f6c_dd1du64;
+                let mut sent = 0;
+
+                while sent < BYTES {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+
+                    let n = (state as usize % 700).min(BYTES - sent);
+
+                    sent += s.write(&stream[sent..sent + n]);
+                }
+            });
+
+            // A socket that takes a random part of what it is offered.
+            let mut state = 0x9e37_79b9_7f4a_7c15u64;
+
+            while socket.len() < BYTES {
+                let (first, second) = r.readable_regions();
+                let offered = first.len() + second.map_or(0, |s| s.len());
+
+                if offered == 0 {
+                    thread::yield_now();
+                    continue;
+                }
+
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+
+                let mut n = state as usize % (offered + 1);
+                let taken = n;
+
+                for region in [Some(first), second].into_iter().flatten() {
+                    let k = n.min(region.len());
+
+                    socket.extend_from_slice(&region[..k]);
+                    n -= k;
+                }
+
+                r.consume(taken);
+            }
+        });
+
+        assert!(socket == stream);
+    }
+}
@@ -0,0 +1,213 @@
+//! Sending and receiving in place: [`Sender::claim`] lends out the slot at
+//! the tail of the ring for the element to be built in, and
+//! [`Receiver::recv_ref`] lends out the element at the head where it lies.
+//! A large element is then never copied in or out of the queue.
+//! [`Sender::claim_many`] lends out a run of slots at once, for a burst of
+//! elements claimed with one CAS and published together.
+//!
+//! A claimed slot holds up the receivers behind it until it is published,
+//! and a slot lent out to a receiver holds up the senders a lap later until
+//! it is released, so neither guard should be kept for long.
+//!
+//! [`Sender::claim`]: crate::Sender::claim
+//! [`Sender::claim_many`]: crate::Sender::claim_many
+//! [`Receiver::recv_ref`]: crate::Receiver::recv_ref
+
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut, Range};
+
+use crate::rb::RingBuffer;
+
+/// A slot claimed with [`Sender::claim`], holding `T::default()` to start
+/// with. It is published when the guard is dropped, or by
+/// [`SendSlot::publish`], with whatever it holds then.
+///
+/// [`Sender::claim`]: crate::Sender::claim
+pub struct SendSlot<'s, T> {
+    rb: &'s RingBuffer<'s, T>,
+    pos: u64,
+}
+
+/// A run of consecutive slots claimed with [`Sender::claim_many`], each
+/// holding `T::default()` to start with. They are published together, in
+/// order, when the guard is dropped or by [`SendSlots::publish`].
+///
+/// [`Sender::claim_many`]: crate::Sender::claim_many
+pub struct SendSlots<'s, T> {
+    rb: &'s RingBuffer<'s, T>,
+    pos: u64,
+    len: u32,
+}
+
+/// The elements of a [`SendSlots`], from [`SendSlots::iter_mut`].
+pub struct SlotsMut<'g, T> {
+    rb: &'g RingBuffer<'g, T>,
+    pos: u64,
+    left: Range<u32>,
+    _slots: PhantomData<&'g mut T>,
+}
+
+/// An element received with [`Receiver::recv_ref`]. It is dropped, and its
+/// slot released, when the guard is.
+///
+/// [`Receiver::recv_ref`]: crate::Receiver::recv_ref
+pub struct RecvSlot<'r, T> {
+    rb: &'r RingBuffer<'r, T>,
+    held: Held<T>,
+}
+
+enum Held<T> {
+    // Still in the slot at this position.
+    Slot(u64),
+    // Taken out of the redelivery queue, or the ring of a priority lane.
+    Owned(T),
+}
+
+impl<'s, T: Default> SendSlot<'s, T> {
+    // `pos` was just claimed; its slot is empty.
+    pub(crate) fn new(rb: &'s RingBuffer<'s, T>, pos: u64) -> Self {
+        unsafe { rb.slot(pos).write(T::default()) };
+
+        Self { rb, pos }
+    }
+}
+
+impl<'s, T> SendSlot<'s, T> {
+    /// Publishes the element, as dropping the guard does.
+    pub fn publish(self) {}
+}
+
+impl<'s, T> Deref for SendSlot<'s, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.rb.slot(self.pos) }
+    }
+}
+
+impl<'s, T> DerefMut for SendSlot<'s, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rb.slot(self.pos) }
+    }
+}
+
+impl<'s, T> Drop for SendSlot<'s, T> {
+    fn drop(&mut self) {
+        self.rb.publish(self.pos);
+    }
+}
+
+impl<'s, T: Default> SendSlots<'s, T> {
+    // `pos..pos + len` were just claimed; their slots are empty.
+    pub(crate) fn new(rb: &'s RingBuffer<'s, T>, pos: u64, len: u32) -> Self {
+        for i in 0..len {
+            unsafe { rb.slot(pos.wrapping_add(i.into())).write(T::default()) };
+        }
+
+        Self { rb, pos, len }
+    }
+}
+
+impl<'s, T> SendSlots<'s, T> {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The elements, in the order they will be received.
+    pub fn iter_mut(&mut self) -> SlotsMut<'_, T> {
+        SlotsMut {
+            rb: self.rb,
+            pos: self.pos,
+            left: 0..self.len,
+            _slots: PhantomData,
+        }
+    }
+
+    /// Publishes the elements, as dropping the guard does.
+    pub fn publish(self) {}
+}
+
+impl<'g, 's, T> IntoIterator for &'g mut SendSlots<'s, T> {
+    type Item = &'g mut T;
+    type IntoIter = SlotsMut<'g, T>;
+
+    fn into_iter(self) -> SlotsMut<'g, T> {
+        self.iter_mut()
+    }
+}
+
+impl<'s, T> Drop for SendSlots<'s, T> {
+    fn drop(&mut self) {
+        self.rb.publish_run(self.pos, self.len);
+    }
+}
+
+impl<'g, T> Iterator for SlotsMut<'g, T> {
+    type Item = &'g mut T;
+
+    fn next(&mut self) -> Option<&'g mut T> {
+        let i = self.left.next()?;
+
+        // Each slot of the run is handed out once, for as long as the guard
+        // is borrowed.
+        Some(unsafe { &mut *self.rb.slot(self.pos.wrapping_add(i.into())) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.left.size_hint()
+    }
+}
+
+impl<'g, T> ExactSizeIterator for SlotsMut<'g, T> {}
+
+impl<'g, T> FusedIterator for SlotsMut<'g, T> {}
+
+impl<'r, T> RecvSlot<'r, T> {
+    pub(crate) fn new(rb: &'r RingBuffer<'r, T>, pos: u64) -> Self {
+        Self {
+            rb,
+            held: Held::Slot(pos),
+        }
+    }
+
+    pub(crate) fn owned(rb: &'r RingBuffer<'r, T>, d: T) -> Self {
+        Self {
+            rb,
+            held: Held::Owned(d),
+        }
+    }
+}
+
+impl<'r, T> Deref for RecvSlot<'r, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.held {
+            Held::Slot(pos) => unsafe { &*self.rb.slot(*pos) },
+            Held::Owned(d) => d,
+        }
+    }
+}
+
+impl<'r, T> Drop for RecvSlot<'r, T> {
+    fn drop(&mut self) {
+        if let Held::Slot(pos) = self.held {
+            if mem::needs_drop::<T>() {
+                // Out of the slot first, so that a panicking drop can't keep
+                // it from the senders.
+                let d = unsafe { self.rb.slot(pos).read() };
+
+                self.rb.release(pos);
+                drop(d);
+            } else {
+                self.rb.release(pos);
+            }
+        }
+    }
+}
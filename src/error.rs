@@ -0,0 +1,339 @@
+use std::error;
+use std::fmt;
+
+/// Why a queue could not be laid out in caller-provided memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The capacity is zero or above [`MAX_CAPACITY`](crate::MAX_CAPACITY).
+    Capacity(usize),
+    /// The memory does not start at a multiple of `align` bytes.
+    Misaligned { align: usize },
+    /// The memory is `provided` bytes long but `required` are needed.
+    TooSmall { required: usize, provided: usize },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Capacity(n) => write!(f, "invalid capacity {}", n),
+            LayoutError::Misaligned { align } => {
+                write!(f, "memory is not aligned to {} bytes", align)
+            }
+            LayoutError::TooSmall { required, provided } => {
+                write!(f, "memory is {} bytes, {} are required", provided, required)
+            }
+        }
+    }
+}
+
+impl error::Error for LayoutError {}
+
+/// Why memory could not be attached to as a [`ShmQueue`](crate::shm::ShmQueue).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachError {
+    /// The memory is too small or misaligned for the header or the slots.
+    Layout(LayoutError),
+    /// The memory does not start with a queue header.
+    Magic,
+    /// The header was written with its fields in big-endian byte order.
+    ForeignEndian,
+    /// The header has a layout version this build does not know.
+    Version(u32),
+    /// The header records another element type; its size, alignment and
+    /// type hash are given.
+    Element {
+        size: u32,
+        align: u32,
+        type_hash: u64,
+    },
+    /// The recorded slot count is not a power of two up to
+    /// [`MAX_CAPACITY`](crate::MAX_CAPACITY) + 1.
+    Slots(u32),
+    /// The recorded capacity is zero or does not round up to the slot
+    /// count.
+    Capacity(u32),
+}
+
+impl fmt::Display for AttachError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachError::Layout(e) => e.fmt(f),
+            AttachError::Magic => f.write_str("no queue header"),
+            AttachError::ForeignEndian => f.write_str("header is big-endian"),
+            AttachError::Version(v) => write!(f, "unknown layout version {}", v),
+            AttachError::Element {
+                size,
+                align,
+                type_hash,
+            } => write!(
+                f,
+                "queue holds elements of size {}, alignment {}, type hash {:#x}",
+                size, align, type_hash
+            ),
+            AttachError::Slots(n) => write!(f, "invalid slot count {}", n),
+            AttachError::Capacity(n) => write!(f, "invalid capacity {}", n),
+        }
+    }
+}
+
+impl error::Error for AttachError {}
+
+impl From<LayoutError> for AttachError {
+    fn from(e: LayoutError) -> Self {
+        AttachError::Layout(e)
+    }
+}
+
+/// Why [`Sender::try_send`](crate::Sender::try_send) failed. Every variant
+/// hands the element back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The queue is full.
+    Full(T),
+    /// The sender belongs to a generation ended by
+    /// [`RingBuffer::reset_generation`](crate::RingBuffer::reset_generation);
+    /// it will never send again.
+    Stale(T),
+    /// The queue was closed by [`Sender::close`](crate::Sender::close),
+    /// [`Receiver::close`](crate::Receiver::close) or
+    /// [`Sender::shutdown`](crate::Sender::shutdown); no sender will send
+    /// again.
+    Closed(T),
+    /// Every receiver is gone, so nothing sent would ever be received.
+    Disconnected(T),
+}
+
+impl<T> TrySendError<T> {
+    /// The element that was not sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(d)
+            | TrySendError::Stale(d)
+            | TrySendError::Closed(d)
+            | TrySendError::Disconnected(d) => d,
+        }
+    }
+
+    // The same failure, handing back `d` instead.
+    pub(crate) fn replace<U>(self, d: U) -> TrySendError<U> {
+        match self {
+            TrySendError::Full(_) => TrySendError::Full(d),
+            TrySendError::Stale(_) => TrySendError::Stale(d),
+            TrySendError::Closed(_) => TrySendError::Closed(d),
+            TrySendError::Disconnected(_) => TrySendError::Disconnected(d),
+        }
+    }
+
+    // The failure without its element, and the element.
+    pub(crate) fn split(self) -> (TrySendError<()>, T) {
+        match self {
+            TrySendError::Full(d) => (TrySendError::Full(()), d),
+            TrySendError::Stale(d) => (TrySendError::Stale(()), d),
+            TrySendError::Closed(d) => (TrySendError::Closed(()), d),
+            TrySendError::Disconnected(d) => (TrySendError::Disconnected(()), d),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("queue is full"),
+            TrySendError::Stale(_) => f.write_str("sender is from an earlier generation"),
+            TrySendError::Closed(_) => f.write_str("queue is closed"),
+            TrySendError::Disconnected(_) => f.write_str("queue has no receivers"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for TrySendError<T> {}
+
+/// Why [`Sender::send_all`](crate::Sender::send_all) stopped early: how
+/// many elements went through, why the next one did not, which `error`
+/// hands back, and the rest of the iterator, not yet consumed.
+#[derive(Debug)]
+pub struct SendAllError<T, I> {
+    pub sent: usize,
+    pub error: TrySendError<T>,
+    pub rest: I,
+}
+
+impl<T, I> fmt::Display for SendAllError<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} sent, then {}", self.sent, self.error)
+    }
+}
+
+impl<T: fmt::Debug, I: fmt::Debug> error::Error for SendAllError<T, I> {}
+
+/// Why [`Sender::send_timeout`](crate::Sender::send_timeout) failed. Every
+/// variant hands the element back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    /// The queue stayed full for the whole timeout.
+    Timeout(T),
+    /// See [`TrySendError::Disconnected`].
+    Disconnected(T),
+    /// See [`TrySendError::Stale`].
+    Stale(T),
+    /// See [`TrySendError::Closed`].
+    Closed(T),
+}
+
+impl<T> SendTimeoutError<T> {
+    /// The element that was not sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            SendTimeoutError::Timeout(d)
+            | SendTimeoutError::Disconnected(d)
+            | SendTimeoutError::Stale(d)
+            | SendTimeoutError::Closed(d) => d,
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => f.write_str("timed out waiting for room"),
+            SendTimeoutError::Disconnected(_) => f.write_str("queue has no receivers"),
+            SendTimeoutError::Stale(_) => f.write_str("sender is from an earlier generation"),
+            SendTimeoutError::Closed(_) => f.write_str("queue is closed"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for SendTimeoutError<T> {}
+
+/// Why [`Receiver::try_recv`](crate::Receiver::try_recv) failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The queue is empty.
+    Empty,
+    /// The receiver belongs to a generation ended by
+    /// [`RingBuffer::reset_generation`](crate::RingBuffer::reset_generation);
+    /// it will never receive again.
+    Stale,
+    /// The queue is empty and every sender is gone, so it stays empty.
+    /// Elements sent before are all received first.
+    Disconnected,
+    /// The queue is empty and was closed, see [`TrySendError::Closed`]. As
+    /// with `Disconnected`, what was sent before the close is received
+    /// first.
+    Closed,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("queue is empty"),
+            TryRecvError::Stale => f.write_str("receiver is from an earlier generation"),
+            TryRecvError::Disconnected => f.write_str("queue is empty and has no senders"),
+            TryRecvError::Closed => f.write_str("queue is empty and closed"),
+        }
+    }
+}
+
+impl error::Error for TryRecvError {}
+
+/// Why [`Receiver::recv_timeout`](crate::Receiver::recv_timeout) failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// The queue stayed empty for the whole timeout.
+    Timeout,
+    /// See [`TryRecvError::Disconnected`].
+    Disconnected,
+    /// See [`TryRecvError::Closed`].
+    Closed,
+    /// See [`TryRecvError::Stale`].
+    Stale,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting for an element"),
+            RecvTimeoutError::Disconnected => f.write_str("queue is empty and has no senders"),
+            RecvTimeoutError::Closed => f.write_str("queue is empty and closed"),
+            RecvTimeoutError::Stale => f.write_str("receiver is from an earlier generation"),
+        }
+    }
+}
+
+impl error::Error for RecvTimeoutError {}
+
+/// Why [`BroadcastReceiver::try_recv`](crate::BroadcastReceiver::try_recv)
+/// failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BroadcastRecvError {
+    /// The receiver has seen every element sent so far.
+    Empty,
+    /// The receiver fell behind and this many elements were overwritten
+    /// before it read them. It has skipped ahead to the oldest element still
+    /// in the ring.
+    Lagged(u64),
+}
+
+impl fmt::Display for BroadcastRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastRecvError::Empty => f.write_str("no new elements"),
+            BroadcastRecvError::Lagged(n) => write!(f, "receiver missed {} elements", n),
+        }
+    }
+}
+
+impl error::Error for BroadcastRecvError {}
+
+/// Why [`Receiver::try_recv_detailed`](crate::Receiver::try_recv_detailed)
+/// returned no element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvProbe {
+    /// Nothing has been sent: worth sleeping.
+    Empty,
+    /// A sender has claimed the head slot but not published it yet: the
+    /// element is moments away, worth spinning.
+    Pending,
+    /// See [`TryRecvError::Stale`].
+    Stale,
+    /// See [`TryRecvError::Disconnected`].
+    Disconnected,
+    /// See [`TryRecvError::Closed`].
+    Closed,
+}
+
+impl From<TryRecvError> for RecvProbe {
+    fn from(e: TryRecvError) -> Self {
+        match e {
+            TryRecvError::Empty => RecvProbe::Empty,
+            TryRecvError::Stale => RecvProbe::Stale,
+            TryRecvError::Disconnected => RecvProbe::Disconnected,
+            TryRecvError::Closed => RecvProbe::Closed,
+        }
+    }
+}
+
+impl From<RecvProbe> for TryRecvError {
+    fn from(e: RecvProbe) -> Self {
+        match e {
+            RecvProbe::Empty | RecvProbe::Pending => TryRecvError::Empty,
+            RecvProbe::Stale => TryRecvError::Stale,
+            RecvProbe::Disconnected => TryRecvError::Disconnected,
+            RecvProbe::Closed => TryRecvError::Closed,
+        }
+    }
+}
+
+impl fmt::Display for RecvProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvProbe::Empty => f.write_str("queue is empty"),
+            RecvProbe::Pending => f.write_str("head element is being published"),
+            RecvProbe::Stale => f.write_str("receiver is from an earlier generation"),
+            RecvProbe::Disconnected => f.write_str("queue is empty and has no senders"),
+            RecvProbe::Closed => f.write_str("queue is empty and closed"),
+        }
+    }
+}
+
+impl error::Error for RecvProbe {}
@@ -0,0 +1,178 @@
+//! A C interface to a queue of pointer-sized elements, with the `ffi`
+//! feature. Build a static library from it with
+//! `cargo rustc --release --features ffi --crate-type staticlib`; the
+//! declarations, for cbindgen or written by hand, are
+//!
+//! ```c
+//! typedef struct mpmcbq_queue mpmcbq_queue;
+//!
+//! mpmcbq_queue *mpmcbq_create(size_t capacity);
+//! int mpmcbq_send(const mpmcbq_queue *q, uintptr_t value);
+//! int mpmcbq_recv(const mpmcbq_queue *q, uintptr_t *value);
+//! void mpmcbq_destroy(mpmcbq_queue *q);
+//! ```
+//!
+//! A queue may be sent to and received from by any number of threads at
+//! once; only [`mpmcbq_destroy`] must come after all of them are done.
+//! Pointers go through as `uintptr_t`; what they point to is the caller's
+//! business.
+
+use std::os::raw::c_int;
+
+use crate::error::{TryRecvError, TrySendError};
+use crate::rb::{Receiver, RingBuffer, Sender, MAX_CAPACITY};
+
+/// The element was sent or received.
+pub const MPMCBQ_OK: c_int = 0;
+/// The queue is full; nothing was sent.
+pub const MPMCBQ_FULL: c_int = 1;
+/// The queue is empty; nothing was received.
+pub const MPMCBQ_EMPTY: c_int = 2;
+/// A null pointer was passed.
+pub const MPMCBQ_INVALID: c_int = -1;
+
+/// A queue with one sender and one receiver for all C callers to share.
+#[allow(non_camel_case_types)]
+pub struct mpmcbq_queue {
+    s: Sender<'static, usize>,
+    r: Receiver<'static, usize>,
+}
+
+/// Creates a queue of `capacity` elements, or returns null if the capacity
+/// is zero or above [`MAX_CAPACITY`].
+#[no_mangle]
+pub extern "C" fn mpmcbq_create(capacity: usize) -> *mut mpmcbq_queue {
+    if capacity == 0 || capacity > MAX_CAPACITY {
+        return std::ptr::null_mut();
+    }
+
+    let (s, r) = RingBuffer::new(capacity);
+
+    Box::into_raw(Box::new(mpmcbq_queue { s, r }))
+}
+
+/// Enqueues `value`: [`MPMCBQ_OK`] or [`MPMCBQ_FULL`].
+///
+/// # Safety
+///
+/// `q` is null or a queue from [`mpmcbq_create`] not yet destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn mpmcbq_send(q: *const mpmcbq_queue, value: usize) -> c_int {
+    let Some(q) = (unsafe { q.as_ref() }) else {
+        return MPMCBQ_INVALID;
+    };
+
+    match q.s.try_send(value) {
+        Ok(()) => MPMCBQ_OK,
+        // The queue holds a receiver of its own, and nothing resets or
+        // closes it.
+        Err(TrySendError::Full(_)) => MPMCBQ_FULL,
+        Err(e) => unreachable!("{}", e),
+    }
+}
+
+/// Dequeues an element into `*value`: [`MPMCBQ_OK`] or [`MPMCBQ_EMPTY`],
+/// which leaves `*value` alone.
+///
+/// # Safety
+///
+/// `q` is as for [`mpmcbq_send`], and `value` is null or valid for a
+/// write.
+#[no_mangle]
+pub unsafe extern "C" fn mpmcbq_recv(q: *const mpmcbq_queue, value: *mut usize) -> c_int {
+    let (Some(q), false) = (unsafe { q.as_ref() }, value.is_null()) else {
+        return MPMCBQ_INVALID;
+    };
+
+    match q.r.try_recv() {
+        Ok(d) => {
+            unsafe { value.write(d) };
+            MPMCBQ_OK
+        }
+        // As for sends, the queue's own sender outlives every receive.
+        Err(TryRecvError::Empty) => MPMCBQ_EMPTY,
+        Err(e) => unreachable!("{}", e),
+    }
+}
+
+/// Frees a queue and the elements left in it. Null is ignored.
+///
+/// # Safety
+///
+/// `q` is null or a queue from [`mpmcbq_create`], not destroyed before and
+/// not used by any other thread from now on.
+#[no_mangle]
+pub unsafe extern "C" fn mpmcbq_destroy(q: *mut mpmcbq_queue) {
+    if !q.is_null() {
+        drop(unsafe { Box::from_raw(q) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+    use std::thread;
+
+    #[test]
+    fn round_trip() {
+        assert!(mpmcbq_create(0).is_null());
+        assert!(mpmcbq_create(MAX_CAPACITY + 1).is_null());
+
+        let q = mpmcbq_create(2);
+        let mut value = 0;
+
+        unsafe {
+            assert_eq!(mpmcbq_recv(q, &mut value), MPMCBQ_EMPTY);
+            assert_eq!(mpmcbq_send(q, 7), MPMCBQ_OK);
+            assert_eq!(mpmcbq_send(q, 8), MPMCBQ_OK);
+            assert_eq!(mpmcbq_send(q, 9), MPMCBQ_FULL);
+            assert_eq!(mpmcbq_recv(q, &mut value), MPMCBQ_OK);
+            assert_eq!(value, 7);
+
+            assert_eq!(mpmcbq_send(ptr::null(), 1), MPMCBQ_INVALID);
+            assert_eq!(mpmcbq_recv(ptr::null(), &mut value), MPMCBQ_INVALID);
+            assert_eq!(mpmcbq_recv(q, ptr::null_mut()), MPMCBQ_INVALID);
+
+            // With an element still queued.
+            mpmcbq_destroy(q);
+            mpmcbq_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn shared_by_threads() {
+        const ITEMS: usize = 10_000;
+
+        // The raw pointer goes across as an address, as it would from C.
+        let q = mpmcbq_create(16) as usize;
+        let sum: usize = thread::scope(|scope| {
+            for p in 0..2 {
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        while unsafe { mpmcbq_send(q as *const _, p * ITEMS + i) } != MPMCBQ_OK {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let mut sum = 0;
+            let mut value = 0;
+
+            for _ in 0..2 * ITEMS {
+                while unsafe { mpmcbq_recv(q as *const _, &mut value) } != MPMCBQ_OK {
+                    thread::yield_now();
+                }
+
+                sum += value;
+            }
+
+            sum
+        });
+
+        assert_eq!(sum, (2 * ITEMS) * (2 * ITEMS - 1) / 2);
+        unsafe { mpmcbq_destroy(q as *mut _) };
+    }
+}
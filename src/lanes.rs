@@ -0,0 +1,379 @@
+//! Priority levels over a set of queues.
+//!
+//! [`Builder::lane_weights`] gives one queue a second lane for
+//! [`PrioritySender`](crate::PrioritySender)s. For more levels than two,
+//! [`LaneBuilder`] creates one queue per level: a [`LaneSender`] sends to
+//! the queue of the level it is given, 0 being the highest, and a
+//! [`LaneReceiver`] takes from the highest level with elements, or, with
+//! [`LaneBuilder::weights`], serves the levels in deficit round robin so a
+//! busy level cannot starve the ones below it.
+//!
+//! The receiver sleeps on a [`SelectGroup`] of the queues, which is meant
+//! for a single waiter, so there is one receiver for all levels; it is not
+//! cloned.
+//!
+//! [`Builder::lane_weights`]: crate::Builder::lane_weights
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::builder::Builder;
+use crate::error::{RecvTimeoutError, TryRecvError, TrySendError};
+use crate::rb::{Receiver, RingBox, Sender};
+use crate::select::SelectGroup;
+
+/// Configuration of a set of levels, see [`LaneSender::builder`].
+pub struct LaneBuilder<'a, T> {
+    queue: Builder<'a, T>,
+    levels: usize,
+    weights: Option<Vec<u32>>,
+}
+
+impl<'a, T> LaneBuilder<'a, T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Builder::new(),
+            levels: 1,
+            weights: None,
+        }
+    }
+
+    /// Number of levels, served in strict priority order. Defaults to 1.
+    pub fn levels(mut self, n: usize) -> Self {
+        self.levels = n;
+        self.weights = None;
+        self
+    }
+
+    /// One level per weight, served in deficit round robin: while several
+    /// levels have elements, level `i` gets up to `weights[i]` receives in
+    /// every round, and an empty level gives up the rest of its turn.
+    pub fn weights(mut self, weights: &[u32]) -> Self {
+        self.levels = weights.len();
+        self.weights = Some(weights.to_vec());
+        self
+    }
+
+    /// Capacity of every level. Required.
+    pub fn capacity(mut self, n: usize) -> Self {
+        self.queue = self.queue.capacity(n);
+        self
+    }
+
+    /// Configuration of every level's queue, replacing any capacity set
+    /// before.
+    pub fn queue(mut self, config: Builder<'a, T>) -> Self {
+        self.queue = config;
+        self
+    }
+
+    /// Creates the queues, in level order.
+    ///
+    /// # Panics
+    ///
+    /// If there are no levels or more than 64, or a weight is zero.
+    #[allow(clippy::type_complexity)]
+    pub fn build(self) -> (Vec<RingBox<'a, T>>, LaneSender<'a, T>, LaneReceiver<'a, T>) {
+        assert!(
+            (1..=64).contains(&self.levels),
+            "levels must be between 1 and 64"
+        );
+
+        if let Some(weights) = &self.weights {
+            assert!(weights.iter().all(|&w| w > 0), "lane weights must be > 0");
+        }
+
+        let group = SelectGroup::new();
+        let mut queues = Vec::with_capacity(self.levels);
+        let mut senders = Vec::with_capacity(self.levels);
+        let mut receivers = Vec::with_capacity(self.levels);
+
+        for _ in 0..self.levels {
+            let (q, s, r) = self.queue.clone().build();
+
+            group.add(&q).expect("a new queue is in no group");
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        let receiver = LaneReceiver {
+            receivers,
+            weights: self.weights,
+            turn: 0,
+            left: 0,
+            group,
+        };
+
+        (queues, LaneSender { senders }, receiver)
+    }
+}
+
+impl<'a, T> Default for LaneBuilder<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends to the queue of a level, see the [module docs](self).
+pub struct LaneSender<'a, T> {
+    senders: Vec<Sender<'a, T>>,
+}
+
+impl<'a, T> LaneSender<'a, T> {
+    pub fn builder() -> LaneBuilder<'a, T> {
+        LaneBuilder::new()
+    }
+
+    pub fn levels(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Enqueues `d` at `level`, returning `false` if that level is full or
+    /// the sender is stale.
+    ///
+    /// # Panics
+    ///
+    /// If there is no such level, as for all sends.
+    pub fn send(&self, level: usize, d: T) -> bool {
+        self.try_send(level, d).is_ok()
+    }
+
+    /// Like [`LaneSender::send`], handing `d` back on failure.
+    pub fn try_send(&self, level: usize, d: T) -> Result<(), TrySendError<T>> {
+        self.senders[level].try_send(d)
+    }
+
+    /// Enqueues `d` at `level`, sleeping while that level is full, see
+    /// [`Sender::send_blocking`].
+    pub fn send_blocking(&self, level: usize, d: T) -> Result<(), TrySendError<T>> {
+        self.senders[level].send_blocking(d)
+    }
+
+    /// Creates another sender for the same levels, or returns `None` if a
+    /// level refuses a new sender, see [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<Self> {
+        let senders = self
+            .senders
+            .iter()
+            .map(Sender::try_clone)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self { senders })
+    }
+}
+
+impl<'a, T> Clone for LaneSender<'a, T> {
+    /// # Panics
+    ///
+    /// If [`LaneSender::try_clone`] fails, see [`Sender::clone`].
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("sender count is zero or would overflow")
+    }
+}
+
+/// Receives from a set of levels, see the [module docs](self).
+pub struct LaneReceiver<'a, T> {
+    receivers: Vec<Receiver<'a, T>>,
+    weights: Option<Vec<u32>>,
+    // The level whose turn it is, and how many receives it has left.
+    turn: usize,
+    left: u32,
+    group: Arc<SelectGroup>,
+}
+
+impl<'a, T> LaneReceiver<'a, T> {
+    pub fn levels(&self) -> usize {
+        self.receivers.len()
+    }
+
+    /// Receives an element and its level without waiting: from the highest
+    /// level with elements, or from the one whose turn it is with weights.
+    ///
+    /// Fails with [`TryRecvError::Empty`] while any level may still get
+    /// elements, and otherwise as the first level does once every level has
+    /// failed for good.
+    pub fn try_recv(&mut self) -> Result<(usize, T), TryRecvError> {
+        let n = self.receivers.len();
+        let mut failed = None;
+        // Empty while any level is, else the first level's error.
+        let mut note = |e: TryRecvError| {
+            failed = Some(match failed {
+                Some(TryRecvError::Empty) => TryRecvError::Empty,
+                Some(_) if e == TryRecvError::Empty => e,
+                Some(first) => first,
+                None => e,
+            });
+        };
+
+        match &self.weights {
+            None => {
+                for (level, r) in self.receivers.iter().enumerate() {
+                    match r.try_recv() {
+                        Ok(d) => return Ok((level, d)),
+                        Err(e) => note(e),
+                    }
+                }
+            }
+            Some(weights) => {
+                // Every level once, and the one the turn started at again in
+                // case only it had elements left after its turn.
+                for _ in 0..=n {
+                    if self.left == 0 {
+                        self.left = weights[self.turn];
+                    }
+
+                    let level = self.turn;
+
+                    match self.receivers[level].try_recv() {
+                        Ok(d) => {
+                            self.left -= 1;
+
+                            if self.left == 0 {
+                                self.turn = (self.turn + 1) % n;
+                            }
+
+                            return Ok((level, d));
+                        }
+                        // An empty level gives up the rest of its turn.
+                        Err(e) => {
+                            note(e);
+                            self.left = 0;
+                            self.turn = (self.turn + 1) % n;
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(failed.unwrap_or(TryRecvError::Empty))
+    }
+
+    /// Like [`LaneReceiver::try_recv`], sleeping while every level is
+    /// empty. Fails once every level has failed for good.
+    pub fn recv_blocking(&mut self) -> Result<(usize, T), TryRecvError> {
+        loop {
+            match self.try_recv() {
+                Err(TryRecvError::Empty) => {
+                    self.group.wait();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Like [`LaneReceiver::recv_blocking`], giving up once every level has
+    /// stayed empty for `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<(usize, T), RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+
+        loop {
+            match self.try_recv() {
+                Ok(d) => return Ok(d),
+                Err(TryRecvError::Empty) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+
+                    self.group.wait_for(deadline);
+                }
+                Err(TryRecvError::Stale) => return Err(RecvTimeoutError::Stale),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Closed) => return Err(RecvTimeoutError::Closed),
+            }
+        }
+    }
+
+    /// The receiver of every level, in level order.
+    pub fn receivers(&self) -> &[Receiver<'a, T>] {
+        &self.receivers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn fill(s: &LaneSender<u32>, per_level: u32) {
+        for level in 0..s.levels() {
+            for i in 0..per_level {
+                assert!(s.send(level, level as u32 * 100 + i));
+            }
+        }
+    }
+
+    #[test]
+    fn strict() {
+        let (_q, s, mut r) = LaneSender::builder().levels(3).capacity(8).build();
+
+        fill(&s, 2);
+
+        let got: Vec<_> = std::iter::from_fn(|| r.try_recv().ok()).collect();
+
+        assert_eq!(
+            got,
+            [(0, 0), (0, 1), (1, 100), (1, 101), (2, 200), (2, 201)]
+        );
+        assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn weighted() {
+        let (_q, s, mut r) = LaneSender::builder()
+            .weights(&[3, 2, 1])
+            .capacity(8)
+            .build();
+
+        fill(&s, 6);
+
+        let levels: Vec<_> = (0..12).map(|_| r.try_recv().unwrap().0).collect();
+
+        // Two full rounds; the lowest level is served in both.
+        assert_eq!(levels, [0, 0, 0, 1, 1, 2, 0, 0, 0, 1, 1, 2]);
+
+        // Level 0 runs dry: the others share its turns.
+        let levels: Vec<_> = std::iter::from_fn(|| r.try_recv().ok().map(|(l, _)| l)).collect();
+
+        assert_eq!(levels, [1, 1, 2, 2, 2, 2]);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn blocking() {
+        let (_q, s, mut r) = LaneSender::builder().levels(2).capacity(4).build();
+
+        assert_eq!(
+            r.recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                assert!(s.send(1, 7));
+                // Dropping the sender disconnects every level.
+            });
+
+            assert_eq!(r.recv_blocking(), Ok((1, 7)));
+            assert_eq!(r.recv_blocking(), Err(TryRecvError::Disconnected));
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn timeout_without_deadline() {
+        let (_q, s, mut r) = LaneSender::builder().levels(2).capacity(4).build();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                assert!(s.send(0, 7));
+            });
+
+            assert_eq!(r.recv_timeout(Duration::MAX), Ok((0, 7)));
+        });
+    }
+}
@@ -1,5 +1,160 @@
+// Single-threaded mode leaves out the tests that share a queue between
+// threads, and with them the uses of their imports and helpers.
+#![cfg_attr(
+    all(test, feature = "single-threaded"),
+    allow(unused_imports, dead_code)
+)]
+
+// The claim CAS compares a 16-bit generation and a 48-bit position packed
+// into one word, so the queue needs 64-bit atomics whatever the pointer width.
+// The index width is not selectable for the same reason: a 32-bit word would
+// leave a generation or a position too narrow to tell a stale claim from a
+// fresh one, and a smaller index saves nothing while the word stays 64 bits.
+#[cfg(not(target_has_atomic = "64"))]
+compile_error!("mpmcbq needs 64-bit atomics for its packed generation and position");
+
+mod ack;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod bits;
+pub mod boxed;
+pub mod broadcast;
+mod builder;
+pub mod bytes;
+mod claim;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod lanes;
+mod local;
+pub mod mpsc;
+mod order;
+mod pad;
+mod park;
+pub mod partition;
+pub mod pipe;
+mod placement;
+// Worker threads, which single-threaded targets do not have.
+#[cfg(not(any(
+    feature = "single-threaded",
+    all(target_family = "wasm", not(target_feature = "atomics"))
+)))]
+pub mod pipeline;
+pub mod pool;
+pub mod progress;
 pub mod rb;
+pub mod ready;
+#[cfg(feature = "registry")]
+pub mod registry;
+pub mod rendezvous;
+pub mod resequencer;
+pub mod router;
+pub mod select;
+pub mod shard;
+pub mod shm;
+mod slot;
+pub mod snapshot;
+pub mod spsc;
+mod static_rb;
+#[cfg(feature = "stats")]
+pub mod stats;
+mod sync;
+mod ticket;
+#[cfg(kani)]
+mod verification;
+mod wait;
+pub use ack::AckGuard;
+pub use bits::BitQueue;
+pub use bits::BitReceiver;
+pub use bits::BitSender;
+pub use boxed::BoxedChannel;
+pub use boxed::BoxedReceiver;
+pub use boxed::BoxedSender;
+pub use broadcast::Broadcast;
+pub use broadcast::BroadcastReceiver;
+pub use broadcast::BroadcastSender;
+pub use builder::Builder;
+pub use builder::Event;
+pub use builder::Fairness;
+pub use builder::OnFull;
+pub use bytes::ByteReceiver;
+pub use bytes::ByteRing;
+pub use bytes::ByteSender;
+pub use bytes::ReadRegion;
+pub use claim::RecvSlot;
+pub use claim::SendSlot;
+pub use claim::SendSlots;
+pub use claim::SlotsMut;
+pub use error::AttachError;
+pub use error::BroadcastRecvError;
+pub use error::LayoutError;
+pub use error::RecvProbe;
+pub use error::RecvTimeoutError;
+pub use error::SendAllError;
+pub use error::SendTimeoutError;
+pub use error::TryRecvError;
+pub use error::TrySendError;
+pub use local::LocalSender;
 pub use rb::Sender;
+pub use rb::PrioritySender;
 pub use rb::Receiver;
 pub use rb::RingBuffer;
+pub use rb::RingBox;
+pub use rb::MAX_CAPACITY;
+pub use rb::MemoryFootprint;
+pub use rb::ChannelId;
+pub use rb::Drain;
+pub use rb::IntoIter;
+pub use rb::Iter;
+pub use rb::TryIter;
+pub use rb::BatchEnd;
+pub use rb::BatchResult;
+pub use rb::ShutdownOutcome;
+pub use rb::checked_capacity;
+#[cfg(all(feature = "eventfd", target_os = "linux"))]
+pub use ready::EventFd;
+#[cfg(feature = "async")]
+pub use rb::Shutdown;
+#[cfg(feature = "async")]
+pub use rb::UntilBelow;
+#[cfg(feature = "async")]
+pub use rb::SendFuture;
+#[cfg(feature = "async")]
+pub use rb::RecvFuture;
+pub use lanes::LaneReceiver;
+pub use lanes::LaneSender;
+pub use partition::PartitionedSender;
+pub use pipe::QueueReader;
+pub use pipe::QueueWriter;
+pub use pool::Pool;
+pub use pool::Pooled;
+pub use progress::Progress;
+pub use rendezvous::RendezvousSender;
+pub use resequencer::GapPolicy;
+pub use resequencer::Resequencer;
+pub use router::Router;
+pub use select::SelectGroup;
+pub use select::Selector;
+pub use shard::QueuePool;
+pub use shard::ShardedReceiver;
+pub use shard::ShardedSender;
+pub use shm::ShmQueue;
+pub use snapshot::SlotState;
+pub use snapshot::Snapshot;
+pub use spsc::SpscReceiver;
+pub use spsc::SpscRing;
+pub use spsc::SpscSender;
+pub use static_rb::StaticRingBuffer;
+pub use wait::WaitBudget;
+pub use wait::WaitProfile;
+#[cfg(feature = "stats")]
+pub use stats::CapacityAdvice;
+#[cfg(feature = "stats")]
+pub use stats::HandleStats;
+#[cfg(feature = "stats")]
+pub use stats::HandleTotals;
+#[cfg(feature = "stats")]
+pub use stats::QueueStats;
+#[cfg(feature = "stats")]
+pub use stats::StallReport;
 
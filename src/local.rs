@@ -0,0 +1,231 @@
+//! A sender that batches on the producer's side: [`LocalSender`] keeps up
+//! to a batch of elements to itself and sends them with one claim of a run
+//! of slots, so a producer of many small elements pays one CAS on the tail
+//! per batch rather than per element, at the cost of holding them back
+//! until the batch fills or is flushed.
+
+use std::collections::VecDeque;
+
+use crate::error::TrySendError;
+use crate::rb::Sender;
+
+/// A [`Sender`] sending in batches of up to `batch` elements, each claimed
+/// as one run of slots. Elements reach the queue in the order they were
+/// sent, once the batch fills, on [`LocalSender::flush`], or when the
+/// handle is dropped, which flushes with
+/// [`LocalSender::flush_blocking`].
+///
+/// Since dropping it waits for room, drop it, or flush it, while the
+/// receivers are still receiving.
+///
+/// Sends take `&mut self`: the batch belongs to one producer. Give each
+/// producer thread its own, from a clone of the sender.
+pub struct LocalSender<'a, T> {
+    sender: Sender<'a, T>,
+    buf: VecDeque<T>,
+    batch: usize,
+}
+
+impl<'a, T> LocalSender<'a, T> {
+    /// Wraps `sender`, batching up to `batch` elements, which must be > 0.
+    pub fn new(sender: Sender<'a, T>, batch: usize) -> Self {
+        assert!(batch > 0, "batch must be > 0");
+
+        Self {
+            sender,
+            buf: VecDeque::with_capacity(batch),
+            batch,
+        }
+    }
+
+    /// Adds `d` to the batch, and sends the batch once it is full.
+    ///
+    /// Fails, handing `d` back, only when the batch is full and none of it
+    /// could be sent: with [`TrySendError::Full`] while the queue is, or as
+    /// [`Sender::try_send`] does once the queue is closed, disconnected or
+    /// reset.
+    pub fn send(&mut self, d: T) -> Result<(), TrySendError<T>> {
+        if self.buf.len() == self.batch {
+            if let Err(e) = self.flush() {
+                return Err(e.replace(d));
+            }
+        }
+
+        self.buf.push_back(d);
+
+        if self.buf.len() == self.batch {
+            // Whatever stopped it stops the next send too, which says why.
+            let _ = self.flush();
+        }
+
+        Ok(())
+    }
+
+    /// Sends as much of the batch as there is room for, in order, and
+    /// returns how many that was; 0 for an empty batch. What is left stays
+    /// batched.
+    ///
+    /// Fails only if nothing was sent, as [`Sender::try_send_many`] does.
+    pub fn flush(&mut self) -> Result<usize, TrySendError<()>> {
+        if self.buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.sender.send_from(&mut self.buf)
+    }
+
+    /// Sends the whole batch, waiting for room as
+    /// [`Sender::send_blocking`] does. On a closed, disconnected or reset
+    /// queue it stops, and the elements not yet sent stay batched.
+    pub fn flush_blocking(&mut self) -> Result<(), TrySendError<()>> {
+        while !self.buf.is_empty() {
+            match self.flush() {
+                Ok(_) => (),
+                Err(TrySendError::Full(())) => {
+                    // Waits for room with the first element, which keeps the
+                    // order.
+                    let d = self.buf.pop_front().unwrap();
+
+                    if let Err(e) = self.sender.send_blocking(d) {
+                        let (e, d) = e.split();
+
+                        self.buf.push_front(d);
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Elements batched and not sent yet.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// The sender the batches go through.
+    pub fn sender(&self) -> &Sender<'a, T> {
+        &self.sender
+    }
+}
+
+impl<'a, T> Drop for LocalSender<'a, T> {
+    fn drop(&mut self) {
+        // Elements a closed or disconnected queue refuses are dropped with
+        // the batch.
+        let _ = self.flush_blocking();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::RingBuffer;
+
+    #[test]
+    fn batches() {
+        let (q, s, r) = RingBuffer::<String>::new_boxed(8);
+        let mut local = LocalSender::new(s, 3);
+
+        local.send("a".to_string()).unwrap();
+        local.send("b".to_string()).unwrap();
+        assert_eq!(local.len(), 2);
+        assert!(r.recv().is_err());
+
+        local.send("c".to_string()).unwrap();
+        assert!(local.is_empty());
+        assert_eq!(q.len(), 3);
+
+        local.send("d".to_string()).unwrap();
+        assert_eq!(local.flush(), Ok(1));
+        assert_eq!(local.flush(), Ok(0));
+
+        let got: Vec<_> = std::iter::from_fn(|| r.recv().ok()).collect();
+
+        assert_eq!(got, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn full() {
+        let (s, r) = RingBuffer::<u32>::new(4);
+        let mut local = LocalSender::new(s, 3);
+
+        for i in 0..6 {
+            local.send(i).unwrap();
+        }
+
+        // The second batch found room for one: the rest stays batched.
+        assert_eq!(local.len(), 2);
+        local.send(6).unwrap();
+        assert_eq!(local.len(), 3);
+        assert_eq!(local.send(9), Err(TrySendError::Full(9)));
+        assert_eq!(local.flush(), Err(TrySendError::Full(())));
+
+        assert_eq!(r.recv(), Ok(0));
+        assert_eq!(local.flush(), Ok(1));
+        assert_eq!(local.len(), 2);
+
+        // Otherwise dropping `local` would wait for room for good.
+        drop(r);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn drop_flushes() {
+        const ITEMS: u32 = 1000;
+
+        let (s, r) = RingBuffer::<u32>::new(4);
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut local = LocalSender::new(s, 7);
+
+                for i in 0..ITEMS {
+                    while let Err(e) = local.send(i) {
+                        assert!(matches!(e, TrySendError::Full(_)));
+                        thread::yield_now();
+                    }
+                }
+
+                // Dropping the handle sends what is left, waiting for the
+                // consumer to make room.
+            });
+
+            for i in 0..ITEMS {
+                loop {
+                    match r.recv() {
+                        Ok(d) => break assert_eq!(d, i),
+                        Err(_) => thread::yield_now(),
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn disconnected() {
+        let (s, r) = RingBuffer::<u32>::new(4);
+        let mut local = LocalSender::new(s, 2);
+
+        local.send(1).unwrap();
+        drop(r);
+        assert_eq!(local.flush(), Err(TrySendError::Disconnected(())));
+        assert_eq!(local.send(2), Ok(()));
+        assert_eq!(local.send(3), Err(TrySendError::Disconnected(3)));
+        assert_eq!(local.flush_blocking(), Err(TrySendError::Disconnected(())));
+        assert_eq!(local.len(), 2);
+    }
+}
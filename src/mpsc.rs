@@ -0,0 +1,287 @@
+//! A stand-in for `std::sync::mpsc` backed by a [`RingBuffer`]: the same
+//! functions, types and methods, returning std's own error types, so that
+//! switching a codebase over is a matter of changing
+//! `use std::sync::mpsc` to `use mpmcbq::mpsc`. [`sync_channel`]`(0)` is a
+//! [rendezvous](crate::rendezvous) queue, as in std.
+//!
+//! It differs in two ways:
+//!
+//! - [`channel`] is a queue of [`CAPACITY`] elements, since the queue is
+//!   always bounded: [`Sender::send`] waits for room when it is full rather
+//!   than growing.
+//! - Elements must be `'static`, as for any queue owned by its handles; a
+//!   scoped thread sending borrowed data needs a [`RingBuffer`] it
+//!   borrows instead.
+//!
+//! Nothing here closes or resets the queue, so every failure is a
+//! disconnection.
+
+use std::fmt;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::time::Duration;
+
+use crate::error;
+use crate::rb::{self, RingBuffer};
+use crate::rendezvous::RendezvousSender;
+
+/// Capacity of the queue [`channel`] creates.
+pub const CAPACITY: usize = 1024;
+
+/// Blocking iterator, see [`Receiver::iter`].
+pub type Iter<'a, T> = rb::Iter<'a, 'static, T>;
+/// Non-blocking iterator, see [`Receiver::try_iter`].
+pub type TryIter<'a, T> = rb::TryIter<'a, 'static, T>;
+/// Blocking iterator, see [`Receiver::into_iter`].
+pub type IntoIter<T> = rb::IntoIter<'static, T>;
+
+/// The sending half of [`channel`], cloned for more producers.
+pub struct Sender<T: 'static> {
+    inner: rb::Sender<'static, T>,
+}
+
+/// The sending half of [`sync_channel`], cloned for more producers.
+pub struct SyncSender<T: 'static> {
+    inner: Flavor<T>,
+}
+
+enum Flavor<T: 'static> {
+    Bounded(rb::Sender<'static, T>),
+    Rendezvous(RendezvousSender<'static, T>),
+}
+
+/// The receiving half of [`channel`] or [`sync_channel`].
+pub struct Receiver<T: 'static> {
+    inner: rb::Receiver<'static, T>,
+}
+
+/// A queue of [`CAPACITY`] elements, see the [module docs](self).
+pub fn channel<T: 'static>() -> (Sender<T>, Receiver<T>) {
+    let (s, r) = RingBuffer::new(CAPACITY);
+
+    (Sender { inner: s }, Receiver { inner: r })
+}
+
+/// A queue of `bound` elements, or a rendezvous queue for a `bound` of 0,
+/// whose sends wait for a receiver to take the element.
+///
+/// # Panics
+///
+/// If `bound` is above [`MAX_CAPACITY`](crate::MAX_CAPACITY).
+pub fn sync_channel<T: 'static>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let (inner, r) = match bound {
+        0 => {
+            let (s, r) = RingBuffer::builder().rendezvous();
+
+            (Flavor::Rendezvous(s), r)
+        }
+        n => {
+            let (s, r) = RingBuffer::new(n);
+
+            (Flavor::Bounded(s), r)
+        }
+    };
+
+    (SyncSender { inner }, Receiver { inner: r })
+}
+
+impl<T: 'static> Sender<T> {
+    /// Sends `t`, waiting while the queue is full. Fails, handing `t` back,
+    /// once the receiver is gone.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        send(&self.inner, t)
+    }
+}
+
+impl<T: 'static> SyncSender<T> {
+    /// Sends `t`, waiting while the queue is full, or, for a rendezvous
+    /// queue, until a receiver takes it. Fails, handing `t` back, once the
+    /// receiver is gone.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        match &self.inner {
+            Flavor::Bounded(s) => send(s, t),
+            Flavor::Rendezvous(s) => s.send(t).map_err(|e| SendError(e.into_inner())),
+        }
+    }
+
+    /// Sends `t` if there is room, or, for a rendezvous queue, if a
+    /// receiver takes it straight away, without waiting.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        match &self.inner {
+            Flavor::Bounded(s) => s.try_send(t).map_err(|e| match e {
+                error::TrySendError::Full(t) => TrySendError::Full(t),
+                e => TrySendError::Disconnected(e.into_inner()),
+            }),
+            Flavor::Rendezvous(s) => s.send_timeout(t, Duration::ZERO).map_err(|e| match e {
+                error::SendTimeoutError::Timeout(t) => TrySendError::Full(t),
+                e => TrySendError::Disconnected(e.into_inner()),
+            }),
+        }
+    }
+}
+
+fn send<T: 'static>(s: &rb::Sender<'static, T>, t: T) -> Result<(), SendError<T>> {
+    s.send_blocking(t).map_err(|e| SendError(e.into_inner()))
+}
+
+impl<T: 'static> Receiver<T> {
+    /// Receives an element, waiting while the queue is empty. Fails once
+    /// the queue is empty and every sender is gone.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.inner.recv_blocking().map_err(|_| RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv().map_err(|e| match e {
+            error::TryRecvError::Empty => TryRecvError::Empty,
+            _ => TryRecvError::Disconnected,
+        })
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv_timeout(timeout).map_err(|e| match e {
+            error::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            _ => RecvTimeoutError::Disconnected,
+        })
+    }
+
+    /// Receives until every sender is gone, waiting while the queue is
+    /// empty.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.inner.iter()
+    }
+
+    /// Receives what is queued now, without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        self.inner.try_iter()
+    }
+}
+
+impl<T: 'static> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: 'static> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        let inner = match &self.inner {
+            Flavor::Bounded(s) => Flavor::Bounded(s.clone()),
+            Flavor::Rendezvous(s) => Flavor::Rendezvous(s.clone()),
+        };
+
+        Self { inner }
+    }
+}
+
+impl<T: 'static> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a, T: 'static> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: 'static> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T: 'static> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+impl<T: 'static> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn like_std() {
+        let (tx, rx) = channel();
+
+        thread::scope(|scope| {
+            for p in 0..4 {
+                let tx = tx.clone();
+
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        tx.send(p * 1000 + i).unwrap();
+                    }
+                });
+            }
+
+            drop(tx);
+
+            let mut got: Vec<u32> = rx.iter().collect();
+
+            got.sort_unstable();
+            assert_eq!(got, (0..4000).collect::<Vec<_>>());
+        });
+
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn sync() {
+        let (tx, rx) = sync_channel(1);
+
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        drop(rx);
+        assert_eq!(tx.send(3), Err(SendError(3)));
+        assert_eq!(tx.try_send(4), Err(TrySendError::Disconnected(4)));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn rendezvous() {
+        let (tx, rx) = sync_channel(0);
+
+        // Nobody is receiving.
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        thread::scope(|scope| {
+            scope.spawn(|| tx.send(2).unwrap());
+            assert_eq!(rx.recv(), Ok(2));
+        });
+
+        drop(rx);
+        assert_eq!(tx.send(3), Err(SendError(3)));
+    }
+}
@@ -0,0 +1,245 @@
+//! Every memory ordering used by the queue, in one place.
+//!
+//! With the `strict-ordering` feature all of them become `SeqCst`. That is
+//! never needed for correctness; it is an experiment switch: if a bug goes
+//! away under it, the tuned orderings below are the prime suspect.
+
+use std::sync::atomic::Ordering;
+
+macro_rules! ordering {
+    ($(#[$m:meta])* $name:ident = $tuned:ident) => {
+        $(#[$m])*
+        #[cfg(not(feature = "strict-ordering"))]
+        pub(crate) const $name: Ordering = Ordering::$tuned;
+        $(#[$m])*
+        #[cfg(feature = "strict-ordering")]
+        pub(crate) const $name: Ordering = Ordering::SeqCst;
+    };
+}
+
+ordering! {
+    /// Reading `enq_pos`/`deq_pos` before a claim. Only a hint: a stale value
+    /// fails the slot check or the claim CAS and is re-read.
+    CLAIM_LOAD = Relaxed
+}
+
+ordering! {
+    /// A claim that carries no payload, the slot's sequence number does, so
+    /// it needs no ordering: the broadcast and shared-memory queues' claims,
+    /// and `reset_generation` moving the generation on. The ring's own
+    /// claims use [`RING_CLAIM`].
+    CLAIM = Relaxed
+}
+
+ordering! {
+    /// The claim CAS on the ring's `enq_pos`/`deq_pos`. The payload needs no
+    /// more than [`CLAIM`], but the claim also stands in for the fence before
+    /// the check for sleepers that follows it, see `park.rs`. On x86 a CAS
+    /// is a full barrier whatever the ordering, so this costs nothing there.
+    RING_CLAIM = SeqCst
+}
+
+ordering! {
+    /// Failure ordering of the claim CAS, see [`CLAIM`].
+    CLAIM_FAILED = Relaxed
+}
+
+ordering! {
+    /// Loading a slot's sequence number. Pairs with [`PUBLISH`] so a receiver
+    /// sees the payload, and with [`RECYCLE`] so a sender cannot overwrite a
+    /// payload that is still being read.
+    SLOT = Acquire
+}
+
+ordering! {
+    /// A sender storing `pos + 1` into the slot after writing the payload.
+    PUBLISH = Release
+}
+
+ordering! {
+    /// A receiver storing `pos + capacity + 1` after reading the payload.
+    RECYCLE = Release
+}
+
+ordering! {
+    /// Monitoring reads of the positions (`positions()`, `len()`). They make
+    /// no promise beyond coherence, see `RingBuffer::positions`.
+    SNAPSHOT = Relaxed
+}
+
+ordering! {
+    /// Reading a handle count, and the failure ordering of the CAS that adds
+    /// a handle. Pairs with [`HANDLE_DOWN`] so whoever sees a count reach zero
+    /// sees everything the departed handles did.
+    HANDLE_LOAD = Acquire
+}
+
+ordering! {
+    /// Adding a handle. The new handle is derived from a live one, so this
+    /// publishes nothing.
+    HANDLE_UP = Relaxed
+}
+
+ordering! {
+    /// Dropping a handle; the thread that takes the count to zero must
+    /// observe all prior operations of the other handles.
+    HANDLE_DOWN = AcqRel
+}
+
+ordering! {
+    /// The fence between copying a payload optimistically (`recv_if`) and
+    /// re-reading the slot's sequence number to validate the copy: keeps the
+    /// copy from being reordered after the check.
+    PEEK = Acquire
+}
+
+ordering! {
+    /// The validating re-read after a [`PEEK`] fence.
+    PEEK_CHECK = Relaxed
+}
+
+ordering! {
+    /// Registering as a sleeping receiver and checking for sleepers after a
+    /// publish, see `park.rs`. Both sides need a total order between their
+    /// store and their subsequent load, which only `SeqCst` gives: a fence
+    /// on the sleeper's side, and on the other the [`RING_CLAIM`] before the
+    /// check or, where there is no claim, a fence.
+    WAKE = SeqCst
+}
+
+ordering! {
+    /// A broadcast sender's fence between marking a slot as being rewritten
+    /// and overwriting its payload. Pairs with [`PEEK`] so a receiver that
+    /// copied any of the new payload sees the marker on its re-read.
+    OVERWRITE = Release
+}
+
+ordering! {
+    /// The closed flag set by `close` and `Sender::shutdown`, and the flag
+    /// that stops a pipeline stage's workers. It only turns sends away;
+    /// elements are handed over through the slots as usual.
+    CLOSE = Relaxed
+}
+
+ordering! {
+    /// Setting and clearing the hint in the tail word that the queue is
+    /// closed or has no receiver. A claim that finds it reads the closed
+    /// flag and the receiver count, and whoever clears it reads them again;
+    /// releasing and acquiring the word orders those reads after the change
+    /// that set it.
+    REFUSE = AcqRel
+}
+
+ordering! {
+    /// Taking the next channel id. Ids only have to be unique.
+    CHANNEL_ID = Relaxed
+}
+
+ordering! {
+    /// The length of the redelivery queue. Only a hint that lets `recv_ack`
+    /// skip the lock; the elements themselves are handed over under it.
+    RETRY = Relaxed
+}
+
+ordering! {
+    /// Statistics counters, the count of failed dead-letter sends, a pool's
+    /// count of its objects and a sharded sender's round-robin cursor. They
+    /// are independent tallies with no ordering relation to the queue
+    /// contents.
+    COUNTER = Relaxed
+}
+
+ordering! {
+    /// A select group's ready bits and member bits. Publishing an element and
+    /// looking at its bit are ordered by [`WAKE`] fences on both sides, see
+    /// `select.rs`.
+    SELECT = Relaxed
+}
+
+ordering! {
+    /// Storing a byte ring's count of bytes written, or of bytes consumed.
+    /// Publishes the bytes written, or releases the bytes consumed, to the
+    /// other side's [`BYTES_LOAD`].
+    BYTES_STORE = Release
+}
+
+ordering! {
+    /// The other side's count in a byte ring, see [`BYTES_STORE`].
+    BYTES_LOAD = Acquire
+}
+
+ordering! {
+    /// Storing an SPSC ring's count of elements sent, or of elements taken.
+    /// Publishes the elements sent, or frees the slots taken, to the other
+    /// side's [`SPSC_LOAD`].
+    SPSC_STORE = Release
+}
+
+ordering! {
+    /// The other side's count in an SPSC ring, see [`SPSC_STORE`].
+    SPSC_LOAD = Acquire
+}
+
+ordering! {
+    /// A receiver's turns at the lanes. Only a receiver shared between
+    /// threads races on them, and a turn lost that way only skews the lane
+    /// weights.
+    DEFICIT = Relaxed
+}
+
+ordering! {
+    /// Whether a readiness hook has fired since it was armed. Arming and
+    /// firing are ordered with the queue contents by [`WAKE`] fences on both
+    /// sides, see `ready.rs`.
+    READY = Relaxed
+}
+
+ordering! {
+    /// Taking and serving the tickets of [`Fairness::Ticket`]. They order
+    /// the claims and nothing else; a sender served before it sees the last
+    /// claim fails the claim CAS once and re-reads the tail.
+    ///
+    /// [`Fairness::Ticket`]: crate::Fairness::Ticket
+    TURN = Relaxed
+}
+
+#[cfg(all(test, feature = "strict-ordering"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict() {
+        for o in [
+            CLAIM_LOAD,
+            CLAIM,
+            RING_CLAIM,
+            CLAIM_FAILED,
+            SLOT,
+            PUBLISH,
+            RECYCLE,
+            SNAPSHOT,
+            HANDLE_LOAD,
+            HANDLE_UP,
+            HANDLE_DOWN,
+            PEEK,
+            PEEK_CHECK,
+            WAKE,
+            OVERWRITE,
+            CLOSE,
+            REFUSE,
+            CHANNEL_ID,
+            RETRY,
+            COUNTER,
+            SELECT,
+            BYTES_STORE,
+            BYTES_LOAD,
+            SPSC_STORE,
+            SPSC_LOAD,
+            DEFICIT,
+            READY,
+            TURN,
+        ] {
+            assert_eq!(o, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Cache-line padding of the fields that different threads write.
+//!
+//! By default this is crossbeam's `CachePadded`: 128 bytes on x86_64,
+//! aarch64 and powerpc64, where the prefetcher pulls lines in pairs or the
+//! line is that long, and the target's line size elsewhere. The `pad-128`
+//! feature forces 128 bytes on every target, for cores crossbeam does not
+//! know about. The `no-padding` feature adds none, for memory-constrained
+//! targets where a queue header of a few hundred bytes matters more than
+//! false sharing; it wins if both are set.
+
+#[cfg(not(any(feature = "no-padding", feature = "pad-128")))]
+pub(crate) use crossbeam_utils::CachePadded;
+
+#[cfg(any(feature = "no-padding", feature = "pad-128"))]
+pub(crate) use self::custom::CachePadded;
+
+#[cfg(any(feature = "no-padding", feature = "pad-128"))]
+mod custom {
+    use std::ops::{Deref, DerefMut};
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    #[cfg_attr(not(feature = "no-padding"), repr(align(128)))]
+    pub(crate) struct CachePadded<T> {
+        value: T,
+    }
+
+    impl<T> CachePadded<T> {
+        pub(crate) const fn new(value: T) -> Self {
+            Self { value }
+        }
+    }
+
+    impl<T> Deref for CachePadded<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.value
+        }
+    }
+
+    impl<T> DerefMut for CachePadded<T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alignment() {
+        let align = std::mem::align_of::<CachePadded<u8>>();
+
+        if cfg!(feature = "no-padding") {
+            assert_eq!(align, 1);
+        } else if cfg!(feature = "pad-128") {
+            assert_eq!(align, 128);
+        } else {
+            assert!(align >= 32);
+        }
+    }
+}
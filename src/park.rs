@@ -0,0 +1,348 @@
+//! Parking for blocking receives and for senders waiting for room.
+//!
+//! A receiver that found the queue empty registers as sleeping, re-checks
+//! and waits on a condvar; a sender that publishes checks for sleepers and
+//! only takes the lock if there are any. The registration is followed by a
+//! `SeqCst` fence. The check needs no fence of its own: it follows the
+//! sender's `SeqCst` claim CAS, so either the sender sees the registration
+//! or the receiver's re-check sees the claim. A receiver that finds a claim
+//! whose element is not yet published therefore cannot count on a wakeup
+//! for it and only sleeps for [`PENDING_RECHECK`] at a time until it shows.
+//! No wakeup is lost, and an uncontended send pays one load that nothing
+//! else writes to until someone sleeps.
+//!
+//! The same holds the other way round for senders waiting on receivers, and
+//! for tasks, which register a waker instead of sleeping. What publishes
+//! without a claim, such as a redelivery, fences before the check instead.
+//!
+//! Where nothing else runs while a call waits, see [`SINGLE_THREADED`], a
+//! wait can only see what held when it started: it checks once, then sleeps
+//! out its deadline, or panics if it has none rather than hang.
+
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Condvar, Mutex, MutexGuard};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use crate::order;
+use crate::wait::WaitBudget;
+
+/// Whether the queue's handles can only be used from one thread: with the
+/// `single-threaded` feature, and on WebAssembly without the `atomics`
+/// target feature, where there are no other threads and blocking panics.
+pub(crate) const SINGLE_THREADED: bool = cfg!(any(
+    feature = "single-threaded",
+    all(target_family = "wasm", not(target_feature = "atomics"))
+));
+
+/// How long a waiter sleeps at a time while what it waits for is claimed
+/// but not yet visible: the claimer may have checked for sleepers before
+/// the waiter registered. Claims are published within a call, so this only
+/// runs out when the claimer is preempted or holds a send slot.
+pub(crate) const PENDING_RECHECK: Duration = Duration::from_millis(1);
+
+pub(crate) struct Waiters {
+    sleeping: AtomicUsize,
+    lock: Mutex<()>,
+    cond: Condvar,
+
+    // Tasks to wake on the next notify, each counted in `sleeping`.
+    #[cfg(feature = "async")]
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Waiters {
+    pub(crate) fn new() -> Self {
+        Self {
+            sleeping: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+            #[cfg(feature = "async")]
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        #[cfg(test)]
+        crate::rb::hooks::lock();
+
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of waiters currently asleep.
+    #[cfg(any(test, feature = "stats"))]
+    pub(crate) fn sleeping(&self) -> usize {
+        self.sleeping.load(order::SNAPSHOT)
+    }
+
+    /// Wakes the sleepers, if any, after progress they may be waiting for.
+    pub(crate) fn notify(&self) {
+        atomic::fence(order::WAKE);
+        self.notify_claimed();
+    }
+
+    /// `notify` after progress made by a `RING_CLAIM` claim, which orders
+    /// the check for sleepers as the fence would, see the module docs.
+    pub(crate) fn notify_claimed(&self) {
+        if self.sleeping.load(order::WAKE) > 0 {
+            self.notify_all();
+        }
+    }
+
+    /// Wakes every sleeper unconditionally, for state changes that are not
+    /// followed by a `notify`: disconnection, generation reset.
+    pub(crate) fn notify_all(&self) {
+        {
+            let _lock = self.lock();
+
+            self.cond.notify_all();
+        }
+
+        #[cfg(feature = "async")]
+        {
+            let wakers =
+                std::mem::take(&mut *self.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+
+            self.sleeping.fetch_sub(wakers.len(), order::WAKE);
+
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+
+    /// The task version of `wait_until`: returns whether `ready()` holds,
+    /// registering `waker` for the next notify if it does not.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_until(&self, waker: &Waker, mut ready: impl FnMut() -> bool) -> bool {
+        if ready() {
+            return true;
+        }
+
+        {
+            let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push(waker.clone());
+                self.sleeping.fetch_add(1, order::WAKE);
+            }
+        }
+
+        atomic::fence(order::WAKE);
+
+        // If this succeeds the waker stays registered; the next notify wakes
+        // it spuriously.
+        ready()
+    }
+
+    /// Waits until `ready()` holds or `deadline`, if any, passes, spinning
+    /// and yielding within `budget` before sleeping. Returns whether
+    /// `ready()` held.
+    pub(crate) fn wait_with(
+        &self,
+        budget: &WaitBudget,
+        deadline: Option<Instant>,
+        ready: impl FnMut() -> bool,
+    ) -> bool {
+        self.wait_claimed(budget, deadline, ready, || false)
+    }
+
+    /// `wait_with` for progress that is claimed before it is visible: while
+    /// `pending()` holds, sleeps last at most [`PENDING_RECHECK`].
+    pub(crate) fn wait_claimed(
+        &self,
+        budget: &WaitBudget,
+        deadline: Option<Instant>,
+        mut ready: impl FnMut() -> bool,
+        mut pending: impl FnMut() -> bool,
+    ) -> bool {
+        if SINGLE_THREADED {
+            return wait_alone(deadline, ready);
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("wait", ?deadline).entered();
+
+        let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+        for spin in 0..budget.spins {
+            if ready() {
+                return true;
+            }
+
+            // Reading the clock costs more than a spin, so only now and then.
+            if spin % 1024 == 1023 && expired() {
+                return false;
+            }
+
+            std::hint::spin_loop();
+        }
+
+        let mut yields = 0;
+
+        while !budget.park || yields < budget.yields {
+            if ready() {
+                return true;
+            }
+
+            if expired() {
+                return false;
+            }
+
+            std::thread::yield_now();
+            yields = yields.saturating_add(1);
+        }
+
+        match budget.recheck {
+            None => self.sleep(deadline, ready, pending),
+            Some(recheck) => loop {
+                // A recheck too long for an Instant caps nothing.
+                let until = match Instant::now().checked_add(recheck) {
+                    Some(until) => Some(deadline.map_or(until, |d| d.min(until))),
+                    None => deadline,
+                };
+
+                if self.sleep(until, &mut ready, &mut pending) {
+                    return true;
+                }
+
+                if expired() {
+                    return false;
+                }
+            },
+        }
+    }
+
+    /// Sleeps until `ready()` holds or `deadline`, if any, passes. Returns
+    /// whether `ready()` held.
+    pub(crate) fn wait_until(
+        &self,
+        deadline: Option<Instant>,
+        ready: impl FnMut() -> bool,
+    ) -> bool {
+        if SINGLE_THREADED {
+            return wait_alone(deadline, ready);
+        }
+
+        self.sleep(deadline, ready, || false)
+    }
+
+    // `wait_until`, waking every `PENDING_RECHECK` while `pending()` holds.
+    fn sleep(
+        &self,
+        deadline: Option<Instant>,
+        mut ready: impl FnMut() -> bool,
+        mut pending: impl FnMut() -> bool,
+    ) -> bool {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?deadline, "parking");
+
+        let mut lock = self.lock();
+
+        self.sleeping.fetch_add(1, order::WAKE);
+        atomic::fence(order::WAKE);
+
+        let ready = loop {
+            if ready() {
+                break true;
+            }
+
+            let now = Instant::now();
+
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                break false;
+            }
+
+            let until = if pending() {
+                let recheck = now + PENDING_RECHECK;
+
+                Some(deadline.map_or(recheck, |d| d.min(recheck)))
+            } else {
+                deadline
+            };
+
+            lock = match until {
+                None => self.cond.wait(lock).unwrap_or_else(|e| e.into_inner()),
+                Some(until) => match self.cond.wait_timeout(lock, until - now) {
+                    Ok((lock, _)) => lock,
+                    Err(e) => e.into_inner().0,
+                },
+            };
+        };
+
+        self.sleeping.fetch_sub(1, order::WAKE);
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(ready, "unparked");
+
+        ready
+    }
+}
+
+// The wait of a single-threaded queue: whoever would make `ready()` hold is
+// the caller itself.
+fn wait_alone(deadline: Option<Instant>, mut ready: impl FnMut() -> bool) -> bool {
+    if ready() {
+        return true;
+    }
+
+    let Some(deadline) = deadline else {
+        panic!("blocking on a single-threaded queue that is not ready would never return");
+    };
+
+    let now = Instant::now();
+
+    if now < deadline {
+        std::thread::sleep(deadline - now);
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+
+    const PARK: WaitBudget = WaitBudget {
+        spins: 0,
+        yields: 0,
+        park: true,
+        recheck: None,
+        polls: 0,
+    };
+
+    #[test]
+    fn recheck_without_cap() {
+        let waiters = Waiters::new();
+        let budget = WaitBudget {
+            recheck: Some(Duration::MAX),
+            ..PARK
+        };
+        let deadline = Instant::now() + Duration::from_millis(10);
+
+        assert!(waiters.wait_with(&budget, None, || true));
+        assert!(!waiters.wait_with(&budget, Some(deadline), || false));
+    }
+
+    // Nothing notifies: only the recheck of a pending wait sees the flag.
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn pending_without_notify() {
+        let waiters = Waiters::new();
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                done.store(true, order::SNAPSHOT);
+            });
+
+            let ready = || done.load(order::SNAPSHOT);
+
+            assert!(waiters.wait_claimed(&PARK, None, ready, || true));
+        });
+    }
+}
@@ -0,0 +1,275 @@
+//! Keyed partitioning over a set of queues.
+//!
+//! A [`PartitionedSender`] owns one [`Sender`] per partition and routes every
+//! element by the hash of its key, so all elements of a key go through the
+//! same queue and a dedicated worker per receiver sees them in send order.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use crate::builder::Builder;
+use crate::error::TrySendError;
+use crate::rb::{Receiver, RingBox, Sender};
+
+/// How keys are mapped to partitions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routing {
+    /// `hash(key) % partitions`. Changing the partition count moves almost
+    /// every key.
+    Modulo,
+    /// A consistent-hash ring with `replicas` points per partition. Adding a
+    /// partition only moves the keys that land on its points, about
+    /// `1 / partitions` of them.
+    Consistent { replicas: usize },
+}
+
+// The routing table built from a `Routing` and a partition count.
+#[derive(Clone, Debug)]
+pub(crate) enum Router {
+    Modulo(usize),
+    // (point, partition), sorted by point.
+    Ring(Vec<(u64, usize)>),
+}
+
+impl Router {
+    pub(crate) fn new(routing: Routing, partitions: usize) -> Self {
+        assert!(partitions > 0, "partitions must be > 0");
+
+        match routing {
+            Routing::Modulo => Router::Modulo(partitions),
+            Routing::Consistent { replicas } => {
+                assert!(replicas > 0, "replicas must be > 0");
+
+                let mut ring: Vec<_> = (0..partitions)
+                    .flat_map(|p| (0..replicas).map(move |r| (hash(&(p, r)), p)))
+                    .collect();
+
+                ring.sort_unstable();
+                Router::Ring(ring)
+            }
+        }
+    }
+
+    pub(crate) fn route(&self, h: u64) -> usize {
+        match self {
+            Router::Modulo(n) => (h % *n as u64) as usize,
+            Router::Ring(ring) => {
+                let i = ring.partition_point(|&(point, _)| point < h);
+
+                ring[i % ring.len()].1
+            }
+        }
+    }
+}
+
+fn hash<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut h = DefaultHasher::new();
+
+    key.hash(&mut h);
+    h.finish()
+}
+
+/// Configuration of a set of partitions, see [`PartitionedSender::builder`].
+pub struct PartitionBuilder<'a, K: ?Sized, T> {
+    queue: Builder<'a, T>,
+    partitions: usize,
+    routing: Routing,
+
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<'a, K: Hash + ?Sized, T> PartitionBuilder<'a, K, T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Builder::new(),
+            partitions: 1,
+            routing: Routing::Modulo,
+            _key: PhantomData,
+        }
+    }
+
+    /// Number of partitions. Defaults to 1.
+    pub fn partitions(mut self, n: usize) -> Self {
+        self.partitions = n;
+        self
+    }
+
+    /// Capacity of every partition. Required.
+    pub fn capacity(mut self, n: usize) -> Self {
+        self.queue = self.queue.capacity(n);
+        self
+    }
+
+    /// Configuration of every partition's queue, replacing any capacity set
+    /// before.
+    pub fn queue(mut self, config: Builder<'a, T>) -> Self {
+        self.queue = config;
+        self
+    }
+
+    /// Defaults to [`Routing::Modulo`].
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Creates the queues. The receivers are in partition order.
+    #[allow(clippy::type_complexity)]
+    pub fn build(
+        self,
+    ) -> (
+        Vec<RingBox<'a, T>>,
+        PartitionedSender<'a, K, T>,
+        Vec<Receiver<'a, T>>,
+    ) {
+        let router = Router::new(self.routing, self.partitions);
+        let mut queues = Vec::with_capacity(self.partitions);
+        let mut senders = Vec::with_capacity(self.partitions);
+        let mut receivers = Vec::with_capacity(self.partitions);
+
+        for _ in 0..self.partitions {
+            let (q, s, r) = self.queue.clone().build();
+
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        let sender = PartitionedSender {
+            senders,
+            router,
+            _key: PhantomData,
+        };
+
+        (queues, sender, receivers)
+    }
+}
+
+impl<'a, K: Hash + ?Sized, T> Default for PartitionBuilder<'a, K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Routes elements to one of several queues by the hash of a key, keeping
+/// the per-producer order of every key.
+pub struct PartitionedSender<'a, K: ?Sized, T> {
+    senders: Vec<Sender<'a, T>>,
+    router: Router,
+
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<'a, K: Hash + ?Sized, T> PartitionedSender<'a, K, T> {
+    pub fn builder() -> PartitionBuilder<'a, K, T> {
+        PartitionBuilder::new()
+    }
+
+    pub fn partitions(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// The partition, and index of the receiver, that `key` is routed to.
+    pub fn partition(&self, key: &K) -> usize {
+        self.router.route(hash(key))
+    }
+
+    /// Enqueues `d` on the partition of `key`, returning `false` if that
+    /// queue is full or the sender is stale.
+    pub fn send(&mut self, key: &K, d: T) -> bool {
+        self.try_send(key, d).is_ok()
+    }
+
+    /// Like [`PartitionedSender::send`], handing `d` back on failure.
+    pub fn try_send(&mut self, key: &K, d: T) -> Result<(), TrySendError<T>> {
+        let p = self.partition(key);
+
+        self.senders[p].try_send(d)
+    }
+
+    /// Creates another partitioned sender with the same routing, or returns
+    /// `None` if a partition refuses a new sender, see [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<Self> {
+        let senders = self
+            .senders
+            .iter()
+            .map(Sender::try_clone)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            senders,
+            router: self.router.clone(),
+            _key: PhantomData,
+        })
+    }
+}
+
+impl<'a, K: Hash + ?Sized, T> Clone for PartitionedSender<'a, K, T> {
+    /// # Panics
+    ///
+    /// If [`PartitionedSender::try_clone`] fails, see [`Sender::clone`].
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("sender count is zero or would overflow")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modulo() {
+        let router = Router::new(Routing::Modulo, 3);
+
+        for h in 0..30 {
+            assert_eq!(router.route(h), (h % 3) as usize);
+        }
+    }
+
+    #[test]
+    fn consistent_moves_few_keys() {
+        let keys = 10_000u64;
+        let before = Router::new(Routing::Consistent { replicas: 64 }, 4);
+        let after = Router::new(Routing::Consistent { replicas: 64 }, 5);
+        let mut moved = 0;
+
+        for k in 0..keys {
+            let (a, b) = (before.route(hash(&k)), after.route(hash(&k)));
+
+            // Keys only ever move to the new partition.
+            if a != b {
+                assert_eq!(b, 4);
+                moved += 1;
+            }
+        }
+
+        // About a fifth of the keys move; modulo would move about 80%.
+        assert!(
+            moved > keys / 10 && moved < keys * 3 / 10,
+            "moved {}",
+            moved
+        );
+    }
+
+    #[test]
+    fn routes_by_key() {
+        let (_q, mut s, mut r) = PartitionedSender::<str, u64>::builder()
+            .partitions(4)
+            .capacity(16)
+            .build();
+
+        let keys = ["a", "b", "c", "d", "a", "b"];
+
+        for (i, key) in keys.iter().enumerate() {
+            assert!(s.send(key, i as u64));
+        }
+
+        for (p, r) in r.iter_mut().enumerate() {
+            while let Ok(i) = r.recv() {
+                assert_eq!(s.partition(keys[i as usize]), p);
+            }
+        }
+    }
+}
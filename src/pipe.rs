@@ -0,0 +1,269 @@
+//! `std::io` adapters for a queue of bytes, for a queue standing in for an
+//! in-process pipe: [`QueueWriter`] implements [`Write`] over a
+//! `Sender<u8>` and [`QueueReader`] implements [`Read`] over a
+//! `Receiver<u8>`, so either end can go to `io::copy`, a `BufReader` or
+//! a parser expecting a stream.
+//!
+//! Bytes move in runs claimed with one CAS each, but every byte still has a
+//! slot of its own; a stream with one reader and one writer that wants its
+//! bytes contiguous is better off with a [`ByteRing`](crate::ByteRing).
+//!
+//! Both ends wait as their [`Blocking`] says when there is no room or
+//! nothing to read. Once every receiver is gone a write fails with
+//! [`io::ErrorKind::BrokenPipe`], and once every sender is gone, or the
+//! queue is closed, reads return what is left and then end of stream.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::error::{RecvTimeoutError, SendTimeoutError, TryRecvError, TrySendError};
+use crate::rb::{Receiver, Sender};
+
+/// What a read from an empty queue, or a write to a full one, does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Blocking {
+    /// Waits until it can go on, with the queue's wait profile.
+    #[default]
+    Block,
+    /// Waits for up to this long, then fails with
+    /// [`io::ErrorKind::TimedOut`].
+    Timeout(Duration),
+    /// Fails with [`io::ErrorKind::WouldBlock`] straight away.
+    NonBlocking,
+}
+
+/// The sending end of a byte pipe, see the [module docs](self). Writes go
+/// straight to the queue, so flushing does nothing.
+pub struct QueueWriter<'a> {
+    sender: Sender<'a, u8>,
+    blocking: Blocking,
+}
+
+/// The receiving end of a byte pipe, see the [module docs](self).
+pub struct QueueReader<'a> {
+    receiver: Receiver<'a, u8>,
+    blocking: Blocking,
+    // Reused for the batch claims, which fill a Vec.
+    scratch: Vec<u8>,
+}
+
+impl<'a> QueueWriter<'a> {
+    pub fn new(sender: Sender<'a, u8>, blocking: Blocking) -> Self {
+        Self { sender, blocking }
+    }
+
+    pub fn set_blocking(&mut self, blocking: Blocking) {
+        self.blocking = blocking;
+    }
+
+    pub fn into_inner(self) -> Sender<'a, u8> {
+        self.sender
+    }
+
+    // Sends the first byte of a write to a full queue as `blocking` says.
+    fn wait_for_room(&self, d: u8) -> io::Result<()> {
+        let e = match self.blocking {
+            Blocking::Block => match self.sender.send_blocking(d) {
+                Ok(()) => return Ok(()),
+                Err(e) => e.split().0,
+            },
+            Blocking::Timeout(timeout) => match self.sender.send_timeout(d, timeout) {
+                Ok(()) => return Ok(()),
+                Err(SendTimeoutError::Timeout(_)) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(SendTimeoutError::Stale(_)) => TrySendError::Stale(()),
+                Err(SendTimeoutError::Closed(_)) => TrySendError::Closed(()),
+                Err(SendTimeoutError::Disconnected(_)) => TrySendError::Disconnected(()),
+            },
+            Blocking::NonBlocking => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+
+        Err(send_error(e))
+    }
+}
+
+impl<'a> Write for QueueWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.sender.try_send_many(buf, false) {
+            Ok(n) => Ok(n),
+            Err(TrySendError::Full(())) => {
+                self.wait_for_room(buf[0])?;
+
+                // Whatever else fits now; the rest is for the next write.
+                Ok(1 + self.sender.send_batch(&buf[1..]))
+            }
+            Err(e) => Err(send_error(e)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> QueueReader<'a> {
+    pub fn new(receiver: Receiver<'a, u8>, blocking: Blocking) -> Self {
+        Self {
+            receiver,
+            blocking,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn set_blocking(&mut self, blocking: Blocking) {
+        self.blocking = blocking;
+    }
+
+    pub fn into_inner(self) -> Receiver<'a, u8> {
+        self.receiver
+    }
+
+    // The first byte of a read from an empty queue, waiting as `blocking`
+    // says, or `None` at the end of the stream.
+    fn wait_for_byte(&self) -> io::Result<Option<u8>> {
+        let result = match self.blocking {
+            Blocking::Block => self.receiver.recv_blocking(),
+            Blocking::Timeout(timeout) => match self.receiver.recv_timeout(timeout) {
+                Ok(d) => Ok(d),
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Stale) => Err(TryRecvError::Stale),
+                Err(RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+                Err(RecvTimeoutError::Closed) => Err(TryRecvError::Closed),
+            },
+            Blocking::NonBlocking => self.receiver.try_recv(),
+        };
+
+        match result {
+            Ok(d) => Ok(Some(d)),
+            Err(TryRecvError::Disconnected | TryRecvError::Closed) => Ok(None),
+            Err(TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(e @ TryRecvError::Stale) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+            }
+        }
+    }
+}
+
+impl<'a> Read for QueueReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.scratch.clear();
+
+        if self.receiver.recv_batch(&mut self.scratch, buf.len()) == 0 {
+            match self.wait_for_byte()? {
+                Some(d) => self.scratch.push(d),
+                None => return Ok(0),
+            }
+
+            self.receiver.recv_batch(&mut self.scratch, buf.len() - 1);
+        }
+
+        let n = self.scratch.len();
+
+        buf[..n].copy_from_slice(&self.scratch);
+        Ok(n)
+    }
+}
+
+fn send_error(e: TrySendError<()>) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::RingBuffer;
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn copy_through() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+        let (s, r) = RingBuffer::<u8>::new(64);
+
+        let got = thread::scope(|scope| {
+            let data = &data;
+
+            scope.spawn(move || {
+                let mut w = QueueWriter::new(s, Blocking::Block);
+
+                // Odd-sized chunks, so that writes straddle full queues.
+                for chunk in data.chunks(37) {
+                    w.write_all(chunk).unwrap();
+                }
+            });
+
+            let mut got = Vec::new();
+
+            QueueReader::new(r, Blocking::Block)
+                .read_to_end(&mut got)
+                .unwrap();
+            got
+        });
+
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn non_blocking() {
+        let (s, r) = RingBuffer::<u8>::new(4);
+        let mut w = QueueWriter::new(s, Blocking::NonBlocking);
+        let mut r = QueueReader::new(r, Blocking::NonBlocking);
+        let mut buf = [0; 8];
+
+        assert_eq!(
+            r.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(w.write(b"abcdef").unwrap(), 4);
+        assert_eq!(
+            w.write(b"ef").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(r.read(&mut buf[..3]).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+
+        w.set_blocking(Blocking::Timeout(Duration::from_millis(10)));
+        assert_eq!(w.write(b"efgh").unwrap(), 3);
+        assert_eq!(w.write(b"h").unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"defg");
+
+        r.set_blocking(Blocking::Timeout(Duration::from_millis(10)));
+        assert_eq!(
+            r.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(r.read(&mut []).unwrap(), 0);
+    }
+
+    #[test]
+    fn ends() {
+        let (s, r) = RingBuffer::<u8>::new(4);
+        let mut w = QueueWriter::new(s, Blocking::Block);
+        let mut r = QueueReader::new(r, Blocking::Block);
+        let mut buf = [0; 8];
+
+        w.write_all(b"ab").unwrap();
+        drop(w);
+
+        // What was written before the writer went, then the end.
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+
+        let (s, r) = RingBuffer::<u8>::new(4);
+        let mut w = QueueWriter::new(s, Blocking::Block);
+
+        drop(r);
+        assert_eq!(w.write(b"a").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
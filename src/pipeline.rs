@@ -0,0 +1,185 @@
+//! Worker pools between two queues.
+//!
+//! [`stage`] runs `input → workers × f → output`: it closes the output once
+//! the input is drained and every sender of it is gone, and stops the whole
+//! pool when a worker panics so the panic reaches [`Stage::join`] instead of
+//! leaving the pipeline half alive.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::{self, Scope, ScopedJoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::TrySendError;
+use crate::order;
+use crate::rb::{BatchEnd, Receiver, Sender};
+
+// Elements a worker takes per receive.
+const BATCH: usize = 64;
+
+// How long a worker sleeps before checking whether another one panicked.
+const POLL: Duration = Duration::from_millis(50);
+
+/// The workers of a pipeline stage, see [`stage`].
+pub struct Stage<'scope> {
+    workers: Vec<ScopedJoinHandle<'scope, ()>>,
+}
+
+impl<'scope> Stage<'scope> {
+    /// Waits for every worker, returning the payload of the first panic.
+    pub fn join(self) -> thread::Result<()> {
+        let mut result = Ok(());
+
+        for worker in self.workers {
+            if let Err(e) = worker.join() {
+                result = result.and(Err(e));
+            }
+        }
+
+        result
+    }
+}
+
+// Tells the other workers to stop if this one unwinds.
+struct PanicGuard<'f>(&'f AtomicBool);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.store(true, order::CLOSE);
+        }
+    }
+}
+
+/// Spawns `workers` threads in `scope` that receive from `input`, apply `f`
+/// and send the results to `output`, waiting whenever `output` is full.
+///
+/// The workers hold the only handles the stage was given, so the output
+/// side disconnects once the workers stop: when `input` is drained and all
+/// its senders are gone, when either queue's generation is reset, or when
+/// a worker panics. A panic also stops the other workers, without waiting
+/// for the input to drain; elements they had received are lost.
+///
+/// # Panics
+///
+/// If `workers` is zero or the handles can't be cloned for every worker.
+pub fn stage<'scope, 'env, 'a, A, B, F>(
+    scope: &'scope Scope<'scope, 'env>,
+    input: Receiver<'a, A>,
+    output: Sender<'a, B>,
+    workers: usize,
+    f: F,
+) -> Stage<'scope>
+where
+    'a: 'scope,
+    A: Send,
+    B: Send,
+    F: Fn(A) -> B + Send + Sync + 'scope,
+{
+    assert!(workers > 0, "workers must be > 0");
+
+    let f = Arc::new(f);
+    let failed = Arc::new(AtomicBool::new(false));
+
+    let workers = (0..workers)
+        .map(|_| {
+            let (input, output) = (input.clone(), output.clone());
+            let (f, failed) = (f.clone(), failed.clone());
+
+            scope.spawn(move || {
+                let _guard = PanicGuard(&failed);
+                let mut buf = Vec::with_capacity(BATCH);
+
+                while !failed.load(order::CLOSE) {
+                    buf.clear();
+
+                    let batch = input.recv_batch_deadline(Instant::now() + POLL, BATCH, &mut buf);
+
+                    for d in buf.drain(..) {
+                        let mut d = f(d);
+
+                        loop {
+                            match output.try_send(d) {
+                                Ok(()) => break,
+                                Err(TrySendError::Full(back)) => d = back,
+                                Err(
+                                    TrySendError::Stale(_)
+                                    | TrySendError::Closed(_)
+                                    | TrySendError::Disconnected(_),
+                                ) => return,
+                            }
+
+                            if failed.load(order::CLOSE) {
+                                return;
+                            }
+
+                            output.wait_below(1.0, POLL);
+                        }
+                    }
+
+                    if batch.end == BatchEnd::Disconnected {
+                        return;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    Stage { workers }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rb::RingBuffer;
+
+    #[test]
+    fn input_closed_early() {
+        let (s, r) = RingBuffer::<u64>::new(16);
+        let (out, results) = RingBuffer::<u64>::new(16);
+
+        thread::scope(|scope| {
+            let stage = stage(scope, r, out, 2, |d| d + 1);
+
+            assert!(s.send(1));
+            drop(s);
+
+            assert!(stage.join().is_ok());
+        });
+
+        assert_eq!(results.recv(), Ok(2));
+        // Every sender of the output is gone.
+        assert_eq!(
+            results.recv_batch_blocking(1, 1, Duration::from_secs(10), &mut Vec::new()),
+            0
+        );
+    }
+
+    #[test]
+    fn panicking_transform() {
+        let (s, r) = RingBuffer::<u64>::new(16);
+        let (out, _results) = RingBuffer::<u64>::new(16);
+
+        let result = thread::scope(|scope| {
+            let stage = stage(scope, r, out, 4, |d| {
+                assert!(d != 3, "bad element");
+                d
+            });
+
+            for i in 0..8 {
+                assert!(s.send(i));
+            }
+
+            // The sender stays alive: only the panic can stop the stage.
+            let result = stage.join();
+
+            drop(s);
+            result
+        });
+
+        let e = result.unwrap_err();
+
+        assert_eq!(e.downcast_ref::<&str>(), Some(&"bad element"));
+    }
+}
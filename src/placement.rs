@@ -0,0 +1,312 @@
+//! Where a queue's slots are allocated. By default they are a `Vec`; with
+//! [`Builder::align`] they are allocated with the given alignment, and with
+//! the `numa` feature on Linux, [`Builder::huge_pages`] and
+//! [`Builder::numa_node`] map them on huge pages or on one NUMA node.
+//!
+//! Only the slots are placed: the queue's header, with the positions, is a
+//! `Box` like any other, on the node the allocator and the first touch put
+//! it on, which is usually the building thread's.
+//!
+//! [`Builder::align`]: crate::Builder::align
+//! [`Builder::huge_pages`]: crate::Builder::huge_pages
+//! [`Builder::numa_node`]: crate::Builder::numa_node
+
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use crate::rb::Cell;
+
+/// Alignment, pages and node of a queue's slots, set through the builder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Placement {
+    pub(crate) align: Option<usize>,
+    pub(crate) huge_pages: bool,
+    pub(crate) node: Option<u32>,
+}
+
+// The slots of a queue built with a placement, freed with the queue.
+pub(crate) struct Placed<T> {
+    ptr: NonNull<Cell<T>>,
+    len: usize,
+    memory: Memory,
+}
+
+enum Memory {
+    Heap(Layout),
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    Mapped(usize),
+}
+
+unsafe impl<T> Send for Placed<T> where Cell<T>: Send {}
+unsafe impl<T> Sync for Placed<T> where Cell<T>: Sync {}
+
+impl Placement {
+    pub(crate) fn is_default(&self) -> bool {
+        *self == Placement::default()
+    }
+}
+
+impl<T> Placed<T> {
+    // `len` slots, slot `i` set to `cell(i)`.
+    pub(crate) fn new(
+        placement: &Placement,
+        len: usize,
+        mut cell: impl FnMut(usize) -> Cell<T>,
+    ) -> Self {
+        let align = placement.align.unwrap_or(1);
+
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        let layout = Layout::array::<Cell<T>>(len)
+            .and_then(|l| l.align_to(align))
+            .expect("slot array too large");
+        let (ptr, memory) = Self::allocate(placement, layout);
+
+        for i in 0..len {
+            unsafe { ptr.as_ptr().add(i).write(cell(i)) };
+        }
+
+        Self { ptr, len, memory }
+    }
+
+    #[cfg(not(all(feature = "numa", target_os = "linux")))]
+    fn allocate(placement: &Placement, layout: Layout) -> (NonNull<Cell<T>>, Memory) {
+        // Only settable with the feature.
+        debug_assert!(!placement.huge_pages && placement.node.is_none());
+
+        (Self::allocate_heap(layout), Memory::Heap(layout))
+    }
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    fn allocate(placement: &Placement, layout: Layout) -> (NonNull<Cell<T>>, Memory) {
+        if !placement.huge_pages && placement.node.is_none() {
+            return (Self::allocate_heap(layout), Memory::Heap(layout));
+        }
+
+        let (ptr, size) = numa::map(layout, placement.huge_pages, placement.node);
+
+        (ptr.cast(), Memory::Mapped(size))
+    }
+
+    fn allocate_heap(layout: Layout) -> NonNull<Cell<T>> {
+        // Never zero-sized: there are at least two slots, each with a
+        // sequence number.
+        let ptr = unsafe { alloc::alloc(layout) };
+
+        match NonNull::new(ptr) {
+            Some(ptr) => ptr.cast(),
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+}
+
+impl<T> Deref for Placed<T> {
+    type Target = [Cell<T>];
+
+    fn deref(&self) -> &[Cell<T>] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for Placed<T> {
+    fn deref_mut(&mut self) -> &mut [Cell<T>] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for Placed<T> {
+    fn drop(&mut self) {
+        // The queue has taken the elements out already; this drops the
+        // cells themselves, as a Vec would.
+        unsafe { ptr::drop_in_place(&mut **self as *mut [Cell<T>]) };
+
+        match self.memory {
+            Memory::Heap(layout) => unsafe { alloc::dealloc(self.ptr.as_ptr().cast(), layout) },
+            #[cfg(all(feature = "numa", target_os = "linux"))]
+            Memory::Mapped(size) => numa::unmap(self.ptr.cast(), size),
+        }
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+mod numa {
+    use std::alloc::Layout;
+    use std::io;
+    use std::os::raw::{c_int, c_ulong};
+    use std::ptr::{self, NonNull};
+
+    // From <linux/mempolicy.h>, which libc does not have.
+    const MPOL_BIND: c_int = 2;
+
+    const HUGE_PAGE: usize = 2 << 20;
+
+    // Maps `layout` on anonymous memory, on huge pages if `huge`, bound to
+    // `node` if given, and returns it with the size mapped. The pages are
+    // not touched, so that they are allocated on the node on first write.
+    pub(super) fn map(layout: Layout, huge: bool, node: Option<u32>) -> (NonNull<u8>, usize) {
+        let page = if huge {
+            HUGE_PAGE
+        } else {
+            unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+        };
+
+        assert!(
+            layout.align() <= page,
+            "alignment must be at most the page size, {} bytes",
+            page
+        );
+
+        let size = layout.size().next_multiple_of(page);
+        let addr = if huge {
+            // Explicit huge pages if the pool has them, transparent ones
+            // otherwise.
+            mmap(size, libc::MAP_HUGETLB).unwrap_or_else(|| {
+                let addr = mmap(size, 0).unwrap_or_else(|| fail("mmap"));
+
+                unsafe { libc::madvise(addr.as_ptr().cast(), size, libc::MADV_HUGEPAGE) };
+                addr
+            })
+        } else {
+            mmap(size, 0).unwrap_or_else(|| fail("mmap"))
+        };
+
+        if let Some(node) = node {
+            let bits = c_ulong::BITS as usize;
+            let mut mask = vec![0 as c_ulong; node as usize / bits + 1];
+
+            mask[node as usize / bits] |= 1 << (node as usize % bits);
+
+            let bound = unsafe {
+                libc::syscall(
+                    libc::SYS_mbind,
+                    addr.as_ptr(),
+                    size,
+                    MPOL_BIND,
+                    mask.as_ptr(),
+                    // The kernel reads one bit fewer than it is told.
+                    mask.len() * bits + 1,
+                    0,
+                )
+            };
+
+            if bound != 0 {
+                let e = io::Error::last_os_error();
+
+                unmap(addr, size);
+                panic!("binding the slots to NUMA node {}: {}", node, e);
+            }
+        }
+
+        (addr, size)
+    }
+
+    pub(super) fn unmap(addr: NonNull<u8>, size: usize) {
+        unsafe { libc::munmap(addr.as_ptr().cast(), size) };
+    }
+
+    fn mmap(size: usize, flags: c_int) -> Option<NonNull<u8>> {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            )
+        };
+
+        if addr == libc::MAP_FAILED {
+            None
+        } else {
+            NonNull::new(addr.cast())
+        }
+    }
+
+    fn fail(what: &str) -> ! {
+        panic!("{} for the slots: {}", what, io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RingBuffer;
+
+    #[test]
+    fn aligned() {
+        for align in [64, 4096] {
+            let (rb, s, r) = RingBuffer::<u8>::builder().capacity(3).align(align).build();
+
+            assert_eq!(rb.slots_addr() % align, 0);
+            assert_eq!(
+                rb.memory_footprint(),
+                RingBuffer::estimate_footprint(&RingBuffer::<u8>::builder().capacity(3))
+            );
+
+            for i in 0..3 {
+                assert!(s.send(i));
+            }
+
+            assert!(!s.send(3));
+            assert_eq!(r.recv(), Ok(0));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be a power of two")]
+    fn not_a_power_of_two() {
+        RingBuffer::<u8>::builder().capacity(3).align(48).build();
+    }
+
+    #[test]
+    fn drops_leftovers() {
+        use std::sync::Arc;
+
+        let d = Arc::new(());
+
+        {
+            let (_rb, s, _r) = RingBuffer::builder().capacity(4).align(128).build();
+
+            assert!(s.send(d.clone()));
+            assert!(s.send(d.clone()));
+        }
+
+        assert_eq!(Arc::strong_count(&d), 1);
+    }
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    #[test]
+    fn mapped() {
+        let configs = [
+            RingBuffer::<u64>::builder().huge_pages(),
+            RingBuffer::<u64>::builder().numa_node(0),
+            RingBuffer::<u64>::builder().huge_pages().numa_node(0),
+        ];
+
+        for config in configs {
+            let (rb, s, r) = config.capacity(1000).build();
+
+            assert_eq!(rb.slots_addr() % 4096, 0);
+
+            for i in 0..1000 {
+                assert!(s.send(i));
+            }
+
+            for i in 0..1000 {
+                assert_eq!(r.recv(), Ok(i));
+            }
+        }
+    }
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    #[test]
+    #[should_panic(expected = "NUMA node 1000")]
+    fn missing_node() {
+        RingBuffer::<u64>::builder()
+            .capacity(8)
+            .numa_node(1000)
+            .build();
+    }
+}
@@ -0,0 +1,270 @@
+//! Recycling objects through a queue.
+//!
+//! A [`Pool`] keeps idle objects in a queue it owns both ends of.
+//! [`Pool::acquire`] takes one out, or makes a new one with the pool's
+//! factory when none is idle, and hands it out in a [`Pooled`] guard that
+//! sends it back when dropped, including by unwinding. An object that comes
+//! back to a full pool, because more were made than it holds, is dropped.
+
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicUsize;
+
+use crate::error::TrySendError;
+use crate::order;
+use crate::rb::{Receiver, RingBuffer, Sender};
+
+/// A bounded set of reusable objects, see the [module docs](self).
+pub struct Pool<T: 'static> {
+    sender: Sender<'static, T>,
+    receiver: Receiver<'static, T>,
+    factory: Box<dyn Fn() -> T + Send + Sync>,
+    // Objects made or put in and not yet dropped or detached, idle or not.
+    live: AtomicUsize,
+}
+
+impl<T: 'static> Pool<T> {
+    /// Creates a pool holding up to `n` idle objects, filled with `n` made
+    /// by `factory` up front.
+    pub fn new(n: usize, factory: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        let pool = Self::empty(n, factory);
+
+        for _ in 0..n {
+            let room = pool.sender.try_send(pool.make()).is_ok();
+
+            assert!(room, "a new pool has room for n objects");
+        }
+
+        pool
+    }
+
+    /// Like [`Pool::new`], but starting with no idle objects: they are made
+    /// on demand and kept once returned.
+    pub fn empty(n: usize, factory: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        let (sender, receiver) = RingBuffer::new(n);
+
+        Self {
+            sender,
+            receiver,
+            factory: Box::new(factory),
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes an idle object, or makes a new one if there is none.
+    pub fn acquire(&self) -> Pooled<'_, T> {
+        self.try_acquire()
+            .unwrap_or_else(|| Pooled::new(self, self.make()))
+    }
+
+    /// Takes an idle object, or returns `None` if there is none.
+    pub fn try_acquire(&self) -> Option<Pooled<'_, T>> {
+        self.receiver.try_recv().ok().map(|d| Pooled::new(self, d))
+    }
+
+    /// Takes an idle object, sleeping until one is returned if there is
+    /// none, for a pool that should never hold more than it was filled
+    /// with.
+    pub fn acquire_blocking(&self) -> Pooled<'_, T> {
+        match self.receiver.recv_blocking() {
+            Ok(d) => Pooled::new(self, d),
+            // The pool holds a sender and a receiver of its own.
+            Err(e) => unreachable!("pool queue failed: {}", e),
+        }
+    }
+
+    /// Adds `d` to the idle objects, handing it back if the pool is full.
+    pub fn put(&self, d: T) -> Result<(), T> {
+        self.live.fetch_add(1, order::COUNTER);
+        self.sender.try_send(d).map_err(|e| {
+            self.live.fetch_sub(1, order::COUNTER);
+            e.into_inner()
+        })
+    }
+
+    fn make(&self) -> T {
+        let d = (self.factory)();
+
+        self.live.fetch_add(1, order::COUNTER);
+        d
+    }
+
+    // Takes back an object that was out.
+    fn give_back(&self, d: T) {
+        let Err(TrySendError::Full(d)) = self.sender.try_send(d) else {
+            return;
+        };
+
+        // A send also finds the queue full while a receiver has yet to
+        // release the slot it took, so only drop `d` if there are more
+        // objects than the pool holds, and otherwise wait for the slot.
+        let surplus = self
+            .live
+            .fetch_update(order::COUNTER, order::COUNTER, |n| {
+                (n > self.capacity()).then(|| n - 1)
+            })
+            .is_ok();
+
+        if !surplus {
+            let _ = self.sender.send_blocking(d);
+        }
+    }
+
+    /// Idle objects.
+    pub fn idle(&self) -> usize {
+        self.receiver.len()
+    }
+
+    /// The most idle objects the pool holds.
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity()
+    }
+
+    /// Drops every idle object, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut n = 0;
+
+        while self.receiver.try_recv().is_ok() {
+            n += 1;
+        }
+
+        self.live.fetch_sub(n, order::COUNTER);
+        n
+    }
+}
+
+/// An object taken from a [`Pool`], returned to it when dropped.
+pub struct Pooled<'p, T: 'static> {
+    pool: &'p Pool<T>,
+    // Taken out by detach() or by the drop that returns it.
+    d: ManuallyDrop<T>,
+    detached: bool,
+}
+
+impl<'p, T: 'static> Pooled<'p, T> {
+    fn new(pool: &'p Pool<T>, d: T) -> Self {
+        Self {
+            pool,
+            d: ManuallyDrop::new(d),
+            detached: false,
+        }
+    }
+
+    /// Takes the object out for good; the pool does not get it back.
+    pub fn detach(mut self) -> T {
+        self.detached = true;
+        self.pool.live.fetch_sub(1, order::COUNTER);
+        // The drop below leaves `d` alone once detached.
+        unsafe { ManuallyDrop::take(&mut self.d) }
+    }
+}
+
+impl<'p, T: 'static> Deref for Pooled<'p, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.d
+    }
+}
+
+impl<'p, T: 'static> DerefMut for Pooled<'p, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.d
+    }
+}
+
+impl<'p, T: 'static> Drop for Pooled<'p, T> {
+    fn drop(&mut self) {
+        if !self.detached {
+            let d = unsafe { ManuallyDrop::take(&mut self.d) };
+
+            self.pool.give_back(d);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn recycles() {
+        let made = Arc::new(AtomicUsize::new(0));
+        let pool = {
+            let made = made.clone();
+
+            Pool::new(2, move || {
+                made.fetch_add(1, Ordering::Relaxed);
+                Vec::<u8>::with_capacity(16)
+            })
+        };
+
+        assert_eq!(made.load(Ordering::Relaxed), 2);
+        assert_eq!(pool.idle(), 2);
+
+        {
+            let mut a = pool.acquire();
+            let b = pool.acquire();
+            // Nothing idle: a third object is made.
+            let c = pool.acquire();
+
+            a.push(1);
+            assert_eq!(pool.idle(), 0);
+            assert!(pool.try_acquire().is_none());
+            assert_eq!(made.load(Ordering::Relaxed), 3);
+            drop((b, c));
+        }
+
+        // The third one found the pool full and was dropped.
+        assert_eq!(pool.idle(), 2);
+
+        let kept = pool.acquire().detach();
+
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(pool.put(kept), Ok(()));
+        assert_eq!(pool.clear(), 2);
+        assert_eq!(made.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn slow_receiver() {
+        let pool = Pool::new(2, || 0u64);
+
+        thread::scope(|scope| {
+            // A receiver that took a slot and has yet to release it leaves
+            // the ring full to a send that wraps around to that slot.
+            let held = pool.receiver.recv_ref().unwrap();
+
+            scope.spawn(|| drop(pool.acquire()));
+            thread::sleep(std::time::Duration::from_millis(10));
+            drop(held);
+        });
+
+        // The object came back rather than being dropped.
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn shared() {
+        let pool = Pool::new(4, || 0u64);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        *pool.acquire_blocking() += 1;
+                    }
+                });
+            }
+        });
+
+        let total: u64 = std::iter::from_fn(|| pool.try_acquire().map(Pooled::detach)).sum();
+
+        assert_eq!(total, 4000);
+    }
+}
@@ -0,0 +1,179 @@
+//! Which operations may run where blocking is not allowed, e.g. in a signal
+//! handler or a real-time thread.
+//!
+//! [`OPERATIONS`] classifies the public operations of a queue and its
+//! handles. A wait-free operation finishes in a bounded number of its own
+//! steps. A lock-free one retries only when another thread's claim of the
+//! same position beat it, so some thread always makes progress; it never
+//! takes a lock and never waits for another thread. A blocking one may
+//! sleep.
+//!
+//! Two features of the queue can make a lock-free operation take a lock
+//! after all: a send wakes receivers asleep in a blocking receive, or the
+//! waiter of a [`SelectGroup`](crate::SelectGroup), under the waiters'
+//! lock, and a receive takes elements put back for redelivery from under a
+//! lock. Neither happens in a queue that is only used with
+//! the operations classified here.
+//!
+//! The unit tests hold the classification to the code: they run every
+//! operation with claims forced to fail and check that it takes no lock and
+//! does no more passes of its retry loop than one plus the failed claims.
+
+/// The progress guarantee of an operation, see the module docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    WaitFree,
+    LockFree,
+    Blocking,
+}
+
+/// Operations by path, with their guarantee.
+pub const OPERATIONS: &[(&str, Progress)] = &[
+    ("RingBuffer::capacity", Progress::WaitFree),
+    ("RingBuffer::empty", Progress::LockFree),
+    ("RingBuffer::positions", Progress::LockFree),
+    ("RingBuffer::len", Progress::LockFree),
+    ("RingBuffer::is_full", Progress::LockFree),
+    ("RingBuffer::remaining_capacity", Progress::LockFree),
+    ("Sender::capacity", Progress::WaitFree),
+    ("Sender::empty", Progress::LockFree),
+    ("Sender::len", Progress::LockFree),
+    ("Sender::send", Progress::LockFree),
+    ("Sender::try_send", Progress::LockFree),
+    ("Sender::claim", Progress::LockFree),
+    ("Sender::send_blocking", Progress::Blocking),
+    ("Sender::send_timeout", Progress::Blocking),
+    ("Sender::wait_below", Progress::Blocking),
+    ("RendezvousSender::send", Progress::Blocking),
+    ("Receiver::capacity", Progress::WaitFree),
+    ("Receiver::empty", Progress::LockFree),
+    ("Receiver::len", Progress::LockFree),
+    ("Receiver::recv", Progress::LockFree),
+    ("Receiver::try_recv", Progress::LockFree),
+    ("Receiver::peek", Progress::LockFree),
+    ("Receiver::recv_ref", Progress::LockFree),
+    ("Receiver::recv_blocking", Progress::Blocking),
+    ("Receiver::recv_timeout", Progress::Blocking),
+    ("Receiver::recv_batch_blocking", Progress::Blocking),
+];
+
+/// The guarantee of `op`, a path as in [`OPERATIONS`].
+pub fn progress(op: &str) -> Option<Progress> {
+    OPERATIONS
+        .iter()
+        .find(|(name, _)| *name == op)
+        .map(|&(_, p)| p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rb::hooks::{FAIL_CLAIMS, ITERATIONS, LOCKS};
+    use crate::rb::RingBuffer;
+
+    // What one call of an operation did.
+    struct Measured {
+        passes: u64,
+        locks: u64,
+        failed_claims: u64,
+    }
+
+    // Runs `op` on a queue holding a few elements with the next `fail`
+    // claims forced to fail.
+    fn measure(op: &str, fail: u64) -> Measured {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(8);
+
+        assert!(s.send(1) && s.send(2));
+        FAIL_CLAIMS.with(|n| n.set(fail));
+        ITERATIONS.with(|n| n.set(0));
+        LOCKS.with(|n| n.set(0));
+
+        match op {
+            "RingBuffer::capacity" => assert_eq!(q.capacity(), 8),
+            "RingBuffer::empty" => assert!(!q.empty()),
+            "RingBuffer::positions" => assert_eq!(q.positions(), (2, 0)),
+            "RingBuffer::len" => assert_eq!(q.len(), 2),
+            "RingBuffer::is_full" => assert!(!q.is_full()),
+            "RingBuffer::remaining_capacity" => assert_eq!(q.remaining_capacity(), 6),
+            "Sender::capacity" => assert_eq!(s.capacity(), q.capacity()),
+            "Sender::empty" => assert!(!s.empty()),
+            "Sender::len" => assert_eq!(s.len(), 2),
+            "Sender::send" => assert!(s.send(3)),
+            "Sender::try_send" => assert_eq!(s.try_send(3), Ok(())),
+            "Sender::claim" => *s.claim().unwrap() = 3,
+            "Receiver::capacity" => assert_eq!(r.capacity(), q.capacity()),
+            "Receiver::empty" => assert!(!r.empty()),
+            "Receiver::len" => assert_eq!(r.len(), 2),
+            "Receiver::recv" => assert_eq!(r.recv(), Ok(1)),
+            "Receiver::try_recv" => assert_eq!(r.try_recv(), Ok(1)),
+            "Receiver::peek" => assert_eq!(r.peek(), Ok(1)),
+            "Receiver::recv_ref" => assert_eq!(*r.recv_ref().unwrap(), 1),
+            op => panic!("no check for {}", op),
+        }
+
+        Measured {
+            passes: ITERATIONS.with(|n| n.get()),
+            locks: LOCKS.with(|n| n.get()),
+            failed_claims: fail - FAIL_CLAIMS.with(|n| n.replace(0)),
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(progress("Sender::try_send"), Some(Progress::LockFree));
+        assert_eq!(progress("Sender::lock"), None);
+    }
+
+    #[test]
+    fn enforced() {
+        // Sends also take a snapshot of the length for the depth histogram.
+        let snapshot = |op: &str| (cfg!(feature = "stats") && op.starts_with("Sender::")) as u64;
+
+        for &(op, guarantee) in OPERATIONS {
+            if guarantee == Progress::Blocking {
+                continue;
+            }
+
+            for fail in [0, 1, 10, 100] {
+                let m = measure(op, fail);
+
+                assert_eq!(m.locks, 0, "{} took a lock", op);
+
+                match guarantee {
+                    Progress::WaitFree => assert_eq!(m.passes, 0, "{} looped", op),
+                    _ => assert!(
+                        m.passes <= 1 + m.failed_claims + snapshot(op),
+                        "{} took {} passes for {} failed claims",
+                        op,
+                        m.passes,
+                        m.failed_claims
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn claims_retried() {
+        // The forced failures do reach the CAS loops, each costing a pass.
+        for op in ["Sender::try_send", "Receiver::try_recv"] {
+            for fail in [1, 10, 100] {
+                assert_eq!(measure(op, fail).failed_claims, fail, "{}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn locks_seen() {
+        // The harness does see locks: a redelivered element is taken under
+        // one.
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        assert!(s.send(1));
+        r.unrecv(2);
+        LOCKS.with(|n| n.set(0));
+        assert_eq!(r.try_recv(), Ok(2));
+        assert!(LOCKS.with(|n| n.get()) > 0);
+    }
+}
@@ -1,312 +1,7462 @@
-use std::cell::UnsafeCell;
-use std::sync::{Arc, Mutex};
-use crossbeam_utils::CachePadded;
+use crossbeam_utils::Backoff;
+use std::alloc::Layout;
+use std::collections::VecDeque;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::iter::FusedIterator;
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::mem::{self, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64};
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-struct Cell<T: Default + Copy> {
-    pos: AtomicU32,
-    data: UnsafeCell<T>,
+use crate::ack::{AckGuard, Retry};
+use crate::claim::{RecvSlot, SendSlot, SendSlots};
+use crate::order;
+use crate::pad::CachePadded;
+use crate::park::Waiters;
+use crate::placement::Placed;
+use crate::ready::Edge;
+use crate::select::Member;
+use crate::slot::{self, Slot};
+use crate::snapshot::{SlotState, Snapshot};
+use crate::sync;
+use crate::ticket::{Turn, Turnstile};
+use crate::wait::WaitBudget;
+
+use crate::builder::{Builder, Event, Fairness, OnFull};
+use crate::error::{
+    LayoutError, RecvProbe, RecvTimeoutError, SendAllError, SendTimeoutError, TryRecvError,
+    TrySendError,
+};
+#[cfg(feature = "registry")]
+use crate::registry;
+#[cfg(feature = "stats")]
+use crate::stats::{
+    self, CapacityAdvice, Counters, HandleCounters, HandleStats, HandleTotals, QueueStats,
+    StallReport,
+};
+use std::fmt;
+
+pub(crate) struct Cell<T> {
+    pos: sync::AtomicU64,
+    // Initialised from publishing until the element is taken out again.
+    data: sync::UnsafeCell<MaybeUninit<T>>,
 }
 
 struct Users {
-    senders: Arc<Mutex<u32>>,
-    receivers: Arc<Mutex<u32>>,
+    senders: AtomicU32,
+    receivers: AtomicU32,
+}
+
+/// Largest capacity accepted by [`RingBuffer::new`].
+///
+/// Positions are 64-bit on every target, and slot counts are kept in `u32`.
+/// Bounding the capacity here keeps every `usize`/`u32` conversion
+/// lossless, including on 32-bit targets.
+pub const MAX_CAPACITY: usize = (1 << 30) - 1;
+
+const _: () = assert!(usize::BITS >= 32);
+const _: () = assert!(MAX_CAPACITY.next_power_of_two() <= 1 << 30);
+
+/// Checks `n` as a capacity the way [`RingBuffer::new`] does and returns
+/// it. Usable in const context, where an invalid `n` is a compile error:
+///
+/// ```
+/// const CAP: usize = mpmcbq::checked_capacity(100);
+///
+/// assert_eq!(CAP, 100);
+/// ```
+///
+/// # Panics
+///
+/// If `n` is zero or above [`MAX_CAPACITY`].
+pub const fn checked_capacity(n: usize) -> usize {
+    assert!(n > 0, "capacity must be > 0");
+    assert!(n <= MAX_CAPACITY, "capacity must be <= MAX_CAPACITY");
+
+    n
+}
+
+/// Identifies a channel for as long as it lives, see
+/// [`RingBuffer::channel_id`]. Ids come from a process-wide counter, so one
+/// is never reused, not even after its channel is dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelId(u64);
+
+static NEXT_CHANNEL_ID: AtomicU64 = AtomicU64::new(0);
+
+impl ChannelId {
+    fn next() -> Self {
+        ChannelId(NEXT_CHANNEL_ID.fetch_add(1, order::CHANNEL_ID))
+    }
+}
+
+/// Bytes used by a queue, see [`RingBuffer::memory_footprint`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// The slot array, sequence numbers and payloads.
+    pub cells_bytes: usize,
+    /// Everything else: the padded positions, handle counts, configuration
+    /// and, with the `stats` feature, the counters.
+    pub overhead_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Why [`Receiver::recv_batch_deadline`] stopped, and how many elements it
+/// received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchResult {
+    pub received: usize,
+    pub end: BatchEnd,
 }
 
-pub struct RingBuffer<'a, T: Default + Copy> {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchEnd {
+    /// The batch is full.
+    Max,
+    /// The deadline passed first.
+    Deadline,
+    /// Every sender is gone and the queue is empty, or the receiver is
+    /// stale: nothing more will come.
+    Disconnected,
+}
+
+/// How [`Sender::shutdown`] ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShutdownOutcome<T> {
+    /// The receivers took every element.
+    Drained,
+    /// The timeout passed with `remaining` elements still queued. The
+    /// receivers are alive, so the elements are left to them.
+    TimedOut { remaining: usize },
+    /// No receiver is left to take the `remaining` elements. If the caller
+    /// held the last sender as well, nothing else can touch the queue and
+    /// `leftover` holds the elements, oldest first; otherwise, or if a
+    /// [`RingBuffer::reset_generation`] made the sender stale, it is `None`.
+    Abandoned {
+        remaining: usize,
+        leftover: Option<Vec<T>>,
+    },
+}
+
+impl MemoryFootprint {
+    fn new<'a, T: 'a>(slots: usize) -> Self {
+        let cells_bytes = slots * mem::size_of::<Cell<T>>();
+        let overhead_bytes = mem::size_of::<RingBuffer<'a, T>>();
+
+        Self {
+            cells_bytes,
+            overhead_bytes,
+            total_bytes: cells_bytes + overhead_bytes,
+        }
+    }
+}
+
+/// A bounded multi-producer multi-consumer queue.
+///
+/// [`RingBuffer::new`] returns just the handles, which own the queue: the
+/// last of them to be dropped frees it, whatever the order.
+/// [`RingBuffer::new_boxed`] also returns a [`RingBox`], a share of the
+/// queue for the calls the handles do not offer.
+///
+/// # Ordering
+///
+/// Sends that are ordered with respect to each other, in particular any
+/// sequence of sends made by one thread, are received in the order they were
+/// sent. With several receivers each receiver observes that order for the
+/// elements it gets, and no element is delivered twice. There is no ordering
+/// between elements of unrelated producers.
+///
+/// # Model checking
+///
+/// Built with `--cfg loom`, the ring's claim, publish and release steps go
+/// through [loom]'s atomics and cells, so a loom model of code on top of
+/// the queue explores their interleavings along with its own. The queue
+/// must then be built inside the model, and the model should stick to the
+/// handles and the `try_` operations: waiting, stats and hooks keep std's
+/// atomics, which loom does not see. The crate's own models are in
+/// `tests/loom.rs`.
+///
+/// ```text
+/// RUSTFLAGS="--cfg loom" cargo test --release
+/// ```
+///
+/// [loom]: https://docs.rs/loom
+pub struct RingBuffer<'a, T> {
     n: CachePadded<usize>,
-    v: CachePadded<Vec<Cell<T>>>,
+    v: CachePadded<Storage<'a, T>>,
     users: CachePadded<Users>,
-    enq_pos: CachePadded<AtomicU32>,
-    deq_pos: CachePadded<AtomicU32>,
+    enq_pos: CachePadded<sync::AtomicU64>,
+    deq_pos: CachePadded<sync::AtomicU64>,
+
+    // Serialises reset_generation().
+    reset: Mutex<()>,
+
+    retry: CachePadded<Retry<T>>,
+
+    // Receivers blocked in recv_batch_blocking().
+    recv_waiters: CachePadded<Waiters>,
+
+    // Senders waiting in wait_below()/until_below().
+    send_waiters: CachePadded<Waiters>,
+
+    // Set by shutdown(); sends fail from then on.
+    closed: AtomicBool,
+
+    // Live handles, plus one for the RingBox until it is dropped. The last
+    // of them frees the queue.
+    refs: AtomicU32,
 
-     _covariant: PhantomData<&'a ()>,
+    // Discarded elements the dead-letter queue had no room for.
+    dead_letter_failures: AtomicU64,
+
+    // The SelectGroup the queue was added to, if any.
+    select: OnceLock<Member>,
+
+    // The Builder::on_readable()/on_writable() hooks' arming.
+    readable: Edge,
+    writable: Edge,
+
+    // Orders the claims of senders with Fairness::Ticket.
+    turnstile: Option<Turnstile>,
+
+    // The priority lane, with Builder::lane_weights(). It shares the
+    // generation of this queue and has no handles of its own.
+    lane: Option<Box<RingBuffer<'a, T>>>,
+
+    config: Builder<'a, T>,
+    // config.wait.budget(), looked up once.
+    wait: WaitBudget,
+    id: ChannelId,
+
+    #[cfg(feature = "stats")]
+    stats: CachePadded<Counters>,
+
+    _covariant: PhantomData<&'a ()>,
+}
+
+pub struct Sender<'a, T> {
+    rb: NonNull<RingBuffer<'a, T>>,
+    generation: u16,
+
+    #[cfg(feature = "stats")]
+    local: HandleCounters,
 }
 
-pub struct Sender<'a, T: Default + Copy> {
-    rb: UnsafeCell<*mut RingBuffer<'a, T>>,
+pub struct Receiver<'a, T> {
+    rb: NonNull<RingBuffer<'a, T>>,
+    generation: u16,
+    deficit: AtomicU64,
+
+    #[cfg(feature = "stats")]
+    local: HandleCounters,
 }
 
-pub struct Receiver<'a, T: Default + Copy> {
-    rb: UnsafeCell<*mut RingBuffer<'a, T>>,
+// What a receiver may still take from each lane in the current round, see
+// Builder::lane_weights(). The receiver keeps it packed in one word.
+#[derive(Clone, Copy, Default)]
+struct Deficit {
+    priority: u32,
+    bulk: u32,
 }
 
-impl<T: Default + Copy> Drop for Cell<T> {
-    fn drop(&mut self) {
-        // println!("Cell drop({:?})", self.pos.load(Ordering::SeqCst));
+impl Deficit {
+    fn load(word: &AtomicU64) -> Self {
+        let word = word.load(order::DEFICIT);
+
+        Self {
+            priority: (word >> 32) as u32,
+            bulk: word as u32,
+        }
+    }
+
+    fn store(self, word: &AtomicU64) {
+        word.store(
+            (self.priority as u64) << 32 | self.bulk as u64,
+            order::DEFICIT,
+        );
     }
 }
 
-impl<'a, T: Default + Copy> Drop for RingBuffer<'a, T> {
+/// A sender that may also use the headroom reserved with
+/// [`Builder::reserve_headroom`]. It counts as a sender of the queue.
+pub struct PrioritySender<'a, T> {
+    rb: NonNull<RingBuffer<'a, T>>,
+    generation: u16,
+
+    #[cfg(feature = "stats")]
+    local: HandleCounters,
+}
+
+// enq_pos/deq_pos hold the generation in the top 16 bits and the position,
+// modulo 2^48, in the rest. A claim CAS compares both, so once
+// reset_generation() moves the generation on no handle of an earlier one can
+// claim a slot again. The top bit of enq_pos's generation is REFUSING rather
+// than part of the count, which leaves generations 15 bits wide. The slots
+// hold full 64-bit sequence numbers, and a position read from a word is
+// widened back with the one of the slot it maps to, see widen().
+//
+// Positions modulo 2^48 leave one ABA window: a claimer that stalls between
+// loading a word and its CAS while exactly a multiple of 2^48 positions go
+// by would succeed on a stale word. At a billion claims a second that takes
+// over three days of a stalled thread.
+const POS_BITS: u32 = 48;
+const POS_MASK: u64 = (1 << POS_BITS) - 1;
+
+// Set in enq_pos's generation while the queue is closed or has no receiver.
+// Every claim then fails its generation check and takes the slow path,
+// which finds out why. A send that gets in loads nothing beyond the slot it
+// claims and the word it claims it with.
+const REFUSING: u16 = 1 << 15;
+const GENERATION_MASK: u16 = REFUSING - 1;
+
+fn pack(generation: u16, pos: u64) -> u64 {
+    (generation as u64) << POS_BITS | pos & POS_MASK
+}
+
+fn unpack(word: u64) -> (u16, u64) {
+    ((word >> POS_BITS) as u16, word & POS_MASK)
+}
+
+// Signed distance from `b` to `a`, modulo 2^48.
+fn distance(a: u64, b: u64) -> i64 {
+    (a.wrapping_sub(b) << (64 - POS_BITS)) as i64 >> (64 - POS_BITS)
+}
+
+// The full position whose low 48 bits are `pos`, from the sequence number
+// `seq` of the slot serving it. A slot is never more than a couple of laps
+// from any position that maps to it, so the nearest one is exact.
+fn widen(pos: u64, seq: u64) -> u64 {
+    seq.wrapping_add_signed(distance(pos, seq))
+}
+
+// Approximate length as seen by a sender about to claim `pos`. A stale `pos`
+// can lag the dequeue position; that counts as empty.
+fn len_at(deq_pos: &sync::AtomicU64, pos: u64) -> u32 {
+    let (_, deq) = unpack(deq_pos.load(order::SNAPSHOT));
+
+    distance(pos, deq).clamp(0, u32::MAX.into()) as u32
+}
+
+impl<'a, T> Drop for RingBuffer<'a, T> {
     fn drop(&mut self) {
-        let n_s;
+        #[cfg(feature = "registry")]
+        registry::deregister(self as *const Self as usize);
 
-        {
-            let n = self.users.senders.lock().unwrap();
+        self.event(Event::Dropped {
+            len: self.retry.len() + self.len(),
+        });
 
-            assert!(*n == 0, "Dropping ring buffer with active senders");
+        // Without a dead-letter queue the leftovers are just dropped.
+        let generation = self.generation();
 
-            n_s = *n;
+        while let Ok(Some((d, _))) = self.redeliver(generation) {
+            self.dead_letter(d);
         }
 
-        let n_r;
-        {
-            let n = self.users.receivers.lock().unwrap();
+        while let Ok(d) = self.recv(generation) {
+            self.dead_letter(d);
+        }
+    }
+}
 
-            assert!(*n == 0, "Dropping ring buffer with active receivers");
+impl<'a, T> fmt::Debug for RingBuffer<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (enq, deq) = self.positions();
 
-            n_r = *n;
+        f.debug_struct("RingBuffer")
+            .field("config", &self.config)
+            .field("capacity", &self.capacity())
+            .field("enq_pos", &enq)
+            .field("deq_pos", &deq)
+            .field("senders", &self.users.senders.load(order::HANDLE_LOAD))
+            .field("receivers", &self.users.receivers.load(order::HANDLE_LOAD))
+            .finish()
+    }
+}
+
+/// The creator's share of a [`RingBuffer`], returned with its first handles
+/// by [`RingBuffer::new_boxed`] and [`Builder::build`]. It derefs to the queue, for
+/// monitoring and configuration calls the handles do not offer.
+///
+/// The queue is freed once this and every handle are dropped, in any order:
+/// dropping it early only gives up its share.
+pub struct RingBox<'a, T> {
+    rb: NonNull<RingBuffer<'a, T>>,
+    _owns: PhantomData<RingBuffer<'a, T>>,
+}
+
+unsafe impl<'a, T> Send for RingBox<'a, T> where T: Send {}
+
+impl<'a, T> RingBox<'a, T> {
+    // Takes over the reference `refs` starts with.
+    fn new(rb: NonNull<RingBuffer<'a, T>>) -> Self {
+        Self {
+            rb,
+            _owns: PhantomData,
         }
+    }
+}
 
-        println!(
-            "RingBuffer drop : senders: {}, receivers: {} {:?}",
-            n_s, n_r, self.n
-        );
+impl<'a, T> Deref for RingBox<'a, T> {
+    type Target = RingBuffer<'a, T>;
+
+    fn deref(&self) -> &RingBuffer<'a, T> {
+        unsafe { self.rb.as_ref() }
+    }
+}
+
+impl<'a, T> Drop for RingBox<'a, T> {
+    fn drop(&mut self) {
+        unsafe { RingBuffer::unref(self.rb.as_ptr()) };
+    }
+}
+
+impl<'a, T> fmt::Debug for RingBox<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T> Drop for Sender<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "stats")]
+        self.rb().stats.retire_sender(&self.local);
+
+        let n = Users::release(&self.rb().users.senders);
+
+        self.rb().event(Event::SenderDropped { senders: n });
+
+        if n == 0 {
+            self.rb().notify_disconnect();
+        }
+
+        unsafe { RingBuffer::unref(self.rb.as_ptr()) };
     }
 }
 
-impl<'a, T: Default + Copy> Drop for Sender<'a, T> {
+impl<'a, T> Drop for PrioritySender<'a, T> {
     fn drop(&mut self) {
-        let mut n = unsafe { (*(*self.rb.get())).users.senders.lock().unwrap() };
+        #[cfg(feature = "stats")]
+        self.rb().stats.retire_sender(&self.local);
 
-        assert!(*n > 0, "Number of senders can't be zero.");
+        let n = Users::release(&self.rb().users.senders);
 
-        *n -= 1;
+        self.rb().event(Event::SenderDropped { senders: n });
 
-        println!("Sender::drop active: {}", *n);
+        if n == 0 {
+            self.rb().notify_disconnect();
+        }
+
+        unsafe { RingBuffer::unref(self.rb.as_ptr()) };
     }
 }
 
-impl<'a, T: Default + Copy> Drop for Receiver<'a, T> {
+impl<'a, T> Drop for Receiver<'a, T> {
     fn drop(&mut self) {
-        let mut n = unsafe { (*(*self.rb.get())).users.receivers.lock().unwrap() };
+        #[cfg(feature = "stats")]
+        self.rb().stats.retire_receiver(&self.local);
 
-        assert!(*n > 0, "Number of receivers can't be zero");
+        let n = Users::release(&self.rb().users.receivers);
 
-        *n -= 1;
+        self.rb().event(Event::ReceiverDropped { receivers: n });
+
+        if n == 0 {
+            self.rb().refuse_sends();
+            // A shutdown waiting for the drain gives up.
+            self.rb().notify_no_receivers();
+        }
 
-        println!("Receiver::drop active: {}", *n);
+        unsafe { RingBuffer::unref(self.rb.as_ptr()) };
     }
 }
 
-unsafe impl<'a, T: Default + Copy> Send for Sender<'a, T> where T: Send {}
-unsafe impl<'a, T: Default + Copy> Sync for Sender<'a, T> where T: Sync {}
+unsafe impl<'a, T> Send for Sender<'a, T> where T: Send {}
+unsafe impl<'a, T> Sync for Sender<'a, T> where T: Send {}
 
-unsafe impl<'a, T: Default + Copy> Send for Receiver<'a, T> where T: Send {}
-unsafe impl<'a, T: Default + Copy> Sync for Receiver<'a, T> where T: Sync {}
+unsafe impl<'a, T> Send for PrioritySender<'a, T> where T: Send {}
+unsafe impl<'a, T> Sync for PrioritySender<'a, T> where T: Send {}
+
+unsafe impl<'a, T> Send for Receiver<'a, T> where T: Send {}
+unsafe impl<'a, T> Sync for Receiver<'a, T> where T: Send {}
 
 impl Users {
     pub fn new(s: u32, r: u32) -> Self {
         Self {
-            senders: Arc::new(Mutex::new(s)),
-            receivers: Arc::new(Mutex::new(r)),
+            senders: AtomicU32::new(s),
+            receivers: AtomicU32::new(r),
         }
     }
-}
 
-impl<'a, T: Default + Copy> Sender<'a, T> {
-    pub fn send(&mut self, d: T) -> bool {
-        unsafe { (*(*self.rb.get())).send(d) }
+    // Adds a handle unless the count is zero (that side is gone for good) or
+    // would overflow. Returns the new count.
+    fn acquire(count: &AtomicU32) -> Option<u32> {
+        let mut n = count.load(order::HANDLE_LOAD);
+
+        loop {
+            if n == 0 || n == u32::MAX {
+                return None;
+            }
+
+            match count.compare_exchange_weak(n, n + 1, order::HANDLE_UP, order::HANDLE_LOAD) {
+                Ok(_) => return Some(n + 1),
+                Err(actual) => n = actual,
+            }
+        }
     }
 
-    pub fn empty(&mut self) -> bool {
-        unsafe { (*(*self.rb.get())).empty() }
+    // Adds a handle even if the count is zero, bringing that side back, unless
+    // the count would overflow. Returns the new count.
+    fn revive(count: &AtomicU32) -> Option<u32> {
+        let mut n = count.load(order::HANDLE_LOAD);
+
+        loop {
+            if n == u32::MAX {
+                return None;
+            }
+
+            match count.compare_exchange_weak(n, n + 1, order::HANDLE_UP, order::HANDLE_LOAD) {
+                Ok(_) => return Some(n + 1),
+                Err(actual) => n = actual,
+            }
+        }
     }
 
-    pub fn capacity(&mut self) -> usize {
-        unsafe { (*(*self.rb.get())).capacity() }
+    // Adds a handle even if the count is zero, for a new generation.
+    // Returns the new count.
+    fn add(count: &AtomicU32) -> u32 {
+        let n = count.fetch_add(1, order::HANDLE_UP);
+
+        debug_assert!(n < u32::MAX, "Number of handles would overflow");
+
+        n + 1
     }
-}
 
-impl<'a, T: Default + Copy> Clone for Sender<'a, T> {
-    fn clone(&self) -> Self {
-        let mut n = unsafe { (*(*self.rb.get())).users.senders.lock().unwrap() };
+    // Removes a handle, returning the remaining count.
+    fn release(count: &AtomicU32) -> u32 {
+        let n = count.fetch_sub(1, order::HANDLE_DOWN);
 
-        assert!(*n > 0, "Number of senders can't be zero");
+        debug_assert!(n > 0, "Number of handles can't be zero");
+
+        n - 1
+    }
+}
 
-        *n += 1;
+impl<'a, T> Sender<'a, T> {
+    fn rb(&self) -> &RingBuffer<'a, T> {
+        unsafe { self.rb.as_ref() }
+    }
 
-        println!("Sender::clone active: {}", *n);
+    fn new(rb: NonNull<RingBuffer<'a, T>>, generation: u16) -> Self {
+        unsafe { rb.as_ref() }.refs.fetch_add(1, order::HANDLE_UP);
 
-        unsafe {
-            Sender {
-                rb: UnsafeCell::new(*self.rb.get()),
-            }
+        Self {
+            rb,
+            generation,
+            #[cfg(feature = "stats")]
+            local: HandleCounters::default(),
         }
     }
-}
 
-impl<'a, T: Default + Copy> Clone for Receiver<'a, T> {
-    fn clone(&self) -> Self {
-        let mut n = unsafe { (*(*self.rb.get())).users.receivers.lock().unwrap() };
+    /// Enqueues `d`, returning `false` if the queue is full or the sender is
+    /// stale. What a full queue means depends on [`Builder::on_full`].
+    /// Elements sent by one thread are received in send order, see
+    /// [`RingBuffer`].
+    ///
+    /// With [`Builder::reserve_headroom`] the queue counts as full while
+    /// fewer than the reserved number of slots are free. That check uses the
+    /// approximate length, so under races a send may fail with a few more
+    /// slots free, or succeed with one fewer.
+    pub fn send(&self, d: T) -> bool {
+        self.try_send(d).is_ok()
+    }
 
-        assert!(*n > 0, "Number of receivers can't be zero");
+    /// Enqueues `d`, or hands it back saying why it could not be. A full
+    /// queue is handled as configured with [`Builder::on_full`].
+    pub fn try_send(&self, d: T) -> Result<(), TrySendError<T>> {
+        let on_full = self.rb().config.on_full;
 
-        *n += 1;
+        match on_full {
+            OnFull::Reject => {
+                let result = self.try_send_now(d);
 
-        println!("Receiver::clone active: {}", *n);
+                #[cfg(feature = "tracing")]
+                if let Err(TrySendError::Full(_)) = result {
+                    self.rb().trace_full();
+                }
 
-        unsafe {
-            Receiver {
-                rb: UnsafeCell::new(*self.rb.get()),
+                result
             }
+            OnFull::Block => self.send_blocking(d),
+            OnFull::Overwrite | OnFull::Evict => self.send_replace(d).map(|evicted| {
+                if let Some(d) = evicted {
+                    #[cfg(feature = "tracing")]
+                    self.rb().trace_full();
+
+                    if on_full == OnFull::Evict {
+                        self.rb().dead_letter(d);
+                    }
+                }
+            }),
         }
     }
-}
 
-impl<'a, T: Default + Copy> Receiver<'a, T> {
-    pub fn recv(&mut self) -> Result<T, bool> {
-        unsafe { (*(*self.rb.get())).recv() }
+    // try_send() as with OnFull::Reject.
+    pub(crate) fn try_send_now(&self, d: T) -> Result<(), TrySendError<T>> {
+        let generation = self.generation;
+        let result = self.rb().send(generation, false, d);
+
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
+
+        result
     }
 
-    pub fn empty(&mut self) -> bool {
-        unsafe { (*(*self.rb.get())).empty() }
+    /// Sends the elements of `iter` in order with [`Sender::try_send`] until
+    /// it runs out, and returns how many were sent. On the first failure,
+    /// e.g. once the queue is full, the error hands back that element and the
+    /// rest of the iterator, so nothing the iterator yielded is lost.
+    pub fn send_all<I: IntoIterator<Item = T>>(
+        &self,
+        iter: I,
+    ) -> Result<usize, SendAllError<T, I::IntoIter>> {
+        let mut rest = iter.into_iter();
+        let mut sent = 0;
+
+        for d in rest.by_ref() {
+            if let Err(error) = self.try_send(d) {
+                return Err(SendAllError { sent, error, rest });
+            }
+
+            sent += 1;
+        }
+
+        Ok(sent)
     }
 
-    pub fn capacity(&mut self) -> usize {
-        unsafe { (*(*self.rb.get())).capacity() }
+    /// Enqueues `d`, sleeping while the queue is full until a receiver makes
+    /// room. Only a send that found the queue full waits, so the uncontended
+    /// path costs what [`Sender::try_send`] does: the claim, the write, the
+    /// publish and one load to check for sleeping receivers.
+    ///
+    /// Fails as `try_send` does with [`TrySendError::Stale`],
+    /// [`TrySendError::Closed`] or [`TrySendError::Disconnected`], including
+    /// when that happens while it sleeps.
+    ///
+    /// # Panics
+    ///
+    /// If the queue is full in single-threaded mode, with the
+    /// `single-threaded` feature or on WebAssembly without threads: nothing
+    /// else could make room.
+    pub fn send_blocking(&self, d: T) -> Result<(), TrySendError<T>> {
+        self.send_within(d, None).map_err(|e| match e {
+            SendTimeoutError::Stale(d) => TrySendError::Stale(d),
+            SendTimeoutError::Closed(d) => TrySendError::Closed(d),
+            SendTimeoutError::Disconnected(d) => TrySendError::Disconnected(d),
+            SendTimeoutError::Timeout(d) => TrySendError::Full(d),
+        })
     }
-}
 
-impl<T: Default + Copy> Cell<T> {
-    pub fn new(i: u32) -> Cell<T> {
-        // println!("Cell::new({})", i);
+    /// Like [`Sender::send_blocking`], giving up once the queue has stayed
+    /// full for `timeout`; a zero timeout makes it a single try. Every error
+    /// hands `d` back.
+    pub fn send_timeout(&self, d: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.send_within(d, Instant::now().checked_add(timeout))
+    }
 
-        Self {
-            pos: AtomicU32::new(i),
-            data: Default::default(),
-        }
+    /// Like [`Sender::send_timeout`], giving up at `deadline`.
+    pub fn send_deadline(&self, d: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.send_within(d, Some(deadline))
     }
-}
 
-impl<'a, T: Default + Copy> RingBuffer<'a, T> {
-    fn send(&mut self, d: T) -> bool {
-        let mut pos = self.enq_pos.load(Ordering::Relaxed);
+    // Waits for room until `deadline`, or for good.
+    fn send_within(&self, mut d: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let generation = self.generation;
 
         loop {
-            let cell = &mut self.v[pos as usize & *self.n];
-            let seq = cell.pos.load(Ordering::Acquire);
-            let diff = seq as i32 - pos as i32;
-
-            if diff == 0 {
-                let new = pos + 1;
-
-                match self.enq_pos.compare_exchange_weak(
-                    pos,
-                    new,
-                    Ordering::Relaxed,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => {
-                        cell.data = UnsafeCell::new(d);
-                        cell.pos.store(new, Ordering::Release);
-                        return true;
-                    }
-                    Err(_) => (),
-                }
-            } else if diff < 0 {
-                return false;
-            } else {
-                pos = self.enq_pos.load(Ordering::Relaxed);
+            match self.try_send_now(d) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(back)) => d = back,
+                Err(TrySendError::Stale(d)) => return Err(SendTimeoutError::Stale(d)),
+                Err(TrySendError::Closed(d)) => return Err(SendTimeoutError::Closed(d)),
+                Err(TrySendError::Disconnected(d)) => {
+                    return Err(SendTimeoutError::Disconnected(d))
+                }
+            }
+
+            let rb = self.rb();
+
+            // Only after a last try, so an element that found room just in
+            // time is sent rather than handed back.
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(SendTimeoutError::Timeout(d));
             }
+
+            let ready = || {
+                rb.has_room()
+                    || rb.closed()
+                    || rb.generation() != generation
+                    || rb.users.receivers.load(order::HANDLE_LOAD) == 0
+            };
+
+            rb.send_waiters.wait_with(&rb.wait, deadline, ready);
         }
     }
 
-    fn recv(&mut self) -> Result<T, bool> {
-        let mut pos = self.deq_pos.load(Ordering::Relaxed);
+    // Sends `d` and waits until a receiver claims it, for a queue of one
+    // element built by Builder::rendezvous: with nothing else in the ring,
+    // the head moving past `pos` means it was ours that was taken. At
+    // `deadline`, or once the receivers are gone, it takes the element back
+    // the way a receiver would, unless one got there first.
+    pub(crate) fn send_handoff(
+        &self,
+        d: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        let generation = self.generation;
+        let rb = self.rb();
+        let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+        let pos = loop {
+            match rb.claim_tail(generation, false) {
+                Ok(pos) => break pos,
+                Err(TrySendError::Full(())) if !expired() => {
+                    let ready = || {
+                        rb.has_room()
+                            || rb.closed()
+                            || rb.generation() != generation
+                            || rb.users.receivers.load(order::HANDLE_LOAD) == 0
+                    };
+
+                    rb.send_waiters.wait_with(&rb.wait, deadline, ready);
+                }
+                Err(TrySendError::Full(())) => return Err(SendTimeoutError::Timeout(d)),
+                Err(TrySendError::Stale(())) => return Err(SendTimeoutError::Stale(d)),
+                Err(TrySendError::Closed(())) => return Err(SendTimeoutError::Closed(d)),
+                Err(TrySendError::Disconnected(())) => {
+                    return Err(SendTimeoutError::Disconnected(d))
+                }
+            }
+        };
+
+        unsafe { rb.slot(pos).write(d) };
+        rb.publish(pos);
+
+        let taken = || {
+            let (g, head) = unpack(rb.deq_pos.load(order::SNAPSHOT));
+
+            // A reset takes the element with the rest of the generation.
+            g != generation || head != pos & POS_MASK
+        };
 
         loop {
-            let cell = &mut self.v[pos as usize & *self.n];
-            let seq = cell.pos.load(Ordering::Acquire);
-            let diff = seq as i32 - (pos + 1) as i32;
-
-            if diff == 0 {
-                let new = pos + 1;
-
-                match self.deq_pos.compare_exchange_weak(
-                    pos,
-                    new,
-                    Ordering::Relaxed,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => {
-                        let d = *cell.data.get_mut();
-                        cell.pos.store(pos + *self.n as u32 + 1, Ordering::Release);
-                        return Ok(d);
-                    }
-                    Err(_) => (),
+            if taken() {
+                return Ok(());
+            }
+
+            let gone = rb.users.receivers.load(order::HANDLE_LOAD) == 0;
+
+            if gone || expired() {
+                let word = pack(generation, pos);
+
+                if rb
+                    .deq_pos
+                    .compare_exchange(
+                        word,
+                        pack(generation, pos.wrapping_add(1)),
+                        order::RING_CLAIM,
+                        order::CLAIM_FAILED,
+                    )
+                    .is_ok()
+                {
+                    let d = unsafe { rb.slot(pos).read() };
+
+                    rb.release(pos);
+
+                    return Err(if gone {
+                        SendTimeoutError::Disconnected(d)
+                    } else {
+                        SendTimeoutError::Timeout(d)
+                    });
                 }
-            } else if diff < 0 {
-                // Ring buffer is empty.
-                return Err(false);
-            } else {
-                pos = self.deq_pos.load(Ordering::Relaxed);
+
+                continue;
             }
+
+            // Receivers notify the senders as they release the slot.
+            rb.send_waiters.wait_with(&rb.wait, deadline, || {
+                taken() || rb.users.receivers.load(order::HANDLE_LOAD) == 0
+            });
         }
     }
 
-    pub fn empty(&self) -> bool {
-        let mut pos = self.deq_pos.load(Ordering::Relaxed);
+    /// Enqueues `d`, making room if the queue is full by removing the oldest
+    /// element and handing it back: `Ok(None)` after a normal send,
+    /// `Ok(Some(evicted))` after an eviction. The oldest element of the ring
+    /// is taken the way a receiver takes it, so it is never also delivered
+    /// to a receiver. If another sender fills the freed slot first, the
+    /// evicted element goes to the front of the redelivery queue, which
+    /// receivers drain before the ring, and the send starts over; a
+    /// receiver may then get it after elements sent later.
+    /// [`OnFull::Overwrite`] makes the plain sends do this.
+    ///
+    /// Fails only for a stale sender.
+    pub fn send_replace(&self, d: T) -> Result<Option<T>, TrySendError<T>> {
+        let generation = self.generation;
+        let result = self.rb().send_replace(generation, d);
 
-        loop {
-            let cell = &self.v[pos as usize & *self.n];
-            let seq = cell.pos.load(Ordering::Acquire);
-            let diff = seq as i32 - (pos + 1) as i32;
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
 
-            if diff == 0 {
-                return false;
-            } else if diff < 0 {
-                // Ring buffer is empty.
-                return true;
-            } else {
-                pos = self.deq_pos.load(Ordering::Relaxed);
+        result
+    }
+
+    /// Claims the slot at the tail for an element built in place, see
+    /// [`SendSlot`]. Receivers get it once the guard is dropped or
+    /// published.
+    ///
+    /// Fails as [`Sender::try_send`] does with [`OnFull::Reject`], whatever
+    /// [`Builder::on_full`] says.
+    pub fn claim(&self) -> Result<SendSlot<'_, T>, TrySendError<()>>
+    where
+        T: Default,
+    {
+        let generation = self.generation;
+        let rb = self.rb();
+        let result = rb
+            .claim_tail(generation, false)
+            .map(|pos| SendSlot::new(rb, pos));
+
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
+
+        result
+    }
+
+    /// Claims the next `n` slots at the tail with one CAS, for a burst of
+    /// elements built in place and published together, see [`SendSlots`].
+    ///
+    /// Fails with [`TrySendError::Full`] unless all `n` are free, so always
+    /// for `n` above the capacity, and otherwise as [`Sender::claim`] does.
+    pub fn claim_many(&self, n: usize) -> Result<SendSlots<'_, T>, TrySendError<()>>
+    where
+        T: Default,
+    {
+        let generation = self.generation;
+        let rb = self.rb();
+        let result = match u32::try_from(n) {
+            Ok(0) => Ok((0, 0)),
+            Ok(n) => rb.claim_run(generation, n, true),
+            Err(_) => Err(TrySendError::Full(())),
+        }
+        .map(|(pos, len)| SendSlots::new(rb, pos, len));
+
+        #[cfg(feature = "stats")]
+        {
+            if let Err(TrySendError::Full(())) = result {
+                rb.stats.on_send_failure();
             }
+
+            self.local.record(&result);
         }
+
+        result
+    }
+
+    /// Enqueues a prefix of `items`, claiming runs of free slots with one CAS
+    /// each, and returns its length. With `all_or_nothing` the whole slice is
+    /// claimed at once or nothing is sent, and the error says so.
+    ///
+    /// Fails with [`TrySendError::Full`] only if nothing was sent. A stale
+    /// sender fails with [`TrySendError::Stale`]. As with [`Sender::send`],
+    /// reserved headroom counts as full.
+    pub fn try_send_many(
+        &self,
+        items: &[T],
+        all_or_nothing: bool,
+    ) -> Result<usize, TrySendError<()>>
+    where
+        T: Copy,
+    {
+        let generation = self.generation;
+        let result = self.rb().send_many(generation, items, all_or_nothing);
+
+        #[cfg(feature = "stats")]
+        self.local.count(*result.as_ref().unwrap_or(&0));
+
+        result
+    }
+
+    // try_send_many() moving the elements out of the front of `buf`, for
+    // LocalSender.
+    pub(crate) fn send_from(&self, buf: &mut VecDeque<T>) -> Result<usize, TrySendError<()>> {
+        let generation = self.generation;
+        let result = self
+            .rb()
+            .send_runs(generation, buf.len(), false, || buf.pop_front().unwrap());
+
+        #[cfg(feature = "stats")]
+        self.local.count(*result.as_ref().unwrap_or(&0));
+
+        result
+    }
+
+    /// Enqueues as much of `items` as there is room for, in order, and
+    /// returns how many that was: [`Sender::try_send_many`] without the
+    /// error, 0 when the queue is full, closed or has no receivers.
+    pub fn send_batch(&self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        self.try_send_many(items, false).unwrap_or(0)
+    }
+
+    /// Creates a [`PrioritySender`] for the same queue, or returns `None` if
+    /// no sender may be added. See [`Sender::try_clone`].
+    pub fn try_clone_priority(&self) -> Option<PrioritySender<'a, T>> {
+        let senders = Users::acquire(&self.rb().users.senders)?;
+
+        self.rb().event(Event::SenderAdded { senders });
+
+        Some(PrioritySender::new(self.rb, self.generation))
+    }
+
+    pub fn empty(&self) -> bool {
+        self.rb().empty()
+    }
+
+    /// See [`RingBuffer::channel_id`].
+    pub fn channel_id(&self) -> ChannelId {
+        self.rb().channel_id()
+    }
+
+    /// Counts of this handle alone: elements it sent and calls that sent
+    /// nothing. A clone starts from zero. When the handle is dropped its
+    /// counts are added to [`RingBuffer::handle_totals`].
+    #[cfg(feature = "stats")]
+    pub fn local_stats(&self) -> HandleStats {
+        self.local.snapshot()
+    }
+
+    /// See [`RingBuffer::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> QueueStats {
+        self.rb().stats()
+    }
+
+    /// See [`RingBuffer::snapshot`].
+    pub fn snapshot(&self) -> Snapshot<T> {
+        self.rb().snapshot()
+    }
+
+    /// See [`RingBuffer::len`].
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.rb().len()
+    }
+
+    /// See [`RingBuffer::is_full`].
+    pub fn is_full(&self) -> bool {
+        self.rb().is_full()
+    }
+
+    /// See [`RingBuffer::remaining_capacity`].
+    pub fn remaining_capacity(&self) -> usize {
+        self.rb().remaining_capacity()
     }
 
     pub fn capacity(&self) -> usize {
-        *self.n
+        self.rb().capacity()
+    }
+
+    /// Blocks until the queue is less than `fraction` full, `timeout`
+    /// passes or the queue is closed. Returns whether the occupancy dropped
+    /// below the threshold.
+    ///
+    /// Occupancy is the approximate length, so a send right after this
+    /// returns may find the queue fuller again; it is meant for producers
+    /// that throttle themselves, e.g. pause above 75% and resume under 50%.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` is not within `0.0..=1.0`.
+    pub fn wait_below(&self, fraction: f32, timeout: Duration) -> bool {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "invalid fraction {}",
+            fraction
+        );
+
+        let rb = self.rb();
+
+        if rb.below(fraction) {
+            return true;
+        }
+
+        rb.send_waiters
+            .wait_with(&rb.wait, Instant::now().checked_add(timeout), || {
+                rb.below(fraction) || rb.closed()
+            });
+        rb.below(fraction)
+    }
+
+    /// The async version of [`Sender::wait_below`], without a timeout. It
+    /// also resolves once the queue is closed. Wakeups may be spurious; the
+    /// future re-checks on every poll.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` is not within `0.0..=1.0`.
+    #[cfg(feature = "async")]
+    pub fn until_below(&self, fraction: f32) -> UntilBelow<'_, 'a, T> {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "invalid fraction {}",
+            fraction
+        );
+
+        UntilBelow {
+            rb: self.rb(),
+            fraction,
+            polls: self.rb().wait.polls,
+        }
     }
 
-    pub fn new(n: usize) -> (Box<RingBuffer<'a, T>>, Sender<'a, T>, Receiver<'a, T>) {
-        assert!(n > 0, "size must be > 0");
+    /// Returns `Poll::Ready` once a send is worth trying: there is room, or
+    /// the send would fail for good. Otherwise the task is woken by the
+    /// next receive, disconnection or close. Wakeups may be spurious, and
+    /// another sender may take the room first.
+    #[cfg(feature = "async")]
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let rb = self.rb();
+        let generation = self.generation;
+        let ready = || {
+            rb.has_room()
+                || rb.closed()
+                || rb.users.receivers.load(order::HANDLE_LOAD) == 0
+                || rb.generation() != generation
+        };
 
-        let n = (n + 1).next_power_of_two();
-        let mut v: Vec<Cell<T>> = Vec::new();
+        if rb.send_waiters.poll_until(cx.waker(), ready) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 
-        for i in 0..n {
-            v.push(Cell::<T>::new(i as u32));
+    /// The async version of [`Sender::send_blocking`]. Dropping the future
+    /// before it resolves drops `d` unsent.
+    #[cfg(feature = "async")]
+    pub fn send_async(&self, d: T) -> SendFuture<'_, 'a, T> {
+        SendFuture {
+            s: self,
+            d: Some(d),
         }
+    }
 
-        let mut rb = Box::new(Self {
-            n: CachePadded::new(n - 1),
-            v: CachePadded::new(v),
-            enq_pos: CachePadded::new(AtomicU32::new(0)),
-            deq_pos: CachePadded::new(AtomicU32::new(0)),
-            users: CachePadded::new(Users::new(1, 1)),
-            _covariant : PhantomData,
-        });
+    /// Arms the [`Builder::on_writable`] hook for the next freed slot,
+    /// close or receiver disconnect. Call it after a send found the queue
+    /// full, and retry before waiting for the hook, see
+    /// [`ready`](crate::ready).
+    pub fn arm_writable(&self) {
+        self.rb().writable.arm();
+    }
 
-        let rb_ptr = &mut *rb as *mut RingBuffer<T>;
+    /// Closes the queue, for every handle: from then on every send fails
+    /// with [`TrySendError::Closed`], and receives take what is left and
+    /// then fail with [`TryRecvError::Closed`]. Blocked sends and receives
+    /// are woken to find out, and so is [`Sender::wait_below`]. A send that
+    /// races with the close may still land. Returns whether this call closed
+    /// the queue; closing it again does nothing.
+    ///
+    /// Closing marks the tail word that every claim loads anyway, so a send
+    /// only reads the flag once its claim has failed.
+    pub fn close(&self) -> bool {
+        self.rb().close()
+    }
 
-        (
-            rb,
-            Sender {
-                rb: UnsafeCell::new(rb_ptr),
-            },
-            Receiver {
-                rb: UnsafeCell::new(rb_ptr),
-            },
-        )
+    /// Closes the queue as [`Sender::close`] does and waits up to `timeout`
+    /// for the receivers to take what is left.
+    ///
+    /// The wait ends early when the last receiver goes away, see
+    /// [`ShutdownOutcome::Abandoned`].
+    pub fn shutdown(self, timeout: Duration) -> ShutdownOutcome<T> {
+        let rb = self.rb();
+
+        rb.close();
+        rb.send_waiters
+            .wait_with(&rb.wait, Instant::now().checked_add(timeout), || {
+                self.shut_down()
+            });
+
+        self.shutdown_outcome()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    /// The async version of [`Sender::shutdown`], without a timeout: it
+    /// resolves to [`ShutdownOutcome::Drained`] or
+    /// [`ShutdownOutcome::Abandoned`], so race it against a deadline of the
+    /// caller's choice. The queue is closed when this is called, not when
+    /// the future is first polled, and stays closed if the future is
+    /// dropped.
+    #[cfg(feature = "async")]
+    pub fn shutdown_async(self) -> Shutdown<'a, T> {
+        self.rb().close();
+
+        Shutdown { sender: Some(self) }
+    }
+
+    // Whether a shutdown has nothing more to wait for.
+    fn shut_down(&self) -> bool {
+        let rb = self.rb();
+
+        rb.empty() || rb.users.receivers.load(order::HANDLE_LOAD) == 0
+    }
+
+    fn shutdown_outcome(&self) -> ShutdownOutcome<T> {
+        let rb = self.rb();
+
+        if rb.empty() {
+            return ShutdownOutcome::Drained;
+        }
+
+        let remaining = rb.len() + rb.retry.len();
+
+        if rb.users.receivers.load(order::HANDLE_LOAD) > 0 {
+            return ShutdownOutcome::TimedOut { remaining };
+        }
+
+        // With no receiver left, handles only come back through
+        // reset_generation(), since every other way derives them from a
+        // live one: holding its lock keeps the sender count from growing
+        // while it decides who owns the rest, and the rest from being
+        // thrown away while it is taken.
+        #[cfg(test)]
+        hooks::lock();
+
+        let _reset = rb.reset.lock().unwrap_or_else(|e| e.into_inner());
+
+        if rb.generation() != self.generation {
+            // A reset got there first: what is left is a later
+            // generation's, not this sender's to take.
+            return ShutdownOutcome::Abandoned {
+                remaining,
+                leftover: None,
+            };
+        }
+
+        if rb.users.senders.load(order::HANDLE_LOAD) > 1 {
+            // No one will receive them: they are dead letters.
+            if rb.config.dead_letter.is_some() {
+                let generation = self.generation;
+
+                while let Ok(Some((d, _))) = rb.redeliver(generation) {
+                    rb.dead_letter(d);
+                }
+
+                while let Ok(d) = rb.recv(generation) {
+                    rb.dead_letter(d);
+                }
+            }
+
+            return ShutdownOutcome::Abandoned {
+                remaining,
+                leftover: None,
+            };
+        }
+
+        // No receiver, and no other sender: the queue is ours alone.
+        let generation = self.generation;
+        let mut leftover = Vec::new();
+
+        while let Ok(Some((d, _))) = rb.redeliver(generation) {
+            leftover.push(d);
+        }
+
+        while let Ok(d) = rb.recv(generation) {
+            leftover.push(d);
+        }
+
+        ShutdownOutcome::Abandoned {
+            remaining: leftover.len(),
+            leftover: Some(leftover),
+        }
+    }
+
+    /// Creates another sender, or returns `None` if no sender may be added.
+    ///
+    /// The count is raised with a CAS loop that never moves it up from zero,
+    /// so once every sender of a channel is gone a racing clone cannot bring
+    /// the side back.
+    pub fn try_clone(&self) -> Option<Sender<'a, T>> {
+        let senders = Users::acquire(&self.rb().users.senders)?;
+
+        self.rb().event(Event::SenderAdded { senders });
+
+        Some(Sender::new(self.rb, self.generation))
+    }
+
+    /// Creates a receiver for the same queue even if every receiver is gone,
+    /// which brings the channel back: sends fail with
+    /// [`TrySendError::Disconnected`] only until then. Returns `None` if the
+    /// sender is stale or the count would overflow.
+    pub fn new_receiver(&self) -> Option<Receiver<'a, T>> {
+        if self.rb().generation() != self.generation {
+            return None;
+        }
+
+        let receivers = Users::revive(&self.rb().users.receivers)?;
+
+        if receivers == 1 {
+            self.rb().admit_sends();
+        }
+
+        self.rb().event(Event::ReceiverAdded { receivers });
+
+        Some(Receiver::new(self.rb, self.generation))
+    }
+}
+
+/// Future returned by [`Sender::until_below`].
+#[cfg(feature = "async")]
+pub struct UntilBelow<'s, 'a, T> {
+    rb: &'s RingBuffer<'a, T>,
+    fraction: f32,
+    // Self-wakes left before the waker is registered.
+    polls: u32,
+}
+
+#[cfg(feature = "async")]
+impl<'s, 'a, T> Future for UntilBelow<'s, 'a, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let (rb, fraction) = (self.rb, self.fraction);
+
+        if self.polls > 0 {
+            if rb.below(fraction) || rb.closed() {
+                return Poll::Ready(());
+            }
+
+            self.polls -= 1;
+            cx.waker().wake_by_ref();
+
+            return Poll::Pending;
+        }
+
+        if rb
+            .send_waiters
+            .poll_until(cx.waker(), || rb.below(fraction) || rb.closed())
+        {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Future returned by [`Sender::shutdown_async`].
+#[cfg(feature = "async")]
+pub struct Shutdown<'a, T> {
+    // Taken once the future resolves.
+    sender: Option<Sender<'a, T>>,
+}
+
+#[cfg(feature = "async")]
+impl<'a, T> Future for Shutdown<'a, T> {
+    type Output = ShutdownOutcome<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ShutdownOutcome<T>> {
+        let s = self.sender.as_ref().expect("polled after completion");
+
+        if !s.rb().send_waiters.poll_until(cx.waker(), || s.shut_down()) {
+            return Poll::Pending;
+        }
+
+        let s = self.sender.take().unwrap();
+
+        Poll::Ready(s.shutdown_outcome())
+    }
+}
+
+/// Iterator returned by [`Receiver::drain`].
+pub struct Drain<'r, 'a, T> {
+    // Cleared once the queue was seen empty.
+    r: Option<&'r Receiver<'a, T>>,
+}
+
+impl<'r, 'a, T> Iterator for Drain<'r, 'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let d = self.r?.try_recv().ok();
+
+        if d.is_none() {
+            self.r = None;
+        }
+
+        d
+    }
+}
+
+impl<'r, 'a, T> FusedIterator for Drain<'r, 'a, T> {}
+
+/// Iterator returned by [`Receiver::try_iter`].
+pub struct TryIter<'r, 'a, T> {
+    r: &'r Receiver<'a, T>,
+}
+
+impl<'r, 'a, T> Iterator for TryIter<'r, 'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.r.try_recv().ok()
+    }
+}
+
+/// Iterator returned by [`Receiver::iter`].
+pub struct Iter<'r, 'a, T> {
+    r: &'r Receiver<'a, T>,
+}
+
+impl<'r, 'a, T> Iterator for Iter<'r, 'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.r.recv_blocking().ok()
+    }
+}
+
+impl<'r, 'a, T> FusedIterator for Iter<'r, 'a, T> {}
+
+/// Iterator returned by `Receiver::into_iter`, blocking as
+/// [`Receiver::iter`] does.
+pub struct IntoIter<'a, T> {
+    r: Receiver<'a, T>,
+}
+
+impl<'a, T> Iterator for IntoIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.r.recv_blocking().ok()
+    }
+}
+
+impl<'a, T> FusedIterator for IntoIter<'a, T> {}
+
+/// Sends with [`Sender::send_blocking`], so it sleeps while the queue is
+/// full. It stops at the first element that can't ever be sent, dropping
+/// that one and not consuming the rest; use [`Sender::send_all`] to get them
+/// back.
+impl<'a, T> Extend<T> for Sender<'a, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for d in iter {
+            if self.send_blocking(d).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for Receiver<'a, T> {
+    type Item = T;
+    type IntoIter = IntoIter<'a, T>;
+
+    fn into_iter(self) -> IntoIter<'a, T> {
+        IntoIter { r: self }
+    }
+}
+
+impl<'r, 'a, T> IntoIterator for &'r mut Receiver<'a, T> {
+    type Item = T;
+    type IntoIter = Iter<'r, 'a, T>;
+
+    fn into_iter(self) -> Iter<'r, 'a, T> {
+        self.iter()
+    }
+}
+
+/// Future returned by [`Sender::send_async`].
+#[cfg(feature = "async")]
+pub struct SendFuture<'s, 'a, T> {
+    s: &'s Sender<'a, T>,
+    // Taken once sent.
+    d: Option<T>,
+}
+
+// The element is never pinned.
+#[cfg(feature = "async")]
+impl<'s, 'a, T> Unpin for SendFuture<'s, 'a, T> {}
+
+#[cfg(feature = "async")]
+impl<'s, 'a, T> Future for SendFuture<'s, 'a, T> {
+    type Output = Result<(), TrySendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        for _ in 0..2 {
+            let d = this.d.take().expect("polled after completion");
+
+            match this.s.try_send(d) {
+                Err(TrySendError::Full(d)) => this.d = Some(d),
+                result => return Poll::Ready(result),
+            }
+
+            if this.s.poll_ready(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        // As in Receiver::poll_recv(): the free slot is still being read.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Future returned by [`Receiver::recv_async`].
+#[cfg(feature = "async")]
+pub struct RecvFuture<'r, 'a, T> {
+    r: &'r Receiver<'a, T>,
+}
+
+#[cfg(feature = "async")]
+impl<'r, 'a, T> Future for RecvFuture<'r, 'a, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.r.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a, T> futures_core::Stream for Receiver<'a, T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<'a, T> Clone for Sender<'a, T> {
+    /// # Panics
+    ///
+    /// If [`Sender::try_clone`] fails, which can't happen while `self` keeps
+    /// the sender count above zero unless the count would overflow `u32`.
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("sender count is zero or would overflow")
+    }
+}
+
+impl<'a, T> PrioritySender<'a, T> {
+    fn rb(&self) -> &RingBuffer<'a, T> {
+        unsafe { self.rb.as_ref() }
+    }
+
+    fn new(rb: NonNull<RingBuffer<'a, T>>, generation: u16) -> Self {
+        unsafe { rb.as_ref() }.refs.fetch_add(1, order::HANDLE_UP);
+
+        Self {
+            rb,
+            generation,
+            #[cfg(feature = "stats")]
+            local: HandleCounters::default(),
+        }
+    }
+
+    /// Enqueues `d` using the whole capacity, headroom included, returning
+    /// `false` if the queue is full or the sender is stale. With
+    /// [`Builder::lane_weights`] it goes into the priority lane instead.
+    pub fn send_reserved(&self, d: T) -> bool {
+        self.try_send_reserved(d).is_ok()
+    }
+
+    /// Like [`PrioritySender::send_reserved`], handing `d` back on failure.
+    pub fn try_send_reserved(&self, d: T) -> Result<(), TrySendError<T>> {
+        let generation = self.generation;
+        let result = self.rb().send_priority(generation, d);
+
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
+
+        result
+    }
+
+    /// See [`RingBuffer::channel_id`].
+    pub fn channel_id(&self) -> ChannelId {
+        self.rb().channel_id()
+    }
+
+    /// See [`Sender::local_stats`].
+    #[cfg(feature = "stats")]
+    pub fn local_stats(&self) -> HandleStats {
+        self.local.snapshot()
+    }
+
+    /// Creates another priority sender. See [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<PrioritySender<'a, T>> {
+        let senders = Users::acquire(&self.rb().users.senders)?;
+
+        self.rb().event(Event::SenderAdded { senders });
+
+        Some(PrioritySender::new(self.rb, self.generation))
+    }
+}
+
+impl<'a, T> Clone for PrioritySender<'a, T> {
+    /// # Panics
+    ///
+    /// If [`PrioritySender::try_clone`] fails, see [`Sender::clone`].
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("sender count is zero or would overflow")
+    }
+}
+
+impl<'a, T> Clone for Receiver<'a, T> {
+    /// # Panics
+    ///
+    /// If [`Receiver::try_clone`] fails, which can't happen while `self`
+    /// keeps the receiver count above zero unless the count would overflow
+    /// `u32`.
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("receiver count is zero or would overflow")
+    }
+}
+
+impl<'a, T> Receiver<'a, T> {
+    pub(crate) fn rb(&self) -> &RingBuffer<'a, T> {
+        unsafe { self.rb.as_ref() }
+    }
+
+    fn new(rb: NonNull<RingBuffer<'a, T>>, generation: u16) -> Self {
+        unsafe { rb.as_ref() }.refs.fetch_add(1, order::HANDLE_UP);
+
+        Self {
+            rb,
+            generation,
+            deficit: AtomicU64::new(0),
+            #[cfg(feature = "stats")]
+            local: HandleCounters::default(),
+        }
+    }
+
+    /// Dequeues an element. The error is `false` if the queue is empty and
+    /// `true` if the receiver is stale, which is permanent.
+    pub fn recv(&self) -> Result<T, bool> {
+        self.try_recv().map_err(|e| e == TryRecvError::Stale)
+    }
+
+    /// Dequeues an element, or says why there is none. Elements put back
+    /// with [`Receiver::unrecv`] or by an unacknowledged [`AckGuard`] come
+    /// first. Once every sender is gone the elements already sent are still
+    /// received, then it fails with [`TryRecvError::Disconnected`].
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let generation = self.generation;
+        let result = match self.rb().redeliver(generation) {
+            Ok(Some((d, _))) => Ok(d),
+            Ok(None) => {
+                let mut deficit = Deficit::load(&self.deficit);
+                let result = self.rb().recv_lanes(generation, &mut deficit);
+
+                deficit.store(&self.deficit);
+                result
+            }
+            Err(e) => Err(e),
+        }
+        .map_err(|e| self.rb().nothing(e));
+
+        #[cfg(feature = "stats")]
+        self.record(&result);
+
+        result
+    }
+
+    #[cfg(feature = "stats")]
+    fn record<R>(&self, result: &Result<R, TryRecvError>) {
+        self.local.record(result);
+
+        if let Err(TryRecvError::Empty | TryRecvError::Disconnected | TryRecvError::Closed) = result
+        {
+            self.rb().stats.on_recv_failure();
+        }
+    }
+
+    /// Dequeues an element, sleeping while the queue is empty until a sender
+    /// publishes one. As with [`Sender::send_blocking`], only a receive that
+    /// found the queue empty touches the waiters.
+    ///
+    /// Fails with [`TryRecvError::Stale`] for a stale receiver, and with
+    /// [`TryRecvError::Disconnected`] or [`TryRecvError::Closed`] once every
+    /// sender is gone or the queue is closed, and it is empty. The last
+    /// sender to go, or the close, wakes it.
+    ///
+    /// # Panics
+    ///
+    /// If the queue is empty in single-threaded mode, as
+    /// [`Sender::send_blocking`] does when it is full.
+    pub fn recv_blocking(&self) -> Result<T, TryRecvError> {
+        self.recv_within(None).map_err(|e| match e {
+            RecvTimeoutError::Stale => TryRecvError::Stale,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+            RecvTimeoutError::Closed => TryRecvError::Closed,
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+        })
+    }
+
+    /// Like [`Receiver::recv_blocking`], giving up once the queue has stayed
+    /// empty for `timeout`; a zero timeout makes it a single try.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_within(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`Receiver::recv_timeout`], giving up at `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_within(Some(deadline))
+    }
+
+    // Waits for an element until `deadline`, or for good.
+    fn recv_within(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let generation = self.generation;
+
+        loop {
+            match self.try_recv() {
+                Ok(d) => return Ok(d),
+                Err(TryRecvError::Stale) => return Err(RecvTimeoutError::Stale),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Closed) => return Err(RecvTimeoutError::Closed),
+                Err(TryRecvError::Empty) => (),
+            }
+
+            let rb = self.rb();
+
+            // As in send_within(), after a last try.
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            let ready = || !rb.empty() || rb.disconnected() || rb.generation() != generation;
+            let pending = || rb.claimed();
+
+            rb.recv_waiters
+                .wait_claimed(&rb.wait, deadline, ready, pending);
+        }
+    }
+
+    /// Like [`Receiver::try_recv`], also telling an empty queue apart from
+    /// one whose head element is claimed by a sender that has not finished
+    /// publishing it. The latter is only a snapshot: by the time the caller
+    /// acts on it the element may be there, or gone to another receiver.
+    pub fn try_recv_detailed(&self) -> Result<T, RecvProbe> {
+        let generation = self.generation;
+        let result = match self.rb().redeliver(generation) {
+            Ok(Some((d, _))) => Ok(d),
+            Ok(None) => self.rb().recv_probe(generation),
+            Err(e) => Err(e.into()),
+        }
+        .map_err(|e| match e {
+            RecvProbe::Empty => self.rb().nothing(TryRecvError::Empty).into(),
+            e => e,
+        });
+
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
+
+        result
+    }
+
+    /// Dequeues an element that is redelivered unless the returned guard is
+    /// acknowledged, see [`AckGuard`]. Elements waiting for redelivery are
+    /// handed out first.
+    pub fn recv_ack(&self) -> Result<AckGuard<'_, T>, TryRecvError> {
+        let generation = self.generation;
+        let result = match self.rb().redeliver(generation) {
+            Ok(Some(d)) => Ok(d),
+            Ok(None) => self.rb().recv(generation).map(|d| (d, 0)),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| self.rb().nothing(e));
+
+        #[cfg(feature = "stats")]
+        self.local.record(&result);
+
+        let (d, redeliveries) = result?;
+
+        Ok(AckGuard::new(self.rb(), generation, d, redeliveries))
+    }
+
+    /// Dequeues an element and lends it out where it lies in the ring,
+    /// rather than moving it out, see [`RecvSlot`]. Otherwise like
+    /// [`Receiver::try_recv`]. Elements waiting for redelivery, and with
+    /// [`Builder::lane_weights`] every element, are moved out after all.
+    pub fn recv_ref(&self) -> Result<RecvSlot<'_, T>, TryRecvError> {
+        let rb = self.rb();
+
+        if rb.config.lanes.is_some() {
+            return self.try_recv().map(|d| RecvSlot::owned(rb, d));
+        }
+
+        let generation = self.generation;
+        let result = match rb.redeliver(generation) {
+            Ok(Some((d, _))) => Ok(RecvSlot::owned(rb, d)),
+            Ok(None) => rb
+                .claim_head(generation)
+                .map(|pos| RecvSlot::new(rb, pos))
+                .map_err(TryRecvError::from),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| rb.nothing(e));
+
+        #[cfg(feature = "stats")]
+        self.record(&result);
+
+        result
+    }
+
+    /// Dequeues the head element only if `pred` accepts it, returning
+    /// `Ok(None)` and leaving the queue alone otherwise.
+    ///
+    /// The head is copied and checked before it is claimed. If another
+    /// receiver takes it in between, `pred` is tried on the new head once
+    /// more; if that one is taken too, the result is `Ok(None)` as well, so
+    /// `None` means nothing was dequeued, not necessarily that the head was
+    /// rejected.
+    pub fn recv_if(&self, mut pred: impl FnMut(&T) -> bool) -> Result<Option<T>, TryRecvError>
+    where
+        T: Copy,
+    {
+        let generation = self.generation;
+        let result = match self.rb().redeliver_if(generation, &mut pred) {
+            Ok(Some(head)) => Ok(head.map(|(d, _)| d)),
+            Ok(None) => self.rb().recv_if(generation, pred),
+            Err(e) => Err(e),
+        };
+
+        #[cfg(feature = "stats")]
+        self.local.count(matches!(result, Ok(Some(_))) as usize);
+
+        result
+    }
+
+    /// A copy of the head element, leaving it queued.
+    ///
+    /// Only advisory with other receivers around: one of them may take the
+    /// element before the next receive here. The copy is never torn, though:
+    /// it is read from a published slot and checked against the slot's
+    /// sequence number afterwards, as [`Receiver::recv_if`] does.
+    pub fn peek(&self) -> Result<T, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.peek_with(|d| *d)
+    }
+
+    /// Runs `f` on the head element, leaving it queued, see
+    /// [`Receiver::peek`]. `f` is given the checked copy.
+    pub fn peek_with<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, TryRecvError>
+    where
+        T: Copy,
+    {
+        let head = self
+            .rb()
+            .peek(self.generation)
+            .map_err(|e| self.rb().nothing(e))?;
+
+        Ok(f(&head))
+    }
+
+    /// Moves head elements into `buf` for as long as `pred` accepts them,
+    /// stopping at the first one it rejects, which stays queued, or when the
+    /// queue is empty. Returns how many elements were added.
+    ///
+    /// Runs of published elements are peeked and then claimed with a single
+    /// CAS. If another receiver claims part of a run first, the new head is
+    /// peeked again, so `pred` may see an element more than once.
+    pub fn drain_while(&self, pred: impl FnMut(&T) -> bool, buf: &mut Vec<T>) -> usize
+    where
+        T: Copy,
+    {
+        let n = self.drain_up_to(Peeking(pred), buf, usize::MAX);
+
+        #[cfg(feature = "stats")]
+        self.local.count(n);
+
+        n
+    }
+
+    /// Discards up to `n` elements without copying them out and returns how
+    /// many were discarded, fewer if the queue runs empty. Elements waiting
+    /// for redelivery go first, as with [`Receiver::try_recv`]; runs of
+    /// published elements are then claimed with a single CAS each, so this
+    /// is much cheaper than `n` receives.
+    pub fn skip(&self, n: usize) -> usize {
+        let generation = self.generation;
+        let mut skipped = 0;
+        let mut stale = false;
+
+        while skipped < n {
+            match self.rb().redeliver(generation) {
+                Ok(Some(_)) => skipped += 1,
+                Ok(None) => break,
+                Err(_) => {
+                    stale = true;
+                    break;
+                }
+            }
+        }
+
+        if !stale {
+            skipped += self.rb().skip(generation, n - skipped).0;
+        }
+
+        #[cfg(feature = "stats")]
+        self.local.count(skipped);
+
+        skipped
+    }
+
+    /// Discards every element available now and returns the newest one,
+    /// for streams where only the latest state matters. Runs of published
+    /// elements are claimed with one CAS each and only the last element of
+    /// a run is copied.
+    ///
+    /// Other receivers keep competing for elements as usual: "newest" is the
+    /// newest of those this call claimed, and an element sent meanwhile may
+    /// go to another receiver or be left for the next call. At most one lap
+    /// of the ring is taken, so a fast producer cannot keep the call going.
+    /// Elements waiting for redelivery are older than the ring and are
+    /// discarded first.
+    pub fn recv_latest(&self) -> Result<T, TryRecvError> {
+        let generation = self.generation;
+        let mut latest = None;
+        let mut discarded = 0;
+
+        while let Some((d, _)) = self.rb().redeliver(generation)? {
+            latest = Some(d);
+            discarded += 1;
+        }
+
+        let rb = self.rb();
+        let (skipped, last) = rb.skip(generation, rb.slots() as usize);
+        let claimed = discarded + skipped;
+
+        #[cfg(feature = "stats")]
+        self.local.count(claimed);
+
+        // Something was claimed exactly when there is something to return.
+        let newest = last.or(latest);
+
+        debug_assert_eq!(claimed > 0, newest.is_some());
+
+        match newest {
+            Some(d) => Ok(d),
+            None if rb.generation() != generation => Err(TryRecvError::Stale),
+            None => Err(rb.nothing(TryRecvError::Empty)),
+        }
+    }
+
+    /// An iterator receiving with [`Receiver::try_recv`]: it ends when the
+    /// queue is empty, and may yield more if called again after that.
+    pub fn try_iter(&self) -> TryIter<'_, 'a, T> {
+        TryIter { r: self }
+    }
+
+    /// An iterator receiving with [`Receiver::recv_blocking`]: it sleeps
+    /// while the queue is empty and ends once every sender is gone or the
+    /// queue is closed and it is empty, or the receiver goes stale.
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        Iter { r: self }
+    }
+
+    /// Receives an element, or registers the task to be woken by the next
+    /// send, disconnection or close and returns `Poll::Pending`. Resolves to
+    /// `None` when [`Receiver::recv_blocking`] would fail. Wakeups may be
+    /// spurious.
+    #[cfg(feature = "async")]
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let generation = self.generation;
+
+        for _ in 0..2 {
+            match self.try_recv() {
+                Ok(d) => return Poll::Ready(Some(d)),
+                Err(TryRecvError::Empty) => (),
+                Err(_) => return Poll::Ready(None),
+            }
+
+            let rb = self.rb();
+            // A task cannot sleep a little, so a pending claim counts.
+            let ready = || {
+                !rb.empty() || rb.claimed() || rb.disconnected() || rb.generation() != generation
+            };
+
+            if !rb.recv_waiters.poll_until(cx.waker(), ready) {
+                return Poll::Pending;
+            }
+        }
+
+        // An element is claimed but not yet published: try again on the
+        // next poll rather than spin here.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    /// The async version of [`Receiver::recv_blocking`], resolving to `None`
+    /// where it fails.
+    #[cfg(feature = "async")]
+    pub fn recv_async(&self) -> RecvFuture<'_, 'a, T> {
+        RecvFuture { r: self }
+    }
+
+    /// Receives elements until the queue is seen empty, for shutdown
+    /// paths. Each element still goes to exactly one receiver, so what
+    /// other receivers take meanwhile is not yielded. Elements sent while
+    /// draining are yielded as long as the queue does not run empty first;
+    /// once every sender is gone, a drain leaves the queue empty.
+    pub fn drain(&self) -> Drain<'_, 'a, T> {
+        Drain { r: Some(self) }
+    }
+
+    /// Up to `n` available elements, oldest first, without waiting, as
+    /// [`Receiver::recv_batch`] takes them.
+    pub fn recv_n(&self, n: usize) -> Vec<T> {
+        let mut v = Vec::new();
+
+        self.recv_batch(&mut v, n);
+        v
+    }
+
+    /// Moves everything available into `v` as [`Receiver::drain`] does, with
+    /// batch claims, and returns how many elements that was.
+    pub fn drain_into(&self, v: &mut Vec<T>) -> usize {
+        self.recv_batch(v, usize::MAX)
+    }
+
+    /// Appends up to `max` available elements to `buf`, oldest first,
+    /// without waiting, and returns how many were added. Elements waiting
+    /// for redelivery go first; runs of published elements are then claimed
+    /// with a single CAS each, as in [`Receiver::drain_while`].
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> usize {
+        let n = self.drain_up_to(All, buf, max);
+
+        #[cfg(feature = "stats")]
+        self.local.count(n);
+
+        n
+    }
+
+    /// Receives at least `min` and at most `max` elements into `buf`,
+    /// sleeping for up to `wait` until `min` are available, and returns how
+    /// many were added. Fewer than `min` are returned only when `wait` runs
+    /// out, when every sender is gone and the queue is empty, or when the
+    /// receiver is stale.
+    ///
+    /// Each wakeup takes whatever is available, up to `max`, with batch
+    /// claims as in [`Receiver::drain_while`].
+    ///
+    /// # Panics
+    ///
+    /// If `min > max`.
+    pub fn recv_batch_blocking(
+        &self,
+        min: usize,
+        max: usize,
+        wait: Duration,
+        buf: &mut Vec<T>,
+    ) -> usize {
+        assert!(min <= max, "min must be <= max");
+
+        let deadline = Instant::now().checked_add(wait);
+        let generation = self.generation;
+        let mut n = 0;
+
+        let n = loop {
+            n += self.drain_up_to(All, buf, max - n);
+
+            if n >= min || n == max {
+                break n;
+            }
+
+            let rb = self.rb();
+            let ready = || !rb.empty() || rb.disconnected() || rb.generation() != generation;
+            let pending = || rb.claimed();
+
+            if !rb
+                .recv_waiters
+                .wait_claimed(&rb.wait, deadline, ready, pending)
+            {
+                break n + self.drain_up_to(All, buf, max - n);
+            }
+
+            if rb.generation() != generation || (rb.disconnected() && rb.empty()) {
+                // Nothing more will come.
+                break n;
+            }
+        };
+
+        #[cfg(feature = "stats")]
+        self.local.count(n);
+
+        n
+    }
+
+    /// Receives up to `max` elements into `buf`, waiting for more until
+    /// `deadline`, and says why it stopped. Returns as soon as `max` are
+    /// received; a deadline that has already passed makes it a single
+    /// non-blocking sweep.
+    ///
+    /// Unlike [`Receiver::recv_batch_blocking`] there is no minimum: a frame
+    /// takes whatever arrived by its deadline.
+    pub fn recv_batch_deadline(
+        &self,
+        deadline: Instant,
+        max: usize,
+        buf: &mut Vec<T>,
+    ) -> BatchResult {
+        let generation = self.generation;
+        let mut n = 0;
+
+        let end = loop {
+            n += self.drain_up_to(All, buf, max - n);
+
+            if n == max {
+                break BatchEnd::Max;
+            }
+
+            let rb = self.rb();
+
+            if rb.generation() != generation || (rb.disconnected() && rb.empty()) {
+                break BatchEnd::Disconnected;
+            }
+
+            if Instant::now() >= deadline {
+                break BatchEnd::Deadline;
+            }
+
+            let ready = || !rb.empty() || rb.disconnected() || rb.generation() != generation;
+            let pending = || rb.claimed();
+
+            rb.recv_waiters
+                .wait_claimed(&rb.wait, Some(deadline), ready, pending);
+        };
+
+        #[cfg(feature = "stats")]
+        self.local.count(n);
+
+        BatchResult { received: n, end }
+    }
+
+    // drain_while() taking at most `limit` elements.
+    fn drain_up_to(&self, mut filter: impl Filter<T>, buf: &mut Vec<T>, limit: usize) -> usize {
+        let generation = self.generation;
+        let mut n = 0;
+
+        while n < limit {
+            match self.rb().redeliver_if(generation, |d| filter.accepts(d)) {
+                Ok(Some(Some((d, _)))) => {
+                    buf.push(d);
+                    n += 1;
+                }
+                Ok(Some(None)) | Err(_) => return n,
+                Ok(None) => break,
+            }
+        }
+
+        if n == limit {
+            return n;
+        }
+
+        n + self.rb().drain_while(generation, filter, buf, limit - n)
+    }
+
+    /// Puts `d` back for the next receive of any receiver. It goes to the
+    /// redelivery queue, which is unbounded and drained before the ring, so
+    /// this never fails, not even on a full queue, but `d` loses its place
+    /// in the send order. A stale receiver's element is discarded along with
+    /// the rest of its generation.
+    pub fn unrecv(&self, d: T) {
+        self.rb().requeue(self.generation, d, 0);
+    }
+
+    /// Returns the dequeue position of this receiver's cursor. Receivers of
+    /// the same queue compete for elements and share one cursor, so this is
+    /// the queue-wide dequeue position with the guarantees documented on
+    /// [`RingBuffer::positions`].
+    pub fn position(&self) -> u64 {
+        self.rb().positions().1
+    }
+
+    /// Closes the queue as [`Sender::close`] does, e.g. for a consumer that
+    /// stops early and wants producers to find out instead of filling the
+    /// queue.
+    pub fn close(&self) -> bool {
+        self.rb().close()
+    }
+
+    /// Arms the [`Builder::on_readable`] hook for the next element, close or
+    /// disconnect. Call it before receiving until the queue is empty, see
+    /// [`ready`](crate::ready).
+    pub fn arm_readable(&self) {
+        self.rb().readable.arm();
+    }
+
+    /// Creates another receiver, or returns `None` if no receiver may be
+    /// added. See [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<Receiver<'a, T>> {
+        let receivers = Users::acquire(&self.rb().users.receivers)?;
+
+        self.rb().event(Event::ReceiverAdded { receivers });
+
+        Some(Receiver::new(self.rb, self.generation))
+    }
+
+    /// Creates a sender for the same queue even if every sender is gone, for
+    /// a producer that is restarted. This brings the channel back: receivers
+    /// that saw [`TryRecvError::Disconnected`] get elements again, and the
+    /// blocking receives wait again. Returns `None` if the receiver is stale
+    /// or the count would overflow.
+    pub fn new_sender(&self) -> Option<Sender<'a, T>> {
+        if self.rb().generation() != self.generation {
+            return None;
+        }
+
+        let senders = Users::revive(&self.rb().users.senders)?;
+
+        self.rb().event(Event::SenderAdded { senders });
+
+        Some(Sender::new(self.rb, self.generation))
+    }
+
+    pub fn empty(&self) -> bool {
+        self.rb().empty()
+    }
+
+    /// See [`RingBuffer::len`].
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.rb().len()
+    }
+
+    /// See [`RingBuffer::is_full`].
+    pub fn is_full(&self) -> bool {
+        self.rb().is_full()
+    }
+
+    /// See [`RingBuffer::remaining_capacity`].
+    pub fn remaining_capacity(&self) -> usize {
+        self.rb().remaining_capacity()
+    }
+
+    /// See [`RingBuffer::channel_id`].
+    pub fn channel_id(&self) -> ChannelId {
+        self.rb().channel_id()
+    }
+
+    /// See [`Sender::local_stats`].
+    #[cfg(feature = "stats")]
+    pub fn local_stats(&self) -> HandleStats {
+        self.local.snapshot()
+    }
+
+    /// See [`RingBuffer::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> QueueStats {
+        self.rb().stats()
+    }
+
+    /// See [`RingBuffer::snapshot`].
+    pub fn snapshot(&self) -> Snapshot<T> {
+        self.rb().snapshot()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rb().capacity()
+    }
+}
+
+impl<T> Cell<T> {
+    pub fn new(i: u64) -> Cell<T> {
+        Self {
+            pos: sync::AtomicU64::new(i),
+            data: sync::UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    // Seqlock read of the payload of a slot seen with sequence number `seq`:
+    // copies it, then checks that the slot was not recycled meanwhile. The
+    // copy can race with a sender rewriting the slot, so it stays
+    // uninterpreted until the check passes.
+    fn peek(&self, seq: u64) -> Option<T>
+    where
+        T: Copy,
+    {
+        let d = self.data.with(|d| unsafe { ptr::read_volatile(d) });
+
+        atomic::fence(order::PEEK);
+
+        if self.pos.load(order::PEEK_CHECK) != seq {
+            return None;
+        }
+
+        Some(unsafe { d.assume_init() })
+    }
+}
+
+// Which head elements a drain takes. Judging an element still in the ring
+// means peeking it, which only a `Copy` payload allows.
+trait Filter<T> {
+    fn accepts(&mut self, d: &T) -> bool;
+
+    // `None` if the slot was recycled under the peek.
+    fn accepts_slot(&mut self, cell: &Cell<T>, seq: u64) -> Option<bool>;
+}
+
+// Takes everything, without peeking.
+struct All;
+
+struct Peeking<F>(F);
+
+impl<T> Filter<T> for All {
+    fn accepts(&mut self, _: &T) -> bool {
+        true
+    }
+
+    fn accepts_slot(&mut self, _: &Cell<T>, _: u64) -> Option<bool> {
+        Some(true)
+    }
+}
+
+impl<T: Copy, F: FnMut(&T) -> bool> Filter<T> for Peeking<F> {
+    fn accepts(&mut self, d: &T) -> bool {
+        (self.0)(d)
+    }
+
+    fn accepts_slot(&mut self, cell: &Cell<T>, seq: u64) -> Option<bool> {
+        cell.peek(seq).map(|d| (self.0)(&d))
+    }
+}
+
+impl<'a, T> RingBuffer<'a, T> {
+    fn send(&self, generation: u16, reserved: bool, d: T) -> Result<(), TrySendError<T>> {
+        self.enqueue(generation, reserved, d)
+    }
+
+    // Hands `d` back if no send can succeed however much room there is: the
+    // queue is closed, or no receiver is left to take what is sent. Only the
+    // priority lane needs to ask, having no handles of its own to refuse
+    // sends by; the claims of the queue itself find out from REFUSING.
+    fn admit<D>(&self, d: D) -> Result<D, TrySendError<D>> {
+        if self.closed() {
+            Err(TrySendError::Closed(d))
+        } else if self.users.receivers.load(order::HANDLE_LOAD) == 0 {
+            Err(TrySendError::Disconnected(d))
+        } else {
+            Ok(d)
+        }
+    }
+
+    // The slow path of a claim by a sender of `generation` that found `g`
+    // in enq_pos instead: the error to fail with, or None to claim again if
+    // REFUSING was left over from receivers that are back.
+    fn refusal(&self, g: u16, generation: u16) -> Option<TrySendError<()>> {
+        if g != generation | REFUSING {
+            Some(TrySendError::Stale(()))
+        } else if self.closed() {
+            Some(TrySendError::Closed(()))
+        } else if self.users.receivers.load(order::HANDLE_LOAD) == 0 {
+            Some(TrySendError::Disconnected(()))
+        } else {
+            self.admit_sends();
+            None
+        }
+    }
+
+    // Sets REFUSING, after the queue was closed or lost its last receiver.
+    fn refuse_sends(&self) {
+        self.enq_pos
+            .fetch_or(u64::from(REFUSING) << POS_BITS, order::REFUSE);
+    }
+
+    // Clears REFUSING after a receiver came back, unless the queue is closed
+    // or the receivers left again meanwhile. Either is re-read after the
+    // clear, so a refuse_sends() that the clear undid is done again.
+    fn admit_sends(&self) {
+        self.enq_pos
+            .fetch_and(!(u64::from(REFUSING) << POS_BITS), order::REFUSE);
+
+        if self.closed() || self.users.receivers.load(order::HANDLE_LOAD) == 0 {
+            self.refuse_sends();
+        }
+    }
+
+    // send() without asking admit(). The priority lane only enqueues.
+    fn enqueue(&self, generation: u16, reserved: bool, d: T) -> Result<(), TrySendError<T>> {
+        match self.claim_tail(generation, reserved) {
+            Ok(pos) => {
+                // Plain write: no receiver touches the payload until it
+                // observes the PUBLISH store in publish() with its SLOT load.
+                unsafe { self.slot(pos).write(d) };
+                self.publish(pos);
+
+                Ok(())
+            }
+            Err(e) => Err(e.replace(d)),
+        }
+    }
+
+    // Claims the position at the tail for a send, leaving the slot to be
+    // written and published.
+    pub(crate) fn claim_tail(
+        &self,
+        generation: u16,
+        reserved: bool,
+    ) -> Result<u64, TrySendError<()>> {
+        let _turn = self.turn();
+        let limit = self.limit(reserved);
+        let bounded = limit < self.slots();
+        let mut word = self.enq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            #[cfg(test)]
+            hooks::iteration();
+
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                match self.refusal(g, generation) {
+                    Some(e) => return Err(e),
+                    None => {
+                        word = self.enq_pos.load(order::CLAIM_LOAD);
+                        continue;
+                    }
+                }
+            }
+
+            let cell = &self.v[pos as usize & *self.n];
+            let seq = cell.pos.load(order::SLOT);
+            let pos = widen(pos, seq);
+
+            match slot::for_send(seq, pos) {
+                Slot::Ready if bounded && len_at(&self.deq_pos, pos) >= limit => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_send_failure();
+
+                    return Err(TrySendError::Full(()));
+                }
+                #[cfg(test)]
+                Slot::Ready if hooks::fail_claim() => word = self.enq_pos.load(order::CLAIM_LOAD),
+                Slot::Ready => {
+                    match self.enq_pos.compare_exchange_weak(
+                        word,
+                        pack(g, pos.wrapping_add(1)),
+                        order::RING_CLAIM,
+                        order::CLAIM_FAILED,
+                    ) {
+                        Ok(_) => return Ok(pos),
+                        Err(actual) => {
+                            #[cfg(feature = "stats")]
+                            self.stats.on_send_retry();
+
+                            word = actual;
+                        }
+                    }
+                }
+                Slot::Behind => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_send_failure();
+
+                    return Err(TrySendError::Full(()));
+                }
+                Slot::Ahead => word = self.enq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    // Waits for this sender's turn to claim, with Fairness::Ticket.
+    fn turn(&self) -> Option<Turn<'_>> {
+        self.turnstile.as_ref().map(Turnstile::enter)
+    }
+
+    // Hands the written slot at claimed position `pos` to the receivers.
+    pub(crate) fn publish(&self, pos: u64) {
+        self.publish_run(pos, 1);
+    }
+
+    // publish() for the claimed positions `pos..pos + n`, in order, waking
+    // the receivers once.
+    pub(crate) fn publish_run(&self, pos: u64, n: u32) {
+        for p in (0..n).map(|i| pos.wrapping_add(i.into())) {
+            #[cfg(test)]
+            hooks::before_publish();
+
+            self.v[p as usize & *self.n]
+                .pos
+                .store(slot::published(p), order::PUBLISH);
+
+            #[cfg(feature = "stats")]
+            self.stats.on_send(self.len());
+        }
+
+        self.notify_receivers();
+    }
+
+    // The full position of the 48-bit `pos` of a word, see widen().
+    fn full(&self, pos: u64) -> u64 {
+        widen(pos, self.v[pos as usize & *self.n].pos.load(order::SLOT))
+    }
+
+    // The payload of the slot serving `pos`. Only the thread that claimed
+    // `pos` may touch it, and only until it publishes or releases it.
+    pub(crate) fn slot(&self, pos: u64) -> *mut T {
+        self.v[pos as usize & *self.n].data.with_mut(|d| d.cast())
+    }
+
+    fn send_replace(&self, generation: u16, mut d: T) -> Result<Option<T>, TrySendError<T>> {
+        loop {
+            match self.send(generation, false, d) {
+                Err(TrySendError::Full(back)) => d = back,
+                result => return result.map(|_| None),
+            }
+
+            // Evicting from the redelivery queue would not free a slot, so
+            // the oldest element of the ring goes.
+            let evicted = match self.recv(generation) {
+                Ok(evicted) => evicted,
+                Err(TryRecvError::Stale) => return Err(TrySendError::Stale(d)),
+                // Receivers made room first.
+                Err(_) => continue,
+            };
+
+            match self.send(generation, false, d) {
+                Ok(()) => return Ok(Some(evicted)),
+                Err(TrySendError::Full(back)) => {
+                    // Another sender took the slot. Receivers get the evicted
+                    // element before the ring, so it is still the oldest.
+                    d = back;
+
+                    match self
+                        .retry
+                        .push_front(evicted, 0, || self.generation() == generation)
+                    {
+                        Ok(()) => self.notify_requeued(),
+                        Err(evicted) => self.dead_letter(evicted),
+                    }
+                }
+                Err(e) => {
+                    self.dead_letter(evicted);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    // `Copy`, so that nothing can panic between claiming a run and
+    // publishing it.
+    fn send_many(
+        &self,
+        generation: u16,
+        items: &[T],
+        all_or_nothing: bool,
+    ) -> Result<usize, TrySendError<()>>
+    where
+        T: Copy,
+    {
+        let mut items = items.iter();
+
+        self.send_runs(generation, items.len(), all_or_nothing, || {
+            *items.next().unwrap()
+        })
+    }
+
+    // Sends up to `len` elements, taken with `next` as their slots are
+    // claimed, in runs of one CAS each: send_many() for any source.
+    fn send_runs(
+        &self,
+        generation: u16,
+        len: usize,
+        all_or_nothing: bool,
+        mut next: impl FnMut() -> T,
+    ) -> Result<usize, TrySendError<()>> {
+        let mut total = 0;
+
+        while total < len {
+            let max = u32::try_from(len - total).unwrap_or(u32::MAX);
+            let (pos, run) = match self.claim_run(generation, max, all_or_nothing) {
+                Ok(claimed) => claimed,
+                Err(_) if total > 0 => return Ok(total),
+                Err(e) => {
+                    #[cfg(feature = "stats")]
+                    if let TrySendError::Full(()) = e {
+                        self.stats.on_send_failure();
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            for i in 0..run {
+                // As in enqueue(), a plain write.
+                unsafe { self.slot(pos.wrapping_add(i.into())).write(next()) };
+            }
+
+            self.publish_run(pos, run);
+            total += run as usize;
+        }
+
+        Ok(total)
+    }
+
+    // Claims the run of up to `max` free positions at the tail with one CAS,
+    // or exactly `max` of them. Returns the first position and the length.
+    pub(crate) fn claim_run(
+        &self,
+        generation: u16,
+        max: u32,
+        exact: bool,
+    ) -> Result<(u64, u32), TrySendError<()>> {
+        let _turn = self.turn();
+        let limit = self.limit(false);
+        let bounded = limit < self.slots();
+        let mut word = self.enq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                match self.refusal(g, generation) {
+                    Some(e) => return Err(e),
+                    None => {
+                        word = self.enq_pos.load(order::CLAIM_LOAD);
+                        continue;
+                    }
+                }
+            }
+
+            let pos = self.full(pos);
+
+            // Count the run of free slots at the tail; the claim below proves
+            // nobody else took them meanwhile.
+            let room = if bounded {
+                limit.saturating_sub(len_at(&self.deq_pos, pos))
+            } else {
+                limit
+            };
+            let want = room.min(max);
+            let mut run = 0;
+            let mut stale = false;
+
+            while run < want {
+                let p = pos.wrapping_add(run.into());
+
+                match slot::for_send(self.v[p as usize & *self.n].pos.load(order::SLOT), p) {
+                    Slot::Ready => run += 1,
+                    Slot::Behind => break,
+                    Slot::Ahead => {
+                        stale = true;
+                        break;
+                    }
+                }
+            }
+
+            if run == 0 && stale {
+                word = self.enq_pos.load(order::CLAIM_LOAD);
+                continue;
+            }
+
+            if run == 0 || (exact && run < max) {
+                return Err(TrySendError::Full(()));
+            }
+
+            match self.enq_pos.compare_exchange_weak(
+                word,
+                pack(g, pos.wrapping_add(run.into())),
+                order::RING_CLAIM,
+                order::CLAIM_FAILED,
+            ) {
+                Ok(_) => return Ok((pos, run)),
+                Err(actual) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_send_retry();
+
+                    word = actual;
+                }
+            }
+        }
+    }
+
+    fn recv(&self, generation: u16) -> Result<T, TryRecvError> {
+        self.recv_probe(generation).map_err(TryRecvError::from)
+    }
+
+    // recv() serving the lanes by deficit round robin, see
+    // Builder::lane_weights().
+    fn recv_lanes(&self, generation: u16, deficit: &mut Deficit) -> Result<T, TryRecvError> {
+        let Some((priority, bulk)) = self.config.lanes else {
+            return self.recv(generation);
+        };
+
+        // A second pass in case only the lane out of turn has elements.
+        for _ in 0..2 {
+            if deficit.priority == 0 && deficit.bulk == 0 {
+                *deficit = Deficit { priority, bulk };
+            }
+
+            if deficit.priority > 0 {
+                let lane = self.lane.as_deref().expect("lanes without a lane");
+
+                match lane.recv(generation) {
+                    Ok(d) => {
+                        deficit.priority -= 1;
+                        return Ok(d);
+                    }
+                    // An empty lane gives up the rest of its turn.
+                    Err(TryRecvError::Empty) => deficit.priority = 0,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if deficit.bulk > 0 {
+                match self.recv(generation) {
+                    Ok(d) => {
+                        deficit.bulk -= 1;
+                        return Ok(d);
+                    }
+                    Err(TryRecvError::Empty) => deficit.bulk = 0,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Err(TryRecvError::Empty)
+    }
+
+    fn send_priority(&self, generation: u16, d: T) -> Result<(), TrySendError<T>> {
+        match self.lane.as_deref() {
+            Some(lane) => {
+                let result = self
+                    .admit(d)
+                    .and_then(|d| lane.enqueue(generation, true, d));
+
+                if result.is_ok() {
+                    self.notify_receivers();
+                }
+
+                result
+            }
+            None => self.enqueue(generation, true, d),
+        }
+    }
+
+    fn recv_probe(&self, generation: u16) -> Result<T, RecvProbe> {
+        let pos = self.claim_head(generation)?;
+        // Plain read: no sender overwrites the payload until it observes the
+        // RECYCLE store in release() with its SLOT load.
+        let d = unsafe { self.slot(pos).read() };
+
+        self.release(pos);
+        Ok(d)
+    }
+
+    // Claims the published element at the head, leaving it in its slot to
+    // be read and released.
+    //
+    // Receivers contend on deq_pos and nothing else: a claim never loads
+    // enq_pos unless the head slot is unpublished, and then only to tell an
+    // empty queue from a pending send, so caching the producers' position
+    // would save nothing. A receiver that loses the CAS backs off instead,
+    // so that it does not take the line straight back from the winner.
+    pub(crate) fn claim_head(&self, generation: u16) -> Result<u64, RecvProbe> {
+        let backoff = Backoff::new();
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            #[cfg(test)]
+            hooks::iteration();
+
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                return Err(RecvProbe::Stale);
+            }
+
+            let cell = &self.v[pos as usize & *self.n];
+            let seq = cell.pos.load(order::SLOT);
+            let pos = widen(pos, seq);
+
+            match slot::for_recv(seq, pos) {
+                #[cfg(test)]
+                Slot::Ready if hooks::fail_claim() => word = self.deq_pos.load(order::CLAIM_LOAD),
+                Slot::Ready => {
+                    match self.deq_pos.compare_exchange_weak(
+                        word,
+                        pack(g, pos.wrapping_add(1)),
+                        order::RING_CLAIM,
+                        order::CLAIM_FAILED,
+                    ) {
+                        Ok(_) => return Ok(pos),
+                        Err(actual) => {
+                            #[cfg(feature = "stats")]
+                            self.stats.on_recv_retry();
+
+                            backoff.spin();
+                            word = actual;
+                        }
+                    }
+                }
+                // Not published yet: either nothing was sent, or a sender
+                // claimed `pos` and is still writing it.
+                Slot::Behind => {
+                    let (_, enq) = unpack(self.enq_pos.load(order::SNAPSHOT));
+
+                    return Err(if enq == pos & POS_MASK {
+                        RecvProbe::Empty
+                    } else {
+                        RecvProbe::Pending
+                    });
+                }
+                Slot::Ahead => word = self.deq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    // Hands the slot at claimed position `pos`, read or dropped, back to the
+    // senders.
+    pub(crate) fn release(&self, pos: u64) {
+        self.v[pos as usize & *self.n]
+            .pos
+            .store(slot::recycled(pos, self.slots()), order::RECYCLE);
+        self.notify_senders();
+
+        #[cfg(feature = "stats")]
+        self.stats.on_recv();
+    }
+
+    fn recv_if(
+        &self,
+        generation: u16,
+        mut pred: impl FnMut(&T) -> bool,
+    ) -> Result<Option<T>, TryRecvError>
+    where
+        T: Copy,
+    {
+        let slots = self.slots();
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+        let mut stolen = false;
+
+        loop {
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                return Err(TryRecvError::Stale);
+            }
+
+            let cell = &self.v[pos as usize & *self.n];
+            let seq = cell.pos.load(order::SLOT);
+            let pos = widen(pos, seq);
+
+            match slot::for_recv(seq, pos) {
+                Slot::Ready => {
+                    let Some(head) = cell.peek(seq) else {
+                        word = self.deq_pos.load(order::CLAIM_LOAD);
+                        continue;
+                    };
+
+                    if !pred(&head) {
+                        return Ok(None);
+                    }
+
+                    // Strong CAS: a spurious failure would be counted as a
+                    // steal.
+                    match self.deq_pos.compare_exchange(
+                        word,
+                        pack(g, pos.wrapping_add(1)),
+                        order::RING_CLAIM,
+                        order::CLAIM_FAILED,
+                    ) {
+                        Ok(_) => {
+                            // The claim proves nobody took `pos` since the
+                            // peek, so this is the element `pred` saw.
+                            let d = cell.data.with(|d| unsafe { (*d).assume_init_read() });
+                            cell.pos.store(slot::recycled(pos, slots), order::RECYCLE);
+                            self.notify_senders();
+
+                            #[cfg(feature = "stats")]
+                            self.stats.on_recv();
+
+                            return Ok(Some(d));
+                        }
+                        Err(_) if stolen => return Ok(None),
+                        Err(actual) => {
+                            #[cfg(feature = "stats")]
+                            self.stats.on_recv_retry();
+
+                            stolen = true;
+                            word = actual;
+                        }
+                    }
+                }
+                // Ring buffer is empty.
+                Slot::Behind => return Err(TryRecvError::Empty),
+                Slot::Ahead => word = self.deq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    // A checked copy of the head element, a redelivered one first.
+    fn peek(&self, generation: u16) -> Result<T, TryRecvError>
+    where
+        T: Copy,
+    {
+        let mut head = None;
+
+        self.redeliver_if(generation, |d| {
+            head = Some(*d);
+            false
+        })?;
+
+        if let Some(d) = head {
+            return Ok(d);
+        }
+
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            #[cfg(test)]
+            hooks::iteration();
+
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                return Err(TryRecvError::Stale);
+            }
+
+            let cell = &self.v[pos as usize & *self.n];
+            let seq = cell.pos.load(order::SLOT);
+            let pos = widen(pos, seq);
+
+            match slot::for_recv(seq, pos) {
+                Slot::Ready => match cell.peek(seq) {
+                    Some(d) => return Ok(d),
+                    None => word = self.deq_pos.load(order::CLAIM_LOAD),
+                },
+                Slot::Behind => return Err(TryRecvError::Empty),
+                Slot::Ahead => word = self.deq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    fn drain_while(
+        &self,
+        generation: u16,
+        mut filter: impl Filter<T>,
+        buf: &mut Vec<T>,
+        limit: usize,
+    ) -> usize {
+        let slots = self.slots();
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+        let mut total = 0;
+
+        loop {
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                return total;
+            }
+
+            let pos = self.full(pos);
+
+            // Peek the run of published elements at the head that `filter`
+            // accepts.
+            let mut run = 0;
+            let mut rejected = false;
+            let mut stale = false;
+            let max = slots.min(u32::try_from(limit - total).unwrap_or(u32::MAX));
+
+            while run < max {
+                let p = pos.wrapping_add(run.into());
+                let cell = &self.v[p as usize & *self.n];
+                let seq = cell.pos.load(order::SLOT);
+
+                match slot::for_recv(seq, p) {
+                    Slot::Ready => match filter.accepts_slot(cell, seq) {
+                        Some(true) => run += 1,
+                        Some(false) => {
+                            rejected = true;
+                            break;
+                        }
+                        None => {
+                            stale = true;
+                            break;
+                        }
+                    },
+                    Slot::Behind => break,
+                    Slot::Ahead => {
+                        stale = true;
+                        break;
+                    }
+                }
+            }
+
+            if run == 0 {
+                if stale {
+                    word = self.deq_pos.load(order::CLAIM_LOAD);
+                    continue;
+                }
+
+                return total;
+            }
+
+            let next = pack(g, pos.wrapping_add(run.into()));
+
+            match self
+                .deq_pos
+                .compare_exchange(word, next, order::RING_CLAIM, order::CLAIM_FAILED)
+            {
+                Ok(_) => {
+                    // As in recv_if(), the claim proves the run is what was
+                    // peeked.
+                    for i in 0..run {
+                        let p = pos.wrapping_add(i.into());
+                        let cell = &self.v[p as usize & *self.n];
+
+                        buf.push(cell.data.with(|d| unsafe { (*d).assume_init_read() }));
+                        cell.pos.store(slot::recycled(p, slots), order::RECYCLE);
+
+                        #[cfg(feature = "stats")]
+                        self.stats.on_recv();
+                    }
+
+                    self.notify_senders();
+                    total += run as usize;
+
+                    if rejected || total == limit {
+                        return total;
+                    }
+
+                    word = next;
+                }
+                Err(actual) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_recv_retry();
+
+                    word = actual;
+                }
+            }
+        }
+    }
+
+    // Discards up to `n` head elements, returning how many and the last one.
+    fn skip(&self, generation: u16, n: usize) -> (usize, Option<T>) {
+        let slots = self.slots();
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+        let mut total = 0;
+        let mut last = None;
+
+        while total < n {
+            let (g, pos) = unpack(word);
+
+            if g != generation {
+                break;
+            }
+
+            let pos = self.full(pos);
+
+            // Count the run of published elements at the head; unlike
+            // drain_while() there is no payload to peek.
+            let max = slots.min(u32::try_from(n - total).unwrap_or(u32::MAX));
+            let mut run = 0;
+            let mut stale = false;
+
+            while run < max {
+                let p = pos.wrapping_add(run.into());
+
+                match slot::for_recv(self.v[p as usize & *self.n].pos.load(order::SLOT), p) {
+                    Slot::Ready => run += 1,
+                    Slot::Behind => break,
+                    Slot::Ahead => {
+                        stale = true;
+                        break;
+                    }
+                }
+            }
+
+            if run == 0 {
+                if stale {
+                    word = self.deq_pos.load(order::CLAIM_LOAD);
+                    continue;
+                }
+
+                break;
+            }
+
+            let next = pack(g, pos.wrapping_add(run.into()));
+
+            match self.deq_pos.compare_exchange_weak(
+                word,
+                next,
+                order::RING_CLAIM,
+                order::CLAIM_FAILED,
+            ) {
+                Ok(_) => {
+                    for i in 0..run {
+                        let p = pos.wrapping_add(i.into());
+                        let cell = &self.v[p as usize & *self.n];
+
+                        // Only the newest element of a run is kept.
+                        let d = cell.data.with_mut(|d| d);
+
+                        if i == run - 1 {
+                            last = Some(unsafe { (*d).assume_init_read() });
+                        } else {
+                            unsafe { (*d).assume_init_drop() };
+                        }
+
+                        cell.pos.store(slot::recycled(p, slots), order::RECYCLE);
+
+                        #[cfg(feature = "stats")]
+                        self.stats.on_recv();
+                    }
+
+                    self.notify_senders();
+                    total += run as usize;
+                    word = next;
+                }
+                Err(actual) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_recv_retry();
+
+                    word = actual;
+                }
+            }
+        }
+
+        (total, last)
+    }
+
+    /// Whether the queue, and the redelivery queue of [`AckGuard`]s, are
+    /// empty.
+    pub fn empty(&self) -> bool {
+        if !self.retry.is_empty() || self.lane.as_ref().is_some_and(|lane| !lane.empty()) {
+            return false;
+        }
+
+        let mut word = self.deq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            #[cfg(test)]
+            hooks::iteration();
+
+            let (_, pos) = unpack(word);
+            let cell = &self.v[pos as usize & *self.n];
+            let seq = cell.pos.load(order::SLOT);
+
+            match slot::for_recv(seq, widen(pos, seq)) {
+                Slot::Ready => return false,
+                // Ring buffer is empty.
+                Slot::Behind => return true,
+                Slot::Ahead => word = self.deq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    // Whether a sender has claimed a position that is not yet received,
+    // published or not. A sender orders its check for sleepers only by its
+    // claim, so a receiver that finds the ring empty with one pending may
+    // not be woken for it, see park.rs.
+    fn claimed(&self) -> bool {
+        self.len() > 0
+    }
+
+    // Number of slots; new() bounds it by MAX_CAPACITY so it fits in u32.
+    fn slots(&self) -> u32 {
+        *self.n as u32 + 1
+    }
+
+    /// Elements the queue holds, as asked for. The slots are rounded up to
+    /// a power of two, see [`RingBuffer::memory_footprint`]; sends stop
+    /// short of the spare ones.
+    pub fn capacity(&self) -> usize {
+        self.config.capacity
+    }
+
+    // Elements sends may leave in the ring before it counts as full: the
+    // capacity, less the headroom unless `reserved`. Past the end of the
+    // ring the slots themselves say it is full, so only a limit below the
+    // slot count costs a length check per send.
+    fn limit(&self, reserved: bool) -> u32 {
+        let headroom = if reserved { 0 } else { self.config.headroom };
+
+        (self.capacity() - headroom) as u32
+    }
+
+    /// Bytes this queue occupies, from the sizes of its actual allocations.
+    /// For a queue made by [`RingBuffer::from_uninit_slice`] these are the
+    /// bytes used in the caller's memory.
+    ///
+    /// There is one slot per element of the capacity rounded up to a power
+    /// of two, so that positions map to slots with a mask: a capacity just
+    /// above a power of two nearly doubles the slots.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let slots = match &*self.v {
+            Storage::Heap(v) => v.capacity(),
+            Storage::Placed(v) => v.len(),
+            Storage::Borrowed(v) => v.len(),
+        };
+
+        MemoryFootprint::new::<T>(slots)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn slots_addr(&self) -> usize {
+        self.v.as_ptr() as usize
+    }
+
+    /// Bytes a queue built from `config` will occupy, see
+    /// [`RingBuffer::memory_footprint`]. Also available as
+    /// [`Builder::estimate_footprint`].
+    pub fn estimate_footprint(config: &Builder<'a, T>) -> MemoryFootprint {
+        let slots = slots_for(config.capacity.clamp(1, MAX_CAPACITY));
+
+        MemoryFootprint::new::<T>(slots)
+    }
+
+    /// Elements sent and not yet claimed by a receiver, in the ring and the
+    /// priority lane, from the positions. While handles are in use this is
+    /// a snapshot that may be stale by the time it returns; once they are
+    /// quiescent it is exact. It never exceeds what the queue can hold.
+    ///
+    /// An element counts from the moment a sender claims its slot, so the
+    /// length can be nonzero while [`RingBuffer::empty`] still finds nothing
+    /// to receive. Elements waiting for redelivery are not counted.
+    // empty() is the check receivers want, so there is no is_empty().
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let lane = self.lane.as_ref().map_or(0, |lane| lane.len());
+
+        self.occupied() + lane
+    }
+
+    /// Whether a send would find the ring full, going by
+    /// [`RingBuffer::len`]. Reserved headroom counts as free.
+    pub fn is_full(&self) -> bool {
+        self.remaining_capacity() == 0
+    }
+
+    /// Free slots in the ring, going by [`RingBuffer::len`]. Reserved
+    /// headroom counts as free.
+    pub fn remaining_capacity(&self) -> usize {
+        self.capacity() - self.occupied()
+    }
+
+    // Claimed slots of the ring alone. The positions are read one after
+    // the other, so the enqueue one can have moved on by more than the
+    // capacity from the dequeue one read before it.
+    fn occupied(&self) -> usize {
+        let (enq, deq) = self.words();
+
+        (distance(enq, deq) as usize).min(self.capacity())
+    }
+
+    // Whether the approximate occupancy is below `fraction` of the capacity.
+    fn below(&self, fraction: f32) -> bool {
+        (self.len() as f32) < fraction * self.capacity() as f32
+    }
+
+    // Whether a send outside the headroom would find a free slot in the
+    // ring, going by the approximate length.
+    fn has_room(&self) -> bool {
+        self.config.headroom < self.remaining_capacity()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> QueueStats {
+        self.stats.snapshot()
+    }
+
+    /// Turns the depths successful sends found and the count of rejected
+    /// sends into a capacity to build the queue with next time. Advisory
+    /// only: nothing about the queue changes. The histogram behind it takes
+    /// a fixed 1KB.
+    #[cfg(feature = "stats")]
+    pub fn capacity_advice(&self) -> CapacityAdvice {
+        self.stats.capacity_advice(self.capacity())
+    }
+
+    /// Sums of the [`Sender::local_stats`] and
+    /// [`Receiver::local_stats`] of every handle dropped so far. Once all
+    /// handles are gone they account for every operation; while some are
+    /// alive, add their local stats for a full picture.
+    #[cfg(feature = "stats")]
+    pub fn handle_totals(&self) -> HandleTotals {
+        self.stats.handle_totals()
+    }
+
+    /// Reports the queue as stalled if nothing was received for `threshold`
+    /// while elements are buffered, or nothing was sent for `threshold`
+    /// while receivers are blocked waiting. Meant to be polled by a
+    /// monitoring loop; the library runs no thread of its own.
+    ///
+    /// Activity is the time of the last successful send and receive, kept
+    /// with millisecond resolution.
+    #[cfg(feature = "stats")]
+    pub fn stall_report(&self, threshold: Duration) -> Option<StallReport> {
+        self.stats
+            .stall_report(threshold, self.len(), self.recv_waiters.sleeping())
+    }
+
+    /// Writes the queue's gauges and counters in the Prometheus text
+    /// exposition format. `labels` is a preformatted `name="value",...` list
+    /// (see [`stats::escape_label_value`]) attached to every sample; pass an
+    /// empty string for none. A named queue adds a `queue` label with its
+    /// name in front.
+    #[cfg(feature = "stats")]
+    pub fn write_prometheus(&self, w: &mut impl fmt::Write, labels: &str) -> fmt::Result {
+        let named;
+        let labels = match self.name() {
+            Some(name) => {
+                let sep = if labels.is_empty() { "" } else { "," };
+
+                named = format!(
+                    "queue=\"{}\"{}{}",
+                    stats::escape_label_value(name),
+                    sep,
+                    labels
+                );
+                &named
+            }
+            None => labels,
+        };
+
+        stats::write_prometheus(w, labels, self.len(), self.capacity(), &self.stats())
+    }
+
+    /// Returns the `(enqueue, dequeue)` positions.
+    ///
+    /// Both are post-claim values: every position below the enqueue position
+    /// has been claimed by a sender, every position below the dequeue position
+    /// by a receiver. A claimed enqueue slot may not be published yet and a
+    /// claimed dequeue slot may not be recycled yet, so in a quiesced queue
+    /// `enqueue - dequeue` is the number of buffered elements.
+    ///
+    /// The loads are `Relaxed` and do not synchronise with the slot contents;
+    /// the only guarantee is that a thread sees at least the positions of its
+    /// own completed claims. The pair is re-read until `dequeue <= enqueue`.
+    /// Both count from 0 for a new queue, go on across
+    /// [`RingBuffer::reset_generation`], and only wrap after 2^64 claims.
+    pub fn positions(&self) -> (u64, u64) {
+        let (enq, deq) = self.words();
+
+        (self.full(enq), self.full(deq))
+    }
+
+    // positions() as kept in the words: modulo 2^48, and the enqueue one
+    // ahead of the dequeue one by distance().
+    fn words(&self) -> (u64, u64) {
+        loop {
+            #[cfg(test)]
+            hooks::iteration();
+
+            let (_, deq) = unpack(self.deq_pos.load(order::SNAPSHOT));
+            let (_, enq) = unpack(self.enq_pos.load(order::SNAPSHOT));
+
+            if distance(enq, deq) >= 0 {
+                return (enq, deq);
+            }
+        }
+    }
+
+    /// A picture of the positions and of every slot, see
+    /// [`snapshot`](crate::snapshot). It claims nothing, so a stuck queue
+    /// can be looked at as it is.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        self.snapshot_with(|_, _| None)
+    }
+
+    /// Like [`RingBuffer::snapshot`], with a copy of the element in every
+    /// full slot. A copy that races with the slot's receiver is left out.
+    pub fn snapshot_elements(&self) -> Snapshot<T>
+    where
+        T: Copy,
+    {
+        self.snapshot_with(|cell, seq| cell.peek(seq))
+    }
+
+    fn snapshot_with(&self, mut peek: impl FnMut(&Cell<T>, u64) -> Option<T>) -> Snapshot<T> {
+        let (enq, deq) = self.positions();
+        let mask = *self.n as u64;
+        let slots = self
+            .v
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let seq = cell.pos.load(order::SLOT);
+                let mut state = SlotState::new(seq, i as u64, mask, enq, deq);
+
+                if let SlotState::Full { d, .. } = &mut state {
+                    *d = peek(cell, seq);
+                }
+
+                state
+            })
+            .collect();
+
+        Snapshot {
+            generation: self.generation(),
+            enq_pos: enq,
+            deq_pos: deq,
+            len: self.len(),
+            capacity: self.capacity(),
+            redelivery: self.retry.len(),
+            closed: self.closed(),
+            senders: self.users.senders.load(order::HANDLE_LOAD),
+            receivers: self.users.receivers.load(order::HANDLE_LOAD),
+            slots,
+        }
+    }
+
+    /// The current generation, 0 until the first
+    /// [`RingBuffer::reset_generation`]. It wraps after 2^15 resets, so a
+    /// handle left over from exactly that many resets ago is no longer
+    /// told apart from a current one.
+    pub fn generation(&self) -> u16 {
+        unpack(self.enq_pos.load(order::SNAPSHOT)).0 & GENERATION_MASK
+    }
+
+    /// Ends the current generation without reallocating the ring: every
+    /// existing handle, and every clone made from one, fails with a `Stale`
+    /// error from now on, the contents are discarded, and a sender and a
+    /// receiver for the new generation are returned.
+    ///
+    /// Operations that claimed a slot before the reset are allowed to finish
+    /// first; the reset waits for in-flight sends to publish and throws
+    /// their elements away. The handles of the old generation still count
+    /// towards the handle totals until they are dropped. Receivers blocked
+    /// in [`Receiver::recv_batch_blocking`] are woken and return; any other
+    /// stale handle sees the error on its next call.
+    pub fn reset_generation(&self) -> (Sender<'a, T>, Receiver<'a, T>) {
+        #[cfg(test)]
+        hooks::lock();
+
+        let _reset = self.reset.lock().unwrap_or_else(|e| e.into_inner());
+        let generation = self.discard();
+
+        // The priority lane moves on in step, so the same generation check
+        // turns stale handles away from both.
+        if let Some(lane) = &self.lane {
+            lane.discard();
+        }
+
+        let senders = Users::add(&self.users.senders);
+        let receivers = Users::add(&self.users.receivers);
+
+        if receivers == 1 {
+            self.admit_sends();
+        }
+
+        self.event(Event::SenderAdded { senders });
+        self.event(Event::ReceiverAdded { receivers });
+        self.recv_waiters.notify_all();
+        self.send_waiters.notify_all();
+        // Stale handles are told either way.
+        atomic::fence(order::WAKE);
+        self.fire_readable();
+        self.fire_writable();
+
+        let rb = NonNull::from(self);
+
+        (Sender::new(rb, generation), Receiver::new(rb, generation))
+    }
+
+    // reset_generation() for one ring: moves the generation on, throws the
+    // contents away and returns the new generation.
+    fn discard(&self) -> u16 {
+        let slots = self.slots();
+
+        // Bump the enqueueing side first: once no sender can claim, the
+        // enqueue position is the end of what there is to discard.
+        let (generation, enq) = self.bump(&self.enq_pos);
+        let (_, deq) = self.bump(&self.deq_pos);
+        let pos = self.full(deq);
+
+        // Guards of the old generation check it under the retry lock, so none
+        // can requeue after this.
+        for (d, _) in self.retry.clear() {
+            self.dead_letter(d);
+        }
+
+        for pos in (0..distance(enq, deq) as u64).map(|i| pos.wrapping_add(i)) {
+            let cell = &self.v[pos as usize & *self.n];
+
+            while slot::for_recv(cell.pos.load(order::SLOT), pos) != Slot::Ready {
+                std::hint::spin_loop();
+            }
+
+            // Without a dead-letter queue this drops it.
+            self.dead_letter(cell.data.with(|d| unsafe { (*d).assume_init_read() }));
+
+            cell.pos.store(slot::recycled(pos, slots), order::RECYCLE);
+        }
+
+        // No handle of the new generation exists yet, so nothing races this.
+        self.deq_pos.store(pack(generation, enq), order::CLAIM);
+
+        generation
+    }
+
+    // Takes an element off the redelivery queue, if there is one for
+    // `generation`. The common case, an empty queue, costs one load.
+    fn redeliver(&self, generation: u16) -> Result<Option<(T, u32)>, TryRecvError> {
+        if self.retry.is_empty() {
+            return Ok(None);
+        }
+
+        if self.generation() != generation {
+            return Err(TryRecvError::Stale);
+        }
+
+        Ok(self.retry.pop())
+    }
+
+    // The redelivery queue's part of recv_if(): `None` if nothing is queued
+    // for `generation`, `Some(None)` if its front element was rejected.
+    #[allow(clippy::type_complexity)]
+    fn redeliver_if(
+        &self,
+        generation: u16,
+        pred: impl FnOnce(&T) -> bool,
+    ) -> Result<Option<Option<(T, u32)>>, TryRecvError> {
+        if self.retry.is_empty() {
+            return Ok(None);
+        }
+
+        if self.generation() != generation {
+            return Err(TryRecvError::Stale);
+        }
+
+        Ok(self.retry.pop_if(pred))
+    }
+
+    // Queues an element for redelivery, unless its generation has ended.
+    pub(crate) fn requeue(&self, generation: u16, d: T, redeliveries: u32) {
+        match self
+            .retry
+            .push(d, redeliveries, || self.generation() == generation)
+        {
+            Ok(()) => self.notify_requeued(),
+            Err(d) => self.dead_letter(d),
+        }
+    }
+
+    // After an element was published. The claim before the publish orders
+    // the check for sleepers, see park.rs.
+    fn notify_receivers(&self) {
+        self.recv_waiters.notify_claimed();
+
+        // A select group or readiness hook looks without a claim to order it
+        // by, so it takes a fence, which only queues that have one pay for.
+        if self.select.get().is_some() || self.config.on_readable.is_some() {
+            atomic::fence(order::WAKE);
+
+            if let Some(member) = self.select.get() {
+                member.notify();
+            }
+
+            self.fire_readable();
+        }
+    }
+
+    // After an element was queued for redelivery, which takes no claim.
+    fn notify_requeued(&self) {
+        atomic::fence(order::WAKE);
+        self.notify_receivers();
+    }
+
+    // After the last sender is gone or the queue was closed: receivers
+    // don't wait for that with an element.
+    fn notify_disconnect(&self) {
+        #[cfg(feature = "tracing")]
+        if !self.closed() {
+            tracing::debug!(channel = self.id.0, "last sender dropped");
+        }
+
+        self.recv_waiters.notify_all();
+        atomic::fence(order::WAKE);
+
+        if let Some(member) = self.select.get() {
+            member.notify();
+        }
+
+        self.fire_readable();
+    }
+
+    // After a slot was released, ordered by the claim as in
+    // notify_receivers().
+    fn notify_senders(&self) {
+        self.send_waiters.notify_claimed();
+
+        if self.config.on_writable.is_some() {
+            atomic::fence(order::WAKE);
+            self.fire_writable();
+        }
+    }
+
+    // After the last receiver is gone or the queue was closed: senders have
+    // nothing more to wait for.
+    fn notify_no_receivers(&self) {
+        #[cfg(feature = "tracing")]
+        if !self.closed() {
+            tracing::debug!(channel = self.id.0, "last receiver dropped");
+        }
+
+        self.send_waiters.notify_all();
+        atomic::fence(order::WAKE);
+
+        self.fire_writable();
+    }
+
+    // Calls the Builder::on_readable() hook if armed, after a WAKE fence.
+    fn fire_readable(&self) {
+        if let Some(hook) = &self.config.on_readable {
+            self.readable.fire(&**hook, self.id);
+        }
+    }
+
+    // The same for the Builder::on_writable() hook.
+    fn fire_writable(&self) {
+        if let Some(hook) = &self.config.on_writable {
+            self.writable.fire(&**hook, self.id);
+        }
+    }
+
+    pub(crate) fn join_select(&self, member: Member) -> Result<(), Member> {
+        self.select.set(member)
+    }
+
+    // Traces a send that found the queue full, whatever Builder::on_full
+    // then made of it.
+    #[cfg(feature = "tracing")]
+    fn trace_full(&self) {
+        tracing::debug!(
+            channel = self.id.0,
+            capacity = self.capacity(),
+            on_full = ?self.config.on_full,
+            "queue full"
+        );
+    }
+
+    // Passes `event` to the Builder::on_event hook, if there is one.
+    fn event(&self, event: Event) {
+        if let Some(hook) = &self.config.on_event {
+            hook(self.id, event);
+        }
+    }
+
+    // Hands a discarded element to the dead-letter queue, if there is one.
+    fn dead_letter(&self, d: T) {
+        if let Some(send) = &self.config.dead_letter {
+            if !send(d) {
+                self.dead_letter_failures.fetch_add(1, order::COUNTER);
+            }
+        }
+    }
+
+    /// Discarded elements that [`Builder::dead_letter`]'s queue refused, so
+    /// they were dropped.
+    pub fn dead_letter_failures(&self) -> u64 {
+        self.dead_letter_failures.load(order::COUNTER)
+    }
+
+    // Every sender is gone, or the queue is closed. No clone can bring the
+    // senders back (see Sender::try_clone), but Receiver::new_sender and a
+    // generation reset can, so this holds only until one of them adds one.
+    fn disconnected(&self) -> bool {
+        self.users.senders.load(order::HANDLE_LOAD) == 0 || self.closed()
+    }
+
+    // The error for a receive that found nothing: Closed or Disconnected if
+    // the queue is empty with no sender left to fill it. The last sender
+    // publishes before it goes, so once it is gone an empty queue stays
+    // empty until Receiver::new_sender or a generation reset adds one.
+    fn nothing(&self, e: TryRecvError) -> TryRecvError {
+        match e {
+            TryRecvError::Empty if self.disconnected() && self.empty() => {
+                if self.closed() {
+                    TryRecvError::Closed
+                } else {
+                    TryRecvError::Disconnected
+                }
+            }
+            e => e,
+        }
+    }
+
+    /// Whether the queue was closed, see [`Sender::close`]. Sends then fail
+    /// with [`TrySendError::Closed`]; receives go on until the queue is
+    /// empty.
+    pub fn closed(&self) -> bool {
+        self.closed.load(order::CLOSE)
+    }
+
+    // Returns whether this call closed the queue.
+    fn close(&self) -> bool {
+        if self.closed.swap(true, order::CLOSE) {
+            return false;
+        }
+
+        self.refuse_sends();
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(channel = self.id.0, "channel closed");
+
+        // Receivers blocked on an empty queue and senders blocked on a full
+        // one have nothing more to wait for.
+        self.notify_disconnect();
+        self.notify_no_receivers();
+        true
+    }
+
+    // Moves `side` to the next generation, returning it and the position.
+    // REFUSING stays as it was.
+    fn bump(&self, side: &sync::AtomicU64) -> (u16, u64) {
+        let mut word = side.load(order::CLAIM_LOAD);
+
+        loop {
+            let (g, pos) = unpack(word);
+            let next = g.wrapping_add(1) & GENERATION_MASK;
+
+            match side.compare_exchange_weak(
+                word,
+                pack(next | (g & REFUSING), pos),
+                order::CLAIM,
+                order::CLAIM_FAILED,
+            ) {
+                Ok(_) => return (next, pos),
+                Err(actual) => word = actual,
+            }
+        }
+    }
+
+    /// Creates a queue of capacity `n` with a sender and a receiver, which
+    /// own it: it is freed when the last of them is dropped, whatever the
+    /// order.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(n: usize) -> (Sender<'a, T>, Receiver<'a, T>) {
+        let (_, s, r) = Self::new_boxed(n);
+
+        (s, r)
+    }
+
+    /// [`RingBuffer::new`], with a [`RingBox`] that shares the queue with
+    /// the handles, for monitoring and [`RingBuffer::reset_generation`].
+    pub fn new_boxed(n: usize) -> (RingBox<'a, T>, Sender<'a, T>, Receiver<'a, T>) {
+        Self::new_at(n, 0)
+    }
+
+    /// Creates a queue of capacity `n` for the duration of `f`, see
+    /// [`Builder::scope`].
+    pub fn scope<R>(n: usize, f: impl FnOnce(&Sender<'a, T>, &Receiver<'a, T>) -> R) -> R
+    where
+        T: 'a,
+    {
+        Builder::new().capacity(n).scope(f)
+    }
+
+    // Drops a reference to `rb`, freeing it with the last one.
+    unsafe fn unref(rb: *mut Self) {
+        if (*rb).refs.fetch_sub(1, order::HANDLE_DOWN) == 1 {
+            drop(Box::from_raw(rb));
+        }
+    }
+
+    pub fn builder() -> Builder<'a, T> {
+        Builder::new()
+    }
+
+    /// The id of this channel, shared by all its handles and kept across
+    /// [`RingBuffer::reset_generation`].
+    pub fn channel_id(&self) -> ChannelId {
+        self.id
+    }
+
+    /// The name set with [`Builder::name`].
+    pub fn name(&self) -> Option<&str> {
+        self.config.name.as_deref()
+    }
+
+    /// The configuration this queue was built with.
+    pub fn config(&self) -> &Builder<'a, T> {
+        &self.config
+    }
+
+    /// Creates a new, empty queue with the same configuration. Nothing is
+    /// shared with `self` and no elements are copied.
+    pub fn clone_empty(&self) -> (RingBox<'a, T>, Sender<'a, T>, Receiver<'a, T>) {
+        Builder::from(self).build()
+    }
+
+    /// Creates a queue together with `n_senders` senders and `n_receivers`
+    /// receivers, with the handle counts set up front instead of by cloning.
+    ///
+    /// Either count may be zero. Cloning cannot add a handle to a side that
+    /// has none: with no receivers every send fails with
+    /// [`TrySendError::Disconnected`] until [`Sender::new_receiver`] adds
+    /// one, and with no senders the queue stays empty until
+    /// [`Receiver::new_sender`] adds one.
+    #[allow(clippy::type_complexity)]
+    pub fn new_with_handles(
+        n: usize,
+        n_senders: usize,
+        n_receivers: usize,
+    ) -> (RingBox<'a, T>, Vec<Sender<'a, T>>, Vec<Receiver<'a, T>>) {
+        let senders = u32::try_from(n_senders).expect("too many senders");
+        let receivers = u32::try_from(n_receivers).expect("too many receivers");
+        let rb = NonNull::from(Box::leak(Self::alloc(
+            Builder::new().capacity(n),
+            0,
+            senders,
+            receivers,
+        )));
+
+        let s = (0..n_senders).map(|_| Sender::new(rb, 0)).collect();
+
+        let r = (0..n_receivers).map(|_| Receiver::new(rb, 0)).collect();
+
+        (RingBox::new(rb), s, r)
+    }
+
+    // Builds a queue whose positions start at `start` rather than 0, so the
+    // tests can exercise the wraps of the positions without 2^48 operations.
+    fn new_at(n: usize, start: u64) -> (RingBox<'a, T>, Sender<'a, T>, Receiver<'a, T>) {
+        Self::with_config(Builder::new().capacity(n), start)
+    }
+
+    pub(crate) fn with_config(
+        config: Builder<'a, T>,
+        start: u64,
+    ) -> (RingBox<'a, T>, Sender<'a, T>, Receiver<'a, T>) {
+        let rb = NonNull::from(Box::leak(Self::alloc(config, start, 1, 1)));
+
+        (RingBox::new(rb), Sender::new(rb, 0), Receiver::new(rb, 0))
+    }
+
+    fn alloc(
+        config: Builder<'a, T>,
+        start: u64,
+        senders: u32,
+        receivers: u32,
+    ) -> Box<RingBuffer<'a, T>> {
+        let n = config.capacity;
+
+        assert!(n > 0, "size must be > 0, see Builder::rendezvous");
+        assert!(n <= MAX_CAPACITY, "size must be <= {}", MAX_CAPACITY);
+        assert!(config.headroom < n, "headroom must be < size");
+
+        let n = slots_for(n);
+        let placement = config.placement;
+        let cells = || {
+            if !placement.is_default() {
+                return Storage::Placed(Placed::new(&placement, n, |i| {
+                    Cell::new(first_seq(i, n, start))
+                }));
+            }
+
+            let mut v: Vec<Cell<T>> = Vec::with_capacity(n);
+
+            for i in 0..n {
+                v.push(Cell::<T>::new(first_seq(i, n, start)));
+            }
+
+            Storage::Heap(v)
+        };
+        let lane = config.lanes.map(|_| {
+            let mut config = config.clone();
+
+            config.lanes = None;
+            config.headroom = 0;
+            config.on_event = None;
+            config.on_readable = None;
+            config.on_writable = None;
+
+            Box::new(Self::init(config, cells(), start, 0, 0))
+        });
+        let mut rb = Box::new(Self::init(config, cells(), start, senders, receivers));
+
+        rb.lane = lane;
+
+        // The lane has no receivers of its own; send_priority() asks for it.
+        if receivers == 0 {
+            rb.refuse_sends();
+        }
+
+        #[cfg(feature = "registry")]
+        rb.register();
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            channel = rb.id.0,
+            name = rb.name(),
+            capacity = rb.capacity(),
+            on_full = ?rb.config.on_full,
+            "channel created"
+        );
+
+        rb
+    }
+
+    // Adds the queue, which must not move again, to the registry. Drop
+    // takes it out.
+    #[cfg(feature = "registry")]
+    fn register(&self) {
+        unsafe fn info<T>(addr: usize) -> registry::ChannelInfo {
+            let rb = &*(addr as *const RingBuffer<'_, T>);
+
+            registry::ChannelInfo {
+                name: rb.name().map(str::to_owned),
+                capacity: rb.capacity(),
+                len: rb.len(),
+                senders: rb.users.senders.load(order::HANDLE_LOAD),
+                receivers: rb.users.receivers.load(order::HANDLE_LOAD),
+                #[cfg(feature = "stats")]
+                stats: rb.stats(),
+            }
+        }
+
+        registry::register(self as *const Self as usize, info::<T>);
+    }
+
+    fn init(
+        config: Builder<'a, T>,
+        v: Storage<'a, T>,
+        start: u64,
+        senders: u32,
+        receivers: u32,
+    ) -> Self {
+        Self {
+            n: CachePadded::new(v.len() - 1),
+            v: CachePadded::new(v),
+            enq_pos: CachePadded::new(sync::AtomicU64::new(pack(0, start))),
+            deq_pos: CachePadded::new(sync::AtomicU64::new(pack(0, start))),
+            reset: Mutex::new(()),
+            retry: CachePadded::new(Retry::new()),
+            recv_waiters: CachePadded::new(Waiters::new()),
+            send_waiters: CachePadded::new(Waiters::new()),
+            users: CachePadded::new(Users::new(senders, receivers)),
+            closed: AtomicBool::new(false),
+            refs: AtomicU32::new(1),
+            dead_letter_failures: AtomicU64::new(0),
+            select: OnceLock::new(),
+            readable: Edge::new(true),
+            writable: Edge::new(false),
+            turnstile: (config.fairness == Fairness::Ticket).then(Turnstile::new),
+            lane: None,
+            wait: config.wait.budget(),
+            config,
+            id: ChannelId::next(),
+            #[cfg(feature = "stats")]
+            stats: CachePadded::new(Counters::default()),
+            _covariant: PhantomData,
+        }
+    }
+
+    /// Size and alignment of the memory [`RingBuffer::from_uninit_slice`]
+    /// needs for `capacity`, and the offset of the slot array in it.
+    pub fn uninit_layout(capacity: usize) -> Result<(Layout, usize), LayoutError> {
+        if capacity == 0 || capacity > MAX_CAPACITY {
+            return Err(LayoutError::Capacity(capacity));
+        }
+
+        let slots = slots_for(capacity);
+        let cells = Layout::array::<Cell<T>>(slots).map_err(|_| LayoutError::Capacity(capacity))?;
+        let (layout, offset) = Layout::new::<Self>()
+            .extend(cells)
+            .map_err(|_| LayoutError::Capacity(capacity))?;
+
+        Ok((layout.pad_to_align(), offset))
+    }
+}
+
+impl<T> RingBuffer<'static, T> {
+    /// Lays a queue of `capacity` out in `mem`, header first and slots after,
+    /// see [`RingBuffer::uninit_layout`]. The memory is never freed or
+    /// dropped: the queue lives as long as the program.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must not be reused or deallocated for the rest of the program
+    /// through any other path, e.g. by rebuilding the `Box` it was leaked
+    /// from.
+    #[allow(clippy::type_complexity)]
+    pub unsafe fn from_uninit_slice(
+        mem: &'static mut [MaybeUninit<u8>],
+        capacity: usize,
+    ) -> Result<
+        (
+            &'static RingBuffer<'static, T>,
+            Sender<'static, T>,
+            Receiver<'static, T>,
+        ),
+        LayoutError,
+    > {
+        let (layout, offset) = Self::uninit_layout(capacity)?;
+        let base = mem.as_mut_ptr();
+
+        if base.align_offset(layout.align()) != 0 {
+            return Err(LayoutError::Misaligned {
+                align: layout.align(),
+            });
+        }
+
+        if mem.len() < layout.size() {
+            return Err(LayoutError::TooSmall {
+                required: layout.size(),
+                provided: mem.len(),
+            });
+        }
+
+        let rb = base as *mut RingBuffer<'static, T>;
+        let cells = base.add(offset) as *mut Cell<T>;
+        let (s, r) = Self::init_in_place(rb, cells, slots_for(capacity), capacity);
+
+        Ok((&*rb, s, r))
+    }
+
+    // Builds a queue of `capacity` at `rb` over the `slots` cells at `cells`,
+    // and returns its first handles. Neither is ever freed.
+    pub(crate) unsafe fn init_in_place(
+        rb: *mut Self,
+        cells: *mut Cell<T>,
+        slots: usize,
+        capacity: usize,
+    ) -> (Sender<'static, T>, Receiver<'static, T>) {
+        debug_assert_eq!(slots, slots_for(capacity));
+
+        for i in 0..slots {
+            cells.add(i).write(Cell::new(first_seq(i, slots, 0)));
+        }
+
+        let v = Storage::Borrowed(slice::from_raw_parts_mut(cells, slots));
+
+        rb.write(Self::init(Builder::new().capacity(capacity), v, 0, 1, 1));
+
+        #[cfg(feature = "registry")]
+        (*rb).register();
+
+        let rb = NonNull::new_unchecked(rb);
+
+        (Sender::new(rb, 0), Receiver::new(rb, 0))
+    }
+}
+
+// Where the slots live: in a Vec owned by the queue, in memory it allocated
+// as the builder's placement says, or in memory handed to from_uninit_slice
+// or inside a StaticRingBuffer, which is never freed.
+enum Storage<'a, T> {
+    Heap(Vec<Cell<T>>),
+    Placed(Placed<T>),
+    Borrowed(&'a mut [Cell<T>]),
+}
+
+impl<'a, T> Deref for Storage<'a, T> {
+    type Target = [Cell<T>];
+
+    fn deref(&self) -> &[Cell<T>] {
+        match self {
+            Storage::Heap(v) => v,
+            Storage::Placed(v) => v,
+            Storage::Borrowed(v) => v,
+        }
+    }
+}
+
+impl<'a, T> DerefMut for Storage<'a, T> {
+    fn deref_mut(&mut self) -> &mut [Cell<T>] {
+        match self {
+            Storage::Heap(v) => v,
+            Storage::Placed(v) => v,
+            Storage::Borrowed(v) => v,
+        }
+    }
+}
+
+// Number of slots for `capacity` elements: a power of two, and at least two
+// since in a single slot a published element and the free slot of the next
+// lap would have the same sequence number.
+fn slots_for(capacity: usize) -> usize {
+    capacity.next_power_of_two().max(2)
+}
+
+// Sequence number of slot `i` out of `slots` in a queue whose positions start
+// at `start`: the first position at or after `start` that maps to the slot.
+fn first_seq(i: usize, slots: usize, start: u64) -> u64 {
+    let mask = (slots - 1) as u64;
+    let i = i as u64;
+
+    start.wrapping_add(i.wrapping_sub(start) & mask)
+}
+
+// Points where tests can run code inside an operation, e.g. to freeze a
+// sender half way through. Hooks are per thread.
+#[cfg(test)]
+pub(crate) mod hooks {
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        pub(super) static BEFORE_PUBLISH: RefCell<Option<Box<dyn FnMut()>>> = RefCell::new(None);
+        // Passes through the retry loops of the operations, see progress.rs.
+        pub(crate) static ITERATIONS: Cell<u64> = const { Cell::new(0) };
+        // Claims to fail before attempting the CAS, as if another thread won.
+        pub(crate) static FAIL_CLAIMS: Cell<u64> = const { Cell::new(0) };
+        pub(crate) static LOCKS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    // Between a sender writing the payload and publishing the slot.
+    pub(super) fn before_publish() {
+        BEFORE_PUBLISH.with(|hook| {
+            if let Some(hook) = hook.borrow_mut().as_mut() {
+                hook();
+            }
+        });
+    }
+
+    // At the top of every pass through a retry loop.
+    pub(crate) fn iteration() {
+        ITERATIONS.with(|n| n.set(n.get() + 1));
+    }
+
+    // Before a claiming CAS: whether to fail it.
+    pub(super) fn fail_claim() -> bool {
+        FAIL_CLAIMS.with(|n| {
+            let fail = n.get() > 0;
+
+            n.set(n.get().saturating_sub(1));
+            fail
+        })
+    }
+
+    // On every lock the queue takes.
+    pub(crate) fn lock() {
+        LOCKS.with(|n| n.set(n.get() + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wait::WaitProfile;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn it_works() {
+        let result = 2 + 2;
+        assert_eq!(result, 4);
+    }
+
+    #[test]
+    fn positions() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(8);
+
+        assert_eq!(q.positions(), (0, 0));
+
+        for i in 0..5 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv(), Ok(0));
+        assert_eq!(r.recv(), Ok(1));
+
+        let (enq, deq) = q.positions();
+
+        assert_eq!((enq, deq), (5, 2));
+        assert_eq!(enq - deq, 3);
+        assert_eq!(r.position(), deq);
+
+        while r.recv().is_ok() {}
+
+        let (enq, deq) = q.positions();
+
+        assert_eq!(enq, deq);
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn positions_past_u32() {
+        let start = u32::MAX as u64 - 2;
+        let (q, s, r) = RingBuffer::<u64>::new_at(4, start);
+
+        for i in 0..4 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv(), Ok(0));
+        assert_eq!(q.positions(), (start + 4, start + 1));
+        assert_eq!(r.position(), start + 1);
+        assert_eq!(q.snapshot().deq_pos, start + 1);
+    }
+
+    #[test]
+    fn wrap() {
+        for n in [1, 3, 8] {
+            let (q, s, r) = RingBuffer::<u64>::new_at(n, u64::MAX - 20);
+            let mut sent = 0;
+            let mut next = 0;
+
+            for _ in 0..64 {
+                while s.send(sent) {
+                    sent += 1;
+                }
+
+                assert!(!q.empty());
+
+                while let Ok(v) = r.recv() {
+                    assert_eq!(v, next);
+                    next += 1;
+                }
+
+                assert!(q.empty());
+            }
+
+            assert_eq!(next, sent);
+
+            let (enq, deq) = q.positions();
+
+            assert_eq!(enq, deq);
+            assert!(enq < 1 << 16, "positions wrapped past 2^64");
+        }
+    }
+
+    #[test]
+    fn wrap_words() {
+        // The words wrap at 2^48 while the positions go on.
+        let start = POS_MASK - 20;
+
+        for n in [1, 3, 8] {
+            let (q, s, r) = RingBuffer::<u64>::new_at(n, start);
+            let mut sent = 0;
+
+            for _ in 0..64 {
+                while s.send(sent) {
+                    sent += 1;
+                }
+
+                for i in sent - n as u64..sent {
+                    assert_eq!(r.recv(), Ok(i));
+                }
+
+                assert!(q.empty());
+                assert_eq!(q.len(), 0);
+            }
+
+            assert!(unpack(q.enq_pos.load(order::SNAPSHOT)).1 < start);
+            assert_eq!(q.positions(), (start + sent, start + sent));
+        }
+    }
+
+    #[test]
+    fn len_across_wrap() {
+        // Starts around the 2^64 wrap and the sign flip of the distance.
+        for start in [0, u64::MAX - 5, u64::MAX, i64::MAX as u64 - 3, POS_MASK - 5] {
+            let (q, s, r) = RingBuffer::<u64>::new_at(3, start);
+            let capacity = q.remaining_capacity();
+            let mut state = 0x9e37_79b9_7f4a_7c15u64 ^ start;
+            let mut len = 0;
+
+            assert_eq!((capacity, q.len(), q.is_full()), (3, 0, false));
+
+            for _ in 0..10_000 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+
+                if state.is_multiple_of(2) {
+                    len += s.send(0) as usize;
+                } else {
+                    len -= r.recv().is_ok() as usize;
+                }
+
+                assert_eq!(q.len(), len);
+                assert_eq!(s.remaining_capacity(), capacity - len);
+                assert_eq!(r.is_full(), len == capacity);
+            }
+        }
+
+        // Racing handles never see a length out of bounds.
+        let (_q, s, r) = RingBuffer::<u64>::new_at(3, u64::MAX - 1000);
+
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                let (s, r) = (s.clone(), r.clone());
+
+                scope.spawn(move || {
+                    for i in 0..20_000 {
+                        s.send(i);
+                        r.recv().ok();
+                    }
+                });
+            }
+
+            for _ in 0..20_000 {
+                let len = s.len();
+
+                assert!(len <= 3, "{}", len);
+                assert!(r.remaining_capacity() <= 3);
+            }
+        });
+    }
+
+    #[test]
+    fn exact_capacity() {
+        for n in [1, 2, 3, 5, 128, 1000] {
+            let (q, s, r) = RingBuffer::<u64>::new_boxed(n);
+
+            assert_eq!(q.capacity(), n);
+
+            // Twice, so the second round starts mid-ring.
+            for _ in 0..2 {
+                for i in 0..n as u64 {
+                    assert_eq!(s.try_send(i), Ok(()), "capacity {}", n);
+                }
+
+                assert_eq!(s.try_send(n as u64), Err(TrySendError::Full(n as u64)));
+                assert!(q.is_full());
+
+                let drained: Vec<_> = std::iter::from_fn(|| r.recv().ok()).collect();
+
+                assert_eq!(drained, (0..n as u64).collect::<Vec<_>>());
+            }
+
+            // Batches stop at the capacity too.
+            let items: Vec<u64> = (0..n as u64 + 1).collect();
+
+            assert_eq!(s.try_send_many(&items, false), Ok(n));
+            assert_eq!(s.try_send_many(&items, false), Err(TrySendError::Full(())));
+        }
+    }
+
+    #[test]
+    fn with_handles() {
+        let (q, mut s, mut r) = RingBuffer::<u64>::new_with_handles(64, 4, 16);
+
+        assert_eq!((s.len(), r.len()), (4, 16));
+
+        for (i, s) in s.iter_mut().enumerate() {
+            assert!(s.send(i as u64));
+        }
+
+        let mut got: Vec<u64> = r.iter_mut().filter_map(|r| r.recv().ok()).collect();
+
+        got.sort();
+        assert_eq!(got, [0, 1, 2, 3]);
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 4);
+        assert_eq!(q.users.receivers.load(order::HANDLE_LOAD), 16);
+
+        drop(s);
+        drop(r);
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 0);
+        assert_eq!(q.users.receivers.load(order::HANDLE_LOAD), 0);
+    }
+
+    #[test]
+    fn new_handles() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        drop(s);
+        assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(r.recv_blocking(), Err(TryRecvError::Disconnected));
+
+        // A restarted producer revives the channel.
+        let s = r.new_sender().unwrap();
+
+        assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+        assert!(s.send(1));
+        assert_eq!(r.recv_blocking(), Ok(1));
+
+        drop(r);
+        assert_eq!(s.try_send(2), Err(TrySendError::Disconnected(2)));
+
+        let r = s.new_receiver().unwrap();
+
+        assert_eq!(s.try_send(2), Ok(()));
+        assert_eq!(r.recv(), Ok(2));
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 1);
+        assert_eq!(q.users.receivers.load(order::HANDLE_LOAD), 1);
+
+        // Stale handles can't mint new ones.
+        let (_s1, _r1) = q.reset_generation();
+
+        assert!(r.new_sender().is_none());
+        assert!(s.new_receiver().is_none());
+
+        let full = AtomicU32::new(u32::MAX);
+
+        assert_eq!(Users::revive(&full), None);
+        assert_eq!(Users::revive(&AtomicU32::new(0)), Some(1));
+    }
+
+    #[test]
+    fn events() {
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(4)
+            .on_event({
+                let seen = seen.clone();
+
+                move |id, event| seen.lock().unwrap().push((id, event))
+            })
+            .build();
+        let id = q.channel_id();
+
+        drop(s.clone());
+        drop(r.try_clone().unwrap());
+        assert!(s.send(1) && s.send(2));
+        r.unrecv(3);
+
+        let (s1, r1) = q.reset_generation();
+
+        assert!(s1.send(4));
+        drop((s, r, s1, r1));
+        drop(q);
+
+        let events: Vec<_> = seen.lock().unwrap().drain(..).collect();
+
+        assert!(events.iter().all(|&(from, _)| from == id));
+        assert_eq!(
+            events.into_iter().map(|(_, e)| e).collect::<Vec<_>>(),
+            [
+                Event::SenderAdded { senders: 2 },
+                Event::SenderDropped { senders: 1 },
+                Event::ReceiverAdded { receivers: 2 },
+                Event::ReceiverDropped { receivers: 1 },
+                Event::SenderAdded { senders: 2 },
+                Event::ReceiverAdded { receivers: 2 },
+                Event::SenderDropped { senders: 1 },
+                Event::ReceiverDropped { receivers: 1 },
+                Event::SenderDropped { senders: 0 },
+                Event::ReceiverDropped { receivers: 0 },
+                // The reset discarded 1 to 3, leaving 4.
+                Event::Dropped { len: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn try_clone() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(Users::acquire(&q.users.senders), Some(2));
+        assert_eq!(Users::release(&q.users.senders), 1);
+
+        let zero = AtomicU32::new(0);
+
+        assert_eq!(Users::acquire(&zero), None);
+
+        let full = AtomicU32::new(u32::MAX);
+
+        assert_eq!(Users::acquire(&full), None);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                let s = s.clone();
+                let r = r.clone();
+
+                scope.spawn(move || {
+                    for _ in 0..1000 {
+                        let s2 = s.try_clone().unwrap();
+                        let r2 = r.try_clone().unwrap();
+
+                        drop(s2);
+                        drop(r2);
+                    }
+
+                    // Last drop of this thread's handles races the other
+                    // threads' clones.
+                });
+            }
+        });
+
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 1);
+        assert_eq!(q.users.receivers.load(order::HANDLE_LOAD), 1);
+    }
+
+    #[test]
+    fn clone_empty() {
+        let (a, s, _r) = RingBuffer::<u64>::builder().capacity(100).build();
+
+        assert!(s.send(7));
+
+        let (b, s2, r2) = a.clone_empty();
+
+        assert_eq!(format!("{:?}", a.config()), format!("{:?}", b.config()));
+        assert_eq!(
+            format!("{:?}", Builder::from(&*b)),
+            "Builder { name: None, capacity: 100, headroom: 0, wait: Balanced, dead_letter: false, \
+             lanes: None, on_full: Reject, fairness: Unfair, on_event: false, \
+             on_readable: false, on_writable: false, placement: Placement { align: None, \
+             huge_pages: false, node: None } }"
+        );
+        assert_eq!(a.capacity(), b.capacity());
+        assert!(b.empty());
+
+        assert!(s2.send(8));
+        assert_eq!(r2.recv(), Ok(8));
+        assert!(!a.empty());
+        assert_eq!(a.positions(), (1, 0));
+        assert_eq!(
+            format!("{:?}", b),
+            "RingBuffer { config: Builder { name: None, capacity: 100, headroom: 0, wait: Balanced, \
+             dead_letter: false, lanes: None, on_full: Reject, fairness: Unfair, on_event: false, \
+             on_readable: false, on_writable: false, placement: Placement { align: None, huge_pages: false, node: None } }, \
+             capacity: 100, \
+             enq_pos: 1, deq_pos: 1, senders: 1, receivers: 1 }"
+        );
+    }
+
+    // Leaks `len` bytes aligned to 128.
+    fn leak(len: usize) -> &'static mut [MaybeUninit<u8>] {
+        #[repr(align(128))]
+        #[derive(Clone, Copy)]
+        struct Block(#[allow(dead_code)] [MaybeUninit<u8>; 128]);
+
+        let blocks = vec![Block([MaybeUninit::uninit(); 128]); len.div_ceil(128)];
+        let mem = Box::leak(blocks.into_boxed_slice());
+
+        unsafe { slice::from_raw_parts_mut(mem.as_mut_ptr().cast(), len) }
+    }
+
+    #[test]
+    fn uninit_slice() {
+        let (layout, _) = RingBuffer::<u64>::uninit_layout(6).unwrap();
+
+        assert!(layout.align() <= 128);
+        assert_eq!(
+            unsafe { RingBuffer::<u64>::from_uninit_slice(leak(layout.size() - 1), 6) }.err(),
+            Some(LayoutError::TooSmall {
+                required: layout.size(),
+                provided: layout.size() - 1,
+            })
+        );
+        assert_eq!(
+            unsafe { RingBuffer::<u64>::from_uninit_slice(&mut leak(layout.size() + 1)[1..], 6) }
+                .err(),
+            Some(LayoutError::Misaligned {
+                align: layout.align(),
+            })
+        );
+        assert_eq!(
+            unsafe { RingBuffer::<u64>::from_uninit_slice(leak(layout.size()), 0) }.err(),
+            Some(LayoutError::Capacity(0))
+        );
+
+        let (q, s, r) =
+            unsafe { RingBuffer::<u64>::from_uninit_slice(leak(layout.size()), 6) }.unwrap();
+
+        assert_eq!(q.capacity(), 6);
+
+        for round in 0..5 {
+            for i in 0..6 {
+                assert!(s.send(round * 6 + i));
+            }
+
+            assert!(!s.send(0));
+
+            for i in 0..6 {
+                assert_eq!(r.recv(), Ok(round * 6 + i));
+            }
+        }
+
+        drop(s);
+        drop(r);
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 0);
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn reset_generation() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+        let s2 = s.clone();
+
+        assert!(s.send(1));
+        assert!(s.send(2));
+        assert_eq!(r.recv(), Ok(1));
+
+        let (s1, r1) = q.reset_generation();
+
+        assert_eq!(q.generation(), 1);
+        assert!(q.empty());
+        assert_eq!(s.try_send(3), Err(TrySendError::Stale(3)));
+        assert_eq!(s2.try_send(4), Err(TrySendError::Stale(4)));
+        assert!(!s.send(5));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Stale));
+        assert_eq!(r.recv(), Err(true));
+
+        // Old clones stay stale, new ones inherit the new generation.
+        let s3 = s.try_clone().unwrap();
+        let r2 = r1.clone();
+
+        assert_eq!(s3.try_send(6), Err(TrySendError::Stale(6)));
+        assert_eq!(r1.try_recv(), Err(TryRecvError::Empty));
+
+        for i in 0..4 {
+            assert_eq!(s1.try_send(i), Ok(()));
+        }
+
+        assert_eq!(s1.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Stale));
+
+        for i in 0..4 {
+            assert_eq!(r2.recv(), Ok(i));
+        }
+
+        assert_eq!(r1.recv(), Err(false));
+        assert_eq!(q.users.senders.load(order::HANDLE_LOAD), 4);
+        assert_eq!(q.users.receivers.load(order::HANDLE_LOAD), 3);
+    }
+
+    #[test]
+    fn owned_elements() {
+        let (s, r) = RingBuffer::<String>::new(4);
+
+        assert!(s.send("a".to_string()));
+        assert_eq!(s.send_replace("b".to_string()), Ok(None));
+        assert_eq!(r.recv().as_deref(), Ok("a"));
+
+        // Redelivery moves the element around as well.
+        drop(r.recv_ack().unwrap());
+
+        let g = r.recv_ack().unwrap();
+
+        assert_eq!((g.as_str(), g.redeliveries()), ("b", 1));
+        assert_eq!(g.ack(), "b");
+        assert_eq!(r.recv(), Err(false));
+    }
+
+    #[test]
+    fn dropped_with_elements() {
+        let d = Arc::new(0);
+        let (q, s, r) = RingBuffer::new_boxed(8);
+
+        for _ in 0..5 {
+            assert!(s.send(d.clone()));
+        }
+
+        drop(r.recv().unwrap());
+        r.unrecv(d.clone());
+        assert_eq!(Arc::strong_count(&d), 6);
+
+        // With no dead-letter queue the leftovers are dropped with the ring.
+        drop((s, r));
+        drop(q);
+        assert_eq!(Arc::strong_count(&d), 1);
+    }
+
+    #[test]
+    fn discarded_elements_dropped() {
+        let d = Arc::new(0);
+        let (q, s, r) = RingBuffer::new_boxed(8);
+
+        for _ in 0..6 {
+            assert!(s.send(d.clone()));
+        }
+
+        assert_eq!(r.skip(2), 2);
+        assert!(r.recv_latest().is_ok());
+        assert_eq!(Arc::strong_count(&d), 1);
+
+        for _ in 0..3 {
+            assert!(s.send(d.clone()));
+        }
+
+        r.unrecv(d.clone());
+        drop(q.reset_generation());
+        assert_eq!(Arc::strong_count(&d), 1);
+    }
+
+    #[test]
+    fn reset_racing_senders() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(64);
+        let stale = AtomicU32::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let s = s.clone();
+                let stale = &stale;
+
+                scope.spawn(move || loop {
+                    match s.try_send(1) {
+                        Ok(()) | Err(TrySendError::Full(_)) => (),
+                        Err(TrySendError::Stale(_)) => {
+                            stale.fetch_add(1, order::HANDLE_UP);
+                            return;
+                        }
+                        Err(TrySendError::Closed(_) | TrySendError::Disconnected(_)) => {
+                            unreachable!()
+                        }
+                    }
+                });
+            }
+
+            while r.recv().is_ok() {}
+
+            let (s1, r1) = q.reset_generation();
+
+            for _ in 0..1000 {
+                assert!(s1.send(2));
+                assert_eq!(r1.recv(), Ok(2));
+            }
+
+            assert_eq!(r1.recv(), Err(false));
+        });
+
+        assert_eq!(stale.load(order::HANDLE_LOAD), 4);
+    }
+
+    #[test]
+    fn memory_footprint() {
+        for n in [1, 7, 8, 100, 1000] {
+            let (q, _s, _r) = RingBuffer::<u64>::new_boxed(n);
+            let estimate = RingBuffer::<u64>::builder()
+                .capacity(n)
+                .estimate_footprint();
+
+            assert_eq!(q.memory_footprint(), estimate);
+            assert_eq!(estimate.cells_bytes, n.next_power_of_two().max(2) * 16);
+            assert_eq!(
+                estimate.total_bytes,
+                estimate.cells_bytes + estimate.overhead_bytes
+            );
+        }
+
+        let (q, _s, _r) = RingBuffer::<[u8; 3]>::new_boxed(5);
+
+        assert_eq!(
+            q.memory_footprint(),
+            Builder::from(&*q).estimate_footprint()
+        );
+        assert_eq!(q.memory_footprint().cells_bytes, 8 * 16);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn on_full() {
+        let (dead, dead_r) = RingBuffer::<u64>::new(4);
+
+        for policy in [
+            OnFull::Reject,
+            OnFull::Block,
+            OnFull::Overwrite,
+            OnFull::Evict,
+        ] {
+            let (q, s, r) = RingBuffer::builder()
+                .capacity(3)
+                .on_full(policy)
+                .dead_letter(dead.clone())
+                .build();
+            let capacity = q.capacity() as u64;
+
+            for i in 0..capacity {
+                assert!(s.send(i));
+            }
+
+            // The same call on a full queue.
+            let sent = std::thread::scope(|scope| {
+                let sending = scope.spawn(|| s.send(100));
+
+                if policy == OnFull::Block {
+                    std::thread::sleep(Duration::from_millis(20));
+                    assert!(!sending.is_finished());
+                    assert_eq!(r.recv(), Ok(0));
+                }
+
+                sending.join().unwrap()
+            });
+            let rest: Vec<_> = std::iter::from_fn(|| r.recv().ok()).collect();
+            let evicted: Vec<_> = std::iter::from_fn(|| dead_r.recv().ok()).collect();
+
+            match policy {
+                OnFull::Reject => {
+                    assert!(!sent);
+                    assert_eq!(rest, (0..capacity).collect::<Vec<_>>());
+                }
+                // Whether a receiver or the send took the oldest element.
+                OnFull::Block | OnFull::Overwrite | OnFull::Evict => {
+                    assert!(sent);
+                    assert_eq!(rest, (1..capacity).chain([100]).collect::<Vec<_>>());
+                }
+            }
+
+            assert_eq!(
+                evicted,
+                if policy == OnFull::Evict {
+                    vec![0]
+                } else {
+                    vec![]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn headroom() {
+        let (_q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(6)
+            .reserve_headroom(3)
+            .build();
+        let p = s.try_clone_priority().unwrap();
+
+        // 6 elements, 3 of them reserved.
+        for i in 0..3 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(s.try_send(3), Err(TrySendError::Full(3)));
+
+        for i in 3..6 {
+            assert!(p.send_reserved(i));
+        }
+
+        assert!(!p.send_reserved(6));
+        assert!(!s.send(6));
+
+        for i in 0..6 {
+            assert_eq!(r.recv(), Ok(i));
+        }
+
+        assert!(s.send(8));
+        assert_eq!(r.recv(), Ok(8));
+    }
+
+    #[test]
+    fn lane_weights() {
+        const ITEMS: usize = 100_000;
+
+        let (_q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(16)
+            .lane_weights(4, 1)
+            .build();
+        let p = s.try_clone_priority().unwrap();
+
+        // Both lanes kept full, priority elements are 1s.
+        while p.send_reserved(1) {}
+        while s.send(0) {}
+
+        let mut priority = 0;
+
+        for _ in 0..ITEMS {
+            match r.recv() {
+                Ok(1) => {
+                    priority += 1;
+                    assert!(p.send_reserved(1));
+                }
+                Ok(_) => assert!(s.send(0)),
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        let ratio = priority as f64 / (ITEMS - priority) as f64;
+
+        assert!((ratio - 4.0).abs() < 0.01, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn lane_weights_idle_priority() {
+        let (_q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(16)
+            .lane_weights(4, 1)
+            .build();
+        let p = s.try_clone_priority().unwrap();
+
+        // An empty priority lane takes no turns from the bulk lane.
+        for i in 0..100_000 {
+            assert!(s.send(i));
+            assert_eq!(r.recv(), Ok(i));
+        }
+
+        for i in 0..3 {
+            assert!(s.send(i));
+        }
+
+        assert!(p.send_reserved(100));
+        assert_eq!(r.recv(), Ok(100));
+        assert_eq!(
+            std::iter::from_fn(|| r.recv().ok()).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn lane_reset() {
+        let (q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(4)
+            .lane_weights(2, 1)
+            .build();
+        let p = s.try_clone_priority().unwrap();
+
+        assert!(p.send_reserved(1));
+        assert!(!q.empty());
+
+        let (_s2, r2) = q.reset_generation();
+
+        // The priority lane was emptied and moved on with the queue.
+        assert!(q.empty());
+        assert!(!p.send_reserved(2));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Stale));
+        assert_eq!(r2.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    #[should_panic(expected = "headroom must be < size")]
+    fn headroom_too_large() {
+        RingBuffer::<u64>::builder()
+            .capacity(4)
+            .reserve_headroom(4)
+            .build();
+    }
+
+    #[test]
+    fn ack() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+        let r2 = r.clone();
+
+        for i in 0..3 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv_ack().unwrap().ack(), 0);
+
+        // Dropped without ack: redelivered first, to any receiver.
+        let g = r.recv_ack().unwrap();
+
+        assert_eq!((*g, g.redeliveries()), (1, 0));
+        drop(g);
+
+        let g = r2.recv_ack().unwrap();
+
+        assert_eq!((*g, g.redeliveries()), (1, 1));
+        drop(g);
+        assert_eq!(r.recv_ack().unwrap().redeliveries(), 2);
+
+        let g = r.recv_ack().unwrap();
+
+        assert_eq!(g.redeliveries(), 3);
+        assert_eq!(g.ack(), 1);
+        assert!(!q.empty());
+        assert_eq!(r.recv(), Ok(2));
+        assert!(q.empty());
+        assert_eq!(r.recv_ack().err(), Some(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn ack_after_panic() {
+        let (s, r) = RingBuffer::<u64>::new(4);
+
+        assert!(s.send(7));
+
+        std::thread::scope(|scope| {
+            let r1 = r.clone();
+            let worker = scope.spawn(move || {
+                let _g = r1.recv_ack().unwrap();
+
+                panic!("worker failed");
+            });
+
+            assert!(worker.join().is_err());
+
+            let r2 = r.clone();
+            let worker = scope.spawn(move || loop {
+                if let Ok(g) = r2.recv_ack() {
+                    assert_eq!(g.redeliveries(), 1);
+                    return g.ack();
+                }
+            });
+
+            assert_eq!(worker.join().unwrap(), 7);
+        });
+    }
+
+    #[test]
+    fn ack_across_reset() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+        let r2 = r.clone();
+
+        assert!(s.send(1));
+        assert!(s.send(2));
+
+        let g = r2.recv_ack().unwrap();
+
+        drop(r.recv_ack().unwrap());
+
+        let (_s1, r1) = q.reset_generation();
+
+        // Neither the queued nor the outstanding element reaches the new
+        // generation.
+        drop(g);
+        assert!(q.empty());
+        assert_eq!(r1.recv_ack().err(), Some(TryRecvError::Empty));
+        assert_eq!(r.recv_ack().err(), Some(TryRecvError::Stale));
+    }
+
+    #[test]
+    fn unrecv_full() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(8);
+
+        for i in 0..8 {
+            assert!(s.send(i));
+        }
+
+        let d = r.recv().unwrap();
+
+        assert!(s.send(8));
+        assert!(!s.send(9));
+
+        r.unrecv(d);
+        assert_eq!(r.recv(), Ok(0));
+
+        r.unrecv(100);
+        r.unrecv(101);
+
+        let mut rest = Vec::new();
+
+        while let Ok(d) = r.recv() {
+            rest.push(d);
+        }
+
+        assert_eq!(rest, [100, 101, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn skip() {
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        for i in 0..5 {
+            assert!(s.send(i));
+        }
+
+        r.unrecv(9);
+
+        assert_eq!(r.skip(3), 3);
+        assert_eq!(r.recv(), Ok(2));
+        assert_eq!(r.skip(10), 2);
+        assert_eq!(r.skip(1), 0);
+        assert!(r.empty());
+    }
+
+    #[test]
+    fn recv_latest() {
+        let (s, r) = RingBuffer::<u64>::new(1024);
+
+        assert_eq!(r.recv_latest(), Err(TryRecvError::Empty));
+
+        for i in 1..=1000 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv_latest(), Ok(1000));
+        assert!(r.empty());
+
+        r.unrecv(7);
+        assert_eq!(r.recv_latest(), Ok(7));
+        assert_eq!(r.recv_latest(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn skip_racing_receivers() {
+        const ITEMS: u64 = 10_000;
+
+        let (s, r) = RingBuffer::<u64>::new(16);
+        let done = AtomicU32::new(0);
+
+        let received: Vec<Vec<u64>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|id| {
+                    let r = r.clone();
+                    let done = &done;
+
+                    scope.spawn(move || {
+                        let mut got = Vec::new();
+
+                        while done.load(order::HANDLE_LOAD) < ITEMS as u32 {
+                            // One worker skips, the others receive.
+                            let n = if id == 0 {
+                                r.skip(3)
+                            } else {
+                                r.recv().map(|d| got.push(d)).map_or(0, |_| 1)
+                            };
+
+                            if n == 0 {
+                                std::thread::yield_now();
+                            }
+
+                            done.fetch_add(n as u32, order::HANDLE_UP);
+                        }
+
+                        got
+                    })
+                })
+                .collect();
+
+            for i in 0..ITEMS {
+                while !s.send(i) {
+                    std::thread::yield_now();
+                }
+            }
+
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        let mut received: Vec<u64> = received.into_iter().flatten().collect();
+        let n = received.len();
+
+        // Every element was either skipped or received, and none twice.
+        received.sort_unstable();
+        received.dedup();
+        assert_eq!(received.len(), n);
+        assert_eq!(done.load(order::HANDLE_LOAD), ITEMS as u32);
+    }
+
+    #[test]
+    fn unrecv_racing_receivers() {
+        const ITEMS: u64 = 10_000;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(16);
+        let done = AtomicU32::new(0);
+
+        let sums: Vec<u64> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|id| {
+                    let r = r.clone();
+                    let done = &done;
+
+                    scope.spawn(move || {
+                        let mut sum = 0;
+                        let mut bounced = false;
+
+                        while done.load(order::HANDLE_LOAD) < ITEMS as u32 {
+                            let Ok(d) = r.recv() else {
+                                std::thread::yield_now();
+                                continue;
+                            };
+
+                            // Every other element a worker gets goes back once.
+                            if (d + id).is_multiple_of(2) && !bounced {
+                                bounced = true;
+                                r.unrecv(d);
+                                continue;
+                            }
+
+                            bounced = false;
+                            sum += d;
+                            done.fetch_add(1, order::HANDLE_UP);
+                        }
+
+                        sum
+                    })
+                })
+                .collect();
+
+            for i in 0..ITEMS {
+                while !s.send(i) {
+                    std::thread::yield_now();
+                }
+            }
+
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        assert_eq!(sums.iter().sum::<u64>(), ITEMS * (ITEMS - 1) / 2);
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn recv_if() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(r.recv_if(|_| true), Err(TryRecvError::Empty));
+
+        for i in [2, 3, 4] {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv_if(|d| d.is_multiple_of(2)), Ok(Some(2)));
+        assert_eq!(r.recv_if(|d| d.is_multiple_of(2)), Ok(None));
+        assert_eq!(r.recv(), Ok(3));
+
+        // The redelivery queue is the head while it has elements.
+        r.unrecv(5);
+        assert_eq!(r.recv_if(|d| d.is_multiple_of(2)), Ok(None));
+        assert_eq!(r.recv_if(|&d| d == 5), Ok(Some(5)));
+        assert_eq!(r.recv_if(|d| d.is_multiple_of(2)), Ok(Some(4)));
+        assert!(q.empty());
+
+        let (_s1, _r1) = q.reset_generation();
+
+        assert_eq!(r.recv_if(|_| true), Err(TryRecvError::Stale));
+    }
+
+    #[test]
+    fn peek() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(r.peek(), Err(TryRecvError::Empty));
+
+        // The one receiver gets what it peeked.
+        assert!(s.send(1) && s.send(2));
+        assert_eq!(r.peek(), Ok(1));
+        assert_eq!(r.peek_with(|d| d * 10), Ok(10));
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(r.peek(), Ok(2));
+
+        // The redelivery queue is the head while it has elements.
+        r.unrecv(5);
+        assert_eq!(r.peek(), Ok(5));
+        assert_eq!(r.recv(), Ok(5));
+        assert_eq!(r.recv(), Ok(2));
+        assert_eq!(q.len(), 0);
+
+        drop(s);
+        assert_eq!(r.peek(), Err(TryRecvError::Disconnected));
+
+        let (_s1, _r1) = q.reset_generation();
+
+        assert_eq!(r.peek(), Err(TryRecvError::Stale));
+    }
+
+    #[test]
+    fn concurrent_peeks() {
+        const ITEMS: u64 = 10_000;
+
+        // Every payload is four copies of one number, so a torn copy shows.
+        let (s, r) = RingBuffer::<[u64; 4]>::new(4);
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                let (r, done) = (&r, &done);
+
+                scope.spawn(move || {
+                    let mut last = 0;
+
+                    while !done.load(order::COUNTER) {
+                        if let Ok(d) = r.peek() {
+                            assert!(d.iter().all(|&x| x == d[0]), "torn {:?}", d);
+                            // Heads only move forwards.
+                            assert!(d[0] >= last);
+                            last = d[0];
+                        }
+
+                        std::thread::yield_now();
+                    }
+                });
+            }
+
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    while !s.send([i; 4]) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            for i in 0..ITEMS {
+                loop {
+                    match r.recv() {
+                        Ok(d) => break assert_eq!(d, [i; 4]),
+                        Err(_) => std::thread::yield_now(),
+                    }
+                }
+            }
+
+            done.store(true, order::COUNTER);
+        });
+    }
+
+    #[test]
+    fn claim_in_place() {
+        let (q, s, r) = RingBuffer::<[u64; 32]>::new_boxed(2);
+        let mut slot = s.claim().unwrap();
+
+        // Built in the ring, and not received before it is published.
+        assert_eq!(*slot, [0; 32]);
+        slot[0] = 1;
+        slot[31] = 2;
+        assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Pending));
+        slot.publish();
+
+        let head = r.recv_ref().unwrap();
+
+        assert_eq!((head[0], head[31]), (1, 2));
+
+        // Until released, the slot is not free for the senders a lap later.
+        drop(s.claim().unwrap());
+        assert_eq!(s.claim().err(), Some(TrySendError::Full(())));
+        drop(head);
+        drop(s.claim().unwrap());
+        assert_eq!(q.len(), 2);
+
+        // Redelivered elements are lent out too.
+        assert_eq!(r.recv(), Ok([0; 32]));
+        r.unrecv([7; 32]);
+        assert_eq!(r.recv_ref().unwrap()[0], 7);
+        assert_eq!(r.recv(), Ok([0; 32]));
+        assert_eq!(r.recv_ref().err(), Some(TryRecvError::Empty));
+
+        s.close();
+        assert_eq!(s.claim().err(), Some(TrySendError::Closed(())));
+
+        // A lent out element is dropped with its guard.
+        let token = Arc::new(());
+        let (s, r) = RingBuffer::<Option<Arc<()>>>::new(2);
+
+        assert!(s.send(Some(token.clone())));
+
+        let head = r.recv_ref().unwrap();
+
+        assert_eq!(Arc::strong_count(&token), 2);
+        drop(head);
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn concurrent_claims() {
+        const ITEMS: u64 = 2_000;
+
+        // Every payload is sixteen copies of one number, so a torn element
+        // shows.
+        let (s, r) = RingBuffer::<[u64; 16]>::new(4);
+        let received = AtomicU64::new(0);
+        let sum = AtomicU64::new(0);
+
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let s = &s;
+
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        let mut slot = loop {
+                            match s.claim() {
+                                Ok(slot) => break slot,
+                                Err(_) => std::thread::yield_now(),
+                            }
+                        };
+
+                        slot.fill(p * ITEMS + i + 1);
+                    }
+                });
+            }
+
+            for _ in 0..2 {
+                let (r, received, sum) = (&r, &received, &sum);
+
+                scope.spawn(move || {
+                    while received.load(order::COUNTER) < 2 * ITEMS {
+                        let Ok(d) = r.recv_ref() else {
+                            std::thread::yield_now();
+                            continue;
+                        };
+
+                        assert!(d.iter().all(|&x| x == d[0]), "torn {:?}", *d);
+                        sum.fetch_add(d[0], order::COUNTER);
+                        received.fetch_add(1, order::COUNTER);
+                    }
+                });
+            }
+        });
+
+        let n = 2 * ITEMS;
+
+        assert_eq!(sum.load(order::COUNTER), n * (n + 1) / 2);
+    }
+
+    #[test]
+    fn claim_many() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+        let mut burst = s.claim_many(3).unwrap();
+
+        assert_eq!(burst.len(), 3);
+
+        for (i, d) in burst.iter_mut().enumerate() {
+            *d = i as u64 + 1;
+        }
+
+        // Nothing is received before the whole burst is published, and a
+        // burst only gets slots if they are all free.
+        assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Pending));
+        assert_eq!(s.claim_many(2).err(), Some(TrySendError::Full(())));
+        assert_eq!(s.claim_many(5).err(), Some(TrySendError::Full(())));
+        burst.publish();
+        assert_eq!(q.len(), 3);
+        assert!(s.claim_many(0).unwrap().is_empty());
+        assert_eq!(r.recv_n(3), [1, 2, 3]);
+
+        // Across the end of the ring.
+        for d in &mut s.claim_many(4).unwrap() {
+            *d = 9;
+        }
+
+        assert_eq!(r.recv_n(4), [9; 4]);
+    }
+
+    #[test]
+    fn concurrent_bursts() {
+        const ITEMS: u64 = 3_000;
+
+        let (s, r) = RingBuffer::<(u64, u64)>::new(8);
+
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let s = &s;
+
+                scope.spawn(move || {
+                    let mut next = 0;
+
+                    while next < ITEMS {
+                        let n = (1 + next % 3).min(ITEMS - next);
+                        let Ok(mut burst) = s.claim_many(n as usize) else {
+                            std::thread::yield_now();
+                            continue;
+                        };
+
+                        for d in &mut burst {
+                            *d = (p, next);
+                            next += 1;
+                        }
+                    }
+                });
+            }
+
+            // Each producer's elements arrive in order, none missing.
+            let mut expected = [0; 2];
+
+            while expected != [ITEMS; 2] {
+                match r.recv() {
+                    Ok((p, seq)) => {
+                        assert_eq!(seq, expected[p as usize]);
+                        expected[p as usize] += 1;
+                    }
+                    Err(_) => std::thread::yield_now(),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn drain_while() {
+        const MARK: u64 = u64::MAX;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(16);
+        let mut buf = Vec::new();
+
+        assert_eq!(r.drain_while(|_| true, &mut buf), 0);
+
+        for d in [1, 2, 3, MARK, 4, 5, MARK, 6] {
+            assert!(s.send(d));
+        }
+
+        assert_eq!(r.drain_while(|&d| d != MARK, &mut buf), 3);
+        assert_eq!(r.drain_while(|&d| d != MARK, &mut buf), 0);
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(r.recv(), Ok(MARK));
+
+        // Put-back elements are the head until they are gone.
+        r.unrecv(7);
+        assert_eq!(r.drain_while(|&d| d != MARK, &mut buf), 3);
+        assert_eq!(buf, [1, 2, 3, 7, 4, 5]);
+        assert_eq!(r.recv(), Ok(MARK));
+
+        // Across the wrap of the ring, up to the empty queue.
+        for d in 8..20 {
+            assert!(s.send(d));
+        }
+
+        buf.clear();
+        assert_eq!(r.drain_while(|_| true, &mut buf), 13);
+        assert_eq!(buf[0], 6);
+        assert!(buf[1..].iter().copied().eq(8..20));
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn batch_ready() {
+        let (s, r) = RingBuffer::<u64>::new(512);
+        let mut buf = Vec::new();
+
+        for i in 0..300 {
+            assert!(s.send(i));
+        }
+
+        let n = r.recv_batch_blocking(16, 256, Duration::from_secs(10), &mut buf);
+
+        assert_eq!(n, 256);
+        assert!(buf.iter().copied().eq(0..256));
+        assert_eq!(
+            r.recv_batch_blocking(16, 256, Duration::from_secs(10), &mut buf),
+            44
+        );
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn batch_before_timeout() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+
+        std::thread::scope(|scope| {
+            let s = s.clone();
+
+            scope.spawn(move || {
+                for i in 0..16 {
+                    std::thread::sleep(Duration::from_millis(2));
+                    assert!(s.send(i));
+                }
+            });
+
+            let wait = Duration::from_secs(10);
+            let start = Instant::now();
+            let n = r.recv_batch_blocking(16, 256, wait, &mut buf);
+
+            assert_eq!(n, 16);
+            assert!(start.elapsed() < wait);
+        });
+    }
+
+    #[test]
+    fn batch_timeout() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+
+        for i in 0..5 {
+            assert!(s.send(i));
+        }
+
+        let wait = Duration::from_millis(20);
+        let start = Instant::now();
+
+        assert_eq!(r.recv_batch_blocking(16, 256, wait, &mut buf), 5);
+        assert!(start.elapsed() >= wait);
+        assert_eq!(buf, [0, 1, 2, 3, 4]);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn batch_disconnect() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..3 {
+                    assert!(s.send(i));
+                }
+
+                std::thread::sleep(Duration::from_millis(20));
+            });
+
+            let wait = Duration::from_secs(10);
+            let start = Instant::now();
+
+            assert_eq!(r.recv_batch_blocking(16, 256, wait, &mut buf), 3);
+            assert!(start.elapsed() < wait);
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn batch_without_deadline() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                assert_eq!(s.send_batch(&[0, 1]), 2);
+            });
+
+            // Too long a wait for an Instant waits for good.
+            assert_eq!(r.recv_batch_blocking(2, 256, Duration::MAX, &mut buf), 2);
+        });
+    }
+
+    #[test]
+    fn deadline_max() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+
+        for i in 0..8 {
+            assert!(s.send(i));
+        }
+
+        let result = r.recv_batch_deadline(Instant::now() + Duration::from_secs(10), 5, &mut buf);
+
+        assert_eq!(
+            result,
+            BatchResult {
+                received: 5,
+                end: BatchEnd::Max
+            }
+        );
+        assert_eq!(buf, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn deadline_passes() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+        let wait = Duration::from_millis(20);
+        let start = Instant::now();
+
+        assert!(s.send(1));
+
+        let result = r.recv_batch_deadline(start + wait, 16, &mut buf);
+
+        assert_eq!(
+            result,
+            BatchResult {
+                received: 1,
+                end: BatchEnd::Deadline
+            }
+        );
+        assert!(start.elapsed() >= wait);
+
+        // A deadline in the past only sweeps what is there.
+        assert!(s.send(2));
+
+        let result = r.recv_batch_deadline(start, 16, &mut buf);
+
+        assert_eq!(
+            result,
+            BatchResult {
+                received: 1,
+                end: BatchEnd::Deadline
+            }
+        );
+        assert_eq!(buf, [1, 2]);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn deadline_disconnect() {
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut buf = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..3 {
+                    assert!(s.send(i));
+                }
+
+                std::thread::sleep(Duration::from_millis(20));
+            });
+
+            let result = r.recv_batch_deadline(deadline, 16, &mut buf);
+
+            assert_eq!(
+                result,
+                BatchResult {
+                    received: 3,
+                    end: BatchEnd::Disconnected
+                }
+            );
+            assert!(Instant::now() < deadline);
+        });
+    }
+
+    #[test]
+    fn channel_id() {
+        let (a, s, r) = RingBuffer::<u64>::new_boxed(4);
+        let (b, s2, _r2) = RingBuffer::<u64>::new_boxed(4);
+        let id = a.channel_id();
+
+        assert_ne!(id, b.channel_id());
+        assert_eq!(s.channel_id(), id);
+        assert_eq!(r.channel_id(), id);
+        assert_eq!(s.try_clone_priority().unwrap().channel_id(), id);
+        assert_eq!(s2.channel_id(), b.channel_id());
+
+        // Stable across handles coming and going, and generations.
+        let r3 = r.clone();
+
+        drop(r);
+        drop(s);
+        assert_eq!(r3.channel_id(), id);
+
+        let (s, r) = a.reset_generation();
+
+        assert_eq!((s.channel_id(), r.channel_id()), (id, id));
+
+        // Not reused after a drop, nor by a clone.
+        drop((s, r, r3));
+        drop(a);
+
+        let (c, _s, _r) = RingBuffer::<u64>::new_boxed(4);
+        let (d, _ds, _dr) = c.clone_empty();
+
+        assert!(c.channel_id() != id && c.channel_id() != d.channel_id());
+    }
+
+    #[test]
+    fn shutdown_drained() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(8);
+        let other = s.clone();
+
+        for i in 0..4 {
+            assert!(s.send(i));
+        }
+
+        std::thread::scope(|scope| {
+            let drain = scope.spawn(move || {
+                let mut buf = Vec::new();
+
+                // Returns once the queue is closed and empty.
+                while r.recv_batch_blocking(1, 2, Duration::from_secs(10), &mut buf) > 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+
+                buf
+            });
+
+            assert_eq!(
+                s.shutdown(Duration::from_secs(10)),
+                ShutdownOutcome::Drained
+            );
+            assert_eq!(drain.join().unwrap(), [0, 1, 2, 3]);
+        });
+
+        assert!(q.closed());
+        assert_eq!(other.try_send(4), Err(TrySendError::Closed(4)));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn shutdown_without_deadline() {
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        assert!(s.send(0));
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                assert_eq!(r.recv(), Ok(0));
+            });
+
+            assert_eq!(s.shutdown(Duration::MAX), ShutdownOutcome::Drained);
+        });
+    }
+
+    #[test]
+    fn shutdown_timed_out() {
+        let (s, _idle) = RingBuffer::<u64>::new(8);
+
+        for i in 0..3 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(
+            s.shutdown(Duration::from_millis(20)),
+            ShutdownOutcome::TimedOut { remaining: 3 }
+        );
+    }
+
+    #[test]
+    fn shutdown_abandoned() {
+        let (s, r) = RingBuffer::<u64>::new(8);
+        let r2 = r.clone();
+
+        for i in 0..4 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r2.recv(), Ok(0));
+        r2.unrecv(0);
+        drop((r, r2));
+
+        // Another sender could still touch the queue.
+        let other = s.clone();
+
+        assert_eq!(
+            other.shutdown(Duration::from_secs(10)),
+            ShutdownOutcome::Abandoned {
+                remaining: 4,
+                leftover: None
+            }
+        );
+
+        // The last handle: redelivered elements first.
+        assert_eq!(
+            s.shutdown(Duration::from_secs(10)),
+            ShutdownOutcome::Abandoned {
+                remaining: 4,
+                leftover: Some(vec![0, 1, 2, 3])
+            }
+        );
+    }
+
+    #[test]
+    fn shutdown_after_reset() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(8);
+        let (s2, r2) = q.reset_generation();
+
+        assert!(s2.send(5));
+        drop((r, r2, s2));
+
+        // The last sender, but of an older generation than the element.
+        assert_eq!(
+            s.shutdown(Duration::from_secs(10)),
+            ShutdownOutcome::Abandoned {
+                remaining: 1,
+                leftover: None
+            }
+        );
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn shutdown_receivers_leave() {
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        assert!(s.send(1));
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                drop(r);
+            });
+
+            // Woken by the drop, long before the timeout.
+            let start = Instant::now();
+
+            assert_eq!(
+                s.shutdown(Duration::from_secs(60)),
+                ShutdownOutcome::Abandoned {
+                    remaining: 1,
+                    leftover: Some(vec![1])
+                }
+            );
+            assert!(start.elapsed() < Duration::from_secs(30));
+        });
+    }
+
+    // Everything the dead-letter receiver holds, oldest first.
+    fn dead_letters(r: &mut Receiver<u64>) -> Vec<u64> {
+        std::iter::from_fn(|| r.recv().ok()).collect()
+    }
+
+    #[test]
+    fn dead_letter_reset() {
+        let (dead, mut dead_r) = RingBuffer::<u64>::new(16);
+        let (q, s, r) = RingBuffer::builder().capacity(8).dead_letter(dead).build();
+
+        for i in 1..=3 {
+            assert!(s.send(i));
+        }
+
+        let r2 = r.clone();
+        let guard = r2.recv_ack().unwrap();
+
+        r.unrecv(9);
+
+        // The redelivery queue goes first, then the ring.
+        let (_s2, _r2) = q.reset_generation();
+
+        assert_eq!(dead_letters(&mut dead_r), [9, 2, 3]);
+
+        // An unacknowledged guard of the ended generation.
+        drop(guard);
+        assert_eq!(dead_letters(&mut dead_r), [1]);
+
+        // A stale receiver putting an element back.
+        r.unrecv(4);
+        assert_eq!(dead_letters(&mut dead_r), [4]);
+        assert_eq!(q.dead_letter_failures(), 0);
+    }
+
+    #[test]
+    fn dead_letter_shutdown() {
+        let (dead, mut dead_r) = RingBuffer::<u64>::new(16);
+        let (_q, s, r) = RingBuffer::builder().capacity(8).dead_letter(dead).build();
+        let _other = s.clone();
+
+        assert!(s.send(1));
+        assert!(s.send(2));
+        drop(r);
+
+        // Another sender is alive, so the leftovers aren't handed back.
+        assert_eq!(
+            s.shutdown(Duration::ZERO),
+            ShutdownOutcome::Abandoned {
+                remaining: 2,
+                leftover: None
+            }
+        );
+        assert_eq!(dead_letters(&mut dead_r), [1, 2]);
+    }
+
+    #[test]
+    fn dead_letter_drop() {
+        let (dead, mut dead_r) = RingBuffer::<u64>::new(16);
+        let (q, s, r) = RingBuffer::builder().capacity(8).dead_letter(dead).build();
+
+        for i in 1..=3 {
+            assert!(s.send(i));
+        }
+
+        let d = r.recv().unwrap();
+
+        r.unrecv(d);
+        drop((s, r));
+        drop(q);
+
+        assert_eq!(dead_letters(&mut dead_r), [1, 2, 3]);
+    }
+
+    #[test]
+    fn dead_letter_full() {
+        let (dead, mut dead_r) = RingBuffer::<u64>::new(1);
+        let (q, s, _r) = RingBuffer::builder().capacity(16).dead_letter(dead).build();
+
+        for i in 0..10 {
+            assert!(s.send(i));
+        }
+
+        let _new = q.reset_generation();
+        let kept = dead_letters(&mut dead_r);
+
+        assert!(!kept.is_empty());
+        assert_eq!(kept.len() as u64 + q.dead_letter_failures(), 10);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn shutdown_async() {
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        for i in 0..4 {
+            assert!(s.send(i));
+        }
+
+        let mut future = std::pin::pin!(s.shutdown_async());
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                while r.recv_batch_blocking(1, 1, Duration::from_secs(10), &mut Vec::new()) > 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            });
+
+            let outcome = loop {
+                match future.as_mut().poll(&mut cx) {
+                    Poll::Ready(outcome) => break outcome,
+                    Poll::Pending => std::thread::park(),
+                }
+            };
+
+            assert_eq!(outcome, ShutdownOutcome::Drained);
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_handoff() {
+        use futures_core::Stream;
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        const ITEMS: u64 = 100_000;
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(f: F) -> F::Output {
+            let mut f = std::pin::pin!(f);
+            let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+            let mut cx = Context::from_waker(&waker);
+
+            loop {
+                match f.as_mut().poll(&mut cx) {
+                    Poll::Ready(v) => return v,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        // There and back again over two small queues, so every task waits
+        // both for room and for elements.
+        let (s, echo_r) = RingBuffer::<u64>::new(4);
+        let (echo_s, r) = RingBuffer::<u64>::new(4);
+
+        let received = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                block_on(async {
+                    for i in 0..ITEMS {
+                        s.send_async(i).await.unwrap();
+                    }
+                })
+            });
+
+            scope.spawn(move || {
+                block_on(async {
+                    while let Some(d) = echo_r.recv_async().await {
+                        echo_s.send_async(d * 2).await.unwrap();
+                    }
+                })
+            });
+
+            block_on(async {
+                let mut n = 0;
+
+                while let Some(d) = r.recv_async().await {
+                    assert_eq!(d, n * 2);
+                    n += 1;
+                }
+
+                n
+            })
+        });
+
+        assert_eq!(received, ITEMS);
+
+        // As a stream, ending at the close.
+        let (s, mut r) = RingBuffer::<u64>::new(4);
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(Pin::new(&mut r).poll_next(&mut cx).is_pending());
+        assert!(s.send(1));
+        assert_eq!(Pin::new(&mut r).poll_next(&mut cx), Poll::Ready(Some(1)));
+        s.close();
+        assert_eq!(Pin::new(&mut r).poll_next(&mut cx), Poll::Ready(None));
+
+        // A full queue keeps the send pending until a receive.
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        assert!(s.send(1) && s.send(2));
+
+        let mut send = s.send_async(3);
+
+        assert!(Pin::new(&mut send).poll(&mut cx).is_pending());
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(Pin::new(&mut send).poll(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn name() {
+        let (q, _s, _r) = RingBuffer::<u64>::builder()
+            .name("ingest")
+            .capacity(4)
+            .build();
+
+        assert_eq!(q.name(), Some("ingest"));
+        assert!(format!("{:?}", q)
+            .starts_with("RingBuffer { config: Builder { name: Some(\"ingest\"),"));
+
+        let (q, _s, _r) = q.clone_empty();
+
+        assert_eq!(q.name(), Some("ingest"));
+
+        let (q, _s, _r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(q.name(), None);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn prometheus_name() {
+        let (q, s, _r) = RingBuffer::<u64>::builder()
+            .name("in\"gest")
+            .capacity(4)
+            .build();
+
+        s.send(1);
+
+        let mut out = String::new();
+
+        q.write_prometheus(&mut out, "").unwrap();
+        assert!(
+            out.contains("mpmcbq_len{queue=\"in\\\"gest\"} 1\n"),
+            "{}",
+            out
+        );
+
+        let mut out = String::new();
+
+        q.write_prometheus(&mut out, "shard=\"3\"").unwrap();
+        assert!(
+            out.contains("mpmcbq_enqueued_total{queue=\"in\\\"gest\",shard=\"3\"} 1\n"),
+            "{}",
+            out
+        );
+    }
+
+    #[cfg(all(feature = "stats", not(feature = "single-threaded")))]
+    #[test]
+    fn stall_report() {
+        let threshold = Duration::from_millis(20);
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(q.stall_report(threshold), None);
+
+        // A stalled consumer.
+        assert!(s.send(1));
+        std::thread::sleep(threshold);
+
+        match q.stall_report(threshold) {
+            Some(StallReport::ConsumersIdle { idle, len: 1 }) => assert!(idle >= threshold),
+            report => panic!("{:?}", report),
+        }
+
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(q.stall_report(threshold), None);
+
+        // A receiver blocked with no producer making progress.
+        std::thread::scope(|scope| {
+            let r = r.clone();
+
+            scope.spawn(move || {
+                let mut buf = Vec::new();
+
+                r.recv_batch_blocking(1, 1, Duration::from_secs(10), &mut buf);
+                assert_eq!(buf, [2]);
+            });
+
+            while q.recv_waiters.sleeping() == 0 {
+                std::thread::yield_now();
+            }
+
+            std::thread::sleep(threshold);
+
+            assert!(matches!(
+                q.stall_report(threshold),
+                Some(StallReport::ProducersIdle { waiters: 1, .. })
+            ));
+            assert!(s.send(2));
+        });
+
+        assert_eq!(q.stall_report(threshold), None);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn capacity_advice() {
+        const BURST: u64 = 100;
+
+        // Bursts of 100 into a queue that holds them easily.
+        let (big, s, r) = RingBuffer::<u64>::new_boxed(1000);
+
+        for _ in 0..50 {
+            for i in 0..BURST {
+                assert!(s.send(i));
+            }
+
+            while r.recv().is_ok() {}
+        }
+
+        let advice = big.capacity_advice();
+
+        assert!(
+            (BURST as usize..=BURST as usize + BURST as usize / 8)
+                .contains(&advice.suggested_capacity),
+            "{:?}",
+            advice
+        );
+        assert_eq!(advice.p99_depth, advice.suggested_capacity);
+        assert_eq!(advice.reject_rate, 0.0);
+
+        // The same bursts into a queue too small for them.
+        let (small, s, r) = RingBuffer::<u64>::new_boxed(64);
+        let mut rejected = 0;
+
+        for _ in 0..50 {
+            rejected += (0..BURST).filter(|&i| !s.send(i)).count();
+
+            while r.recv().is_ok() {}
+        }
+
+        let advice = small.capacity_advice();
+
+        assert!(advice.suggested_capacity >= 2 * small.capacity());
+        assert!(advice.suggested_capacity as u64 >= BURST);
+        assert_eq!(advice.reject_rate, rejected as f64 / (50 * BURST) as f64);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn local_stats() {
+        const N: u32 = 2000;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(16);
+        let received = AtomicU32::new(0);
+
+        let consume = |r: Receiver<'_, u64>, pause: Duration| {
+            while received.load(order::SNAPSHOT) < N {
+                if r.recv().is_ok() {
+                    received.fetch_add(1, order::SNAPSHOT);
+                    std::thread::sleep(pause);
+                }
+            }
+
+            r.local_stats()
+        };
+
+        let (slow, fast) = std::thread::scope(|scope| {
+            let r2 = r.clone();
+            let slow = scope.spawn(move || consume(r2, Duration::from_micros(200)));
+            let fast = scope.spawn(move || consume(r, Duration::ZERO));
+
+            for i in 0..N as u64 {
+                while !s.send(i) {
+                    std::thread::yield_now();
+                }
+            }
+
+            assert_eq!(s.local_stats().succeeded, N as u64);
+            drop(s);
+
+            (slow.join().unwrap(), fast.join().unwrap())
+        });
+
+        assert!(slow.succeeded < fast.succeeded, "{:?} vs {:?}", slow, fast);
+        assert_eq!(slow.succeeded + fast.succeeded, N as u64);
+
+        // Every handle is gone, so the totals are complete.
+        let totals = q.handle_totals();
+
+        assert_eq!(totals.senders.succeeded, N as u64);
+        assert_eq!(totals.receivers.succeeded, N as u64);
+        assert_eq!(totals.receivers.failed, slow.failed + fast.failed);
+        assert_eq!(q.stats().dequeued, totals.receivers.succeeded);
+        assert_eq!(q.stats().recv_failures, totals.receivers.failed);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn queue_stats() {
+        const N: u64 = 100;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(N as usize);
+
+        for i in 0..N {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(
+            s.stats(),
+            QueueStats {
+                enqueued: N,
+                high_watermark: N,
+                ..QueueStats::default()
+            }
+        );
+
+        assert!(!s.send(N));
+        assert_eq!(r.drain().count() as u64, N);
+
+        let stats = r.stats();
+
+        assert_eq!((stats.send_failures, stats.dequeued), (1, N));
+        // The drain ends with a receive that found nothing.
+        assert_eq!(stats.recv_failures, 1);
+        assert_eq!((stats.send_retries, stats.recv_retries), (0, 0));
+        assert_eq!(q.stats(), stats);
+    }
+
+    // Fills a queue, then drains it slowly from another thread while `wait`
+    // blocks the producer until the drain crosses a threshold.
+    fn slow_drain(wait: impl FnOnce(&Sender<u64>, &AtomicU32, usize)) {
+        let (s, r) = RingBuffer::<u64>::new(8);
+        let capacity = s.capacity();
+        let received = AtomicU32::new(0);
+
+        for i in 0..capacity as u64 {
+            assert!(s.send(i));
+        }
+
+        std::thread::scope(|scope| {
+            let received = &received;
+
+            scope.spawn(move || {
+                for _ in 0..capacity {
+                    std::thread::sleep(Duration::from_millis(2));
+                    // Counted first, so the count never lags the queue.
+                    received.fetch_add(1, order::SNAPSHOT);
+                    assert!(r.recv().is_ok());
+                }
+            });
+
+            wait(&s, received, capacity);
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn wait_below() {
+        slow_drain(|s, received, capacity| {
+            // Too long a timeout for an Instant waits for good.
+            assert!(s.wait_below(0.75, Duration::MAX));
+            assert!(s.wait_below(0.5, Duration::from_secs(10)));
+            assert!(received.load(order::SNAPSHOT) as usize > capacity / 2);
+            assert!(!s.wait_below(0.0, Duration::from_millis(1)));
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn until_below() {
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        slow_drain(|s, received, capacity| {
+            let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = std::pin::pin!(s.until_below(0.5));
+
+            while future.as_mut().poll(&mut cx).is_pending() {
+                std::thread::park();
+            }
+
+            assert!(received.load(order::SNAPSHOT) as usize > capacity / 2);
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn wait_profiles() {
+        for (profile, parks) in [
+            (WaitProfile::LowLatency, false),
+            (WaitProfile::Balanced, true),
+            (WaitProfile::PowerSave, true),
+        ] {
+            let (q, s, r) = RingBuffer::<u64>::builder()
+                .capacity(4)
+                .wait_profile(profile)
+                .build();
+
+            assert_eq!(q.wait, profile.budget());
+
+            std::thread::scope(|scope| {
+                let blocked = scope.spawn(move || {
+                    r.recv_batch_blocking(1, 1, Duration::from_secs(10), &mut Vec::new())
+                });
+
+                // Long past any spin or yield budget but LowLatency's.
+                std::thread::sleep(Duration::from_millis(50));
+                assert_eq!(q.recv_waiters.sleeping() > 0, parks, "{:?}", profile);
+
+                assert!(s.send(1));
+                assert_eq!(blocked.join().unwrap(), 1);
+            });
+        }
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn wait_strategies() {
+        const ITEMS: u64 = 500;
+
+        let custom = WaitBudget {
+            spins: 10,
+            yields: 10,
+            park: true,
+            recheck: Some(Duration::from_millis(1)),
+            polls: 4,
+        };
+
+        // Both ends block on a tiny queue, so every strategy gets to wait.
+        for profile in [
+            WaitProfile::LowLatency,
+            WaitProfile::Balanced,
+            WaitProfile::PowerSave,
+            WaitProfile::Spin,
+            WaitProfile::SpinThenYield { spins: 100 },
+            WaitProfile::Custom(custom),
+        ] {
+            let (_q, s, r) = RingBuffer::<u64>::builder()
+                .capacity(4)
+                .wait_profile(profile)
+                .build();
+            let sum = AtomicU64::new(0);
+            let received = AtomicU64::new(0);
+
+            std::thread::scope(|scope| {
+                for t in 0..2 {
+                    let s = s.clone();
+
+                    scope.spawn(move || {
+                        for i in 0..ITEMS {
+                            s.send_blocking(t * ITEMS + i).unwrap();
+                        }
+                    });
+                }
+
+                drop(s);
+
+                for _ in 0..2 {
+                    let r = r.clone();
+                    let (sum, received) = (&sum, &received);
+
+                    scope.spawn(move || {
+                        while let Ok(d) = r.recv_blocking() {
+                            sum.fetch_add(d, order::COUNTER);
+                            received.fetch_add(1, order::COUNTER);
+                        }
+                    });
+                }
+            });
+
+            let n = 2 * ITEMS;
+
+            assert_eq!(received.into_inner(), n, "{:?}", profile);
+            assert_eq!(sum.into_inner(), n * (n - 1) / 2, "{:?}", profile);
+        }
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn recv_blocking() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(move || {
+                let first = r.recv_blocking();
+
+                // Every sender gone and nothing left: no more to wait for.
+                (first, r.recv_blocking(), r.recv_blocking())
+            });
+
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(q.recv_waiters.sleeping() > 0);
+
+            assert!(s.send(1) && s.send(2));
+            drop(s);
+
+            assert_eq!(
+                blocked.join().unwrap(),
+                (Ok(1), Ok(2), Err(TryRecvError::Disconnected))
+            );
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn send_blocking() {
+        const SENDERS: u64 = 4;
+        const ITEMS: u64 = 1000;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(2);
+        let mut received = vec![Vec::new(); SENDERS as usize];
+
+        std::thread::scope(|scope| {
+            for t in 0..SENDERS {
+                let s = s.clone();
+
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        assert_eq!(s.send_blocking(t * ITEMS + i), Ok(()));
+                    }
+                });
+            }
+
+            // Let them fill the queue and go to sleep.
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(q.send_waiters.sleeping() > 0);
+
+            for _ in 0..SENDERS * ITEMS {
+                let d = r.recv_blocking().unwrap();
+
+                received[(d / ITEMS) as usize].push(d % ITEMS);
+            }
+        });
+
+        // Each blocked sender got through, in its own order.
+        for per_sender in received {
+            assert_eq!(per_sender, (0..ITEMS).collect::<Vec<_>>());
+        }
+
+        // A receiver-less full queue can't make room.
+        while s.send(0) {}
+        drop(r);
+        assert_eq!(s.send_blocking(7), Err(TrySendError::Disconnected(7)));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (s, r) = RingBuffer::<u64>::new(4);
+
+        // A zero timeout is a single try.
+        assert_eq!(
+            r.recv_timeout(Duration::ZERO),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(s.send(1));
+        assert_eq!(r.recv_timeout(Duration::ZERO), Ok(1));
+
+        let start = Instant::now();
+
+        assert_eq!(
+            r.recv_timeout(Duration::from_millis(50)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let waited = start.elapsed();
+
+        assert!(waited >= Duration::from_millis(50), "{:?}", waited);
+        assert!(waited < Duration::from_secs(1), "{:?}", waited);
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(|| r.recv_timeout(Duration::from_secs(10)));
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(s.send(2));
+            assert_eq!(blocked.join().unwrap(), Ok(2));
+        });
+
+        drop(s);
+        assert_eq!(
+            r.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn send_timeout() {
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        while s.send(0) {}
+
+        assert_eq!(
+            s.send_timeout(7, Duration::ZERO),
+            Err(SendTimeoutError::Timeout(7))
+        );
+
+        let start = Instant::now();
+
+        assert_eq!(
+            s.send_timeout(8, Duration::from_millis(50)),
+            Err(SendTimeoutError::Timeout(8))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(|| s.send_timeout(9, Duration::from_secs(10)));
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(r.recv(), Ok(0));
+            assert_eq!(blocked.join().unwrap(), Ok(()));
+        });
+
+        drop(r);
+        assert_eq!(
+            s.send_timeout(10, Duration::from_secs(10)),
+            Err(SendTimeoutError::Disconnected(10))
+        );
+    }
+
+    #[test]
+    fn deadlines() {
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        // A deadline already passed is a single try.
+        assert_eq!(
+            r.recv_deadline(Instant::now()),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        while s.send(0) {}
+
+        assert_eq!(
+            s.send_deadline(7, Instant::now()),
+            Err(SendTimeoutError::Timeout(7))
+        );
+
+        let deadline = Instant::now() + Duration::from_millis(30);
+
+        assert_eq!(
+            s.send_deadline(8, deadline),
+            Err(SendTimeoutError::Timeout(8))
+        );
+        assert!(Instant::now() >= deadline);
+
+        while r.recv().is_ok() {}
+
+        let deadline = Instant::now() + Duration::from_millis(30);
+
+        assert_eq!(r.recv_deadline(deadline), Err(RecvTimeoutError::Timeout));
+        assert!(Instant::now() >= deadline);
+        assert_eq!(s.send_deadline(9, deadline), Ok(()));
+        assert_eq!(r.recv_deadline(deadline), Ok(9));
+    }
+
+    #[test]
+    fn timeouts_racing_arrivals() {
+        // Elements arrive around the moment the waits time out; each one is
+        // received exactly once either way.
+        const ITEMS: u64 = 2000;
+
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let mut received = Vec::new();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    if i.is_multiple_of(8) {
+                        std::thread::sleep(Duration::from_micros(100));
+                    }
+
+                    let mut d = i;
+
+                    loop {
+                        match s.send_timeout(d, Duration::from_micros(50)) {
+                            Ok(()) => break,
+                            Err(SendTimeoutError::Timeout(back)) => d = back,
+                            Err(e) => panic!("{:?}", e),
+                        }
+                    }
+                }
+            });
+
+            loop {
+                match r.recv_timeout(Duration::from_micros(50)) {
+                    Ok(d) => received.push(d),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        });
+
+        assert_eq!(received, (0..ITEMS).collect::<Vec<_>>());
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn send_blocking_closed() {
+        let (s, _r) = RingBuffer::<u64>::new(2);
+        let closer = s.clone();
+
+        while s.send(0) {}
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(move || s.send_blocking(7));
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(matches!(
+                closer.shutdown(Duration::ZERO),
+                ShutdownOutcome::TimedOut { .. }
+            ));
+            assert_eq!(blocked.join().unwrap(), Err(TrySendError::Closed(7)));
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn until_below_polls() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        struct Count(AtomicUsize);
+
+        impl Wake for Count {
+            fn wake(self: Arc<Self>) {
+                self.wake_by_ref();
+            }
+
+            fn wake_by_ref(self: &Arc<Self>) {
+                self.0.fetch_add(1, order::SNAPSHOT);
+            }
+        }
+
+        for profile in [WaitProfile::LowLatency, WaitProfile::PowerSave] {
+            let (q, s, _r) = RingBuffer::<u64>::builder()
+                .capacity(4)
+                .wait_profile(profile)
+                .build();
+            let count = Arc::new(Count(AtomicUsize::new(0)));
+            let waker = Waker::from(count.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            while s.send(0) {}
+
+            let mut future = std::pin::pin!(s.until_below(0.5));
+            let polls = profile.budget().polls as usize;
+
+            // The future wakes itself until its budget is spent, then waits
+            // for the receivers.
+            for _ in 0..=polls {
+                assert!(future.as_mut().poll(&mut cx).is_pending());
+            }
+
+            assert_eq!(count.0.load(order::SNAPSHOT), polls);
+            assert_eq!(q.send_waiters.sleeping(), 1, "{:?}", profile);
+        }
+    }
+
+    #[test]
+    fn recv_probe() {
+        use std::sync::{Arc, Barrier};
+
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let (frozen, resume) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+
+        assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Empty));
+
+        std::thread::scope(|scope| {
+            let (at_publish, resumed) = (frozen.clone(), resume.clone());
+
+            scope.spawn(move || {
+                // Only the first send is frozen.
+                let mut once = true;
+                let hook = move || {
+                    if mem::take(&mut once) {
+                        at_publish.wait();
+                        resumed.wait();
+                    }
+                };
+
+                hooks::BEFORE_PUBLISH.with(|h| *h.borrow_mut() = Some(Box::new(hook)));
+                assert!(s.send(1));
+            });
+
+            frozen.wait();
+            assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Pending));
+            assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+            resume.wait();
+        });
+
+        assert_eq!(r.try_recv_detailed(), Ok(1));
+        assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Disconnected));
+    }
+
+    #[test]
+    fn send_replace() {
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let slots = std::iter::from_fn(|| s.send(0).then_some(())).count() as u64;
+
+        // The consumer is stalled: drain the zeros, then refill in order.
+        while r.recv().is_ok() {}
+
+        for i in 0..slots {
+            assert_eq!(s.send_replace(i), Ok(None));
+        }
+
+        for i in 0..3 {
+            assert_eq!(s.send_replace(slots + i), Ok(Some(i)));
+        }
+
+        let rest: Vec<_> = std::iter::from_fn(|| r.recv().ok()).collect();
+
+        assert_eq!(rest, (3..slots + 3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn send_replace_racing_senders() {
+        const ITEMS: u64 = 10_000;
+
+        let (s, r) = RingBuffer::<u64>::new(8);
+
+        // Every element is either evicted by some sender or left in the
+        // queue, exactly once.
+        let mut seen: Vec<u64> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|id| {
+                    let s = s.clone();
+
+                    scope.spawn(move || {
+                        (0..ITEMS)
+                            .filter_map(|i| s.send_replace(id * ITEMS + i).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+
+        seen.extend(std::iter::from_fn(|| r.recv().ok()));
+        seen.sort_unstable();
+
+        assert_eq!(seen, (0..4 * ITEMS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn send_replace_slow_consumer() {
+        const ITEMS: u64 = 10_000;
+
+        for producers in [1, 2] {
+            let (s, r) = RingBuffer::<u64>::new(2);
+            let done = AtomicBool::new(false);
+
+            let (mut evicted, mut received) = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..producers)
+                    .map(|id| {
+                        let s = s.clone();
+
+                        scope.spawn(move || {
+                            (0..ITEMS)
+                                .filter_map(|i| s.send_replace(id * ITEMS + i).unwrap())
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                let consumer = scope.spawn(|| {
+                    let mut received = Vec::new();
+
+                    while !done.load(order::COUNTER) {
+                        received.extend(r.recv());
+
+                        for _ in 0..100 {
+                            std::hint::spin_loop();
+                        }
+
+                        std::thread::yield_now();
+                    }
+
+                    received
+                });
+                let evicted: Vec<_> = workers
+                    .into_iter()
+                    .flat_map(|w| w.join().unwrap())
+                    .collect();
+
+                done.store(true, order::COUNTER);
+                (evicted, consumer.join().unwrap())
+            });
+
+            received.extend(std::iter::from_fn(|| r.recv().ok()));
+
+            // A lone producer's elements arrive in order, gaps and all. With
+            // two, an element evicted by one and requeued because the other
+            // took the freed slot can come after a later one.
+            if producers == 1 {
+                assert!(received.windows(2).all(|w| w[0] < w[1]), "{:?}", received);
+                assert_eq!(received.last(), Some(&(ITEMS - 1)));
+            }
+
+            // What was not received was handed back, and nothing twice.
+            let n = producers * ITEMS;
+
+            assert_eq!((evicted.len() + received.len()) as u64, n);
+            evicted.append(&mut received);
+            evicted.sort_unstable();
+            assert_eq!(evicted, (0..n).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn send_many() {
+        let (s, mut r) = RingBuffer::<u64>::new(4);
+        let slots = std::iter::from_fn(|| s.send(0).then_some(())).count();
+        let drain =
+            |r: &mut Receiver<u64>| std::iter::from_fn(|| r.recv().ok()).collect::<Vec<_>>();
+
+        drain(&mut r);
+
+        for all_or_nothing in [false, true] {
+            // Exactly as many slots free as items.
+            assert_eq!(s.try_send_many(&vec![0; slots - 3], false), Ok(slots - 3));
+            assert_eq!(s.try_send_many(&[1, 2, 3], all_or_nothing), Ok(3));
+            assert_eq!(
+                s.try_send_many(&[4], all_or_nothing),
+                Err(TrySendError::Full(()))
+            );
+            assert_eq!(drain(&mut r)[slots - 3..], [1, 2, 3]);
+
+            // One slot fewer.
+            assert_eq!(s.try_send_many(&vec![0; slots - 3], false), Ok(slots - 3));
+
+            if all_or_nothing {
+                assert_eq!(
+                    s.try_send_many(&[1, 2, 3, 4], true),
+                    Err(TrySendError::Full(()))
+                );
+                assert_eq!(drain(&mut r).len(), slots - 3);
+            } else {
+                assert_eq!(s.try_send_many(&[1, 2, 3, 4], false), Ok(3));
+                assert_eq!(drain(&mut r)[slots - 3..], [1, 2, 3]);
+            }
+        }
+
+        assert_eq!(s.try_send_many(&[], true), Ok(0));
+    }
+
+    #[test]
+    fn send_all() {
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let slots = s.capacity() as u64;
+
+        // The queue fills partway through: the stuck element and the rest
+        // come back.
+        let e = s.send_all(0..10).unwrap_err();
+
+        assert_eq!((e.sent as u64, e.error), (slots, TrySendError::Full(slots)));
+        assert_eq!(r.recv_n(2), [0, 1]);
+        assert_eq!(s.send_all(e.rest.take(1)).unwrap(), 1);
+        assert_eq!(
+            r.recv_n(100),
+            (2..slots).chain([slots + 1]).collect::<Vec<_>>()
+        );
+        assert_eq!(s.send_all([]).unwrap(), 0);
+
+        drop(r);
+
+        let e = s.send_all(0..10).unwrap_err();
+
+        assert_eq!(e.error, TrySendError::Disconnected(0));
+        assert_eq!(e.rest.start, 1);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn extend_and_collect() {
+        const ITEMS: u64 = 100_000;
+
+        let (mut s, r) = RingBuffer::<u64>::new(64);
+
+        let all: Vec<_> = std::thread::scope(|scope| {
+            scope.spawn(move || s.extend(0..ITEMS));
+            r.iter().collect()
+        });
+
+        assert_eq!(all, (0..ITEMS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drain() {
+        const ITEMS: u64 = 50_000;
+
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(64);
+        let (r, other) = (r.clone(), r);
+        let mut seen = Vec::new();
+
+        assert_eq!(r.drain().count(), 0);
+
+        std::thread::scope(|scope| {
+            let taken = scope.spawn(move || {
+                let mut taken = Vec::new();
+
+                loop {
+                    match other.try_recv() {
+                        Ok(d) => taken.push(d),
+                        Err(TryRecvError::Empty) => std::thread::yield_now(),
+                        Err(_) => break taken,
+                    }
+                }
+            });
+
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    while !s.send(i) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            while !q.disconnected() || !q.empty() {
+                seen.extend(r.drain());
+                r.drain_into(&mut seen);
+            }
+
+            seen.extend(taken.join().unwrap());
+        });
+
+        // Every element went to one receiver exactly, and the queue is
+        // left empty.
+        seen.sort_unstable();
+        assert_eq!(seen, (0..ITEMS).collect::<Vec<_>>());
+        assert!(q.empty());
+
+        // A drained iterator stays done.
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let mut drain = r.drain();
+
+        assert_eq!(drain.next(), None);
+        assert!(s.send(1));
+        assert_eq!(drain.next(), None);
+        assert_eq!(r.drain().collect::<Vec<_>>(), [1]);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn iterators() {
+        const ITEMS: u64 = 10_000;
+
+        let (s, r) = RingBuffer::<u64>::new(16);
+
+        assert_eq!(r.try_iter().next(), None);
+        assert!(s.send(1) && s.send(2));
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), [1, 2]);
+
+        let received = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    s.send_blocking(i).unwrap();
+                }
+            });
+
+            r.iter().collect::<Vec<_>>()
+        });
+
+        // All of them, in order, and then the loop ends.
+        assert_eq!(received, (0..ITEMS).collect::<Vec<_>>());
+
+        // Consuming the receiver, until the queue is closed.
+        let (s, r) = RingBuffer::<u64>::new(16);
+
+        let received = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    s.send_blocking(i).unwrap();
+                }
+
+                s.close();
+            });
+
+            let mut n = 0;
+
+            for d in r {
+                assert_eq!(d, n);
+                n += 1;
+            }
+
+            n
+        });
+
+        assert_eq!(received, ITEMS);
+    }
+
+    #[test]
+    fn owned_by_handles() {
+        // Elements left in the queue are dropped with it.
+        let alive = Arc::new(());
+
+        for receiver_first in [false, true] {
+            let (s, r) = RingBuffer::new(4);
+            let r2 = r.clone();
+
+            assert!(s.send(alive.clone()) && s.send(alive.clone()));
+            assert_eq!(Arc::strong_count(&alive), 3);
+
+            if receiver_first {
+                drop((r, r2));
+                assert!(!s.send(alive.clone()));
+                drop(s);
+            } else {
+                drop(s);
+                drop(r);
+                assert_eq!(Arc::strong_count(&alive), 3);
+                drop(r2);
+            }
+
+            assert_eq!(Arc::strong_count(&alive), 1);
+        }
+
+        // Handles dropped on other threads, in any order.
+        for _ in 0..100 {
+            let (s, r) = RingBuffer::builder().capacity(8).channel();
+            let handles = (s.clone(), r.clone(), s.try_clone_priority().unwrap());
+
+            std::thread::scope(|scope| {
+                let alive = &alive;
+
+                scope.spawn(move || drop(handles));
+                scope.spawn(move || while s.send(alive.clone()) {});
+                scope.spawn(move || drop(r));
+            });
+        }
+
+        assert_eq!(Arc::strong_count(&alive), 1);
+
+        // The box is a share like the handles: dropping it first leaves the
+        // queue to them, and one without handles goes with it.
+        let (q, s, r) = RingBuffer::new_boxed(4);
+
+        assert!(s.send(alive.clone()));
+        drop(q);
+        assert!(s.send(alive.clone()));
+        assert_eq!(r.recv(), Ok(alive.clone()));
+        drop((s, r));
+        assert_eq!(Arc::strong_count(&alive), 1);
+
+        let (q, s, r) = RingBuffer::<Arc<()>>::new_with_handles(4, 0, 0);
+
+        assert!(s.is_empty() && r.is_empty());
+        drop(q);
+
+        // The box, too, can go on any thread at any point.
+        for _ in 0..100 {
+            let (q, s, r) = RingBuffer::new_boxed(8);
+
+            std::thread::scope(|scope| {
+                let alive = &alive;
+
+                scope.spawn(move || drop(q));
+                scope.spawn(move || while s.send(alive.clone()) {});
+                scope.spawn(move || while r.recv().is_ok() {});
+            });
+        }
+
+        assert_eq!(Arc::strong_count(&alive), 1);
+    }
+
+    #[test]
+    fn scoped() {
+        let sum = RingBuffer::scope(16, |s, r| {
+            std::thread::scope(|scope| {
+                for i in 0..4 {
+                    scope.spawn(move || assert!(s.send(i)));
+                }
+            });
+
+            r.try_iter().sum::<u64>()
+        });
+
+        assert_eq!(sum, 6);
+
+        // A clone taken out of the scope keeps the queue alive.
+        let alive = Arc::new(());
+        let r = RingBuffer::scope(4, |s, r| {
+            assert!(s.send(alive.clone()));
+            r.clone()
+        });
+
+        assert_eq!(r.recv_blocking().map(|a| Arc::ptr_eq(&a, &alive)), Ok(true));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn shared_handles() {
+        const ITEMS: u64 = 20_000;
+
+        // One sender and one receiver, each used from several threads.
+        let (s, r) = RingBuffer::<u64>::new(16);
+        let (s, r) = (Arc::new(s), &r);
+        let received = std::sync::Mutex::new(Vec::new());
+        let count = AtomicU64::new(0);
+
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let s = s.clone();
+
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        while !s.send(t * ITEMS + i) {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    let mut mine = Vec::new();
+
+                    while count.load(order::COUNTER) < 4 * ITEMS {
+                        match r.recv() {
+                            Ok(d) => {
+                                mine.push(d);
+                                count.fetch_add(1, order::COUNTER);
+                            }
+                            Err(_) => std::thread::yield_now(),
+                        }
+                    }
+
+                    received.lock().unwrap().extend(mine);
+                });
+            }
+        });
+
+        let mut received = received.into_inner().unwrap();
+
+        received.sort_unstable();
+        assert_eq!(received, (0..4 * ITEMS).collect::<Vec<_>>());
+        assert!(r.empty() && s.empty());
+    }
+
+    #[test]
+    fn batches() {
+        let (s, r) = RingBuffer::<u64>::new(10);
+        let mut buf = vec![99];
+
+        assert_eq!(r.recv_batch(&mut buf, 8), 0);
+
+        // Only the free slots are taken, and nothing past them is lost.
+        assert_eq!(s.send_batch(&(0..7).collect::<Vec<_>>()), 7);
+        assert_eq!(s.send_batch(&(7..17).collect::<Vec<_>>()), 3);
+        assert_eq!(s.send_batch(&[17]), 0);
+
+        assert_eq!(r.recv_batch(&mut buf, 4), 4);
+        assert_eq!(buf, [99, 0, 1, 2, 3]);
+
+        // Redelivered elements come first, then the ring in send order.
+        r.unrecv(50);
+        assert_eq!(s.send_batch(&[10, 11]), 2);
+        assert_eq!(r.recv_batch(&mut buf, usize::MAX), 9);
+        assert_eq!(buf[5..], [50, 4, 5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(r.recv_batch(&mut buf, 0), 0);
+
+        drop(r);
+        assert_eq!(s.send_batch(&[1]), 0);
+    }
+
+    #[test]
+    fn without_senders() {
+        let (q, s, r) = RingBuffer::<u64>::new_with_handles(4, 0, 2);
+
+        assert!(s.is_empty());
+        assert!(q.empty());
+        assert!(r[0].recv().is_err());
+        assert!(r[1].recv().is_err());
+    }
+
+    #[test]
+    fn without_receivers() {
+        let (q, s, r) = RingBuffer::<u64>::new_with_handles(1, 1, 0);
+
+        assert!(r.is_empty());
+
+        // Nobody would ever receive them.
+        assert_eq!(s[0].try_send(1), Err(TrySendError::Disconnected(1)));
+        assert!(!s[0].send(2));
+        assert!(q.empty());
+    }
+
+    #[test]
+    fn refusing_across_generations() {
+        let (q, s, _) = RingBuffer::<u64>::new_with_handles(4, 1, 0);
+
+        assert_eq!(s[0].try_send(1), Err(TrySendError::Disconnected(1)));
+
+        // The new generation's receiver takes the refusal back, and the
+        // generation count does not see it.
+        let (s1, r1) = q.reset_generation();
+
+        assert_eq!(q.generation(), 1);
+        assert_eq!(s1.try_send(2), Ok(()));
+        assert_eq!(s[0].try_send(3), Err(TrySendError::Stale(3)));
+
+        // A closed queue stays closed through a reset.
+        assert!(q.close());
+
+        let (s2, r2) = q.reset_generation();
+
+        assert_eq!(q.generation(), 2);
+        assert_eq!(s2.try_send(4), Err(TrySendError::Closed(4)));
+        assert_eq!(s2.claim_many(1).err(), Some(TrySendError::Closed(())));
+        assert_eq!(r1.try_recv(), Err(TryRecvError::Stale));
+        assert_eq!(r2.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn close() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(2);
+
+        assert!(s.send(1) && s.send(2));
+
+        // A consumer stopping early: the producer finds out on its next
+        // send, the consumer still gets what was sent.
+        assert!(r.close());
+        assert!(!s.close() && !r.close());
+        assert!(q.closed());
+        assert_eq!(s.try_send(3), Err(TrySendError::Closed(3)));
+        assert_eq!(r.try_recv(), Ok(1));
+        assert_eq!(r.recv_timeout(Duration::from_secs(10)), Ok(2));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Closed));
+        assert_eq!(r.try_recv_detailed(), Err(RecvProbe::Closed));
+        assert_eq!(
+            r.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Closed)
+        );
+
+        // Blocked operations are woken by the close.
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        while s.send(0) {}
+
+        let start = Instant::now();
+
+        std::thread::scope(|scope| {
+            let sends = scope.spawn(move || {
+                (
+                    s.send_blocking(7),
+                    s.wait_below(0.5, Duration::from_secs(10)),
+                )
+            });
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(r.close());
+            assert_eq!(sends.join().unwrap(), (Err(TrySendError::Closed(7)), false));
+        });
+
+        drop(r);
+
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        std::thread::scope(|scope| {
+            let recv = scope.spawn(move || r.recv_blocking());
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(s.close());
+            assert_eq!(recv.join().unwrap(), Err(TryRecvError::Closed));
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn disconnected() {
+        const ITEMS: u64 = 10_000;
+
+        let (s, r) = RingBuffer::<u64>::new(64);
+        let mut received = [0; 2];
+
+        std::thread::scope(|scope| {
+            for p in 0..2 {
+                let s = s.clone();
+
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        while !s.send(p << 32 | i) {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            drop(s);
+
+            // Everything sent arrives, in order per sender, and only then
+            // does the queue report that no more will come.
+            loop {
+                match r.try_recv() {
+                    Ok(d) => {
+                        let p = (d >> 32) as usize;
+
+                        assert_eq!(d & 0xffff_ffff, received[p]);
+                        received[p] += 1;
+                    }
+                    Err(TryRecvError::Empty) => std::thread::yield_now(),
+                    Err(e) => {
+                        assert_eq!(e, TryRecvError::Disconnected);
+                        break;
+                    }
+                }
+            }
+        });
+
+        assert_eq!(received, [ITEMS; 2]);
+        assert_eq!(r.recv_ack().err(), Some(TryRecvError::Disconnected));
+        assert_eq!(r.recv_latest(), Err(TryRecvError::Disconnected));
+
+        // A waiting receive wakes when the last sender goes.
+        let (s, r) = RingBuffer::<u64>::new(4);
+        let start = Instant::now();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                drop(s);
+            });
+
+            assert_eq!(
+                r.recv_timeout(Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Symmetrically, sends fail with the receivers gone.
+        let (s, r) = RingBuffer::<u64>::new(4);
+
+        assert!(s.send(1));
+        drop(r);
+        assert_eq!(s.try_send(2), Err(TrySendError::Disconnected(2)));
+        assert_eq!(s.send_blocking(3), Err(TrySendError::Disconnected(3)));
+    }
+
+    #[test]
+    #[should_panic(expected = "size must be <=")]
+    fn too_large() {
+        let _ = RingBuffer::<u8>::new(MAX_CAPACITY + 1);
+    }
+
+    #[test]
+    fn fair_claims() {
+        const ITEMS: u64 = 10_000;
+
+        let (_q, s, r) = RingBuffer::<u64>::builder()
+            .capacity(8)
+            .fairness(Fairness::Ticket)
+            .build();
+
+        let mut next = [0; 4];
+
+        std::thread::scope(|scope| {
+            for id in 0..4u64 {
+                let s = s.clone();
+
+                scope.spawn(move || {
+                    let mut i = 0;
+
+                    // Single claims and runs take the same turns.
+                    while i < ITEMS {
+                        let sent = if id % 2 == 0 || i + 1 == ITEMS {
+                            s.send(id << 32 | i) as u64
+                        } else {
+                            s.send_batch(&[id << 32 | i, id << 32 | (i + 1)]) as u64
+                        };
+
+                        if sent == 0 {
+                            std::thread::yield_now();
+                        }
+
+                        i += sent;
+                    }
+                });
+            }
+
+            drop(s);
+
+            loop {
+                match r.try_recv() {
+                    Ok(d) => {
+                        let id = (d >> 32) as usize;
+
+                        assert_eq!(d & 0xffff_ffff, next[id]);
+                        next[id] += 1;
+                    }
+                    Err(TryRecvError::Empty) => std::thread::yield_now(),
+                    Err(e) => {
+                        assert_eq!(e, TryRecvError::Disconnected);
+                        break;
+                    }
+                }
+            }
+        });
+
+        assert_eq!(next, [ITEMS; 4]);
+    }
+
+    #[cfg(feature = "single-threaded")]
+    #[test]
+    fn single_threaded() {
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        // Blocking calls return at once when they need not wait...
+        assert_eq!(s.send_blocking(1), Ok(()));
+        assert_eq!(r.recv_blocking(), Ok(1));
+
+        // ...and a timed one sleeps out its timeout instead of parking.
+        let start = Instant::now();
+
+        assert_eq!(
+            r.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(s.send(2) && s.send(3));
+        assert_eq!(
+            s.send_timeout(4, Duration::ZERO),
+            Err(SendTimeoutError::Timeout(4))
+        );
+    }
+
+    #[cfg(feature = "single-threaded")]
+    #[test]
+    #[should_panic(expected = "would never return")]
+    fn single_threaded_forever() {
+        let (_s, r) = RingBuffer::<u64>::new(2);
+
+        let _ = r.recv_blocking();
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn prometheus() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        for i in 0..9 {
+            s.send(i);
+        }
+
+        for _ in 0..3 {
+            r.recv().unwrap();
+        }
+
+        let mut out = String::new();
+
+        q.write_prometheus(&mut out, "queue=\"ingest\"").unwrap();
+
+        assert_eq!(
+            out,
+            "# HELP mpmcbq_len Number of buffered elements.\n\
+             # TYPE mpmcbq_len gauge\n\
+             mpmcbq_len{queue=\"ingest\"} 1\n\
+             # HELP mpmcbq_capacity Number of elements the queue can hold.\n\
+             # TYPE mpmcbq_capacity gauge\n\
+             mpmcbq_capacity{queue=\"ingest\"} 4\n\
+             # HELP mpmcbq_enqueued_total Elements successfully sent.\n\
+             # TYPE mpmcbq_enqueued_total counter\n\
+             mpmcbq_enqueued_total{queue=\"ingest\"} 4\n\
+             # HELP mpmcbq_dequeued_total Elements successfully received.\n\
+             # TYPE mpmcbq_dequeued_total counter\n\
+             mpmcbq_dequeued_total{queue=\"ingest\"} 3\n\
+             # HELP mpmcbq_send_failures_total Sends rejected because the queue was full.\n\
+             # TYPE mpmcbq_send_failures_total counter\n\
+             mpmcbq_send_failures_total{queue=\"ingest\"} 5\n\
+             # HELP mpmcbq_high_watermark Highest number of buffered elements observed.\n\
+             # TYPE mpmcbq_high_watermark gauge\n\
+             mpmcbq_high_watermark{queue=\"ingest\"} 4\n"
+        );
+
+        let mut out = String::new();
+
+        q.write_prometheus(&mut out, "").unwrap();
+
+        assert!(out.contains("\nmpmcbq_len 1\n"));
+        assert_eq!(stats::escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
     }
 }
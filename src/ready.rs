@@ -0,0 +1,270 @@
+//! Readiness hooks, for driving a queue from an event loop.
+//!
+//! [`Builder::on_readable`] and [`Builder::on_writable`] register a hook
+//! that the queue calls once per arming: the readable hook on the first
+//! send, or close or disconnect, after [`Receiver::arm_readable`], the
+//! writable hook on the first receive, or receiver disconnect, after
+//! [`Sender::arm_writable`]. A queue starts armed for reading and not for
+//! writing; it starts empty and with room.
+//!
+//! The hooks are edge-triggered. A consumer arms, then receives until the
+//! queue is empty, and waits for the hook before it tries again; a producer
+//! arms once a send fails with [`TrySendError::Full`], retries, and waits
+//! for the hook if it is still full. Arming is ordered with the element
+//! that follows it the way a parked receiver is with the send that wakes
+//! it, see `park.rs`: either the hook fires or the retry sees the element or
+//! the room.
+//!
+//! A hook runs on the thread whose send or receive caused it, so it should
+//! be quick: set a flag, wake a task, or signal an `EventFd` that the
+//! event loop polls, with the `eventfd` feature on Linux.
+//!
+//! [`Builder::on_readable`]: crate::Builder::on_readable
+//! [`Builder::on_writable`]: crate::Builder::on_writable
+//! [`Receiver::arm_readable`]: crate::Receiver::arm_readable
+//! [`Sender::arm_writable`]: crate::Sender::arm_writable
+//! [`TrySendError::Full`]: crate::TrySendError::Full
+
+use std::sync::atomic::{self, AtomicBool};
+
+use crate::builder::OnReady;
+use crate::order;
+use crate::rb::ChannelId;
+
+// Whether the hook of one direction has fired since it was last armed.
+pub(crate) struct Edge {
+    fired: AtomicBool,
+}
+
+impl Edge {
+    pub(crate) fn new(armed: bool) -> Self {
+        Self {
+            fired: AtomicBool::new(!armed),
+        }
+    }
+
+    /// Calls `hook` if armed, disarming. Must follow a [`order::WAKE`]
+    /// fence after the progress it reports.
+    pub(crate) fn fire(&self, hook: &OnReady<'_>, id: ChannelId) {
+        if !self.fired.load(order::READY) && !self.fired.swap(true, order::READY) {
+            hook(id);
+        }
+    }
+
+    /// Arms the hook for the next progress, ordered before the caller's
+    /// next look at the queue.
+    pub(crate) fn arm(&self) {
+        self.fired.store(false, order::READY);
+        atomic::fence(order::WAKE);
+    }
+}
+
+#[cfg(all(feature = "eventfd", target_os = "linux"))]
+pub use self::eventfd::EventFd;
+
+#[cfg(all(feature = "eventfd", target_os = "linux"))]
+mod eventfd {
+    use std::io;
+    use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+
+    /// A non-blocking Linux `eventfd`, for waking an epoll loop from a
+    /// readiness hook. Needs the `eventfd` feature.
+    ///
+    /// ```ignore
+    /// let fd = Arc::new(EventFd::new()?);
+    /// let hook = fd.clone();
+    /// let (q, s, r) = RingBuffer::builder()
+    ///     .capacity(64)
+    ///     .on_readable(move |_| hook.signal())
+    ///     .build();
+    /// // Register fd.as_raw_fd() for EPOLLIN; when it fires, fd.clear(),
+    /// // r.arm_readable(), then drain r.
+    /// ```
+    #[derive(Debug)]
+    pub struct EventFd(OwnedFd);
+
+    impl EventFd {
+        pub fn new() -> io::Result<Self> {
+            let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+        }
+
+        /// Makes the descriptor readable. Signals until the next
+        /// [`EventFd::clear`] add up to one.
+        pub fn signal(&self) {
+            let one = 1u64;
+
+            // Only fails with EAGAIN once the counter is about to overflow,
+            // when the descriptor is readable anyway.
+            unsafe {
+                libc::write(
+                    self.0.as_raw_fd(),
+                    (&one as *const u64).cast(),
+                    std::mem::size_of::<u64>(),
+                )
+            };
+        }
+
+        /// Makes the descriptor unreadable again, returning whether it was
+        /// signalled.
+        pub fn clear(&self) -> bool {
+            let mut n = 0u64;
+            let read = unsafe {
+                libc::read(
+                    self.0.as_raw_fd(),
+                    (&mut n as *mut u64).cast(),
+                    std::mem::size_of::<u64>(),
+                )
+            };
+
+            read == std::mem::size_of::<u64>() as isize
+        }
+    }
+
+    impl AsFd for EventFd {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.0.as_fd()
+        }
+    }
+
+    impl AsRawFd for EventFd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+
+    use crate::{RingBuffer, TryRecvError};
+
+    fn counter() -> (Arc<AtomicUsize>, impl Fn(crate::ChannelId) + Send + Sync) {
+        let n = Arc::new(AtomicUsize::new(0));
+        let hook = n.clone();
+
+        (n, move |_| {
+            hook.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    #[test]
+    fn once_per_arming() {
+        let (readable, on_readable) = counter();
+        let (writable, on_writable) = counter();
+        let (_q, s, r) = RingBuffer::builder()
+            .capacity(2)
+            .on_readable(on_readable)
+            .on_writable(on_writable)
+            .build();
+
+        // Armed for reading from the start, and only once.
+        assert!(s.send(1) && s.send(2));
+        assert_eq!(readable.load(Ordering::Relaxed), 1);
+        assert!(!s.send(3));
+
+        // Not armed for writing until the producer asks.
+        assert_eq!(r.try_recv(), Ok(1));
+        assert_eq!(writable.load(Ordering::Relaxed), 0);
+        assert!(s.send(3) && !s.send(4));
+        s.arm_writable();
+        assert_eq!(r.try_recv(), Ok(2));
+        assert_eq!(r.try_recv(), Ok(3));
+        assert_eq!(writable.load(Ordering::Relaxed), 1);
+
+        r.arm_readable();
+        assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+        assert!(s.send(5) && s.send(6));
+        assert_eq!(readable.load(Ordering::Relaxed), 2);
+
+        // The last sender going away counts, so the consumer finds out.
+        r.arm_readable();
+        drop(s);
+        assert_eq!(readable.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn no_lost_edge() {
+        const ITEMS: u64 = 10_000;
+
+        let ready = Arc::new((Mutex::new(false), Condvar::new()));
+        let hook = ready.clone();
+        let (_q, s, r) = RingBuffer::builder()
+            .capacity(8)
+            .on_readable(move |_| {
+                *hook.0.lock().unwrap() = true;
+                hook.1.notify_one();
+            })
+            .build();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..ITEMS {
+                    while !s.send(i) {
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            // An event loop that only looks at the queue after the hook.
+            let mut next = 0;
+
+            loop {
+                {
+                    let (flag, cond) = &*ready;
+                    let mut flag = flag.lock().unwrap();
+
+                    while !*flag {
+                        flag = cond.wait(flag).unwrap();
+                    }
+
+                    *flag = false;
+                }
+
+                r.arm_readable();
+
+                loop {
+                    match r.try_recv() {
+                        Ok(i) => {
+                            assert_eq!(i, next);
+                            next += 1;
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(e) => {
+                            assert_eq!(e, TryRecvError::Disconnected);
+                            assert_eq!(next, ITEMS);
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    #[cfg(all(feature = "eventfd", target_os = "linux"))]
+    #[test]
+    fn eventfd() {
+        let fd = Arc::new(super::EventFd::new().unwrap());
+        let hook = fd.clone();
+        let (_q, s, r) = RingBuffer::builder()
+            .capacity(4)
+            .on_readable(move |_| hook.signal())
+            .build();
+
+        assert!(!fd.clear());
+        assert!(s.send(1u8));
+        assert!(fd.clear());
+        assert!(!fd.clear());
+        r.arm_readable();
+        assert!(s.send(2));
+        assert!(fd.clear());
+    }
+}
@@ -0,0 +1,126 @@
+//! A process-wide registry of live queues, for introspection.
+//!
+//! Every queue registers itself when it is constructed and deregisters at
+//! the start of its `Drop`, so the registry never keeps a queue alive and
+//! never sees one that is being torn down. Entries are type-erased: the
+//! queue's address plus a function that knows its element type.
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "stats")]
+use crate::stats::QueueStats;
+
+/// A snapshot of one live queue, see [`snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub name: Option<String>,
+    pub capacity: usize,
+    pub len: usize,
+    pub senders: u32,
+    pub receivers: u32,
+    #[cfg(feature = "stats")]
+    pub stats: QueueStats,
+}
+
+struct Entry {
+    addr: usize,
+    info: unsafe fn(usize) -> ChannelInfo,
+}
+
+// Registration is not on any hot path; sharding only keeps snapshots from
+// stalling construction across the whole process.
+const SHARDS: usize = 8;
+
+static REGISTRY: [RwLock<Vec<Entry>>; SHARDS] = [const { RwLock::new(Vec::new()) }; SHARDS];
+
+fn shard(addr: usize) -> &'static RwLock<Vec<Entry>> {
+    // Queues are at least cache line aligned, skip the always-zero bits.
+    &REGISTRY[(addr >> 7) % SHARDS]
+}
+
+fn read(shard: &RwLock<Vec<Entry>>) -> RwLockReadGuard<'_, Vec<Entry>> {
+    shard.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write(shard: &RwLock<Vec<Entry>>) -> RwLockWriteGuard<'_, Vec<Entry>> {
+    shard.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Adds the queue at `addr`. `info` is called with `addr` until
+/// [`deregister`] returns.
+pub(crate) fn register(addr: usize, info: unsafe fn(usize) -> ChannelInfo) {
+    write(shard(addr)).push(Entry { addr, info });
+}
+
+pub(crate) fn deregister(addr: usize) {
+    let mut entries = write(shard(addr));
+
+    if let Some(i) = entries.iter().position(|e| e.addr == addr) {
+        entries.swap_remove(i);
+    }
+}
+
+/// Every live queue, in no particular order.
+pub fn snapshot() -> Vec<ChannelInfo> {
+    REGISTRY
+        .iter()
+        .flat_map(|shard| {
+            // The entry can't deregister, and so its queue can't be dropped,
+            // while the shard is read locked.
+            read(shard)
+                .iter()
+                .map(|e| unsafe { (e.info)(e.addr) })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+
+    // Other tests create queues concurrently, so only look at our own.
+    fn find(name: &str) -> Vec<ChannelInfo> {
+        snapshot()
+            .into_iter()
+            .filter(|c| c.name.as_deref() == Some(name))
+            .collect()
+    }
+
+    #[test]
+    fn register_and_deregister() {
+        let (q, s, r) = RingBuffer::<u64>::builder()
+            .name("registry-a")
+            .capacity(6)
+            .build();
+        let r2 = r.clone();
+
+        assert!(s.send(1));
+        assert!(s.send(2));
+
+        let found = find("registry-a");
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].capacity, 6);
+        assert_eq!(found[0].len, 2);
+        assert_eq!((found[0].senders, found[0].receivers), (1, 2));
+        #[cfg(feature = "stats")]
+        assert_eq!(found[0].stats.enqueued, 2);
+
+        let (q2, s2, rx2) = q.clone_empty();
+
+        assert_eq!(find("registry-a").len(), 2);
+
+        drop((s, r, r2));
+        drop(q);
+
+        let found = find("registry-a");
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].len, 0);
+        drop((s2, rx2));
+        drop(q2);
+        assert!(find("registry-a").is_empty());
+    }
+}
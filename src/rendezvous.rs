@@ -0,0 +1,161 @@
+//! Rendezvous queues, from [`Builder::rendezvous`]: a send returns only
+//! once a receiver has taken the element, for strict backpressure between
+//! stages, where a queue of even one element would let the sender run a
+//! step ahead.
+//!
+//! The queue underneath holds one element, the one being handed over, and
+//! the receivers are plain [`Receiver`](crate::Receiver)s. A sender waits
+//! first for room and then for its element to be claimed; one that gives up
+//! takes its element back, so an element is either received or handed back,
+//! never both.
+//!
+//! [`Builder::rendezvous`]: crate::Builder::rendezvous
+
+use std::time::{Duration, Instant};
+
+use crate::error::{SendTimeoutError, TrySendError};
+use crate::rb::Sender;
+
+/// The sending half of a rendezvous queue, see the [module docs](self).
+pub struct RendezvousSender<'a, T> {
+    inner: Sender<'a, T>,
+}
+
+impl<'a, T> RendezvousSender<'a, T> {
+    pub(crate) fn new(inner: Sender<'a, T>) -> Self {
+        Self { inner }
+    }
+
+    /// Hands `d` to a receiver, waiting for one to take it. Fails, handing
+    /// `d` back, as [`Sender::send_blocking`] does, and with
+    /// [`TrySendError::Disconnected`] when the last receiver goes before
+    /// taking it.
+    ///
+    /// If the queue is reset while `d` waits, it goes with the elements the
+    /// reset drops and the send returns as if it was taken.
+    pub fn send(&self, d: T) -> Result<(), TrySendError<T>> {
+        self.inner.send_handoff(d, None).map_err(|e| match e {
+            SendTimeoutError::Timeout(d) => TrySendError::Full(d),
+            SendTimeoutError::Stale(d) => TrySendError::Stale(d),
+            SendTimeoutError::Closed(d) => TrySendError::Closed(d),
+            SendTimeoutError::Disconnected(d) => TrySendError::Disconnected(d),
+        })
+    }
+
+    /// Like [`RendezvousSender::send`], giving up after `timeout`. A zero
+    /// timeout only succeeds with a receiver polling at that very moment.
+    pub fn send_timeout(&self, d: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.inner
+            .send_handoff(d, Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`RendezvousSender::send_timeout`], giving up at `deadline`.
+    pub fn send_deadline(&self, d: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.send_handoff(d, Some(deadline))
+    }
+
+    /// Creates another sender for the same queue, or returns `None` if no
+    /// sender may be added, see [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<Self> {
+        self.inner.try_clone().map(Self::new)
+    }
+}
+
+impl<'a, T> Clone for RendezvousSender<'a, T> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use crate::RingBuffer;
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn hands_over() {
+        let (s, r) = RingBuffer::<u32>::builder().rendezvous();
+        let sent = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| {
+                s.send(1).unwrap();
+                sent.store(true, Ordering::SeqCst);
+            });
+
+            // Queued, but not taken: the send is still waiting.
+            thread::sleep(Duration::from_millis(20));
+            assert!(!sent.load(Ordering::SeqCst));
+            assert_eq!(r.recv_blocking(), Ok(1));
+
+            sender.join().unwrap();
+            assert!(sent.load(Ordering::SeqCst));
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn receiver_leaves() {
+        let (s, r) = RingBuffer::<u32>::builder().rendezvous();
+
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| s.send(1));
+
+            thread::sleep(Duration::from_millis(20));
+            drop(r);
+
+            assert_eq!(sender.join().unwrap(), Err(TrySendError::Disconnected(1)));
+        });
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn many() {
+        const ITEMS: u32 = 500;
+
+        let (s, r) = RingBuffer::<u32>::builder().rendezvous();
+
+        thread::scope(|scope| {
+            for p in 0..2 {
+                let s = s.clone();
+
+                scope.spawn(move || {
+                    for i in 0..ITEMS {
+                        s.send(p * ITEMS + i).unwrap();
+                    }
+                });
+            }
+
+            drop(s);
+
+            let mut got: Vec<_> = r.iter().collect();
+
+            got.sort_unstable();
+            assert_eq!(got, (0..2 * ITEMS).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn takes_back() {
+        let (s, r) = RingBuffer::<String>::builder().rendezvous();
+
+        // No receiver is receiving: the element comes back and the queue
+        // is left empty.
+        assert_eq!(
+            s.send_timeout("a".to_string(), Duration::from_millis(5)),
+            Err(SendTimeoutError::Timeout("a".to_string()))
+        );
+        assert!(r.try_recv().is_err());
+
+        drop(r);
+        assert_eq!(
+            s.send("b".to_string()),
+            Err(TrySendError::Disconnected("b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Restoring a global order across several queues.
+//!
+//! Producers tag every element with its sequence number and spread them
+//! over any number of queues, e.g. with a
+//! [`PartitionedSender`](crate::PartitionedSender) or a
+//! [`Router`](crate::Router). A [`Resequencer`] receives from all of them
+//! and hands the elements out strictly by sequence number.
+//!
+//! Early arrivals wait in a reorder window of fixed size, one slot per
+//! sequence number from the next one due. An arrival beyond the window is
+//! held back and its queue left unread until the window moves. Once every
+//! queue is empty or held back this way, the next one due is missing or
+//! very late, and the [`GapPolicy`] decides whether to wait for it or give
+//! up on it and report the gap.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::rb::Receiver;
+
+/// What a [`Resequencer`] does when an element arrives too far ahead of the
+/// next sequence number due to fit in the reorder window.
+pub enum GapPolicy<'f> {
+    /// Wait for the missing elements. Loses nothing, but stalls forever if
+    /// one never arrives, or arrives on a queue behind a held back element.
+    Wait,
+    /// Give up on the sequence numbers that keep the earliest held back
+    /// element out of the window, passing each run of them to the
+    /// callback, and move on. A skipped element that turns up later is
+    /// dropped.
+    Skip(Box<dyn FnMut(Range<u64>) + Send + 'f>),
+}
+
+/// Receives `(sequence, element)` pairs from several queues and returns the
+/// elements in sequence order, starting at 0.
+pub struct Resequencer<'a, 'f, T> {
+    sources: Vec<Receiver<'a, (u64, T)>>,
+    // An element per source held back for not fitting the window yet.
+    parked: Vec<Option<(u64, T)>>,
+    // Early arrivals, at `seq % window`.
+    window: Vec<Option<(u64, T)>>,
+    ready: VecDeque<T>,
+    next: u64,
+    policy: GapPolicy<'f>,
+    // Where the next sweep over the sources starts.
+    cursor: usize,
+    late: u64,
+}
+
+impl<'a, 'f, T> Resequencer<'a, 'f, T> {
+    /// # Panics
+    ///
+    /// If `sources` is empty or `window` is zero.
+    pub fn new(sources: Vec<Receiver<'a, (u64, T)>>, window: usize, policy: GapPolicy<'f>) -> Self {
+        assert!(!sources.is_empty(), "a resequencer needs a source");
+        assert!(window > 0, "window must be > 0");
+
+        Self {
+            parked: sources.iter().map(|_| None).collect(),
+            sources,
+            window: (0..window).map(|_| None).collect(),
+            ready: VecDeque::new(),
+            next: 0,
+            policy,
+            cursor: 0,
+            late: 0,
+        }
+    }
+
+    /// The sequence number of the next element to be returned.
+    pub fn next_seq(&self) -> u64 {
+        self.next
+    }
+
+    /// Elements dropped for arriving after their sequence number was handed
+    /// out or skipped.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Returns the next element in sequence, or `None` if it hasn't arrived
+    /// yet. Reads about one window's worth of elements from the queues.
+    pub fn try_recv(&mut self) -> Option<T> {
+        if self.ready.is_empty() {
+            self.sweep();
+        }
+
+        self.ready.pop_front()
+    }
+
+    // Reads the sources in turn, one element each per round, until they
+    // run dry or block or the budget is spent. Reading round by round keeps
+    // them roughly level, so one queue racing ahead doesn't fill the window.
+    fn sweep(&mut self) {
+        let (n, cursor) = (self.sources.len(), self.cursor);
+        // Enough for a window's worth plus one parked element per source.
+        let mut budget = self.window.len() + n;
+        let mut progress = true;
+
+        while progress && budget > 0 {
+            progress = false;
+
+            for i in (0..n).map(|i| (cursor + i) % n) {
+                progress |= self.unpark(i);
+
+                if self.parked[i].is_some() || budget == 0 {
+                    continue;
+                }
+
+                if let Ok(item) = self.sources[i].try_recv() {
+                    budget -= 1;
+                    progress = true;
+                    self.parked[i] = Some(item);
+                    self.unpark(i);
+                }
+            }
+        }
+
+        self.cursor = (cursor + 1) % n;
+
+        // Every source is empty or blocked on an element past the window:
+        // the next one due is missing.
+        if !progress && self.ready.is_empty() {
+            let first = self.parked.iter().flatten().map(|&(seq, _)| seq).min();
+
+            if let (Some(first), GapPolicy::Skip(_)) = (first, &self.policy) {
+                self.skip_to(first + 1 - self.window.len() as u64);
+                (0..n).for_each(|i| {
+                    self.unpark(i);
+                });
+            }
+        }
+    }
+
+    // Files the element `source` holds back if it now fits the window, and
+    // returns whether it did.
+    fn unpark(&mut self, source: usize) -> bool {
+        let size = self.window.len() as u64;
+
+        match self.parked[source] {
+            Some((seq, _)) if seq >= self.next + size => false,
+            Some(_) => {
+                let (seq, d) = self.parked[source].take().unwrap();
+
+                if seq < self.next {
+                    self.late += 1;
+                } else {
+                    self.window[(seq % size) as usize] = Some((seq, d));
+                    self.advance();
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Moves the contiguous run of arrivals from `next` on to `ready`.
+    fn advance(&mut self) {
+        let size = self.window.len() as u64;
+
+        while let Some((_, d)) = self.window[(self.next % size) as usize].take() {
+            self.ready.push_back(d);
+            self.next += 1;
+        }
+    }
+
+    // Moves `next` on to `to`, handing out what arrived on the way and
+    // reporting each run of what didn't.
+    fn skip_to(&mut self, to: u64) {
+        let size = self.window.len() as u64;
+        let mut missing: Option<Range<u64>> = None;
+
+        while self.next < to {
+            match self.window[(self.next % size) as usize].take() {
+                Some((_, d)) => {
+                    self.report(missing.take());
+                    self.ready.push_back(d);
+                }
+                None => {
+                    let start = missing.map_or(self.next, |m| m.start);
+
+                    missing = Some(start..self.next + 1);
+                }
+            }
+
+            self.next += 1;
+        }
+
+        self.report(missing);
+        self.advance();
+    }
+
+    fn report(&mut self, gap: Option<Range<u64>>) {
+        if let (Some(gap), GapPolicy::Skip(on_gap)) = (gap, &mut self.policy) {
+            on_gap(gap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::rb::{RingBox, RingBuffer, Sender};
+
+    const ITEMS: u64 = 1024;
+    const BLOCK: u64 = 16;
+
+    // Every sequence number but `dropped`, shuffled within blocks of 16 and
+    // spread over the senders.
+    fn scatter(senders: &mut [Sender<(u64, u64)>], dropped: Option<u64>) {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for block in (0..ITEMS).step_by(BLOCK as usize) {
+            let mut seqs: Vec<u64> = (block..block + BLOCK).collect();
+
+            for i in (1..seqs.len()).rev() {
+                seqs.swap(i, next() as usize % (i + 1));
+            }
+
+            for seq in seqs.into_iter().filter(|&seq| Some(seq) != dropped) {
+                let n = senders.len();
+
+                assert!(senders[next() as usize % n].send((seq, seq * 10)));
+            }
+        }
+    }
+
+    type Channels = (
+        Vec<RingBox<'static, (u64, u64)>>,
+        Vec<Sender<'static, (u64, u64)>>,
+        Vec<Receiver<'static, (u64, u64)>>,
+    );
+
+    fn channels(n: usize) -> Channels {
+        let mut queues = Vec::new();
+        let mut senders = Vec::new();
+        let mut receivers = Vec::new();
+
+        for _ in 0..n {
+            let (q, s, r) = RingBuffer::new_boxed(ITEMS as usize);
+
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        (queues, senders, receivers)
+    }
+
+    #[test]
+    fn in_order() {
+        let (_queues, mut senders, receivers) = channels(3);
+        let mut reseq = Resequencer::new(receivers, 2 * BLOCK as usize, GapPolicy::Wait);
+
+        scatter(&mut senders, None);
+
+        let out: Vec<_> = std::iter::from_fn(|| reseq.try_recv()).collect();
+
+        assert_eq!(out, (0..ITEMS).map(|seq| seq * 10).collect::<Vec<_>>());
+        assert_eq!(reseq.late(), 0);
+    }
+
+    #[test]
+    fn gap_skipped() {
+        let (_queues, mut senders, receivers) = channels(3);
+        let gaps = Arc::new(Mutex::new(Vec::new()));
+        let on_gap = {
+            let gaps = gaps.clone();
+
+            move |gap| gaps.lock().unwrap().push(gap)
+        };
+        let mut reseq = Resequencer::new(
+            receivers,
+            2 * BLOCK as usize,
+            GapPolicy::Skip(Box::new(on_gap)),
+        );
+
+        scatter(&mut senders, Some(500));
+
+        let out: Vec<_> = std::iter::from_fn(|| reseq.try_recv()).collect();
+        let expected: Vec<_> = (0..ITEMS)
+            .filter(|&seq| seq != 500)
+            .map(|seq| seq * 10)
+            .collect();
+
+        assert_eq!(out, expected);
+        assert_eq!(
+            gaps.lock().unwrap()[..],
+            [Range {
+                start: 500,
+                end: 501
+            }]
+        );
+
+        // The missing element turns up after all.
+        assert!(senders[0].send((500, 5000)));
+        assert_eq!(reseq.try_recv(), None);
+        assert_eq!(reseq.late(), 1);
+    }
+
+    #[test]
+    fn gap_waited_for() {
+        // The missing element comes late on a queue of its own.
+        let (_queues, mut senders, receivers) = channels(4);
+        let mut reseq = Resequencer::new(receivers, 2 * BLOCK as usize, GapPolicy::Wait);
+
+        scatter(&mut senders[..3], Some(500));
+
+        let out: Vec<_> = std::iter::from_fn(|| reseq.try_recv()).collect();
+
+        assert_eq!(out, (0..500).map(|seq| seq * 10).collect::<Vec<_>>());
+        assert_eq!(reseq.next_seq(), 500);
+
+        // Nothing was lost while waiting.
+        assert!(senders[3].send((500, 5000)));
+
+        let rest: Vec<_> = std::iter::from_fn(|| reseq.try_recv()).collect();
+
+        assert_eq!(rest, (500..ITEMS).map(|seq| seq * 10).collect::<Vec<_>>());
+        assert_eq!(reseq.late(), 0);
+    }
+}
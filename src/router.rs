@@ -0,0 +1,158 @@
+//! Scattering sends over several queues by load.
+//!
+//! Unlike [`PartitionedSender`](crate::PartitionedSender), which pins every
+//! key to one queue, a [`Router`] sends each element to whichever queue is
+//! least full, so a slow consumer gets less work instead of a backlog.
+
+use crate::error::TrySendError;
+use crate::rb::Sender;
+
+/// Per-destination counters of a [`Router`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DestinationStats {
+    /// Elements the destination accepted.
+    pub sent: u64,
+    /// Sends the destination refused because it was full or stale.
+    pub rejected: u64,
+}
+
+/// Sends every element to the least loaded of several queues.
+pub struct Router<'a, T> {
+    senders: Vec<Sender<'a, T>>,
+    stats: Vec<DestinationStats>,
+    // Where the round-robin among equally loaded destinations resumes.
+    next: usize,
+}
+
+impl<'a, T> Router<'a, T> {
+    /// # Panics
+    ///
+    /// If `senders` is empty.
+    pub fn new(senders: Vec<Sender<'a, T>>) -> Self {
+        assert!(!senders.is_empty(), "a router needs a destination");
+
+        Self {
+            stats: vec![DestinationStats::default(); senders.len()],
+            senders,
+            next: 0,
+        }
+    }
+
+    pub fn destinations(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Counters of each destination, in the order given to
+    /// [`Router::new`].
+    pub fn stats(&self) -> &[DestinationStats] {
+        &self.stats
+    }
+
+    /// Sends `d` to the destination with the lowest approximate length,
+    /// taking turns between equally loaded ones, and returns its index. If
+    /// that destination refuses `d` the others are tried in turn. Fails
+    /// only if every destination refuses: with [`TrySendError::Full`] if any
+    /// of them was full, otherwise with the last one's error.
+    pub fn send(&mut self, d: T) -> Result<usize, TrySendError<T>> {
+        let n = self.senders.len();
+        // Scanning from `next` makes the first minimum the round-robin pick.
+        let best = (0..n)
+            .map(|i| (self.next + i) % n)
+            .min_by_key(|&i| self.senders[i].len())
+            .unwrap();
+
+        let mut d = d;
+        let mut full = false;
+        // How the last one tried refused.
+        let mut refusal: fn(T) -> TrySendError<T> = TrySendError::Stale;
+
+        for i in (0..n).map(|i| (best + i) % n) {
+            match self.senders[i].try_send(d) {
+                Ok(()) => {
+                    self.stats[i].sent += 1;
+                    self.next = (i + 1) % n;
+
+                    return Ok(i);
+                }
+                Err(e) => {
+                    self.stats[i].rejected += 1;
+                    full |= matches!(e, TrySendError::Full(_));
+                    refusal = match e {
+                        TrySendError::Full(_) => TrySendError::Full,
+                        TrySendError::Stale(_) => TrySendError::Stale,
+                        TrySendError::Closed(_) => TrySendError::Closed,
+                        TrySendError::Disconnected(_) => TrySendError::Disconnected,
+                    };
+                    d = e.into_inner();
+                }
+            }
+        }
+
+        Err(if full {
+            TrySendError::Full(d)
+        } else {
+            refusal(d)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rb::RingBuffer;
+
+    #[test]
+    fn round_robin_on_ties() {
+        let (s0, _r0) = RingBuffer::<u64>::new(8);
+        let (s1, _r1) = RingBuffer::<u64>::new(8);
+        let (s2, _r2) = RingBuffer::<u64>::new(8);
+        let mut router = Router::new(vec![s0, s1, s2]);
+
+        // All empty, then all holding one: every tie goes to the next one.
+        let picks: Vec<_> = (0..6).map(|i| router.send(i).unwrap()).collect();
+
+        assert_eq!(picks, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn shifts_away_from_stalled() {
+        let (s0, _r0) = RingBuffer::<u64>::new(4);
+        let (s1, r1) = RingBuffer::<u64>::new(4);
+        let (s2, r2) = RingBuffer::<u64>::new(4);
+        let mut router = Router::new(vec![s0, s1, s2]);
+
+        for i in 0..1000 {
+            assert!(router.send(i).is_ok());
+
+            // The healthy consumers keep up, the stalled one never runs.
+            if i % 2 == 1 {
+                while r1.recv().is_ok() {}
+                while r2.recv().is_ok() {}
+            }
+        }
+
+        let [stalled, a, b] = [0, 1, 2].map(|i| router.stats()[i].sent);
+
+        assert!(stalled < 10, "stalled destination got {}", stalled);
+        assert!(a.abs_diff(b) <= 1, "{} vs {}", a, b);
+        assert_eq!(stalled + a + b, 1000);
+    }
+
+    #[test]
+    fn tries_others_when_full() {
+        let (s0, _r0) = RingBuffer::<u64>::new(1);
+        let (s1, _r1) = RingBuffer::<u64>::new(1);
+        let mut router = Router::new(vec![s0, s1]);
+        let mut sent = 0;
+
+        while router.send(sent).is_ok() {
+            sent += 1;
+        }
+
+        // Both queues were filled before a send failed.
+        assert_eq!(router.stats().iter().map(|s| s.sent).sum::<u64>(), sent);
+        assert!(router.stats().iter().all(|s| s.sent > 0 && s.rejected > 0));
+        assert_eq!(router.send(0), Err(TrySendError::Full(0)));
+    }
+}
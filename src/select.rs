@@ -0,0 +1,494 @@
+//! Waiting on up to 64 queues at once.
+//!
+//! Queues added to a [`SelectGroup`] share one word of ready bits, one bit
+//! each. A send or redelivery into a member queue sets its bit if it is
+//! clear and then wakes the group's waiter, so notification costs the same
+//! however many queues there are: one load, plus an atomic OR and a check
+//! for a sleeper when the bit was clear. Closing a member queue or dropping
+//! its last sender sets the bit too, so the waiter finds out. [`SelectGroup::wait`] takes the
+//! bits, clearing them, and the caller receives from the queues they name.
+//!
+//! The bits are only hints, and racy in one direction. A bit says an
+//! element arrived since the bits were last taken, but a receiver that is
+//! not the waiter may have taken it already, or the waiter itself while
+//! draining an earlier bit; receiving from a queue whose bit is set can
+//! find it empty. The other direction holds: an element sent after the
+//! bits were taken sets its bit again, so nothing is missed as long as the
+//! waiter drains every queue it was told about, or remembers the ones it
+//! left elements in.
+//!
+//! A group is meant for a single waiter. Several can wait, but each set of
+//! bits goes to only one of them.
+//!
+//! A [`Selector`] hands the bits out one queue index at a time, taking turns
+//! among queues that are ready together.
+
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::order;
+use crate::pad::CachePadded;
+use crate::park::Waiters;
+use crate::rb::{Receiver, RingBuffer};
+
+/// Up to 64 queues sharing one word of ready bits, see the module docs.
+pub struct SelectGroup {
+    ready: CachePadded<AtomicU64>,
+    // The bits handed out to member queues.
+    members: AtomicU64,
+    waiters: Waiters,
+}
+
+// A queue's place in a group, dropped with the queue.
+pub(crate) struct Member {
+    group: Arc<SelectGroup>,
+    bit: u64,
+}
+
+impl SelectGroup {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            ready: CachePadded::new(AtomicU64::new(0)),
+            members: AtomicU64::new(0),
+            waiters: Waiters::new(),
+        })
+    }
+
+    /// Adds `q` to the group and returns the index of its bit, or `None` if
+    /// the group has 64 members or `q` is in a group already. A queue that
+    /// has elements starts with its bit set. Dropping the queue frees the
+    /// bit.
+    pub fn add<T>(self: &Arc<Self>, q: &RingBuffer<'_, T>) -> Option<u32> {
+        let mut members = self.members.load(order::SELECT);
+        let index = loop {
+            let index = (!members).trailing_zeros();
+
+            if index == u64::BITS {
+                return None;
+            }
+
+            match self.members.compare_exchange_weak(
+                members,
+                members | 1 << index,
+                order::SELECT,
+                order::SELECT,
+            ) {
+                Ok(_) => break index,
+                Err(actual) => members = actual,
+            }
+        };
+        let member = Member {
+            group: self.clone(),
+            bit: 1 << index,
+        };
+
+        // On failure the member is dropped, freeing the bit again.
+        q.join_select(member).ok()?;
+
+        if !q.empty() {
+            self.ready.fetch_or(1 << index, order::SELECT);
+            self.waiters.notify();
+        }
+
+        Some(index)
+    }
+
+    /// Adds the queue of `r`, see [`SelectGroup::add`].
+    pub fn add_receiver<T>(self: &Arc<Self>, r: &Receiver<'_, T>) -> Option<u32> {
+        self.add(r.rb())
+    }
+
+    /// The bits of the queues that had elements arrive since the bits were
+    /// last taken, clearing them, without waiting. 0 if there are none.
+    pub fn poll(&self) -> u64 {
+        let ready = self.ready.swap(0, order::SELECT);
+
+        // Pairs with the fence every send to a member queue makes between
+        // publishing and looking at its bit: either the sender sees its bit cleared and
+        // sets it again, or the caller sees the element.
+        atomic::fence(order::WAKE);
+        ready
+    }
+
+    /// Waits until some bits are set, then takes them as [`SelectGroup::poll`]
+    /// does.
+    pub fn wait(&self) -> u64 {
+        self.wait_for(None)
+    }
+
+    /// Like [`SelectGroup::wait`], giving up after `timeout` and returning 0.
+    pub fn wait_timeout(&self, timeout: Duration) -> u64 {
+        self.wait_for(Instant::now().checked_add(timeout))
+    }
+
+    // wait() until `deadline`, or for good.
+    pub(crate) fn wait_for(&self, deadline: Option<Instant>) -> u64 {
+        let mut ready = 0;
+
+        self.waiters.wait_until(deadline, || {
+            ready = self.poll();
+            ready != 0
+        });
+        ready
+    }
+
+    /// Number of member queues.
+    pub fn len(&self) -> usize {
+        self.members.load(order::SELECT).count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Waits on a [`SelectGroup`] for one queue at a time.
+pub struct Selector {
+    group: Arc<SelectGroup>,
+    // Bits taken from the group and not handed out yet.
+    pending: u64,
+    // The index handed out last.
+    last: u32,
+}
+
+impl Selector {
+    pub fn new(group: Arc<SelectGroup>) -> Self {
+        Self {
+            group,
+            pending: 0,
+            last: u64::BITS - 1,
+        }
+    }
+
+    /// The index of a queue that had an element arrive, was closed or lost
+    /// its last sender, waiting until there is one. Of the queues ready
+    /// together, the first one after the index returned last goes next.
+    ///
+    /// The index is a hint, like the bits: receive from the queue until it is
+    /// empty, or pass the index to [`Selector::keep`] when leaving elements
+    /// in it. A queue another receiver emptied first costs one more call,
+    /// which sleeps again if no other queue is ready.
+    pub fn select(&mut self) -> u32 {
+        self.select_timeout(Duration::MAX)
+            .expect("a wait without a deadline ends with a queue ready")
+    }
+
+    /// Like [`Selector::select`], giving up after `timeout`.
+    pub fn select_timeout(&mut self, timeout: Duration) -> Option<u32> {
+        self.pending |= match self.pending {
+            0 => self.group.wait_timeout(timeout),
+            _ => self.group.poll(),
+        };
+
+        if self.pending == 0 {
+            return None;
+        }
+
+        let after = self.pending & (!0u64).checked_shl(self.last + 1).unwrap_or(0);
+        let index = match after {
+            0 => self.pending.trailing_zeros(),
+            after => after.trailing_zeros(),
+        };
+
+        self.pending &= !(1 << index);
+        self.last = index;
+        Some(index)
+    }
+
+    /// Hands out `index` again, for a queue left with elements in it.
+    pub fn keep(&mut self, index: u32) {
+        self.pending |= 1 << index;
+    }
+}
+
+impl Member {
+    // After an element was published or queued for redelivery, and after a
+    // WAKE fence.
+    pub(crate) fn notify(&self) {
+        let group = &self.group;
+
+        if group.ready.load(order::SELECT) & self.bit == 0 {
+            group.ready.fetch_or(self.bit, order::SELECT);
+            group.waiters.notify();
+        }
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        self.group.members.fetch_and(!self.bit, order::SELECT);
+        self.group.ready.fetch_and(!self.bit, order::SELECT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::error::TryRecvError;
+    use crate::rb::Sender;
+
+    #[test]
+    fn bits() {
+        let group = SelectGroup::new();
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(group.add(&q), Some(0));
+        assert_eq!(group.add(&q), None);
+        assert_eq!(group.poll(), 0);
+
+        // Set once, however many elements arrive.
+        assert!(s.send(1) && s.send(2));
+        assert_eq!(group.poll(), 1);
+        assert_eq!(group.poll(), 0);
+
+        // Set again by redelivery.
+        assert_eq!(r.recv(), Ok(1));
+        r.unrecv(1);
+        assert_eq!(group.wait(), 1);
+
+        // A queue with elements starts out ready.
+        let (other, s2, _r2) = RingBuffer::<u64>::new_boxed(4);
+
+        assert!(s2.send(1));
+        assert_eq!(group.add(&other), Some(1));
+        assert_eq!(group.poll(), 0b10);
+        assert_eq!(group.wait_timeout(Duration::from_millis(1)), 0);
+
+        // Dropping a queue frees its bit.
+        drop((s, r));
+        drop(q);
+        assert_eq!(group.len(), 1);
+
+        let (q, _s, _r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(group.add(&q), Some(0));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn wait_without_deadline() {
+        let group = SelectGroup::new();
+        let (q, s, _r) = RingBuffer::<u64>::new_boxed(4);
+
+        assert_eq!(group.add(&q), Some(0));
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                assert!(s.send(1));
+            });
+
+            assert_eq!(group.wait_timeout(Duration::MAX), 1);
+        });
+    }
+
+    #[test]
+    fn full_group() {
+        let group = SelectGroup::new();
+        let queues: Vec<_> = (0..65)
+            .map(|_| {
+                let (q, s, r) = RingBuffer::<u64>::new_boxed(2);
+
+                drop((s, r));
+                q
+            })
+            .collect();
+
+        for (i, q) in queues.iter().enumerate() {
+            assert_eq!(group.add(q), (i < 64).then_some(i as u32));
+        }
+
+        assert_eq!(group.len(), 64);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn nothing_missed() {
+        const QUEUES: usize = 64;
+        const ITEMS: u64 = 20_000;
+
+        let group = SelectGroup::new();
+        let mut queues = Vec::new();
+        let mut senders: Vec<Sender<u64>> = Vec::new();
+        let mut receivers: Vec<Receiver<u64>> = Vec::new();
+
+        for _ in 0..QUEUES {
+            let (q, s, r) = RingBuffer::new_boxed(16);
+
+            group.add(&q).unwrap();
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        thread::scope(|scope| {
+            for (t, mut senders) in [senders.split_off(QUEUES / 2), senders]
+                .into_iter()
+                .enumerate()
+            {
+                scope.spawn(move || {
+                    let mut state = 0x9e37_79b9_7f4a_7c15u64 + t as u64;
+
+                    for i in 0..ITEMS {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+
+                        let s = &mut senders[state as usize % (QUEUES / 2)];
+
+                        while !s.send(i) {
+                            thread::yield_now();
+                        }
+
+                        // Bursts with pauses, so queues go empty and get
+                        // woken up again.
+                        if state.is_multiple_of(64) {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            // The waiter only looks where the bits point: if a bit were lost,
+            // the elements behind it would stay put and the wait time out.
+            let mut received = 0;
+
+            while received < 2 * ITEMS {
+                let ready = group.wait_timeout(Duration::from_secs(10));
+
+                assert_ne!(ready, 0, "{} of {} received", received, 2 * ITEMS);
+
+                for i in (0..QUEUES).filter(|i| ready & 1 << i != 0) {
+                    while receivers[i].recv().is_ok() {
+                        received += 1;
+                    }
+                }
+            }
+        });
+
+        assert!(receivers.iter_mut().all(|r| r.empty()));
+    }
+
+    #[test]
+    fn rotation() {
+        let group = SelectGroup::new();
+        let mut selector = Selector::new(group.clone());
+        let mut queues = Vec::new();
+        let mut senders = Vec::new();
+        let mut receivers = Vec::new();
+
+        for _ in 0..3 {
+            let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+            group.add(&q).unwrap();
+            assert!(s.send(1));
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        // All ready together: they take turns, and a kept one waits for the
+        // others.
+        assert_eq!(selector.select(), 0);
+        selector.keep(0);
+        assert_eq!(selector.select(), 1);
+        assert_eq!(selector.select(), 2);
+        assert_eq!(selector.select(), 0);
+        assert_eq!(selector.select_timeout(Duration::from_millis(1)), None);
+
+        // Losing the last sender wakes the selector too, a clone of it not.
+        drop(senders[1].clone());
+        assert_eq!(selector.select_timeout(Duration::from_millis(1)), None);
+        drop(senders.remove(1));
+        assert_eq!(selector.select(), 1);
+        assert_eq!(receivers[1].try_recv(), Ok(1));
+        assert_eq!(receivers[1].try_recv(), Err(TryRecvError::Disconnected));
+
+        // And so does closing a queue, here one added by its receiver.
+        let (q, s, r) = RingBuffer::<u32>::new_boxed(4);
+
+        assert_eq!(group.add_receiver(&r), Some(3));
+        assert!(r.close());
+        assert_eq!(selector.select(), 3);
+        assert_eq!(r.try_recv(), Err(TryRecvError::Closed));
+        drop((s, r));
+        drop(q);
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn slow_and_bursty() {
+        const SLOW: u32 = 50;
+        const BURSTS: u64 = 20;
+        const BURST: u64 = 100;
+
+        let group = SelectGroup::new();
+        let mut selector = Selector::new(group.clone());
+        let (slow, slow_r) = RingBuffer::<u32>::new(4);
+        let (bursty, bursty_r) = RingBuffer::<u64>::new(256);
+
+        assert_eq!(group.add_receiver(&slow_r), Some(0));
+        assert_eq!(group.add_receiver(&bursty_r), Some(1));
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..SLOW {
+                    thread::sleep(Duration::from_millis(1));
+                    slow.send_blocking(i).unwrap();
+                }
+            });
+
+            scope.spawn(move || {
+                for b in 0..BURSTS {
+                    thread::sleep(Duration::from_millis(3));
+
+                    for i in 0..BURST {
+                        bursty.send_blocking(b * BURST + i).unwrap();
+                    }
+                }
+            });
+
+            let (mut slow_seen, mut bursty_seen, mut empty) = (0, 0, 0);
+            let mut open = [true, true];
+
+            // A missed wakeup leaves elements, or a disconnect, unreported
+            // and the wait times out.
+            while open != [false, false] {
+                let index = selector
+                    .select_timeout(Duration::from_secs(10))
+                    .expect("missed wakeup");
+                let mut got = 0;
+                let end = loop {
+                    let result = match index {
+                        0 => slow_r.try_recv().map(|d| assert_eq!(d, slow_seen + got)),
+                        _ => bursty_r
+                            .try_recv()
+                            .map(|d| assert_eq!(d, bursty_seen + got as u64)),
+                    };
+
+                    match result {
+                        Ok(()) => got += 1,
+                        Err(e) => break e,
+                    }
+                };
+
+                match index {
+                    0 => slow_seen += got,
+                    _ => bursty_seen += got as u64,
+                }
+
+                empty += (got == 0) as u32;
+                open[index as usize] &= end != TryRecvError::Disconnected;
+            }
+
+            assert_eq!((slow_seen, bursty_seen), (SLOW, BURSTS * BURST));
+            // A report finds nothing only when an element arriving during a
+            // drain set its bit again after being drained.
+            assert!(empty <= SLOW + BURSTS as u32 + 2, "{} empty", empty);
+        });
+    }
+}
@@ -0,0 +1,413 @@
+//! Work distribution over one queue per consumer.
+//!
+//! A [`QueuePool`] is a set of shards, one queue each. A [`ShardedSender`]
+//! spreads its sends over the shards in turn, going on to the next shard
+//! when one is full, so with one [`ShardedReceiver`] per shard most claims
+//! contend only with the producers that happen to pick the same shard. A
+//! receiver takes from its own shard first and steals from the others, in
+//! shard order after its own, once that is empty.
+//!
+//! Spreading gives up the order between elements of one producer; use a
+//! [`PartitionedSender`](crate::PartitionedSender) to keep it per key.
+//!
+//! A blocked receiver sleeps on its own shard and looks at the others every
+//! [`STEAL_INTERVAL`], so an element sent to an idle shard waits at most
+//! that long for a sleeping thief.
+
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
+
+use crate::builder::Builder;
+use crate::error::{RecvTimeoutError, TryRecvError, TrySendError};
+use crate::order;
+use crate::rb::{Receiver, RingBox, Sender};
+
+/// Longest a blocked [`ShardedReceiver`] sleeps on its own shard before it
+/// tries to steal again.
+pub const STEAL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Configuration of a pool, see [`QueuePool::builder`].
+pub struct ShardBuilder<'a, T> {
+    queue: Builder<'a, T>,
+    shards: usize,
+}
+
+impl<'a, T> ShardBuilder<'a, T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Builder::new(),
+            shards: 1,
+        }
+    }
+
+    /// Number of shards, and of receivers. Defaults to 1.
+    pub fn shards(mut self, n: usize) -> Self {
+        self.shards = n;
+        self
+    }
+
+    /// Capacity of every shard. Required.
+    pub fn capacity(mut self, n: usize) -> Self {
+        self.queue = self.queue.capacity(n);
+        self
+    }
+
+    /// Configuration of every shard's queue, replacing any capacity set
+    /// before.
+    pub fn queue(mut self, config: Builder<'a, T>) -> Self {
+        self.queue = config;
+        self
+    }
+
+    /// Creates the shards, with one receiver per shard in shard order.
+    ///
+    /// # Panics
+    ///
+    /// If there are no shards.
+    #[allow(clippy::type_complexity)]
+    pub fn build(
+        self,
+    ) -> (
+        QueuePool<'a, T>,
+        ShardedSender<'a, T>,
+        Vec<ShardedReceiver<'a, T>>,
+    ) {
+        assert!(self.shards > 0, "shards must be > 0");
+
+        let mut queues = Vec::with_capacity(self.shards);
+        let mut senders = Vec::with_capacity(self.shards);
+        let mut receivers = Vec::with_capacity(self.shards);
+
+        for _ in 0..self.shards {
+            let (q, s, r) = self.queue.clone().build();
+
+            queues.push(q);
+            senders.push(s);
+            receivers.push(r);
+        }
+
+        // Every receiver holds a handle to every shard, its own first.
+        let mut handles: Vec<_> = (1..self.shards)
+            .map(|_| {
+                receivers
+                    .iter()
+                    .map(|r| r.try_clone().expect("a new queue has one receiver"))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        handles.push(receivers);
+
+        let receivers = handles
+            .into_iter()
+            .enumerate()
+            .map(|(home, mut receivers)| {
+                receivers.rotate_left(home);
+                ShardedReceiver { receivers, home }
+            })
+            .collect();
+
+        let sender = ShardedSender {
+            senders,
+            next: AtomicUsize::new(0),
+        };
+
+        (QueuePool { queues }, sender, receivers)
+    }
+}
+
+impl<'a, T> Default for ShardBuilder<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The queues of a pool, see the [module docs](self). The handles keep the
+/// queues alive without it.
+pub struct QueuePool<'a, T> {
+    queues: Vec<RingBox<'a, T>>,
+}
+
+impl<'a, T> QueuePool<'a, T> {
+    pub fn builder() -> ShardBuilder<'a, T> {
+        ShardBuilder::new()
+    }
+
+    pub fn shards(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// The queue of every shard, in shard order.
+    pub fn queues(&self) -> &[RingBox<'a, T>] {
+        &self.queues
+    }
+
+    /// Elements in all shards together.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|q| q.len()).sum()
+    }
+
+    pub fn empty(&self) -> bool {
+        self.queues.iter().all(|q| q.empty())
+    }
+}
+
+/// Spreads sends over the shards of a pool, see the [module docs](self).
+pub struct ShardedSender<'a, T> {
+    senders: Vec<Sender<'a, T>>,
+    // The shard the next send starts at.
+    next: AtomicUsize,
+}
+
+impl<'a, T> ShardedSender<'a, T> {
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Enqueues `d` on the next shard with room, returning `false` if every
+    /// shard is full or the sender is stale.
+    pub fn send(&self, d: T) -> bool {
+        self.try_send(d).is_ok()
+    }
+
+    /// Like [`ShardedSender::send`], handing `d` back on failure: as
+    /// [`TrySendError::Full`] if any shard was full, and otherwise as the
+    /// first shard tried failed.
+    pub fn try_send(&self, d: T) -> Result<(), TrySendError<T>> {
+        let n = self.senders.len();
+        let start = self.next.fetch_add(1, order::COUNTER) % n;
+        let mut d = d;
+        let mut failed = None;
+
+        for i in 0..n {
+            match self.senders[(start + i) % n].try_send(d) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    let (e, back) = e.split();
+
+                    d = back;
+
+                    if failed.is_none() || e == TrySendError::Full(()) {
+                        failed = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(failed.expect("a pool has shards").replace(d))
+    }
+
+    /// Like [`ShardedSender::try_send`], sleeping on the shard it started at
+    /// while every shard is full, see [`Sender::send_blocking`].
+    pub fn send_blocking(&self, d: T) -> Result<(), TrySendError<T>> {
+        match self.try_send(d) {
+            Err(TrySendError::Full(d)) => {
+                let n = self.senders.len();
+
+                self.senders[self.next.load(order::COUNTER) % n].send_blocking(d)
+            }
+            result => result,
+        }
+    }
+
+    /// Creates another sender for the same shards, or returns `None` if a
+    /// shard refuses a new sender, see [`Sender::try_clone`].
+    pub fn try_clone(&self) -> Option<Self> {
+        let senders = self
+            .senders
+            .iter()
+            .map(Sender::try_clone)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            senders,
+            next: AtomicUsize::new(self.next.load(order::COUNTER)),
+        })
+    }
+}
+
+impl<'a, T> Clone for ShardedSender<'a, T> {
+    /// # Panics
+    ///
+    /// If [`ShardedSender::try_clone`] fails, see [`Sender::clone`].
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("sender count is zero or would overflow")
+    }
+}
+
+/// Receives from its own shard of a pool and steals from the others, see
+/// the [module docs](self).
+pub struct ShardedReceiver<'a, T> {
+    // Every shard, starting with `home`.
+    receivers: Vec<Receiver<'a, T>>,
+    home: usize,
+}
+
+impl<'a, T> ShardedReceiver<'a, T> {
+    /// The index of the receiver's own shard.
+    pub fn home(&self) -> usize {
+        self.home
+    }
+
+    /// Receives an element from the receiver's own shard, or steals one
+    /// from another, without waiting.
+    ///
+    /// Fails with [`TryRecvError::Empty`] while any shard may still get
+    /// elements, and otherwise as the own shard does once every shard has
+    /// failed for good.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut failed = None;
+
+        for r in &self.receivers {
+            match r.try_recv() {
+                Ok(d) => return Ok(d),
+                Err(e) => {
+                    if failed.is_none() || e == TryRecvError::Empty {
+                        failed = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(failed.unwrap_or(TryRecvError::Empty))
+    }
+
+    /// Like [`ShardedReceiver::try_recv`], sleeping while every shard is
+    /// empty. Fails once every shard has failed for good.
+    pub fn recv_blocking(&self) -> Result<T, TryRecvError> {
+        self.recv_within(None).map_err(|e| match e {
+            RecvTimeoutError::Stale => TryRecvError::Stale,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+            RecvTimeoutError::Closed => TryRecvError::Closed,
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+        })
+    }
+
+    /// Like [`ShardedReceiver::recv_blocking`], giving up once every shard
+    /// has stayed empty for `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_within(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_within(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(d) => return Ok(d),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Stale) => return Err(RecvTimeoutError::Stale),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Closed) => return Err(RecvTimeoutError::Closed),
+            }
+
+            let now = Instant::now();
+            let mut wait = STEAL_INTERVAL;
+
+            if let Some(deadline) = deadline {
+                if now >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+
+                wait = wait.min(deadline - now);
+            }
+
+            // Whatever this finds, the next try_recv() finds it too or
+            // reports why not.
+            if let Ok(d) = self.receivers[0].recv_timeout(wait) {
+                return Ok(d);
+            }
+        }
+    }
+
+    /// Creates another receiver with the same own shard, or returns `None`
+    /// if a shard refuses a new receiver, see [`Receiver::try_clone`].
+    pub fn try_clone(&self) -> Option<Self> {
+        let receivers = self
+            .receivers
+            .iter()
+            .map(Receiver::try_clone)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            receivers,
+            home: self.home,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn spreads_and_steals() {
+        let (pool, s, r) = QueuePool::builder().shards(3).capacity(4).build();
+
+        for i in 0..6 {
+            assert!(s.send(i));
+        }
+
+        for q in pool.queues() {
+            assert_eq!(q.len(), 2);
+        }
+
+        // The first receiver drains its own shard, then the others in turn.
+        let got: Vec<_> = std::iter::from_fn(|| r[1].try_recv().ok()).collect();
+
+        assert_eq!(got, [1, 4, 2, 5, 0, 3]);
+        assert_eq!(r[0].try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn full_shard_is_skipped() {
+        let (pool, s, r) = QueuePool::builder().shards(2).capacity(2).build();
+
+        for i in 0..4 {
+            assert!(s.send(i));
+        }
+
+        // The next send starts at the full shard 0 and goes on to shard 1.
+        assert_eq!(r[1].try_recv(), Ok(1));
+        assert!(s.send(4));
+        assert_eq!(pool.queues()[1].len(), 2);
+        assert_eq!(s.try_send(5), Err(TrySendError::Full(5)));
+    }
+
+    #[cfg(not(feature = "single-threaded"))]
+    #[test]
+    fn blocking() {
+        let (_pool, s, r) = QueuePool::builder().shards(4).capacity(64).build();
+        let seen = Mutex::new(HashSet::new());
+
+        thread::scope(|scope| {
+            for r in &r {
+                let seen = &seen;
+
+                scope.spawn(move || {
+                    while let Ok(i) = r.recv_blocking() {
+                        assert!(seen.lock().unwrap().insert(i));
+                    }
+                });
+            }
+
+            scope.spawn(move || {
+                for i in 0..1000 {
+                    assert_eq!(s.send_blocking(i), Ok(()));
+                }
+                // Dropping the sender disconnects every shard.
+            });
+        });
+
+        assert_eq!(seen.into_inner().unwrap().len(), 1000);
+
+        assert_eq!(
+            r[0].recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
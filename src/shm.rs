@@ -0,0 +1,645 @@
+//! A queue laid out for memory shared between processes.
+//!
+//! [`ShmQueue::init`] writes a header and the slots into memory the caller
+//! maps, e.g. with `mmap`, and [`ShmQueue::attach`] opens it from another
+//! process, possibly one built for another architecture. Nothing in the
+//! memory depends on the build that wrote it:
+//!
+//! ```text
+//!   offset  size  field
+//!   0       8     magic "MPMCBQ\0\0"
+//!   8       4     layout version, LAYOUT_VERSION
+//!   12      4     byte order marker 0x01020304
+//!   16      4     element size
+//!   20      4     element alignment
+//!   24      8     element type hash
+//!   32      4     slot count, a power of two
+//!   36      4     capacity, at most the slot count
+//!   40      24    padding
+//!   64      8     enqueue position, alone in its cache line
+//!   128     8     dequeue position, alone in its cache line
+//!   192           slots: an 8-byte sequence number, then the element,
+//!                 each padded to the element's alignment
+//! ```
+//!
+//! Every integer is little-endian, the atomics included: big-endian hosts
+//! swap bytes on each access, which is why the protocol only loads, stores
+//! and compare-exchanges. Elements are copied as they are, so a `T` shared
+//! across architectures must have the same layout on both, e.g. a
+//! `#[repr(C)]` struct of little-endian fixed-width integers.
+
+use std::alloc::Layout;
+use std::any;
+use std::cell::UnsafeCell;
+use std::cmp::Ordering;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering as MemOrdering};
+
+use crate::error::{AttachError, LayoutError, TryRecvError, TrySendError};
+use crate::order;
+use crate::rb::MAX_CAPACITY;
+
+/// The version of the layout written by [`ShmQueue::init`].
+pub const LAYOUT_VERSION: u32 = 2;
+
+const MAGIC: [u8; 8] = *b"MPMCBQ\0\0";
+
+const BYTE_ORDER: u32 = 0x0102_0304;
+
+#[repr(transparent)]
+struct Le32(u32);
+
+impl Le32 {
+    fn new(v: u32) -> Self {
+        Self(v.to_le())
+    }
+
+    fn get(&self) -> u32 {
+        u32::from_le(self.0)
+    }
+}
+
+#[repr(transparent)]
+struct Le64(u64);
+
+impl Le64 {
+    fn new(v: u64) -> Self {
+        Self(v.to_le())
+    }
+
+    fn get(&self) -> u64 {
+        u64::from_le(self.0)
+    }
+}
+
+// An AtomicU64 holding a little-endian value.
+#[repr(transparent)]
+struct LeAtomic64(AtomicU64);
+
+impl LeAtomic64 {
+    fn new(v: u64) -> Self {
+        Self(AtomicU64::new(v.to_le()))
+    }
+
+    fn load(&self, order: MemOrdering) -> u64 {
+        u64::from_le(self.0.load(order))
+    }
+
+    fn store(&self, v: u64, order: MemOrdering) {
+        self.0.store(v.to_le(), order)
+    }
+
+    fn compare_exchange_weak(
+        &self,
+        current: u64,
+        new: u64,
+        success: MemOrdering,
+        failure: MemOrdering,
+    ) -> Result<u64, u64> {
+        self.0
+            .compare_exchange_weak(current.to_le(), new.to_le(), success, failure)
+            .map(u64::from_le)
+            .map_err(u64::from_le)
+    }
+}
+
+#[repr(C, align(64))]
+struct Header {
+    magic: [u8; 8],
+    version: Le32,
+    byte_order: Le32,
+    element_size: Le32,
+    element_align: Le32,
+    type_hash: Le64,
+    slots: Le32,
+    capacity: Le32,
+    _pad0: [u8; 24],
+    enq_pos: LeAtomic64,
+    _pad1: [u8; 56],
+    deq_pos: LeAtomic64,
+    _pad2: [u8; 56],
+}
+
+#[repr(C)]
+struct Slot<T> {
+    seq: LeAtomic64,
+    data: UnsafeCell<T>,
+}
+
+// The table in the module doc.
+const _: () = {
+    assert!(mem::offset_of!(Header, magic) == 0);
+    assert!(mem::offset_of!(Header, version) == 8);
+    assert!(mem::offset_of!(Header, byte_order) == 12);
+    assert!(mem::offset_of!(Header, element_size) == 16);
+    assert!(mem::offset_of!(Header, element_align) == 20);
+    assert!(mem::offset_of!(Header, type_hash) == 24);
+    assert!(mem::offset_of!(Header, slots) == 32);
+    assert!(mem::offset_of!(Header, capacity) == 36);
+    assert!(mem::offset_of!(Header, enq_pos) == 64);
+    assert!(mem::offset_of!(Header, deq_pos) == 128);
+    assert!(mem::size_of::<Header>() == 192);
+    assert!(mem::offset_of!(Slot<u8>, data) == 8);
+    assert!(mem::size_of::<Slot<u8>>() == 16);
+    assert!(mem::size_of::<Slot<[u64; 3]>>() == 32);
+};
+
+// FNV-1a of the type's name, which unlike a TypeId does not change with
+// every build. It is only stable for one compiler, though: type_name's output
+// is not specified and may change between Rust releases, so processes built
+// with different toolchains can fail to attach for the same type. Types that
+// share a name and path but not a definition are only told apart by their
+// size and alignment.
+fn type_hash<T>() -> u64 {
+    any::type_name::<T>()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100_0000_01b3)
+        })
+}
+
+fn check(mem: *mut u8, len: usize, layout: Layout) -> Result<(), LayoutError> {
+    if mem.align_offset(layout.align()) != 0 {
+        return Err(LayoutError::Misaligned {
+            align: layout.align(),
+        });
+    }
+
+    if len < layout.size() {
+        return Err(LayoutError::TooSmall {
+            required: layout.size(),
+            provided: len,
+        });
+    }
+
+    Ok(())
+}
+
+/// A bounded multi-producer multi-consumer queue in shared memory. Every
+/// process that attaches can both send and receive.
+pub struct ShmQueue<'m, T: Default + Copy> {
+    header: &'m Header,
+    slots: &'m [Slot<T>],
+    capacity: usize,
+}
+
+unsafe impl<'m, T: Default + Copy> Send for ShmQueue<'m, T> where T: Send {}
+unsafe impl<'m, T: Default + Copy> Sync for ShmQueue<'m, T> where T: Send {}
+
+impl<'m, T: Default + Copy> ShmQueue<'m, T> {
+    /// Size and alignment of the memory for `capacity` elements, and the
+    /// offset of the slots in it. There is a slot per element of the
+    /// capacity rounded up to a power of two.
+    pub fn layout(capacity: usize) -> Result<(Layout, usize), LayoutError> {
+        if capacity == 0 || capacity > MAX_CAPACITY {
+            return Err(LayoutError::Capacity(capacity));
+        }
+
+        Self::slots_layout(capacity.next_power_of_two())
+    }
+
+    fn slots_layout(slots: usize) -> Result<(Layout, usize), LayoutError> {
+        let array = Layout::array::<Slot<T>>(slots).map_err(|_| LayoutError::Capacity(slots))?;
+        let (layout, offset) = Layout::new::<Header>()
+            .extend(array)
+            .map_err(|_| LayoutError::Capacity(slots))?;
+
+        Ok((layout.pad_to_align(), offset))
+    }
+
+    /// Lays out an empty queue of `capacity` elements in the `len` bytes at
+    /// `mem`.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be valid for reads and writes of `len` bytes for `'m`, and
+    /// no one may use it until this returns.
+    pub unsafe fn init(mem: *mut u8, len: usize, capacity: usize) -> Result<Self, LayoutError> {
+        let (layout, offset) = Self::layout(capacity)?;
+        let slots = capacity.next_power_of_two();
+
+        check(mem, len, layout)?;
+
+        (mem as *mut Header).write(Header {
+            magic: MAGIC,
+            version: Le32::new(LAYOUT_VERSION),
+            byte_order: Le32::new(BYTE_ORDER),
+            element_size: Le32::new(mem::size_of::<T>() as u32),
+            element_align: Le32::new(mem::align_of::<T>() as u32),
+            type_hash: Le64::new(type_hash::<T>()),
+            slots: Le32::new(slots as u32),
+            capacity: Le32::new(capacity as u32),
+            _pad0: [0; 24],
+            enq_pos: LeAtomic64::new(0),
+            _pad1: [0; 56],
+            deq_pos: LeAtomic64::new(0),
+            _pad2: [0; 56],
+        });
+
+        let first = mem.add(offset) as *mut Slot<T>;
+
+        for i in 0..slots {
+            first.add(i).write(Slot {
+                seq: LeAtomic64::new(i as u64),
+                data: UnsafeCell::new(T::default()),
+            });
+        }
+
+        Ok(Self::from_parts(mem, offset, slots, capacity))
+    }
+
+    /// Opens the queue that [`ShmQueue::init`] laid out in the `len` bytes
+    /// at `mem`, checking that it was written with this layout version
+    /// and for elements of type `T`.
+    ///
+    /// The element type is recognized by a hash of its name as the compiler
+    /// spells it, so the processes must be built with the same Rust
+    /// release; one built with another may be refused with
+    /// [`AttachError::Element`] even for the same `T`.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be valid for reads and writes of `len` bytes for `'m`,
+    /// and, if it holds a queue, `init` must have returned before.
+    pub unsafe fn attach(mem: *mut u8, len: usize) -> Result<Self, AttachError> {
+        check(mem, len, Layout::new::<Header>())?;
+
+        let header = &*(mem as *const Header);
+
+        if header.magic != MAGIC {
+            return Err(AttachError::Magic);
+        }
+
+        match header.byte_order.get() {
+            BYTE_ORDER => {}
+            swapped if swapped == BYTE_ORDER.swap_bytes() => {
+                return Err(AttachError::ForeignEndian)
+            }
+            _ => return Err(AttachError::Magic),
+        }
+
+        if header.version.get() != LAYOUT_VERSION {
+            return Err(AttachError::Version(header.version.get()));
+        }
+
+        let (size, align, hash) = (
+            header.element_size.get(),
+            header.element_align.get(),
+            header.type_hash.get(),
+        );
+
+        if (size as usize, align as usize, hash)
+            != (mem::size_of::<T>(), mem::align_of::<T>(), type_hash::<T>())
+        {
+            return Err(AttachError::Element {
+                size,
+                align,
+                type_hash: hash,
+            });
+        }
+
+        let slots = header.slots.get();
+
+        if !slots.is_power_of_two() || slots as usize > MAX_CAPACITY + 1 {
+            return Err(AttachError::Slots(slots));
+        }
+
+        let capacity = header.capacity.get();
+
+        if capacity == 0 || capacity.next_power_of_two() != slots {
+            return Err(AttachError::Capacity(capacity));
+        }
+
+        let (layout, offset) = Self::slots_layout(slots as usize)?;
+
+        check(mem, len, layout)?;
+
+        Ok(Self::from_parts(
+            mem,
+            offset,
+            slots as usize,
+            capacity as usize,
+        ))
+    }
+
+    unsafe fn from_parts(mem: *mut u8, offset: usize, slots: usize, capacity: usize) -> Self {
+        Self {
+            header: &*(mem as *const Header),
+            slots: std::slice::from_raw_parts(mem.add(offset) as *const Slot<T>, slots),
+            capacity,
+        }
+    }
+
+    /// Elements the queue holds, as asked for by [`ShmQueue::init`].
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Approximate number of queued elements.
+    pub fn len(&self) -> usize {
+        let deq = self.header.deq_pos.load(order::SNAPSHOT);
+        let enq = self.header.enq_pos.load(order::SNAPSHOT);
+
+        enq.saturating_sub(deq) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Whether claiming `pos` would leave more than the capacity queued.
+    fn at_capacity(&self, pos: u64) -> bool {
+        let deq = self.header.deq_pos.load(order::SNAPSHOT);
+
+        pos.wrapping_sub(deq) >= self.capacity as u64
+    }
+
+    fn slot(&self, pos: u64) -> &Slot<T> {
+        &self.slots[pos as usize & (self.slots.len() - 1)]
+    }
+
+    /// Enqueues `d`, or hands it back if the queue is full.
+    pub fn try_send(&self, d: T) -> Result<(), TrySendError<T>> {
+        let enq_pos = &self.header.enq_pos;
+        let mut pos = enq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(order::SLOT);
+
+            match (seq.wrapping_sub(pos) as i64).cmp(&0) {
+                // A capacity short of the slot count stops sends at the
+                // capacity, before the slots run out.
+                Ordering::Equal if self.capacity < self.slots.len() && self.at_capacity(pos) => {
+                    return Err(TrySendError::Full(d))
+                }
+                Ordering::Equal => {
+                    match enq_pos.compare_exchange_weak(
+                        pos,
+                        pos + 1,
+                        order::CLAIM,
+                        order::CLAIM_FAILED,
+                    ) {
+                        Ok(_) => {
+                            unsafe { slot.data.get().write(d) };
+                            slot.seq.store(pos + 1, order::PUBLISH);
+
+                            return Ok(());
+                        }
+                        Err(actual) => pos = actual,
+                    }
+                }
+                // The slot still holds the element from a lap ago.
+                Ordering::Less => return Err(TrySendError::Full(d)),
+                Ordering::Greater => pos = enq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+
+    /// Dequeues an element. The only error is [`TryRecvError::Empty`].
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let deq_pos = &self.header.deq_pos;
+        let mut pos = deq_pos.load(order::CLAIM_LOAD);
+
+        loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(order::SLOT);
+
+            match (seq.wrapping_sub(pos + 1) as i64).cmp(&0) {
+                Ordering::Equal => {
+                    match deq_pos.compare_exchange_weak(
+                        pos,
+                        pos + 1,
+                        order::CLAIM,
+                        order::CLAIM_FAILED,
+                    ) {
+                        Ok(_) => {
+                            let d = unsafe { slot.data.get().read() };
+
+                            slot.seq
+                                .store(pos + self.slots.len() as u64, order::RECYCLE);
+
+                            return Ok(d);
+                        }
+                        Err(actual) => pos = actual,
+                    }
+                }
+                Ordering::Less => return Err(TryRecvError::Empty),
+                Ordering::Greater => pos = deq_pos.load(order::CLAIM_LOAD),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // Zeroed memory laid out for `ShmQueue<u64>` of `capacity`.
+    struct Region {
+        mem: *mut u8,
+        layout: Layout,
+        capacity: usize,
+    }
+
+    impl Region {
+        fn new(capacity: usize) -> Self {
+            let (layout, _) = ShmQueue::<u64>::layout(capacity).unwrap();
+            let mem = unsafe { std::alloc::alloc_zeroed(layout) };
+
+            assert!(!mem.is_null());
+            Self {
+                mem,
+                layout,
+                capacity,
+            }
+        }
+
+        fn header(&mut self) -> &mut Header {
+            unsafe { &mut *(self.mem as *mut Header) }
+        }
+
+        fn init(&self) -> ShmQueue<'_, u64> {
+            unsafe { ShmQueue::init(self.mem, self.layout.size(), self.capacity) }.unwrap()
+        }
+
+        fn attach<T: Default + Copy>(&self) -> Result<ShmQueue<'_, T>, AttachError> {
+            unsafe { ShmQueue::attach(self.mem, self.layout.size()) }
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe { std::alloc::dealloc(self.mem, self.layout) };
+        }
+    }
+
+    #[test]
+    fn attach_and_share() {
+        let region = Region::new(8);
+        let a = region.init();
+        let b = region.attach::<u64>().unwrap();
+
+        assert_eq!(b.capacity(), 8);
+
+        // Several laps, sending through one view and receiving through the
+        // other.
+        for i in 0..100 {
+            assert!(a.try_send(i).is_ok());
+            assert_eq!(b.try_recv(), Ok(i));
+        }
+
+        assert_eq!(b.try_recv(), Err(TryRecvError::Empty));
+        assert!((0..8).all(|i| b.try_send(i).is_ok()));
+        assert_eq!(a.try_send(8), Err(TrySendError::Full(8)));
+        assert_eq!(a.len(), 8);
+    }
+
+    #[test]
+    fn exact_capacity() {
+        let region = Region::new(5);
+        let a = region.init();
+        let b = region.attach::<u64>().unwrap();
+
+        assert_eq!((a.capacity(), b.capacity()), (5, 5));
+
+        // Across laps of the 8 slots, with the queue full each time.
+        for lap in 0..4 {
+            assert!((0..5).all(|i| a.try_send(lap * 5 + i).is_ok()));
+            assert_eq!(b.try_send(99), Err(TrySendError::Full(99)));
+            assert_eq!(a.len(), 5);
+            assert!((0..5).all(|i| b.try_recv() == Ok(lap * 5 + i)));
+        }
+
+        assert_eq!(a.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn concurrent_views() {
+        const ITEMS: u64 = 10_000;
+
+        let region = Region::new(16);
+        let _init = region.init();
+        let views: Vec<_> = (0..4).map(|_| region.attach::<u64>().unwrap()).collect();
+        let (senders, receivers) = views.split_at(2);
+        let received = &std::sync::atomic::AtomicU64::new(0);
+
+        let sums: Vec<u64> = thread::scope(|scope| {
+            for s in senders {
+                scope.spawn(move || {
+                    for i in 1..=ITEMS {
+                        while s.try_send(i).is_err() {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let consumers: Vec<_> = receivers
+                .iter()
+                .map(|r| {
+                    scope.spawn(move || {
+                        let mut sum = 0;
+
+                        while received.load(MemOrdering::Relaxed) < 2 * ITEMS {
+                            match r.try_recv() {
+                                Ok(d) => {
+                                    sum += d;
+                                    received.fetch_add(1, MemOrdering::Relaxed);
+                                }
+                                Err(_) => thread::yield_now(),
+                            }
+                        }
+
+                        sum
+                    })
+                })
+                .collect();
+
+            consumers.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+
+        assert_eq!(sums.iter().sum::<u64>(), ITEMS * (ITEMS + 1));
+    }
+
+    #[test]
+    fn foreign_endian() {
+        let mut region = Region::new(8);
+
+        region.init();
+
+        // What a writer storing its fields big-endian would have left.
+        let header = region.header();
+
+        for field in [
+            &mut header.version,
+            &mut header.byte_order,
+            &mut header.element_size,
+            &mut header.element_align,
+            &mut header.slots,
+            &mut header.capacity,
+        ] {
+            field.0 = field.0.swap_bytes();
+        }
+
+        header.type_hash.0 = header.type_hash.0.swap_bytes();
+
+        assert_eq!(
+            region.attach::<u64>().err(),
+            Some(AttachError::ForeignEndian)
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let mut region = Region::new(8);
+        let len = region.layout.size();
+
+        assert_eq!(region.attach::<u64>().err(), Some(AttachError::Magic));
+
+        region.init();
+
+        let hash = type_hash::<u64>();
+        let stored = Some(AttachError::Element {
+            size: 8,
+            align: 8,
+            type_hash: hash,
+        });
+
+        // Another size, and another type of the same size.
+        assert_eq!(region.attach::<u32>().err(), stored);
+        assert_eq!(region.attach::<i64>().err(), stored);
+
+        let short = unsafe { ShmQueue::<u64>::attach(region.mem, len - 1) };
+
+        assert_eq!(
+            short.err(),
+            Some(AttachError::Layout(LayoutError::TooSmall {
+                required: len,
+                provided: len - 1,
+            }))
+        );
+
+        let misaligned = unsafe { ShmQueue::<u64>::attach(region.mem.add(8), len - 8) };
+
+        assert_eq!(
+            misaligned.err(),
+            Some(AttachError::Layout(LayoutError::Misaligned { align: 64 }))
+        );
+
+        region.header().capacity = Le32::new(3);
+        assert_eq!(region.attach::<u64>().err(), Some(AttachError::Capacity(3)));
+
+        region.header().capacity = Le32::new(8);
+        region.header().slots = Le32::new(12);
+        assert_eq!(region.attach::<u64>().err(), Some(AttachError::Slots(12)));
+
+        region.header().version = Le32::new(LAYOUT_VERSION + 1);
+        assert_eq!(
+            region.attach::<u64>().err(),
+            Some(AttachError::Version(LAYOUT_VERSION + 1))
+        );
+    }
+}
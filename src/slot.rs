@@ -0,0 +1,149 @@
+//! The per-slot sequence protocol, as pure functions of the slot's sequence
+//! number and a position.
+//!
+//! A slot serving positions `p, p + slots, p + 2 * slots, ...` moves through
+//!
+//! ```text
+//!   seq == p           free for the sender claiming p
+//!   seq == p + 1       published, full for the receiver claiming p
+//!   seq == p + slots   recycled, free for the sender claiming p + slots
+//! ```
+//!
+//! `send`/`recv` in `rb.rs` only add the position CAS and the payload copy on
+//! top of these transitions, which keeps the part a model checker has to
+//! reason about small (see `verification.rs`).
+
+/// What a slot's sequence number says about it, relative to a position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Slot {
+    /// The slot is ready for the operation at the position.
+    Ready,
+    /// The slot is still a lap behind the position: full for a sender,
+    /// empty for a receiver.
+    Behind,
+    /// Another thread already completed the operation at the position; the
+    /// caller's position is stale and must be reloaded.
+    Ahead,
+}
+
+fn classify(seq: u64, expected: u64) -> Slot {
+    match seq.wrapping_sub(expected) as i64 {
+        0 => Slot::Ready,
+        d if d < 0 => Slot::Behind,
+        _ => Slot::Ahead,
+    }
+}
+
+/// Classifies a slot for the sender claiming `pos`.
+pub(crate) fn for_send(seq: u64, pos: u64) -> Slot {
+    classify(seq, pos)
+}
+
+/// Classifies a slot for the receiver claiming `pos`.
+pub(crate) fn for_recv(seq: u64, pos: u64) -> Slot {
+    classify(seq, pos.wrapping_add(1))
+}
+
+/// The sequence number a sender stores after writing the slot at `pos`.
+pub(crate) fn published(pos: u64) -> u64 {
+    pos.wrapping_add(1)
+}
+
+/// The sequence number a receiver stores after reading the slot at `pos`,
+/// `slots` being the number of slots in the ring.
+pub(crate) fn recycled(pos: u64, slots: u32) -> u64 {
+    pos.wrapping_add(slots.into())
+}
+
+/// A single slot driven by one sender and one receiver cursor, checking the
+/// state machine against ghost write/read counts. `next` picks the actor for
+/// each step: 0 sends, 1 receives, 2 retries a completed send and 3 retries a
+/// completed receive with a stale position.
+#[cfg(any(test, kani))]
+pub(crate) fn check_lifecycle(start: u64, slots: u32, steps: usize, mut next: impl FnMut() -> u8) {
+    assert!(slots >= 2 && slots.is_power_of_two());
+
+    let mut seq = start;
+    let mut send_pos = start;
+    let mut recv_pos = start;
+    let mut written = 0u32;
+    let mut read = 0u32;
+
+    for _ in 0..steps {
+        match next() % 4 {
+            0 => match for_send(seq, send_pos) {
+                Slot::Ready => {
+                    // Never overwrite a value that has not been read.
+                    assert_eq!(written, read);
+
+                    seq = published(send_pos);
+                    written += 1;
+                    send_pos = send_pos.wrapping_add(slots.into());
+                }
+                Slot::Behind => assert_eq!(written, read + 1),
+                Slot::Ahead => panic!("sender cursor can't be stale"),
+            },
+            1 => match for_recv(seq, recv_pos) {
+                Slot::Ready => {
+                    // Every value is read exactly once.
+                    assert_eq!(written, read + 1);
+
+                    seq = recycled(recv_pos, slots);
+                    read += 1;
+                    recv_pos = recv_pos.wrapping_add(slots.into());
+                }
+                Slot::Behind => assert_eq!(written, read),
+                Slot::Ahead => panic!("receiver cursor can't be stale"),
+            },
+            2 if written > 0 => {
+                let stale = send_pos.wrapping_sub(slots.into());
+
+                assert_eq!(for_send(seq, stale), Slot::Ahead);
+            }
+            3 if read > 0 => {
+                let stale = recv_pos.wrapping_sub(slots.into());
+
+                assert_eq!(for_recv(seq, stale), Slot::Ahead);
+            }
+            _ => (),
+        }
+
+        // The sender is never more than one lap ahead of the receiver.
+        assert!(written - read <= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive() {
+        for seq in [0, 1, 2, u64::MAX - 1, u64::MAX] {
+            for pos in [0, 1, u64::MAX - 1, u64::MAX] {
+                assert!(!(for_send(seq, pos) == Slot::Ready && for_recv(seq, pos) == Slot::Ready));
+            }
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        const STEPS: u32 = 8;
+
+        for start in [0, 5, u64::MAX - 3, u64::MAX] {
+            for slots in [2, 4, 1 << 16] {
+                // Every choice sequence of STEPS steps.
+                for choices in 0..4u32.pow(STEPS) {
+                    let mut c = choices;
+
+                    check_lifecycle(start, slots, STEPS as usize, || {
+                        let step = (c % 4) as u8;
+
+                        c /= 4;
+                        step
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! A picture of a queue's state, for debugging.
+//!
+//! [`RingBuffer::snapshot`] reads the positions and every slot's sequence
+//! number without claiming anything, so it can be taken from a debugger or
+//! a signal-driven dump while the queue is in use, and
+//! [`RingBuffer::snapshot_elements`] also copies the pending elements of a
+//! `Copy` type. The reads are not atomic together: a busy queue can move on
+//! while the slots are read, and slots near the positions may show either
+//! side of a race. A stuck queue holds still and shows where it is stuck,
+//! e.g. a [`SlotState::Writing`] slot at the head is a sender that claimed
+//! a position and never published it.
+//!
+//! [`RingBuffer::snapshot`]: crate::RingBuffer::snapshot
+//! [`RingBuffer::snapshot_elements`]: crate::RingBuffer::snapshot_elements
+
+/// The state of a queue, see the [module docs](self).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<T> {
+    pub generation: u16,
+    /// The enqueue and dequeue positions, see
+    /// [`RingBuffer::positions`](crate::RingBuffer::positions).
+    pub enq_pos: u64,
+    pub deq_pos: u64,
+    pub len: usize,
+    pub capacity: usize,
+    /// Elements waiting for redelivery, received before the ring.
+    pub redelivery: usize,
+    pub closed: bool,
+    pub senders: u32,
+    pub receivers: u32,
+    /// Every slot, by index. The priority lane of
+    /// [`Builder::lane_weights`](crate::Builder::lane_weights) is left out.
+    pub slots: Vec<SlotState<T>>,
+}
+
+/// What a slot's sequence number says, relative to the positions of the
+/// [`Snapshot`] it is part of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlotState<T> {
+    /// Free for the sender that claims `pos`.
+    Free { pos: u64 },
+    /// Claimed by the sender of `pos`, which has not published it yet.
+    Writing { pos: u64 },
+    /// Holds the element sent at `pos` for a receiver; `d` is a copy of it
+    /// with [`RingBuffer::snapshot_elements`](crate::RingBuffer::snapshot_elements).
+    Full { pos: u64, d: Option<T> },
+    /// Claimed by the receiver of `pos`, which has not released it yet.
+    Reading { pos: u64 },
+}
+
+impl<T> SlotState<T> {
+    // Classifies a slot from its sequence number, `enq` and `deq` being the
+    // positions; see slot.rs for the sequence protocol.
+    pub(crate) fn new(seq: u64, index: u64, mask: u64, enq: u64, deq: u64) -> Self {
+        // Below `bound`, counting from the dequeue position.
+        let before = |pos: u64, bound: u64| pos.wrapping_sub(deq) < bound.wrapping_sub(deq);
+
+        if seq & mask == index {
+            if before(seq, enq) {
+                SlotState::Writing { pos: seq }
+            } else {
+                SlotState::Free { pos: seq }
+            }
+        } else {
+            let pos = seq.wrapping_sub(1);
+
+            // Positions claimed by receivers end at `deq`.
+            if (deq.wrapping_sub(pos) as i64) > 0 {
+                SlotState::Reading { pos }
+            } else {
+                SlotState::Full { pos, d: None }
+            }
+        }
+    }
+
+    /// The position the slot serves next, or is serving.
+    pub fn pos(&self) -> u64 {
+        match *self {
+            SlotState::Free { pos }
+            | SlotState::Writing { pos }
+            | SlotState::Full { pos, .. }
+            | SlotState::Reading { pos } => pos,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::RingBuffer;
+
+    #[test]
+    fn slots() {
+        let (q, s, r) = RingBuffer::<u64>::new_boxed(4);
+
+        for i in 0..3 {
+            assert!(s.send(i));
+        }
+
+        assert_eq!(r.recv(), Ok(0));
+
+        let reading = r.recv_ref().unwrap();
+        let mut writing = s.claim().unwrap();
+        let snapshot = q.snapshot_elements();
+
+        assert_eq!((snapshot.enq_pos, snapshot.deq_pos), (4, 2));
+        assert_eq!(
+            snapshot.slots,
+            [
+                SlotState::Free { pos: 4 },
+                SlotState::Reading { pos: 1 },
+                SlotState::Full { pos: 2, d: Some(2) },
+                SlotState::Writing { pos: 3 },
+            ]
+        );
+
+        // Without copies, and once the claims are done.
+        drop(reading);
+        *writing = 7;
+        writing.publish();
+
+        let snapshot = q.snapshot();
+
+        assert_eq!(snapshot.len, 2);
+        assert_eq!(snapshot.slots[1], SlotState::Free { pos: 5 });
+        assert_eq!(snapshot.slots[3], SlotState::Full { pos: 3, d: None });
+    }
+}
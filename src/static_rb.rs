@@ -0,0 +1,170 @@
+//! Queues that live in a `static`.
+//!
+//! [`StaticRingBuffer::new`] is a `const fn` that checks the capacity at
+//! compile time. The queue and its slots are stored inline, so a `static`
+//! queue never touches the heap; they are only initialized on first use.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::OnceLock;
+
+use crate::rb::{checked_capacity, Cell, Receiver, RingBuffer, Sender};
+
+/// A queue of capacity `N`, a power of two, that can be a `static` item,
+/// see [`static_ring_buffer!`](crate::static_ring_buffer). It runs the same
+/// code as a [`RingBuffer`] laid out by [`RingBuffer::from_uninit_slice`].
+///
+/// The queue is never freed, and it keeps a sender and a receiver of its own
+/// to clone handles from, so receivers never see every sender gone.
+pub struct StaticRingBuffer<T: 'static, const N: usize> {
+    rb: UnsafeCell<MaybeUninit<RingBuffer<'static, T>>>,
+    slots: UnsafeCell<MaybeUninit<[Cell<T>; N]>>,
+    handles: OnceLock<(Sender<'static, T>, Receiver<'static, T>)>,
+}
+
+// Only the handles touch the queue once it is initialized.
+unsafe impl<T: Send, const N: usize> Sync for StaticRingBuffer<T, N> {}
+
+impl<T: 'static, const N: usize> StaticRingBuffer<T, N> {
+    /// The capacity of the queue.
+    pub const CAPACITY: usize = {
+        let n = checked_capacity(N);
+
+        // A single slot can't tell a published element from a free one.
+        assert!(
+            n.is_power_of_two() && n > 1,
+            "capacity must be a power of two above 1"
+        );
+        n
+    };
+
+    /// # Panics
+    ///
+    /// At compile time if `N` is not a power of two above 1 or is above
+    /// [`MAX_CAPACITY`](crate::MAX_CAPACITY), when used to initialize a
+    /// `static` or `const`.
+    pub const fn new() -> Self {
+        let _ = Self::CAPACITY;
+
+        Self {
+            rb: UnsafeCell::new(MaybeUninit::uninit()),
+            slots: UnsafeCell::new(MaybeUninit::uninit()),
+            handles: OnceLock::new(),
+        }
+    }
+
+    // Borrowing for 'static keeps the queue from moving once initialized.
+    fn handles(&'static self) -> &'static (Sender<'static, T>, Receiver<'static, T>) {
+        self.handles.get_or_init(|| unsafe {
+            RingBuffer::init_in_place(self.rb.get().cast(), self.slots.get().cast(), N, N)
+        })
+    }
+
+    /// A new sender, initializing the queue on first use.
+    pub fn sender(&'static self) -> Sender<'static, T> {
+        self.handles().0.clone()
+    }
+
+    /// A new receiver, initializing the queue on first use.
+    pub fn receiver(&'static self) -> Receiver<'static, T> {
+        self.handles().1.clone()
+    }
+}
+
+impl<T: 'static, const N: usize> Default for StaticRingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Declares a `static` [`StaticRingBuffer`], checking its capacity at
+/// compile time:
+///
+/// ```
+/// mpmcbq::static_ring_buffer! {
+///     /// Events from every worker.
+///     pub static EVENTS: [u64; 64];
+/// }
+///
+/// assert!(EVENTS.sender().send(7));
+/// assert_eq!(EVENTS.receiver().recv(), Ok(7));
+/// ```
+#[macro_export]
+macro_rules! static_ring_buffer {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: [$t:ty; $n:expr];) => {
+        $(#[$attr])*
+        $vis static $name: $crate::StaticRingBuffer<$t, { $n }> = $crate::StaticRingBuffer::new();
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static_ring_buffer! {
+        static QUEUE: [u64; 128];
+    }
+
+    #[test]
+    fn from_a_static() {
+        assert_eq!(StaticRingBuffer::<u64, 128>::CAPACITY, 128);
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                scope.spawn(move || {
+                    let s = QUEUE.sender();
+
+                    for j in 0..10 {
+                        while !s.send(i * 10 + j) {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+
+        let r = QUEUE.receiver();
+        let mut all: Vec<_> = std::iter::from_fn(|| r.recv().ok()).collect();
+
+        all.sort_unstable();
+        assert_eq!(all, (0..40).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn inline_slots() {
+        static_ring_buffer! {
+            static BIG: [[u64; 4]; 1024];
+        }
+
+        // The slots are part of the static itself.
+        let bytes = std::mem::size_of_val(&BIG);
+
+        assert!(bytes > 1024 * 32, "{} bytes", bytes);
+
+        // Threads share the queue through plain references to the static.
+        let q: &'static StaticRingBuffer<[u64; 4], 1024> = &BIG;
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let s = q.sender();
+
+                for i in 0..10_000 {
+                    while !s.send([i; 4]) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            let r = q.receiver();
+
+            for i in 0..10_000 {
+                loop {
+                    match r.recv() {
+                        Ok(d) => break assert_eq!(d, [i; 4]),
+                        Err(_) => std::thread::yield_now(),
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,395 @@
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+
+use crate::order;
+use crate::rb::MAX_CAPACITY;
+
+// Buckets of the depth histogram: depths 0 to 3 on their own, then four
+// buckets per power of two, up to u32::MAX.
+const DEPTH_BUCKETS: usize = 124;
+
+fn depth_bucket(depth: u32) -> usize {
+    if depth < 4 {
+        return depth as usize;
+    }
+
+    let octave = 31 - depth.leading_zeros();
+    let sub = (depth >> (octave - 2)) & 3;
+
+    (4 * (octave - 1) + sub) as usize
+}
+
+// The largest depth counted in `bucket`.
+fn bucket_max(bucket: usize) -> u64 {
+    if bucket < 4 {
+        return bucket as u64;
+    }
+
+    let (octave, sub) = (bucket as u64 / 4 + 1, bucket as u64 % 4);
+
+    ((5 + sub) << (octave - 2)) - 1
+}
+
+pub(crate) struct Counters {
+    enqueued: AtomicU64,
+    dequeued: AtomicU64,
+    send_failures: AtomicU64,
+    recv_failures: AtomicU64,
+    high_watermark: AtomicU64,
+
+    // Claims lost to another handle of the same side.
+    send_retries: AtomicU64,
+    recv_retries: AtomicU64,
+
+    // How many elements each successful send found queued, bucketed by
+    // depth_bucket().
+    depths: [AtomicU64; DEPTH_BUCKETS],
+
+    // Milliseconds since `epoch` of the last successful send and receive.
+    last_enqueue: AtomicU64,
+    last_dequeue: AtomicU64,
+    epoch: Instant,
+
+    // The local counters of dropped handles, folded in by their drop.
+    retired_senders: HandleCounters,
+    retired_receivers: HandleCounters,
+}
+
+/// Operation counts of a single handle, see
+/// [`Sender::local_stats`](crate::Sender::local_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandleStats {
+    /// Elements sent or received.
+    pub succeeded: u64,
+    /// Calls that sent or received nothing.
+    pub failed: u64,
+}
+
+/// Channel-wide sums of [`HandleStats`] of dropped handles, see
+/// [`RingBuffer::handle_totals`](crate::RingBuffer::handle_totals).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandleTotals {
+    pub senders: HandleStats,
+    pub receivers: HandleStats,
+}
+
+// The counters of one handle. Nothing is shared with other handles, but
+// one handle may be used from several threads at once, so an update is a
+// read-modify-write, uncontended unless the handle is shared.
+#[derive(Default)]
+pub(crate) struct HandleCounters {
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl HandleCounters {
+    fn bump(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, order::COUNTER);
+    }
+
+    /// Counts `n` elements, or a failure if `n` is zero.
+    pub(crate) fn count(&self, n: usize) {
+        match n {
+            0 => Self::bump(&self.failed, 1),
+            n => Self::bump(&self.succeeded, n as u64),
+        }
+    }
+
+    /// Counts one element for `Ok`, a failure for `Err`.
+    pub(crate) fn record<R, E>(&self, result: &Result<R, E>) {
+        self.count(result.is_ok() as usize);
+    }
+
+    pub(crate) fn snapshot(&self) -> HandleStats {
+        HandleStats {
+            succeeded: self.succeeded.load(order::COUNTER),
+            failed: self.failed.load(order::COUNTER),
+        }
+    }
+
+    // Adds a dropped handle's counts, racing other drops.
+    fn fold(&self, stats: HandleStats) {
+        self.succeeded.fetch_add(stats.succeeded, order::COUNTER);
+        self.failed.fetch_add(stats.failed, order::COUNTER);
+    }
+}
+
+/// Point-in-time copy of a queue's counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub enqueued: u64,
+    pub dequeued: u64,
+    /// Sends rejected because the queue was full.
+    pub send_failures: u64,
+    /// Receives that found nothing.
+    pub recv_failures: u64,
+    /// Claims of a position lost to another sender, each costing a retry.
+    pub send_retries: u64,
+    /// Claims of a position lost to another receiver.
+    pub recv_retries: u64,
+    pub high_watermark: u64,
+}
+
+/// What [`RingBuffer::capacity_advice`](crate::RingBuffer::capacity_advice)
+/// makes of the depths seen so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CapacityAdvice {
+    /// A capacity for the next run: the 99th percentile depth, or, if sends
+    /// were rejected, twice the larger of it and the current capacity.
+    pub suggested_capacity: usize,
+    /// The depth at or below which 99% of successful sends found the
+    /// queue, rounded up to the histogram's resolution of an eighth.
+    pub p99_depth: usize,
+    /// Fraction of sends rejected because the queue was full.
+    pub reject_rate: f64,
+}
+
+/// Why [`RingBuffer::stall_report`](crate::RingBuffer::stall_report)
+/// thinks a queue is stuck. `idle` is how long the stalled side has made no
+/// progress, at millisecond resolution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallReport {
+    /// Nothing was received for `idle` while `len` elements were buffered.
+    ConsumersIdle { idle: Duration, len: usize },
+    /// Nothing was sent for `idle` while `waiters` receivers were blocked.
+    ProducersIdle { idle: Duration, waiters: usize },
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            enqueued: AtomicU64::new(0),
+            dequeued: AtomicU64::new(0),
+            send_failures: AtomicU64::new(0),
+            recv_failures: AtomicU64::new(0),
+            high_watermark: AtomicU64::new(0),
+            send_retries: AtomicU64::new(0),
+            recv_retries: AtomicU64::new(0),
+            depths: std::array::from_fn(|_| AtomicU64::new(0)),
+            last_enqueue: AtomicU64::new(0),
+            last_dequeue: AtomicU64::new(0),
+            epoch: Instant::now(),
+            retired_senders: HandleCounters::default(),
+            retired_receivers: HandleCounters::default(),
+        }
+    }
+}
+
+impl Counters {
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    pub(crate) fn on_send(&self, len: usize) {
+        self.enqueued.fetch_add(1, order::COUNTER);
+        self.high_watermark.fetch_max(len as u64, order::COUNTER);
+        self.depths[depth_bucket(len as u32)].fetch_add(1, order::COUNTER);
+        self.last_enqueue.store(self.now(), order::COUNTER);
+    }
+
+    pub(crate) fn on_send_failure(&self) {
+        self.send_failures.fetch_add(1, order::COUNTER);
+    }
+
+    pub(crate) fn on_recv_failure(&self) {
+        self.recv_failures.fetch_add(1, order::COUNTER);
+    }
+
+    pub(crate) fn on_send_retry(&self) {
+        self.send_retries.fetch_add(1, order::COUNTER);
+    }
+
+    pub(crate) fn on_recv_retry(&self) {
+        self.recv_retries.fetch_add(1, order::COUNTER);
+    }
+
+    pub(crate) fn on_recv(&self) {
+        self.dequeued.fetch_add(1, order::COUNTER);
+        self.last_dequeue.store(self.now(), order::COUNTER);
+    }
+
+    pub(crate) fn retire_sender(&self, local: &HandleCounters) {
+        self.retired_senders.fold(local.snapshot());
+    }
+
+    pub(crate) fn retire_receiver(&self, local: &HandleCounters) {
+        self.retired_receivers.fold(local.snapshot());
+    }
+
+    pub(crate) fn handle_totals(&self) -> HandleTotals {
+        HandleTotals {
+            senders: self.retired_senders.snapshot(),
+            receivers: self.retired_receivers.snapshot(),
+        }
+    }
+
+    /// See `RingBuffer::capacity_advice`.
+    pub(crate) fn capacity_advice(&self, capacity: usize) -> CapacityAdvice {
+        let depths = self.depths.each_ref().map(|d| d.load(order::COUNTER));
+        let sends: u64 = depths.iter().sum();
+        let failures = self.send_failures.load(order::COUNTER);
+
+        // The first bucket by which 99% of the sends are counted.
+        let target = (sends * 99).div_ceil(100);
+        let mut seen = 0;
+        let p99 = depths
+            .iter()
+            .position(|&n| {
+                seen += n;
+                seen >= target
+            })
+            .map_or(0, |bucket| bucket_max(bucket) as usize);
+
+        // A queue that rejected sends cut its depths off at the capacity.
+        let suggested = match failures {
+            0 => p99.max(1),
+            _ => p99.max(capacity) * 2,
+        };
+
+        CapacityAdvice {
+            suggested_capacity: suggested.min(MAX_CAPACITY),
+            p99_depth: p99,
+            reject_rate: match sends + failures {
+                0 => 0.0,
+                attempts => failures as f64 / attempts as f64,
+            },
+        }
+    }
+
+    /// See `RingBuffer::stall_report`.
+    pub(crate) fn stall_report(
+        &self,
+        threshold: Duration,
+        len: usize,
+        waiters: usize,
+    ) -> Option<StallReport> {
+        let now = self.now();
+        let idle = |last: &AtomicU64| {
+            let idle = Duration::from_millis(now.saturating_sub(last.load(order::COUNTER)));
+
+            (idle >= threshold).then_some(idle)
+        };
+
+        if len > 0 {
+            if let Some(idle) = idle(&self.last_dequeue) {
+                return Some(StallReport::ConsumersIdle { idle, len });
+            }
+        }
+
+        if waiters > 0 {
+            if let Some(idle) = idle(&self.last_enqueue) {
+                return Some(StallReport::ProducersIdle { idle, waiters });
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn snapshot(&self) -> QueueStats {
+        QueueStats {
+            enqueued: self.enqueued.load(order::COUNTER),
+            dequeued: self.dequeued.load(order::COUNTER),
+            send_failures: self.send_failures.load(order::COUNTER),
+            recv_failures: self.recv_failures.load(order::COUNTER),
+            send_retries: self.send_retries.load(order::COUNTER),
+            recv_retries: self.recv_retries.load(order::COUNTER),
+            high_watermark: self.high_watermark.load(order::COUNTER),
+        }
+    }
+}
+
+/// Escapes a label value for the Prometheus text format: backslash,
+/// double quote and line feed are the only characters that need it.
+pub fn escape_label_value(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+
+    for c in v.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+pub(crate) fn write_prometheus(
+    w: &mut impl fmt::Write,
+    labels: &str,
+    len: usize,
+    capacity: usize,
+    stats: &QueueStats,
+) -> fmt::Result {
+    let metrics: [(&str, &str, &str, u64); 6] = [
+        ("len", "gauge", "Number of buffered elements.", len as u64),
+        (
+            "capacity",
+            "gauge",
+            "Number of elements the queue can hold.",
+            capacity as u64,
+        ),
+        (
+            "enqueued_total",
+            "counter",
+            "Elements successfully sent.",
+            stats.enqueued,
+        ),
+        (
+            "dequeued_total",
+            "counter",
+            "Elements successfully received.",
+            stats.dequeued,
+        ),
+        (
+            "send_failures_total",
+            "counter",
+            "Sends rejected because the queue was full.",
+            stats.send_failures,
+        ),
+        (
+            "high_watermark",
+            "gauge",
+            "Highest number of buffered elements observed.",
+            stats.high_watermark,
+        ),
+    ];
+
+    for (name, kind, help, value) in metrics {
+        writeln!(w, "# HELP mpmcbq_{} {}", name, help)?;
+        writeln!(w, "# TYPE mpmcbq_{} {}", name, kind)?;
+
+        if labels.is_empty() {
+            writeln!(w, "mpmcbq_{} {}", name, value)?;
+        } else {
+            writeln!(w, "mpmcbq_{}{{{}}} {}", name, labels, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_buckets() {
+        for depth in (0..100_000).chain([u32::MAX - 1, u32::MAX]) {
+            let bucket = depth_bucket(depth);
+
+            assert!(depth as u64 <= bucket_max(bucket), "{}", depth);
+            assert!(
+                bucket == 0 || depth as u64 > bucket_max(bucket - 1),
+                "{}",
+                depth
+            );
+        }
+
+        assert_eq!(depth_bucket(u32::MAX), DEPTH_BUCKETS - 1);
+        assert_eq!(bucket_max(DEPTH_BUCKETS - 1), u32::MAX as u64);
+        assert_eq!(bucket_max(depth_bucket(99)), 111);
+    }
+}
@@ -0,0 +1,43 @@
+//! The atomics and the payload cells of the ring: std's, or loom's in a
+//! `cfg(loom)` build, so that the models in `tests/loom.rs`, and those of
+//! downstream crates built with the same cfg, explore the interleavings of
+//! the claim, publish and release steps. See "Model checking" on
+//! [`RingBuffer`](crate::RingBuffer).
+//!
+//! Only the ring's own words go through here. Statics, and the atomics of
+//! the parking, stats and hook machinery, stay std's: a model that touches
+//! them does so outside of what it checks.
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::AtomicU64;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicU64;
+
+#[cfg(loom)]
+pub(crate) use loom::cell::UnsafeCell;
+
+/// std's `UnsafeCell` behind loom's closure API, which lets loom see when
+/// each access starts.
+#[cfg(not(loom))]
+#[repr(transparent)]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(data: T) -> Self {
+        Self(std::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
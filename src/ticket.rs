@@ -0,0 +1,102 @@
+//! Claiming the tail in ticket order, for [`Fairness::Ticket`].
+//!
+//! A sender takes a ticket, waits until it is served, then claims as usual;
+//! the claim CAS meets no other sender, and the turn passes on once the
+//! claim succeeds or fails. Senders claim in the order they took their
+//! tickets, so one that keeps losing the CAS race cannot starve: it waits at
+//! most one claim per sender ahead of it. The price is lock-freedom among
+//! senders, since one preempted holding the turn holds up the others until
+//! it runs again.
+//!
+//! [`Fairness::Ticket`]: crate::Fairness::Ticket
+
+use std::sync::atomic::AtomicU32;
+
+use crossbeam_utils::Backoff;
+
+use crate::order;
+use crate::pad::CachePadded;
+
+pub(crate) struct Turnstile {
+    // The next ticket handed out, and the one being served; both wrap.
+    next: CachePadded<AtomicU32>,
+    serving: CachePadded<AtomicU32>,
+}
+
+impl Turnstile {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: CachePadded::new(AtomicU32::new(0)),
+            serving: CachePadded::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Waits for a turn, which lasts until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> Turn<'_> {
+        let ticket = self.next.fetch_add(1, order::TURN);
+        let backoff = Backoff::new();
+
+        while self.serving.load(order::TURN) != ticket {
+            backoff.snooze();
+        }
+
+        Turn {
+            turnstile: self,
+            ticket,
+        }
+    }
+}
+
+pub(crate) struct Turn<'t> {
+    turnstile: &'t Turnstile,
+    ticket: u32,
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        // Only the holder of the turn moves `serving` on.
+        self.turnstile
+            .serving
+            .store(self.ticket.wrapping_add(1), order::TURN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn in_order() {
+        let turnstile = Turnstile::new();
+        let entered = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let turn = turnstile.enter();
+
+            scope.spawn(|| {
+                let _turn = turnstile.enter();
+
+                entered.store(true, Ordering::Relaxed);
+            });
+
+            thread::sleep(Duration::from_millis(10));
+            assert!(!entered.load(Ordering::Relaxed));
+            drop(turn);
+        });
+
+        assert!(entered.load(Ordering::Relaxed));
+
+        // Turns that end by unwinding pass on too.
+        let _ = std::panic::catch_unwind(|| {
+            let _turn = turnstile.enter();
+
+            panic!("claim failed");
+        });
+
+        drop(turnstile.enter());
+    }
+}
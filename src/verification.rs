@@ -0,0 +1,59 @@
+//! Kani proof harnesses for the slot state machine in `slot.rs`.
+//!
+//! Run with `cargo kani`; the module only exists under `cfg(kani)`.
+
+use crate::slot::{self, Slot};
+
+/// No sequence number makes a slot ready for both the sender and the
+/// receiver claiming the same position.
+#[kani::proof]
+fn ready_is_exclusive() {
+    let seq: u64 = kani::any();
+    let pos: u64 = kani::any();
+
+    assert!(!(slot::for_send(seq, pos) == Slot::Ready && slot::for_recv(seq, pos) == Slot::Ready));
+}
+
+/// A publish followed by a recycle lands exactly on the next lap's free state,
+/// for every starting position including those around the 2^64 wrap.
+#[kani::proof]
+fn publish_recycle_round_trip() {
+    let pos: u64 = kani::any();
+    let shift: u32 = kani::any();
+
+    kani::assume((1..=30).contains(&shift));
+
+    let slots = 1u32 << shift;
+    let seq = slot::published(pos);
+
+    assert_eq!(slot::for_recv(seq, pos), Slot::Ready);
+    assert_eq!(
+        slot::for_send(seq, pos.wrapping_add(slots.into())),
+        Slot::Behind
+    );
+
+    let seq = slot::recycled(pos, slots);
+
+    assert_eq!(
+        slot::for_send(seq, pos.wrapping_add(slots.into())),
+        Slot::Ready
+    );
+    assert_eq!(
+        slot::for_recv(seq, pos.wrapping_add(slots.into())),
+        Slot::Behind
+    );
+    assert_eq!(slot::for_recv(seq, pos), Slot::Ahead);
+}
+
+/// Any interleaving of claim/publish and claim/recycle on one slot, with
+/// stale retries, reads every written value exactly once.
+#[kani::proof]
+#[kani::unwind(9)]
+fn single_slot_interleavings() {
+    let start: u64 = kani::any();
+    let shift: u32 = kani::any();
+
+    kani::assume((1..=4).contains(&shift));
+
+    slot::check_lifecycle(start, 1 << shift, 8, kani::any);
+}
@@ -0,0 +1,79 @@
+//! A workload defined outside the crate: producers send tagged sequence
+//! numbers, a single consumer checks each producer's arrive in order.
+#![cfg(feature = "bench")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use mpmcbq::bench::{self, Config, Ctx, Workload};
+use mpmcbq::{Receiver, Sender};
+
+const ITEMS: u64 = 10_000;
+
+#[derive(Clone, Copy, Default)]
+struct Tagged {
+    producer: usize,
+    seq: u64,
+}
+
+#[derive(Default)]
+struct InOrder {
+    out_of_order: AtomicU64,
+}
+
+impl Workload<Tagged> for InOrder {
+    fn producer(&self, tx: &mut Sender<'_, Tagged>, ctx: &Ctx) {
+        for seq in 0..ITEMS {
+            let d = Tagged {
+                producer: ctx.index,
+                seq,
+            };
+
+            while !tx.send(d) {
+                thread::yield_now();
+            }
+        }
+    }
+
+    fn consumer(&self, rx: &mut Receiver<'_, Tagged>, ctx: &Ctx) {
+        let mut next = vec![0; ctx.producers];
+        let mut left = ITEMS * ctx.producers as u64;
+
+        while left > 0 {
+            match rx.recv() {
+                Ok(d) => {
+                    if d.seq != next[d.producer] {
+                        self.out_of_order.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    next[d.producer] = d.seq + 1;
+                    left -= 1;
+                }
+                Err(_) => thread::yield_now(),
+            }
+        }
+    }
+
+    fn verify(&self) -> Result<(), String> {
+        match self.out_of_order.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            n => Err(format!("{} elements out of order", n)),
+        }
+    }
+}
+
+#[test]
+fn custom_workload() {
+    let config = Config {
+        capacity: 64,
+        producers: 4,
+        consumers: 1,
+        rounds: 3,
+        pin: true,
+        ..Config::default()
+    };
+
+    let report = bench::run(&config, InOrder::default).unwrap();
+
+    assert_eq!(report.rounds.len(), 3);
+}
@@ -0,0 +1,91 @@
+//! Broadcast senders racing each other and receivers that keep up or fall
+//! behind. Every receiver must see each producer's elements in send order,
+//! and never a torn or stale payload.
+//!
+//! Not part of the ThreadSanitizer run: receivers copy slots with a seqlock
+//! read that races with senders by design.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use mpmcbq::{Broadcast, BroadcastRecvError};
+
+fn items() -> u64 {
+    std::env::var("MPMCBQ_STRESS_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100_000)
+}
+
+// Both halves carry the same counter, so a torn copy shows up as a mismatch.
+#[derive(Clone, Copy, Default)]
+struct Item {
+    producer: u64,
+    seq: u64,
+    check: u64,
+}
+
+#[test]
+fn ordered_per_producer() {
+    const PRODUCERS: u64 = 3;
+
+    let items = items();
+    let (_q, s, r) = Broadcast::<Item>::new(64);
+    let done = AtomicU64::new(0);
+
+    thread::scope(|scope| {
+        let receivers: Vec<_> = (0..3)
+            .map(|_| {
+                let mut r = r.clone();
+                let done = &done;
+
+                scope.spawn(move || {
+                    let mut next = [0u64; PRODUCERS as usize];
+                    let mut seen = 0;
+
+                    loop {
+                        // Read before the attempt, so an empty ring after the
+                        // last producer finished means there is nothing left.
+                        let finished = done.load(Ordering::Acquire) == PRODUCERS;
+
+                        match r.try_recv() {
+                            Ok(d) => {
+                                assert_eq!(d.seq, d.check);
+                                assert!(d.seq >= next[d.producer as usize]);
+
+                                next[d.producer as usize] = d.seq + 1;
+                                seen += 1;
+                            }
+                            Err(BroadcastRecvError::Empty) if finished => break,
+                            Err(BroadcastRecvError::Empty) => thread::yield_now(),
+                            Err(BroadcastRecvError::Lagged(n)) => assert!(n > 0),
+                        }
+                    }
+
+                    seen
+                })
+            })
+            .collect();
+
+        for producer in 0..PRODUCERS {
+            let mut s = s.clone();
+            let done = &done;
+
+            scope.spawn(move || {
+                for seq in 0..items {
+                    s.send(Item {
+                        producer,
+                        seq,
+                        check: seq,
+                    });
+                }
+
+                done.fetch_add(1, Ordering::Release);
+            });
+        }
+
+        for receiver in receivers {
+            assert!(receiver.join().unwrap() > 0);
+        }
+    });
+}
@@ -0,0 +1,7 @@
+//! Capacities, and uses of scoped handles, that must be rejected at compile
+//! time.
+
+#[test]
+fn rejected() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,111 @@
+//! Per-producer FIFO: every producer tags its elements with (id, seq) and
+//! every consumer checks that, for each producer, the sequence numbers it
+//! receives strictly increase. Small capacities maximise interleaving.
+//! Together the consumers must have received each producer's sequence
+//! numbers exactly once.
+//!
+//! `MPMCBQ_STRESS_ITEMS` overrides the number of elements per producer.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use mpmcbq::RingBuffer;
+
+fn items() -> u64 {
+    std::env::var("MPMCBQ_STRESS_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100_000)
+}
+
+fn run(capacity: usize, producers: u64, consumers: usize) {
+    let items = items();
+    let total = (producers * items) as usize;
+    let (s, r) = RingBuffer::<u64>::new(capacity);
+    let received = AtomicUsize::new(0);
+
+    let seen = thread::scope(|scope| {
+        for id in 0..producers {
+            let s = s.clone();
+
+            scope.spawn(move || {
+                for seq in 0..items {
+                    while !s.send(id << 32 | seq) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+
+        let handles: Vec<_> = (0..consumers)
+            .map(|_| {
+                let r = r.clone();
+                let received = &received;
+
+                scope.spawn(move || {
+                    let mut last = vec![None; producers as usize];
+                    let mut got = vec![Vec::new(); producers as usize];
+
+                    while received.load(Ordering::Relaxed) < total {
+                        let Ok(tok) = r.recv() else {
+                            thread::yield_now();
+                            continue;
+                        };
+
+                        let (id, seq) = ((tok >> 32) as usize, tok & 0xffff_ffff);
+
+                        if let Some(prev) = last[id] {
+                            assert!(seq > prev, "producer {id}: {seq} after {prev}");
+                        }
+
+                        last[id] = Some(seq);
+                        got[id].push(seq);
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    got
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    // Exactly what was sent: nothing lost, nothing twice.
+    for id in 0..producers as usize {
+        let mut all: Vec<u64> = seen.iter().flat_map(|got| &got[id]).copied().collect();
+
+        all.sort_unstable();
+        assert!(all.iter().copied().eq(0..items), "producer {id}");
+    }
+
+    assert!(r.empty());
+}
+
+#[test]
+fn fifo_1x1() {
+    run(1, 1, 1);
+}
+
+#[test]
+fn fifo_4x1() {
+    run(2, 4, 1);
+}
+
+#[test]
+fn fifo_1x4() {
+    run(2, 1, 4);
+}
+
+#[test]
+fn fifo_4x4() {
+    run(4, 4, 4);
+}
+
+#[test]
+fn fifo_8x8() {
+    run(16, 8, 8);
+}
@@ -0,0 +1,197 @@
+//! Loom models of the claim paths: senders and receivers racing on a
+//! capacity-2 queue, with every interleaving of their atomic steps explored
+//! up to a preemption bound. Each producer tags its elements with (id, seq).
+//!
+//! The threads make a fixed number of attempts rather than spinning until
+//! they succeed, since loom would explore a spinner's retries without end;
+//! whatever they leave in the queue is drained once they are joined. What
+//! the receivers got, in order, must then be exactly what the senders had
+//! accepted: nothing lost, nothing twice, and each producer's elements in
+//! the order it sent them.
+//!
+//! Past those, a queue that wraps around while a receiver races the sender
+//! for its first slot, and senders that drop or close while a receiver
+//! drains: it must see every accepted element before it sees the end.
+//!
+//! They only build with the ring's atomics swapped for loom's:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+//!
+//! `LOOM_MAX_PREEMPTIONS` overrides the bound of 3.
+#![cfg(loom)]
+
+use loom::thread;
+
+use mpmcbq::{Receiver, RingBuffer, Sender, TryRecvError};
+
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+
+    builder.preemption_bound.get_or_insert(3);
+    builder.check(f);
+}
+
+// Tries to send `items` elements tagged with `id`, returning the accepted.
+fn send(s: &Sender<u64>, id: u64, items: u64) -> Vec<u64> {
+    (0..items)
+        .map(|seq| id << 32 | seq)
+        .filter(|&tok| s.try_send(tok).is_ok())
+        .collect()
+}
+
+// Tries to receive `attempts` times, returning what was received.
+fn recv(r: &Receiver<u64>, attempts: usize) -> Vec<u64> {
+    (0..attempts).filter_map(|_| r.try_recv().ok()).collect()
+}
+
+// Checks that everything `sent` was received exactly once, with each
+// receiver's share of every producer in order.
+fn check(mut sent: Vec<u64>, seen: &[Vec<u64>]) {
+    for got in seen {
+        for id in 0..2 {
+            let mine: Vec<u64> = got.iter().copied().filter(|tok| tok >> 32 == id).collect();
+
+            assert!(mine.windows(2).all(|w| w[0] < w[1]), "{:?}", seen);
+        }
+    }
+
+    let mut received = seen.concat();
+
+    sent.sort_unstable();
+    received.sort_unstable();
+    assert_eq!(sent, received);
+}
+
+#[test]
+fn two_senders_one_receiver() {
+    model(|| {
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        let senders: Vec<_> = (0..2)
+            .map(|id| {
+                let s = s.clone();
+
+                thread::spawn(move || send(&s, id, 2))
+            })
+            .collect();
+
+        let mut got = recv(&r, 2);
+        let sent = senders
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+
+        got.extend(recv(&r, 4));
+        assert!(r.try_recv().is_err());
+        check(sent, &[got]);
+        drop(s);
+    });
+}
+
+#[test]
+fn one_sender_two_receivers() {
+    model(|| {
+        let (s, r) = RingBuffer::<u64>::new(2);
+
+        let receivers: Vec<_> = (0..2)
+            .map(|_| {
+                let r = r.clone();
+
+                thread::spawn(move || recv(&r, 2))
+            })
+            .collect();
+
+        let sent = send(&s, 0, 3);
+        let mut seen: Vec<_> = receivers.into_iter().map(|h| h.join().unwrap()).collect();
+
+        seen.push(recv(&r, 3));
+        assert!(r.try_recv().is_err());
+        check(sent, &seen);
+        drop(s);
+    });
+}
+
+#[test]
+fn wrap_around() {
+    model(|| {
+        let (s, r) = RingBuffer::<u64>::new(2);
+        let mut sent = send(&s, 0, 2);
+
+        assert_eq!(sent.len(), 2);
+
+        let receiver = {
+            let r = r.clone();
+
+            thread::spawn(move || recv(&r, 2))
+        };
+
+        // The third element goes to the first slot again, which the receiver
+        // may be releasing meanwhile.
+        if (0..2).any(|_| s.try_send(2).is_ok()) {
+            sent.push(2);
+        }
+
+        let mut seen = vec![receiver.join().unwrap()];
+
+        seen.push(recv(&r, 3));
+        assert!(r.try_recv().is_err());
+        check(sent, &seen);
+        drop(s);
+    });
+}
+
+// Sends two elements from another thread, then ends the queue with `end`,
+// while the receiver takes what it can: the end must come after the last
+// accepted element, never before.
+fn drain_then(end: fn(Sender<'static, u64>), expected: TryRecvError) {
+    model(move || {
+        let (s, r) = RingBuffer::<u64>::new(2);
+        let sender = thread::spawn(move || {
+            let sent = send(&s, 0, 2);
+
+            end(s);
+            sent
+        });
+
+        let mut got = Vec::new();
+        let mut last = None;
+
+        for _ in 0..3 {
+            match r.try_recv() {
+                Ok(d) => got.push(d),
+                Err(TryRecvError::Empty) => (),
+                Err(e) => {
+                    last = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let sent = sender.join().unwrap();
+
+        if last.is_none() {
+            got.extend(recv(&r, 2));
+            last = r.try_recv().err();
+        }
+
+        assert_eq!(last, Some(expected));
+        check(sent, &[got]);
+    });
+}
+
+#[test]
+fn drain_then_disconnect() {
+    drain_then(drop, TryRecvError::Disconnected);
+}
+
+#[test]
+fn drain_then_close() {
+    drain_then(
+        |s| {
+            s.close();
+        },
+        TryRecvError::Closed,
+    );
+}
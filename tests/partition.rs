@@ -0,0 +1,90 @@
+//! Per-key ordering through a `PartitionedSender`: producers send random
+//! keys tagged with a per-producer sequence number, and the worker of every
+//! partition checks that it only sees its own keys and that, for each
+//! producer and key, the sequence numbers strictly increase.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use mpmcbq::partition::Routing;
+use mpmcbq::PartitionedSender;
+
+const KEYS: u64 = 64;
+
+fn items() -> u64 {
+    std::env::var("MPMCBQ_STRESS_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100_000)
+}
+
+fn run(routing: Routing, partitions: usize, producers: u64) {
+    let items = items();
+    let total = (producers * items) as usize;
+    let (_queues, s, r) = PartitionedSender::<u64, u64>::builder()
+        .partitions(partitions)
+        .capacity(8)
+        .routing(routing)
+        .build();
+    let received = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for id in 0..producers {
+            let mut s = s.clone();
+
+            scope.spawn(move || {
+                let mut x = id + 1;
+
+                for seq in 0..items {
+                    // xorshift
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+
+                    let key = x % KEYS;
+
+                    while !s.send(&key, id << 48 | key << 32 | seq) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+
+        for (p, r) in r.into_iter().enumerate() {
+            let s = &s;
+            let received = &received;
+
+            scope.spawn(move || {
+                let mut last = HashMap::new();
+
+                while received.load(Ordering::Relaxed) < total {
+                    let Ok(tok) = r.recv() else {
+                        thread::yield_now();
+                        continue;
+                    };
+
+                    let (id, key, seq) = (tok >> 48, tok >> 32 & 0xffff, tok & 0xffff_ffff);
+
+                    assert_eq!(s.partition(&key), p, "key {key}");
+
+                    if let Some(prev) = last.insert((id, key), seq) {
+                        assert!(seq > prev, "producer {id} key {key}: {seq} after {prev}");
+                    }
+
+                    received.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn modulo_4x4() {
+    run(Routing::Modulo, 4, 4);
+}
+
+#[test]
+fn consistent_4x3() {
+    run(Routing::Consistent { replicas: 32 }, 3, 4);
+}
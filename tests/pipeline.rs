@@ -0,0 +1,54 @@
+//! A two-stage pipeline end to end: every element comes out transformed,
+//! exactly once, and the output disconnects when the input is done.
+#![cfg(not(feature = "single-threaded"))]
+
+use std::thread;
+use std::time::Duration;
+
+use mpmcbq::pipeline;
+use mpmcbq::RingBuffer;
+
+const ITEMS: u64 = 1_000_000;
+
+#[test]
+fn transform_a_million() {
+    let (s, r) = RingBuffer::<u64>::new(256);
+    let (mid, mid_r) = RingBuffer::<u64>::new(256);
+    let (out, results) = RingBuffer::<u64>::new(256);
+
+    let (sum, count) = thread::scope(|scope| {
+        let first = pipeline::stage(scope, r, mid, 4, |d| d * 2);
+        let second = pipeline::stage(scope, mid_r, out, 3, |d| d + 1);
+
+        let consumer = scope.spawn(move || {
+            let (mut sum, mut count) = (0u64, 0u64);
+            let mut buf = Vec::new();
+
+            // Returns fewer than one only once every output sender is gone.
+            while results.recv_batch_blocking(1, 256, Duration::from_secs(10), &mut buf) > 0 {
+                sum += buf.iter().sum::<u64>();
+                count += buf.len() as u64;
+                buf.clear();
+            }
+
+            (sum, count)
+        });
+
+        for i in 0..ITEMS {
+            while !s.send(i) {
+                s.wait_below(0.5, Duration::from_millis(10));
+            }
+        }
+
+        drop(s);
+
+        assert!(first.join().is_ok());
+        assert!(second.join().is_ok());
+
+        consumer.join().unwrap()
+    });
+
+    assert_eq!(count, ITEMS);
+    // The sum of 2 * i + 1 over 0..ITEMS.
+    assert_eq!(sum, ITEMS * ITEMS);
+}
@@ -0,0 +1,127 @@
+//! Conditional receives racing each other: `recv_if` workers with different
+//! predicates, and `drain_while` workers consuming up to barrier markers.
+//! Together the workers must receive every token exactly once.
+//!
+//! Not part of the ThreadSanitizer run: both copy the head with a seqlock
+//! read that races with senders by design.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use mpmcbq::RingBuffer;
+
+fn items() -> u64 {
+    std::env::var("MPMCBQ_STRESS_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100_000)
+}
+
+#[test]
+fn even_and_any() {
+    let items = items();
+    let (s, r) = RingBuffer::<u64>::new(8);
+    let received = AtomicU64::new(0);
+
+    let got = thread::scope(|scope| {
+        let workers: Vec<_> = [|d: &u64| d.is_multiple_of(2), |_: &u64| true]
+            .into_iter()
+            .map(|pred| {
+                let r = r.clone();
+                let received = &received;
+
+                scope.spawn(move || {
+                    let mut got = Vec::new();
+
+                    while received.load(Ordering::Relaxed) < items {
+                        match r.recv_if(pred) {
+                            Ok(Some(d)) => {
+                                got.push(d);
+                                received.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Ok(None) | Err(_) => thread::yield_now(),
+                        }
+                    }
+
+                    got
+                })
+            })
+            .collect();
+
+        for i in 0..items {
+            while !s.send(i) {
+                thread::yield_now();
+            }
+        }
+
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    assert!(got[0].iter().all(|d| d.is_multiple_of(2)));
+
+    let mut all: Vec<u64> = got.concat();
+
+    all.sort_unstable();
+    assert!(all.iter().copied().eq(0..items));
+}
+
+#[test]
+fn drain_to_marker() {
+    const MARK: u64 = u64::MAX;
+
+    let items = items();
+    let (s, r) = RingBuffer::<u64>::new(64);
+    let received = AtomicU64::new(0);
+
+    let got = thread::scope(|scope| {
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let r = r.clone();
+                let received = &received;
+
+                scope.spawn(move || {
+                    let mut got = Vec::new();
+
+                    while received.load(Ordering::Relaxed) < items {
+                        let n = r.drain_while(|&d| d != MARK, &mut got);
+
+                        received.fetch_add(n as u64, Ordering::Relaxed);
+
+                        let mark = r.recv_if(|&d| d == MARK) == Ok(Some(MARK));
+
+                        if n == 0 && !mark {
+                            thread::yield_now();
+                        }
+                    }
+
+                    got
+                })
+            })
+            .collect();
+
+        for i in 0..items {
+            while !s.send(i) {
+                thread::yield_now();
+            }
+
+            if i % 10 == 9 {
+                while !s.send(MARK) {
+                    thread::yield_now();
+                }
+            }
+        }
+
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let mut all: Vec<u64> = got.concat();
+
+    all.sort_unstable();
+    assert!(all.iter().copied().eq(0..items));
+}
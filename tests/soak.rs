@@ -0,0 +1,432 @@
+//! Soak test: round after round of channels created, worked hard with a
+//! random mix of operations and handle churn, and torn down again.
+//!
+//! Every round is a scenario drawn from its own seed: capacity, thread
+//! counts and the phase, which picks the operations the threads cycle
+//! through. Elements are checksummed per producer, so a lost or duplicated
+//! element fails the round, as does a position going backwards, a queue
+//! not empty after everything was received, or a panic anywhere. A counting
+//! allocator tracks live allocations between rounds: teardown that leaks
+//! shows up as a count that keeps growing.
+//!
+//! `soak_smoke` runs a few short rounds with every test run. The real soak
+//! is ignored by default; leave it going with
+//!
+//! ```text
+//! MPMCBQ_SOAK_SECS=28800 cargo test --release --test soak -- --ignored --nocapture
+//! ```
+//!
+//! A failure names the round's seed. `MPMCBQ_SOAK_SEED=<seed>` makes that
+//! the first round; thread timing is not reproducible, the scenario is.
+#![cfg(not(feature = "single-threaded"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mpmcbq::{RecvTimeoutError, RingBuffer, SendTimeoutError, TrySendError};
+
+// Counts live allocations. Blocks rather than bytes: buffers that only grow,
+// such as captured test output, keep their count.
+struct Counting;
+
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+
+        if !p.is_null() {
+            LIVE.fetch_add(1, Ordering::Relaxed);
+        }
+
+        p
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc_zeroed(layout);
+
+        if !p.is_null() {
+            LIVE.fetch_add(1, Ordering::Relaxed);
+        }
+
+        p
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        System.dealloc(p, layout);
+        LIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, p: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        System.realloc(p, layout, size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// The two tests share the allocation count, so they take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+// Live allocations may exceed the count after the first round by this much,
+// for lazily initialised runtime state.
+const LEAK_SLACK: isize = 64;
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn one_in(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+}
+
+// Scrambles a value for the checksums, so that losing one element and
+// duplicating another rarely cancels out.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ x >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ x >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ x >> 31
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    // try_send and try_recv only.
+    Singles,
+    // try_send_many and batch receives.
+    Batches,
+    // send_blocking, recv_blocking and the timeouts.
+    Blocking,
+    // Everything, plus redelivery through unrecv and dropped ack guards.
+    Mixed,
+}
+
+const PHASES: [Phase; 4] = [
+    Phase::Singles,
+    Phase::Batches,
+    Phase::Blocking,
+    Phase::Mixed,
+];
+
+#[derive(Debug)]
+struct Scenario {
+    seed: u64,
+    phase: Phase,
+    capacity: usize,
+    producers: u64,
+    consumers: u64,
+    items: u64,
+}
+
+impl Scenario {
+    fn new(seed: u64, round: u64, max_items: u64) -> Self {
+        let mut rng = Rng(seed | 1);
+
+        Self {
+            seed,
+            phase: PHASES[(round % PHASES.len() as u64) as usize],
+            capacity: 1 + rng.below(300) as usize,
+            producers: 1 + rng.below(4),
+            consumers: 1 + rng.below(4),
+            items: max_items / 2 + rng.below(max_items / 2),
+        }
+    }
+}
+
+// Per producer: how many elements, and the sum of their mixes.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+struct Checksum {
+    count: u64,
+    sum: u64,
+}
+
+impl Checksum {
+    fn add(&mut self, d: u64) {
+        self.count += 1;
+        self.sum = self.sum.wrapping_add(mix(d));
+    }
+}
+
+fn element(producer: u64, seq: u64) -> u64 {
+    producer << 48 | seq
+}
+
+fn producer_of(d: u64) -> usize {
+    (d >> 48) as usize
+}
+
+fn run_round(sc: &Scenario) {
+    let (q, s, r) = RingBuffer::<u64>::new_boxed(sc.capacity);
+    let total = sc.producers * sc.items;
+    let received = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+
+    let sums = thread::scope(|scope| {
+        for p in 0..sc.producers {
+            let (mut s, mut rng) = (s.clone(), Rng(mix(sc.seed ^ p) | 1));
+
+            scope.spawn(move || {
+                let mut seq = 0;
+
+                while seq < sc.items {
+                    // Handle churn: carry on with a fresh clone.
+                    if rng.one_in(256) {
+                        s = s.clone();
+                    }
+
+                    seq += produce(sc.phase, &mut s, &mut rng, p, seq, sc.items);
+                }
+            });
+        }
+
+        // Only the producers' senders are left, so receivers see the end.
+        drop(s);
+
+        let consumers: Vec<_> = (0..sc.consumers)
+            .map(|c| {
+                let (mut r, mut rng) = (r.clone(), Rng(mix(!sc.seed ^ c) | 1));
+                let received = &received;
+
+                scope.spawn(move || {
+                    let mut sums = vec![Checksum::default(); sc.producers as usize];
+                    let mut buf = Vec::new();
+
+                    while received.load(Ordering::Relaxed) < total {
+                        if rng.one_in(256) {
+                            r = r.clone();
+                        }
+
+                        buf.clear();
+                        consume(sc.phase, &mut r, &mut rng, &mut buf);
+
+                        for &d in &buf {
+                            sums[producer_of(d)].add(d);
+                        }
+
+                        received.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                    }
+
+                    sums
+                })
+            })
+            .collect();
+
+        // The dequeue position only moves forward. The queue itself can't be
+        // shared with a thread, a receiver that never receives can.
+        let (watcher, done) = (r.clone(), &done);
+
+        scope.spawn(move || {
+            let mut last = watcher.position();
+
+            while !done.load(Ordering::Relaxed) {
+                let pos = watcher.position();
+
+                assert!(pos >= last, "dequeue position {} after {}", pos, last);
+                last = pos;
+                thread::sleep(Duration::from_micros(200));
+            }
+        });
+
+        let sums: Vec<_> = consumers.into_iter().map(|h| h.join().unwrap()).collect();
+
+        done.store(true, Ordering::Relaxed);
+        sums
+    });
+
+    for p in 0..sc.producers {
+        let mut expected = Checksum::default();
+        let mut got = Checksum::default();
+
+        (0..sc.items).for_each(|seq| expected.add(element(p, seq)));
+
+        for sums in &sums {
+            got.count += sums[p as usize].count;
+            got.sum = got.sum.wrapping_add(sums[p as usize].sum);
+        }
+
+        assert_eq!(got, expected, "producer {}: elements lost or duplicated", p);
+    }
+
+    let (enq, deq) = q.positions();
+
+    assert!(
+        q.empty() && enq == deq,
+        "{} - {} left after the round",
+        enq,
+        deq
+    );
+    drop(r);
+}
+
+// Sends one or more elements from `seq` on, returning how many.
+fn produce(
+    phase: Phase,
+    s: &mut mpmcbq::Sender<'_, u64>,
+    rng: &mut Rng,
+    p: u64,
+    seq: u64,
+    items: u64,
+) -> u64 {
+    let op = match phase {
+        Phase::Singles => 0,
+        Phase::Batches => 1,
+        Phase::Blocking => 2 + rng.below(2),
+        Phase::Mixed => rng.below(4),
+    };
+
+    match op {
+        0 => {
+            while let Err(e) = s.try_send(element(p, seq)) {
+                assert!(matches!(e, TrySendError::Full(_)), "{:?}", e);
+                thread::yield_now();
+            }
+
+            1
+        }
+        1 => {
+            let n = (1 + rng.below(32)).min(items - seq);
+            let batch: Vec<_> = (seq..seq + n).map(|seq| element(p, seq)).collect();
+
+            loop {
+                match s.try_send_many(&batch, false) {
+                    Ok(sent) => return sent as u64,
+                    Err(TrySendError::Full(())) => thread::yield_now(),
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+        2 => {
+            assert_eq!(s.send_blocking(element(p, seq)), Ok(()));
+            1
+        }
+        _ => {
+            let mut d = element(p, seq);
+
+            loop {
+                match s.send_timeout(d, Duration::from_micros(rng.below(200))) {
+                    Ok(()) => return 1,
+                    Err(SendTimeoutError::Timeout(back)) => d = back,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+    }
+}
+
+// Receives into `buf`; may leave it empty.
+fn consume(phase: Phase, r: &mut mpmcbq::Receiver<'_, u64>, rng: &mut Rng, buf: &mut Vec<u64>) {
+    let op = match phase {
+        Phase::Singles => 0,
+        Phase::Batches => 1 + rng.below(2),
+        Phase::Blocking => 3 + rng.below(2),
+        Phase::Mixed => rng.below(7),
+    };
+
+    match op {
+        0 => buf.extend(r.try_recv()),
+        1 => {
+            r.recv_batch_deadline(Instant::now() + Duration::from_micros(100), 16, buf);
+        }
+        2 => {
+            r.drain_while(|_| !rng.one_in(8), buf);
+        }
+        3 => buf.extend(r.recv_blocking()),
+        4 => match r.recv_timeout(Duration::from_micros(rng.below(200))) {
+            Ok(d) => buf.push(d),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => (),
+            Err(e) => panic!("{:?}", e),
+        },
+        // Put back, to be received again.
+        5 => {
+            if let Ok(d) = r.try_recv() {
+                r.unrecv(d);
+            }
+        }
+        // Acknowledged, or dropped and redelivered.
+        _ => {
+            if let Ok(g) = r.recv_ack() {
+                if !rng.one_in(4) {
+                    buf.push(g.ack());
+                }
+            }
+        }
+    }
+}
+
+fn soak(rounds: Option<u64>, duration: Duration, max_items: u64) {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let seed: u64 = std::env::var("MPMCBQ_SOAK_SEED")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0x2545_f491_4f6c_dd1d);
+    let start = Instant::now();
+    let mut baseline = None;
+    let mut round = 0;
+
+    while rounds.map_or(start.elapsed() < duration, |n| round < n) {
+        let sc = Scenario::new(
+            seed.wrapping_add(round.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            round,
+            max_items,
+        );
+
+        if let Err(e) = panic::catch_unwind(|| run_round(&sc)) {
+            let msg = e
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| e.downcast_ref::<&str>().copied())
+                .unwrap_or("panic");
+
+            panic!(
+                "round {} failed, MPMCBQ_SOAK_SEED={}: {}\n{:?}",
+                round, sc.seed, msg, sc
+            );
+        }
+
+        let live = LIVE.load(Ordering::Relaxed);
+        let baseline = *baseline.get_or_insert(live);
+
+        assert!(
+            live - baseline <= LEAK_SLACK,
+            "round {}, MPMCBQ_SOAK_SEED={}: {} allocations live, {} after the first round",
+            round,
+            sc.seed,
+            live,
+            baseline
+        );
+
+        round += 1;
+    }
+
+    eprintln!("soak: {} rounds in {:?}", round, start.elapsed());
+}
+
+#[test]
+fn soak_smoke() {
+    soak(Some(2 * PHASES.len() as u64), Duration::ZERO, 2_000);
+}
+
+#[test]
+#[ignore]
+fn soak_long() {
+    let secs = std::env::var("MPMCBQ_SOAK_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+
+    soak(None, Duration::from_secs(secs), 100_000);
+}
@@ -0,0 +1,58 @@
+//! An SPSC ring under load: one producer, one consumer, a small capacity so
+//! both ends keep hitting full and empty. The consumer must see every
+//! element once, in order.
+//!
+//! `MPMCBQ_STRESS_ITEMS` overrides the number of elements, e.g. 100000000
+//! for a long run.
+
+use std::thread;
+
+use mpmcbq::SpscRing;
+
+fn items() -> u64 {
+    std::env::var("MPMCBQ_STRESS_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_000_000)
+}
+
+fn run(capacity: usize) {
+    let items = items();
+    let (q, mut s, mut r) = SpscRing::<u64>::new(capacity);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..items {
+                let mut d = i;
+
+                while let Err(back) = s.send(d) {
+                    d = back;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for i in 0..items {
+            let d = loop {
+                match r.recv() {
+                    Some(d) => break d,
+                    None => thread::yield_now(),
+                }
+            };
+
+            assert_eq!(d, i);
+        }
+    });
+
+    assert!(q.is_empty());
+}
+
+#[test]
+fn tiny() {
+    run(2);
+}
+
+#[test]
+fn small() {
+    run(128);
+}
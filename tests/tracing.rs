@@ -0,0 +1,114 @@
+//! The events of the `tracing` feature, caught by a subscriber that keeps
+//! their messages. One test only: the subscriber is the process-wide
+//! default, and a second test would interleave its events with the first's.
+#![cfg(feature = "tracing")]
+
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use mpmcbq::{Builder, OnFull, RecvTimeoutError, TrySendError};
+
+#[derive(Clone, Default)]
+struct Messages(Arc<Mutex<Vec<String>>>);
+
+impl Messages {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.0, "{:?}", value).unwrap();
+        }
+    }
+}
+
+impl Subscriber for Messages {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = Message(String::new());
+
+        event.record(&mut message);
+        self.0.lock().unwrap().push(message.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn events() {
+    let messages = Messages::default();
+
+    tracing::subscriber::set_global_default(messages.clone()).unwrap();
+
+    let (s, r) = Builder::<u32>::new().capacity(1).name("traced").channel();
+
+    assert_eq!(messages.take(), ["channel created"]);
+
+    // Sends and receives that go through are not traced.
+    assert!(s.send(1));
+    assert_eq!(r.try_recv(), Ok(1));
+    assert!(messages.take().is_empty());
+
+    assert!(s.send(2));
+    assert_eq!(s.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(messages.take(), ["queue full"]);
+
+    assert_eq!(r.try_recv(), Ok(2));
+    assert_eq!(
+        r.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    assert_eq!(messages.take(), ["parking", "unparked"]);
+
+    assert!(s.close());
+    assert!(!s.close());
+    assert_eq!(messages.take(), ["channel closed"]);
+
+    drop(s);
+    drop(r);
+    assert!(messages.take().is_empty());
+
+    let (s, r) = Builder::<u32>::new()
+        .capacity(1)
+        .on_full(OnFull::Overwrite)
+        .channel();
+
+    assert!(s.send(1));
+    assert!(s.send(2));
+    assert_eq!(r.try_recv(), Ok(2));
+
+    drop(s);
+    drop(r);
+    assert_eq!(
+        messages.take(),
+        [
+            "channel created",
+            "queue full",
+            "last sender dropped",
+            "last receiver dropped"
+        ]
+    );
+}
@@ -0,0 +1,7 @@
+mpmcbq::static_ring_buffer! {
+    static QUEUE: [u64; 100];
+}
+
+fn main() {
+    QUEUE.sender();
+}
@@ -0,0 +1,7 @@
+mpmcbq::static_ring_buffer! {
+    static QUEUE: [u64; mpmcbq::MAX_CAPACITY + 1];
+}
+
+fn main() {
+    QUEUE.sender();
+}
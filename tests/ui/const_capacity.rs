@@ -0,0 +1,5 @@
+const CAP: usize = mpmcbq::checked_capacity(0);
+
+fn main() {
+    println!("{}", CAP);
+}
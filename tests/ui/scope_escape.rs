@@ -0,0 +1,6 @@
+fn main() {
+    let mut escaped = None;
+
+    mpmcbq::RingBuffer::<u64>::scope(4, |s, _| escaped = Some(s));
+    escaped.unwrap().send(1);
+}
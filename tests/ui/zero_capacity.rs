@@ -0,0 +1,7 @@
+mpmcbq::static_ring_buffer! {
+    static QUEUE: [u64; 0];
+}
+
+fn main() {
+    QUEUE.sender();
+}